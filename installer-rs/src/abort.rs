@@ -0,0 +1,81 @@
+use nix::sys::signal::{self, killpg, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::Pid;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Set from the signal handler; everything else only reads it
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+/// How long a child gets to clean up after SIGTERM before SIGKILL
+const TERM_GRACE: Duration = Duration::from_secs(10);
+
+/// How often a running child is checked for exit or abort
+const POLL: Duration = Duration::from_millis(200);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    ABORTED.store(true, Ordering::SeqCst);
+}
+
+/// Until `restore_handlers`, SIGINT, SIGTERM and SIGHUP abort the
+/// installation instead of killing the installer halfway: the running step
+/// stops its children and `Installer::unwind` releases the target. Only
+/// set while installing, so Ctrl+C at the prompts still just quits.
+pub fn install_handlers() {
+    set_handlers(SigHandler::Handler(on_signal));
+}
+
+pub fn restore_handlers() {
+    set_handlers(SigHandler::SigDfl);
+}
+
+fn set_handlers(handler: SigHandler) {
+    let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+    for sig in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        // SAFETY: on_signal only stores to an atomic, which is async-signal-safe
+        let _ = unsafe { signal::sigaction(sig, &action) };
+    }
+}
+
+/// The installation was asked to stop
+pub fn requested() -> bool {
+    ABORTED.load(Ordering::SeqCst)
+}
+
+/// Run `cmd` as the leader of its own process group and wait for it.
+/// On abort the whole group gets SIGTERM (pacstrap then unmounts its API
+/// filesystems), then SIGKILL after `TERM_GRACE`; returns Err once every
+/// process is gone, so the caller can unmount the target safely.
+pub fn run(cmd: &mut Command) -> Result<bool, String> {
+    // Terminal Ctrl+C reaches only the installer, which stops the group itself
+    let mut child = cmd.process_group(0).spawn().map_err(|e| e.to_string())?;
+    let pgid = Pid::from_raw(child.id() as i32);
+
+    let mut terminated: Option<Instant> = None;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) if terminated.is_some() => {
+                // Helpers left behind in the group would keep the target busy
+                let _ = killpg(pgid, Signal::SIGKILL);
+                return Err("aborted".to_string());
+            }
+            Ok(Some(status)) => return Ok(status.success()),
+            Ok(None) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        match terminated {
+            None if requested() => {
+                let _ = killpg(pgid, Signal::SIGTERM);
+                terminated = Some(Instant::now());
+            }
+            Some(since) if since.elapsed() > TERM_GRACE => {
+                let _ = killpg(pgid, Signal::SIGKILL);
+            }
+            _ => {}
+        }
+        thread::sleep(POLL);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::repos;
 use serde::Deserialize;
 use std::fs;
 use std::path::Path;
@@ -9,6 +10,7 @@ pub enum SwapMode {
     Small,   // RAM * 0.5
     Suspend, // RAM * 1.0 (for hibernation)
     File,    // Swap file with reasonable default size
+    Partition, // RAM-sized swap partition at the end of the disk (hibernation)
 }
 
 impl SwapMode {
@@ -18,18 +20,107 @@ impl SwapMode {
             "small" => SwapMode::Small,
             "suspend" => SwapMode::Suspend,
             "file" => SwapMode::File,
+            "partition" => SwapMode::Partition,
             _ => SwapMode::Suspend, // default
         }
     }
 
+    /// Value of [disk] swap
+    pub fn as_toml(&self) -> &'static str {
+        match self {
+            SwapMode::None => "none",
+            SwapMode::Small => "small",
+            SwapMode::Suspend => "suspend",
+            SwapMode::File => "file",
+            SwapMode::Partition => "partition",
+        }
+    }
+
     pub fn label(&self) -> &str {
         match self {
             SwapMode::None => "none",
             SwapMode::Small => "small (RAM/2)",
             SwapMode::Suspend => "suspend (RAM size)",
             SwapMode::File => "file",
+            SwapMode::Partition => "partition (RAM size)",
+        }
+    }
+
+    /// Swap size in MB for a machine with `ram_mb` of memory
+    pub fn size_mb(&self, ram_mb: u64) -> u64 {
+        match self {
+            SwapMode::None => 0,
+            SwapMode::Small => ram_mb / 2,
+            SwapMode::Suspend | SwapMode::Partition => ram_mb,
+            SwapMode::File => ram_mb.min(8192),
+        }
+    }
+}
+
+/// Root filesystem from [disk] section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filesystem {
+    Ext4,
+    Xfs,
+    F2fs,     // Flash-friendly: eMMC / SD card installs
+    Btrfs,    // Subvolumes @, @home, @snapshots (optional snapper snapshots)
+    Bcachefs, // Experimental: native compression + encryption (no LUKS)
+    Zfs,      // Advanced: root-on-ZFS pool with datasets (archzfs)
+}
+
+impl Filesystem {
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "xfs" => Filesystem::Xfs,
+            "f2fs" => Filesystem::F2fs,
+            "btrfs" => Filesystem::Btrfs,
+            "bcachefs" => Filesystem::Bcachefs,
+            "zfs" => Filesystem::Zfs,
+            _ => Filesystem::Ext4, // default
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Xfs => "xfs",
+            Filesystem::F2fs => "f2fs (flash storage)",
+            Filesystem::Btrfs => "btrfs (subvolumes)",
+            Filesystem::Bcachefs => "bcachefs (experimental)",
+            Filesystem::Zfs => "zfs (advanced)",
+        }
+    }
+
+    /// Filesystem name as understood by mount/fstab
+    pub fn fs_type(&self) -> &str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Xfs => "xfs",
+            Filesystem::F2fs => "f2fs",
+            Filesystem::Btrfs => "btrfs",
+            Filesystem::Bcachefs => "bcachefs",
+            Filesystem::Zfs => "zfs",
         }
     }
+
+    /// Filesystems that encrypt on their own instead of sitting inside LUKS
+    pub fn has_native_encryption(&self) -> bool {
+        matches!(self, Filesystem::Bcachefs | Filesystem::Zfs)
+    }
+
+    /// GRUB cannot reliably read these, so the kernel must boot from the ESP
+    pub fn requires_efistub(&self) -> bool {
+        matches!(self, Filesystem::Bcachefs | Filesystem::Zfs)
+    }
+
+    /// Whether a swapfile on this root filesystem is safe to use
+    pub fn supports_swapfile(&self) -> bool {
+        matches!(self, Filesystem::Ext4 | Filesystem::Xfs)
+    }
+
+    pub fn is_experimental(&self) -> bool {
+        matches!(self, Filesystem::Bcachefs)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,11 +138,61 @@ impl Default for BlunuxConfig {
     }
 }
 
+/// One [locale] keyboard entry: an XKB layout with an optional variant,
+/// written "layout" or "layout-variant" (e.g. "de-neo", "us-intl")
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyboard {
+    pub layout: String,
+    /// Empty for the layout's default variant
+    pub variant: String,
+}
+
+impl Keyboard {
+    pub fn parse(s: &str) -> Self {
+        let (layout, variant) = s.trim().split_once('-').unwrap_or((s.trim(), ""));
+        Self { layout: layout.to_string(), variant: variant.to_string() }
+    }
+
+    /// Closest console (kbd) keymap; the console has no variants for most
+    /// layouts and no Korean or Japanese-input keymaps at all
+    pub fn console_keymap(&self) -> String {
+        let keymap = match (self.layout.as_str(), self.variant.as_str()) {
+            ("us", "intl" | "altgr-intl") => "us-acentos",
+            ("us", "dvorak") => "dvorak",
+            ("us", "colemak") => "colemak",
+            ("de", "neo") => "neo",
+            ("de", "nodeadkeys") => "de-latin1-nodeadkeys",
+            ("de", _) => "de-latin1",
+            ("fr", "bepo") => "fr-bepo",
+            ("fr", _) => "fr-latin9",
+            ("gb", _) => "uk",
+            ("se", _) => "sv-latin1",
+            ("jp", _) => "jp106",
+            ("kr", _) => "us",
+            (layout, _) => layout,
+        };
+        keymap.to_string()
+    }
+}
+
+impl std::fmt::Display for Keyboard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.variant.is_empty() {
+            write!(f, "{}", self.layout)
+        } else {
+            write!(f, "{}-{}", self.layout, self.variant)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LocaleConfig {
     pub languages: Vec<String>,
     pub timezone: String,
-    pub keyboards: Vec<String>,
+    pub keyboards: Vec<Keyboard>,
+    /// LANG of the user's sessions when it differs from the system one
+    /// (first of `languages`); None = same as the system
+    pub user_language: Option<String>,
 }
 
 impl Default for LocaleConfig {
@@ -59,8 +200,28 @@ impl Default for LocaleConfig {
         Self {
             languages: vec!["ko_KR".to_string()],
             timezone: "Asia/Seoul".to_string(),
-            keyboards: vec!["us".to_string()],
+            keyboards: vec![Keyboard::parse("us")],
+            user_language: None,
+        }
+    }
+}
+
+impl LocaleConfig {
+    /// System LANG without the .UTF-8 suffix
+    pub fn system_language(&self) -> &str {
+        self.languages.first().map_or("en_US", String::as_str)
+    }
+
+    /// Every locale locale-gen has to build: the languages, the user's
+    /// language and en_US as a fallback
+    pub fn generated_languages(&self) -> Vec<&str> {
+        let mut langs: Vec<&str> = self.languages.iter().map(String::as_str).collect();
+        for extra in [self.user_language.as_deref(), Some("en_US")].into_iter().flatten() {
+            if !langs.contains(&extra) {
+                langs.push(extra);
+            }
         }
+        langs
     }
 }
 
@@ -92,19 +253,538 @@ impl Default for KernelConfig {
     }
 }
 
+/// [disk.encryption] section
+#[derive(Debug, Clone)]
+pub struct EncryptionConfig {
+    /// Device holding the LUKS header (e.g. /dev/disk/by-label/KEYS).
+    /// Empty means the header stays on the root partition.
+    pub detached_header: String,
+    /// Keep /boot inside the LUKS container and let GRUB unlock it
+    pub encrypt_boot: bool,
+    /// Public key allowed to unlock root over SSH from the initramfs.
+    /// Empty disables remote unlock.
+    pub ssh_unlock_key: String,
+    /// SSH server embedded in the initramfs: "dropbear" or "tinyssh"
+    pub ssh_unlock_server: String,
+    /// Extra keyslot unlocking root at boot from a keyfile: "initramfs"
+    /// (embedded; implied by encrypt_boot) or a device holding the keyfile,
+    /// e.g. /dev/disk/by-label/KEYS. Empty = passphrase only.
+    pub keyfile: String,
+    /// Enroll the TPM (PCR 7) so root unlocks without a prompt on this
+    /// machine; the passphrase stays as fallback
+    pub tpm2: bool,
+    /// cryptsetup --cipher, e.g. "aes-xts-plain64". Empty = cryptsetup default.
+    pub cipher: String,
+    /// Master key size in bits (0 = cryptsetup default)
+    pub key_size: u32,
+    /// Keyslot key derivation: "argon2id", "argon2i" or "pbkdf2".
+    /// Empty = cryptsetup default (argon2id); encrypt_boot forces pbkdf2.
+    pub pbkdf: String,
+    /// Milliseconds spent unlocking a keyslot (0 = cryptsetup default, 2000)
+    pub iter_time: u32,
+    /// argon2 memory cost in KiB (0 = benchmarked by cryptsetup)
+    pub pbkdf_memory: u32,
+    /// Fixed iteration count, skipping the benchmark (0 = use iter_time)
+    pub pbkdf_iterations: u32,
+    /// LUKS header format: 2, or 1 for bootloaders and firmware tools that
+    /// cannot read LUKS2 (PBKDF2 keyslots only, no TPM2 tokens)
+    pub luks_version: u8,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            detached_header: String::new(),
+            encrypt_boot: false,
+            ssh_unlock_key: String::new(),
+            ssh_unlock_server: "dropbear".to_string(),
+            keyfile: String::new(),
+            tpm2: false,
+            cipher: String::new(),
+            key_size: 0,
+            pbkdf: String::new(),
+            iter_time: 0,
+            pbkdf_memory: 0,
+            pbkdf_iterations: 0,
+            luks_version: 2,
+        }
+    }
+}
+
+/// [[disk.extra]] entry: a non-OS data disk added to fstab
+#[derive(Debug, Clone)]
+pub struct ExtraDisk {
+    pub device: String,
+    pub filesystem: String,
+    pub mountpoint: String,
+    pub options: String,
+    /// Create a new filesystem (destroys data); false only mounts it
+    pub format: bool,
+    /// Steam library drive: owned by the install user (uid/gid mount options
+    /// on NTFS, ownership + default ACL on ext4)
+    pub games: bool,
+}
+
+/// [[disk.partitions]] entry: one partition of a custom layout on the
+/// target disk, created in the order declared
+#[derive(Debug, Clone)]
+pub struct PartitionSpec {
+    /// "/", "/boot/efi", "/home", ... (empty for swap)
+    pub mountpoint: String,
+    /// "512M", "50G", "1T" or "rest" (remaining space, last entry only)
+    pub size: String,
+    /// mkfs filesystem or "swap"; "/" always uses [disk] filesystem
+    pub filesystem: String,
+    /// LUKS ([disk.encryption] luks_version) with the disk encryption
+    /// password, independent of whether root is encrypted (not for /,
+    /// /boot, /boot/efi or swap)
+    pub encrypt: bool,
+    /// false mounts the existing filesystem untouched; the partition table
+    /// is then kept and entry N is partition N of the target disk
+    pub format: bool,
+    /// Existing partition to use instead of partition N of the target disk,
+    /// e.g. "/dev/nvme0n1p5"; the partition table is then never touched
+    pub device: String,
+}
+
+/// Parse "512M", "50G" or "1T" into MiB
+pub fn parse_size_mib(size: &str) -> Option<u64> {
+    let num = size.strip_suffix(['M', 'G', 'T'])?;
+    let n: u64 = num.parse().ok()?;
+    match &size[num.len()..] {
+        "M" => Some(n),
+        "G" => n.checked_mul(1024),
+        _ => n.checked_mul(1024 * 1024),
+    }
+}
+
+impl PartitionSpec {
+    /// Size in MiB, or None for "rest"
+    pub fn size_mib(&self) -> Option<u64> {
+        if self.size == "rest" {
+            return None;
+        }
+        parse_size_mib(&self.size)
+    }
+
+    pub fn is_swap(&self) -> bool {
+        self.filesystem == "swap"
+    }
+}
+
+/// [disk.lvm] section: root (and optional home/swap) as logical volumes
+/// in one volume group on the root partition
+#[derive(Debug, Clone, Default)]
+pub struct LvmConfig {
+    pub enabled: bool,
+    /// lvcreate size for root (e.g. "50G"); empty = all free space
+    pub root_size: String,
+    /// Separate /home volume taking the space left after root
+    pub home: bool,
+}
+
+/// [disk.labels] section: filesystem labels of the installer-created
+/// filesystems; empty = BLX-ROOT-<hostname>, BLX-EFI, BLX-HOME
+#[derive(Debug, Clone, Default)]
+pub struct LabelsConfig {
+    pub root: String,
+    pub esp: String,
+    pub home: String,
+}
+
+/// [disk.alongside] section: dual boot by shrinking an existing Windows
+/// (NTFS) or Linux (ext4) partition instead of wiping the disk
+#[derive(Debug, Clone, Default)]
+pub struct AlongsideConfig {
+    pub enabled: bool,
+    /// Partition to shrink; empty = the largest detected OS partition
+    pub partition: String,
+    /// Space taken from it for Blunux (e.g. "60G")
+    pub size: String,
+}
+
+/// [disk.keep_home] section: reinstall over an existing Linux install,
+/// reformatting root and the ESP but keeping the separate /home partition
+#[derive(Debug, Clone, Default)]
+pub struct KeepHomeConfig {
+    /// Existing /home partition; empty = disabled
+    pub partition: String,
+    /// Old root partition to reformat; empty = the install whose fstab
+    /// mounts `partition` at /home
+    pub root: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct DiskConfig {
     pub swap: SwapMode,
+    pub filesystem: Filesystem,
+    pub encryption: EncryptionConfig,
+    /// Named layout template ("simple", "encrypted-lvm", ...); empty = none
+    pub layout: String,
+    /// Snapper timeline + pacman snapshots of the btrfs root
+    pub snapshots: bool,
+    /// Snapshot tool for `snapshots`: "snapper" (default) or "timeshift"
+    pub snapshot_tool: String,
+    /// Separate /home partition in the default layout: root size as "30%"
+    /// of the disk or "80G"; /home takes the rest. Empty = no /home partition
+    pub home_split: String,
+    /// How fstab and the kernel command line name filesystems: "uuid"
+    /// (default) or "label" (see `labels`)
+    pub fstab: String,
+    pub labels: LabelsConfig,
+    /// Second disk that holds the ESP (wiped); empty = ESP on the target disk
+    pub esp_disk: String,
+    /// Existing ESP on another disk (e.g. Windows' drive) used as /boot/efi
+    /// without formatting: a partition path, or "auto" for the first one found
+    pub reuse_esp: String,
+    /// GPT + bios_grub partition on BIOS machines; automatic on disks over
+    /// 2 TiB, which MBR cannot address
+    pub bios_gpt: bool,
+    /// ESP size of the default layout in MB; 0 = 512, or 1024 when NMBL or
+    /// a second kernel puts more images on it
+    pub esp_size_mb: u64,
+    /// Erase the old data before partitioning: "none" (default), "auto"
+    /// (by drive type), "discard", "nvme-format" or "zero"
+    pub secure_erase: String,
+    /// Read-test this many evenly spaced 1 MiB blocks of the target before
+    /// installing; 0 = only check SMART data
+    pub read_scan: u64,
+    /// Ask before installing onto a target that writes slower than this
+    /// many MB/s (a USB 2 stick); 0 = no write test
+    pub min_write_mbps: u64,
+    pub lvm: LvmConfig,
+    pub alongside: AlongsideConfig,
+    pub keep_home: KeepHomeConfig,
+    /// Custom layout; empty = ESP (esp_size_mb) + root on the rest of the disk
+    pub partitions: Vec<PartitionSpec>,
+    pub extra: Vec<ExtraDisk>,
 }
 
 impl Default for DiskConfig {
     fn default() -> Self {
         Self {
             swap: SwapMode::Suspend,
+            filesystem: Filesystem::Ext4,
+            encryption: EncryptionConfig::default(),
+            layout: String::new(),
+            snapshots: false,
+            snapshot_tool: "snapper".to_string(),
+            home_split: String::new(),
+            fstab: "uuid".to_string(),
+            labels: LabelsConfig::default(),
+            esp_disk: String::new(),
+            reuse_esp: String::new(),
+            bios_gpt: false,
+            esp_size_mb: 0,
+            secure_erase: "none".to_string(),
+            read_scan: 0,
+            min_write_mbps: 15,
+            lvm: LvmConfig::default(),
+            alongside: AlongsideConfig::default(),
+            keep_home: KeepHomeConfig::default(),
+            partitions: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl DiskConfig {
+    /// Manual mode: some [[disk.partitions]] entry names an existing
+    /// partition or is reused without formatting, so the existing partition
+    /// table is kept as is
+    pub fn keeps_partition_table(&self) -> bool {
+        self.partitions.iter().any(|p| !p.format || !p.device.is_empty())
+    }
+
+    /// The default layout creates its ESP on the target disk (neither
+    /// esp_disk nor reuse_esp is set)
+    pub fn esp_on_target(&self) -> bool {
+        self.esp_disk.is_empty() && self.reuse_esp.is_empty()
+    }
+}
+
+/// [power] section
+#[derive(Debug, Clone)]
+pub struct PowerConfig {
+    pub suspend_then_hibernate: bool,
+    /// Time spent suspended before hibernating (systemd time span)
+    pub hibernate_delay: String,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            suspend_then_hibernate: false,
+            hibernate_delay: "2h".to_string(),
+        }
+    }
+}
+
+/// [system] section: small boot/console tweaks
+#[derive(Debug, Clone)]
+pub struct SystemConfig {
+    /// Turn NumLock on in SDDM, Plasma and the virtual consoles
+    pub numlock: bool,
+    /// Virtual console that logs the user in automatically (e.g. "tty2"), empty = none
+    pub tty_autologin: String,
+    /// "graphical" or "multi-user"
+    pub default_target: String,
+    /// Plasma session SDDM starts: "wayland" (default) or "x11"; decides
+    /// how input methods are wired up
+    pub session: String,
+}
+
+impl Default for SystemConfig {
+    fn default() -> Self {
+        Self {
+            numlock: false,
+            tty_autologin: String::new(),
+            default_target: "graphical".to_string(),
+            session: "wayland".to_string(),
+        }
+    }
+}
+
+/// [kiosk] section: single fullscreen application for signage/kiosks
+#[derive(Debug, Clone)]
+pub struct KioskConfig {
+    pub enabled: bool,
+    /// Restricted account the application runs as
+    pub user: String,
+    /// Command line launched fullscreen under cage
+    pub app: String,
+    pub disable_usb_storage: bool,
+}
+
+impl Default for KioskConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user: "kiosk".to_string(),
+            app: String::new(),
+            disable_usb_storage: true,
+        }
+    }
+}
+
+/// [security] section. `preset` switches on a coherent set of the
+/// individual options; keys given explicitly still override it.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityConfig {
+    pub preset: String,
+    /// ufw with incoming traffic denied
+    pub firewall: bool,
+    pub apparmor: bool,
+    /// Kernel/network sysctl hardening drop-in
+    pub sysctl_hardening: bool,
+    /// Daily unattended pacman upgrade timer
+    pub auto_updates: bool,
+    /// PermitRootLogin no for sshd
+    pub disable_root_ssh: bool,
+    /// ClamAV daemon with on-access scanning of /home (not part of any preset)
+    pub antivirus: bool,
+}
+
+impl SecurityConfig {
+    pub const PRESETS: [&'static str; 1] = ["workstation"];
+
+    fn apply_preset(&mut self) {
+        if self.preset == "workstation" {
+            self.firewall = true;
+            self.apparmor = true;
+            self.sysctl_hardening = true;
+            self.auto_updates = true;
+            self.disable_root_ssh = true;
+        }
+    }
+}
+
+/// [hardware] section: overrides for automatic driver detection
+#[derive(Debug, Clone, Default)]
+pub struct HardwareConfig {
+    /// Installed in addition to whatever detection picks
+    pub extra_drivers: Vec<String>,
+    /// Never installed even when detected
+    pub skip_drivers: Vec<String>,
+}
+
+/// [services] section: optional system daemons
+#[derive(Debug, Clone, Default)]
+pub struct ServicesConfig {
+    /// smartd watching every disk, warnings sent as desktop notifications
+    pub disk_monitoring: bool,
+    /// Also mail smartd warnings here (needs a working `mail` command)
+    pub disk_monitoring_email: String,
+    /// Periodic scrubs for the chosen filesystems: btrfs/ZFS monthly,
+    /// e2scrub for ext4 on LVM
+    pub fs_maintenance: bool,
+    /// Versions of each package paccache.timer keeps in the pacman cache
+    /// every week; 0 = no cleanup
+    pub pacman_cache_keep: u32,
+    /// reflector.timer re-ranking the pacman mirrors every week
+    pub mirror_refresh: bool,
+    /// Countries reflector picks mirrors from ("KR", "Japan"); empty = all
+    pub mirror_countries: Vec<String>,
+}
+
+/// [[containers]] entry: a systemd-nspawn container bootstrapped under
+/// /var/lib/machines
+#[derive(Debug, Clone)]
+pub struct ContainerConfig {
+    pub name: String,
+    /// "arch" or "debian"
+    pub distro: String,
+    /// Debian suite ("stable", "bookworm", ...); ignored for Arch
+    pub release: String,
+    /// Start with machines.target at boot
+    pub autostart: bool,
+}
+
+impl ContainerConfig {
+    pub const DISTROS: [&'static str; 2] = ["arch", "debian"];
+}
+
+/// [[files]] entry: a file or directory created in the target after the
+/// users exist. With neither content nor source it is a directory.
+#[derive(Debug, Clone, Default)]
+pub struct FileEntry {
+    /// Absolute path in the target, or "~/..." for the user's home
+    pub path: String,
+    /// Octal mode; empty = 0644 for files, 0755 for directories
+    pub mode: String,
+    /// "user" or "user:group"; empty = the user under ~/, root elsewhere
+    pub owner: String,
+    pub content: Option<String>,
+    /// File on the live system copied instead of `content`
+    pub source: String,
+}
+
+impl FileEntry {
+    /// A file written by the installer itself
+    pub fn file(path: &str, mode: &str, content: &str) -> Self {
+        FileEntry {
+            path: path.to_string(),
+            mode: mode.to_string(),
+            content: Some(content.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.content.is_none() && self.source.is_empty()
+    }
+
+    pub fn in_home(&self) -> bool {
+        self.path.starts_with("~/")
+    }
+
+    /// Path inside the target, with ~/ expanded to the user's home
+    pub fn target_path(&self, username: &str) -> String {
+        match self.path.strip_prefix("~/") {
+            Some(rest) => format!("/home/{username}/{rest}"),
+            None => self.path.clone(),
+        }
+    }
+
+    pub fn mode(&self) -> &str {
+        match self.mode.as_str() {
+            "" if self.is_dir() => "0755",
+            "" => "0644",
+            mode => mode,
+        }
+    }
+
+    pub fn owner<'a>(&'a self, username: &'a str) -> &'a str {
+        match self.owner.as_str() {
+            "" if self.in_home() => username,
+            "" => "root",
+            owner => owner,
+        }
+    }
+}
+
+/// [provision] section: hand the machine to configuration management
+#[derive(Debug, Clone)]
+pub struct ProvisionConfig {
+    /// ansible_pull.repo: git URL pulled on first boot
+    pub ansible_repo: String,
+    /// ansible_pull.playbook, relative to the repository root
+    pub ansible_playbook: String,
+    /// Salt master the minion connects to
+    pub salt_master: String,
+}
+
+impl Default for ProvisionConfig {
+    fn default() -> Self {
+        Self {
+            ansible_repo: String::new(),
+            ansible_playbook: "local.yml".to_string(),
+            salt_master: String::new(),
+        }
+    }
+}
+
+/// [[hooks.<point>]] entry: a shell command run mid-install
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub command: String,
+    /// Run inside arch-chroot on the target instead of on the live host
+    pub chroot: bool,
+}
+
+/// [hooks] section, one list per point in `Installer::install`
+#[derive(Debug, Clone, Default)]
+pub struct HooksConfig {
+    /// Before the target disk is touched (host only)
+    pub pre_partition: Vec<Hook>,
+    /// Target mounted at /mnt, nothing installed yet (host only)
+    pub pre_pacstrap: Vec<Hook>,
+    /// Base system installed and fstab written
+    pub post_pacstrap: Vec<Hook>,
+    /// System configuration (swap, initramfs, network) done
+    pub post_configure: Vec<Hook>,
+    /// Drivers and packages installed
+    pub post_packages: Vec<Hook>,
+    /// Boot loader installed, before finalize unmounts the target
+    pub post_bootloader: Vec<Hook>,
+}
+
+impl HooksConfig {
+    /// Hook point names in execution order
+    pub const POINTS: [&'static str; 6] = [
+        "pre_partition",
+        "pre_pacstrap",
+        "post_pacstrap",
+        "post_configure",
+        "post_packages",
+        "post_bootloader",
+    ];
+
+    pub fn get(&self, point: &str) -> &[Hook] {
+        match point {
+            "pre_partition" => &self.pre_partition,
+            "pre_pacstrap" => &self.pre_pacstrap,
+            "post_pacstrap" => &self.post_pacstrap,
+            "post_configure" => &self.post_configure,
+            "post_packages" => &self.post_packages,
+            "post_bootloader" => &self.post_bootloader,
+            _ => &[],
         }
     }
 }
 
+/// Driver packages for a [packages.printing] brand
+pub fn printer_driver_packages(brand: &str) -> Option<&'static [&'static str]> {
+    match brand {
+        "hp" => Some(&["hplip"]),
+        "brother" => Some(&["brlaser"]),
+        "epson" => Some(&["epson-inkjet-printer-escpr"]),
+        "samsung" => Some(&["splix"]),
+        "canon" | "generic" => Some(&["gutenprint", "foomatic-db-gutenprint-ppds"]),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct PackagesConfig {
     // Desktop
@@ -136,6 +816,13 @@ pub struct PackagesConfig {
     pub steam: bool,
     pub unciv: bool,
     pub snes9x: bool,
+    /// Lutris with wine-staging and its 32-bit dependencies
+    pub lutris: bool,
+    /// Latest GE-Proton in the user's Steam compatibilitytools.d
+    pub proton_ge: bool,
+    /// Game controller support: "none", "kernel" (xpad/hid-playstation) or
+    /// "xpadneo" (AUR xpadneo + xone); empty = "kernel" when a game is selected
+    pub controllers: String,
     // Virtualization
     pub virtualbox: bool,
     pub docker: bool,
@@ -148,19 +835,53 @@ pub struct PackagesConfig {
     pub conky: bool,
     pub vnc: bool,
     pub samba: bool,
+    pub flatpak: bool,
+    // Printing: vendor driver bundles and mDNS printer discovery
+    pub printer_brands: Vec<String>,
+    pub printer_discovery: bool,
+    // Flatpak apps (Flathub IDs), installed when flatpak is enabled
+    pub flatpak_apps: Vec<String>,
+    // AUR packages, built in the chroot or queued for first boot
+    pub aur: Vec<String>,
+    pub aur_build_in_chroot: bool,
+    /// Third-party repositories with a pinned signing key (repos::KNOWN),
+    /// enabled on the live system and the target
+    pub repos: Vec<String>,
+    /// 32-bit [multilib] repo: Some(true/false) forces it; None = "auto",
+    /// enabled when lib32 drivers, Steam or Lutris need it
+    pub multilib: Option<bool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct InstallConfig {
+    /// Disk node; config.toml may also name it by serial, WWN, model or
+    /// "largest", resolved by `disk::resolve_target_disk` before the wizard
     pub target_disk: String,
     pub hostname: String,
     pub username: String,
+    /// UID (and GID of the user's own group); None = first free one, 1000
+    pub uid: Option<u32>,
     pub root_password: String,
     pub user_password: String,
     pub use_encryption: bool,
     pub encryption_password: String,
     pub bootloader: String,
     pub autologin: bool,
+    /// Golden image: strip machine-id and SSH host keys so each clone
+    /// regenerates its own on first boot
+    pub generalize: bool,
+    /// Also install the bootloader (or NMBL's UKI) at EFI/BOOT/BOOTX64.EFI,
+    /// for firmware that loses its NVRAM boot entries
+    pub removable_fallback: bool,
+    /// Seconds the final confirmations wait before taking their default;
+    /// 0 = wait for an answer
+    pub prompt_timeout: u64,
+    /// The partition plan and data-loss confirmations default to "yes", so
+    /// with prompt_timeout the disk is wiped with nobody present
+    pub unattended: bool,
+    /// Anonymous install report: None = ask after the install,
+    /// Some(false) = never ask or send, Some(true) = send without asking
+    pub telemetry: Option<bool>,
 }
 
 impl Default for InstallConfig {
@@ -169,43 +890,53 @@ impl Default for InstallConfig {
             target_disk: String::new(),
             hostname: "blunux".to_string(),
             username: "user".to_string(),
+            uid: None,
             root_password: String::new(),
             user_password: String::new(),
             use_encryption: false,
             encryption_password: String::new(),
             bootloader: "grub".to_string(),
             autologin: true,
+            generalize: false,
+            removable_fallback: true,
+            prompt_timeout: 0,
+            unattended: false,
+            telemetry: None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Config {
     pub blunux: BlunuxConfig,
     pub locale: LocaleConfig,
     pub input_method: InputMethodConfig,
     pub kernel: KernelConfig,
     pub disk: DiskConfig,
+    pub power: PowerConfig,
+    pub system: SystemConfig,
+    pub kiosk: KioskConfig,
+    pub hooks: HooksConfig,
+    pub provision: ProvisionConfig,
+    pub containers: Vec<ContainerConfig>,
+    pub files: Vec<FileEntry>,
+    pub security: SecurityConfig,
+    pub services: ServicesConfig,
+    pub hardware: HardwareConfig,
     pub packages: PackagesConfig,
     pub install: InstallConfig,
     /// True when config was successfully loaded from a TOML file.
     /// When true, all fields are trusted and interactive prompts are skipped.
     pub loaded_from_file: bool,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            blunux: BlunuxConfig::default(),
-            locale: LocaleConfig::default(),
-            input_method: InputMethodConfig::default(),
-            kernel: KernelConfig::default(),
-            disk: DiskConfig::default(),
-            packages: PackagesConfig::default(),
-            install: InstallConfig::default(),
-            loaded_from_file: false,
-        }
-    }
+    /// --force on the command line: allow reusing the existing root
+    /// filesystem ([[disk.partitions]] "/" with format = false)
+    pub force: bool,
+    /// --skip-disk-checks: install even if the target is too small for the
+    /// package set or belongs to an active RAID array / LVM volume group
+    pub skip_disk_checks: bool,
+    /// --image: disk image file installed through the loop device in
+    /// target_disk; no boot entries go into this machine's NVRAM
+    pub image: String,
 }
 
 // TOML deserialization structures
@@ -216,6 +947,16 @@ struct TomlRoot {
     input_method: Option<TomlInputMethod>,
     kernel: Option<TomlKernel>,
     disk: Option<TomlDisk>,
+    power: Option<TomlPower>,
+    system: Option<TomlSystem>,
+    kiosk: Option<TomlKiosk>,
+    hooks: Option<TomlHooks>,
+    provision: Option<TomlProvision>,
+    containers: Option<Vec<TomlContainer>>,
+    files: Option<Vec<TomlFile>>,
+    security: Option<TomlSecurity>,
+    services: Option<TomlServices>,
+    hardware: Option<TomlHardware>,
     install: Option<TomlInstall>,
     packages: Option<TomlPackages>,
 }
@@ -231,6 +972,7 @@ struct TomlLocale {
     language: Option<TomlStringOrArray>,
     timezone: Option<String>,
     keyboard: Option<Vec<String>>,
+    user_language: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -240,6 +982,14 @@ enum TomlStringOrArray {
     Array(Vec<String>),
 }
 
+/// true, false or "auto"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlBoolOrAuto {
+    Bool(bool),
+    Auto(String),
+}
+
 #[derive(Deserialize, Default)]
 struct TomlInputMethod {
     enabled: Option<bool>,
@@ -255,90 +1005,284 @@ struct TomlKernel {
 #[derive(Deserialize, Default)]
 struct TomlDisk {
     swap: Option<String>,
+    filesystem: Option<String>,
+    layout: Option<String>,
+    snapshots: Option<bool>,
+    snapshot_tool: Option<String>,
+    home_split: Option<String>,
+    fstab: Option<String>,
+    esp_disk: Option<String>,
+    reuse_esp: Option<String>,
+    bios_gpt: Option<bool>,
+    esp_size_mb: Option<u64>,
+    secure_erase: Option<String>,
+    read_scan: Option<u64>,
+    min_write_mbps: Option<u64>,
+    encryption: Option<TomlEncryption>,
+    lvm: Option<TomlLvm>,
+    labels: Option<TomlLabels>,
+    alongside: Option<TomlAlongside>,
+    keep_home: Option<TomlKeepHome>,
+    partitions: Option<Vec<TomlPartition>>,
+    extra: Option<Vec<TomlExtraDisk>>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlInstall {
-    hostname: Option<String>,
-    username: Option<String>,
-    root_password: Option<String>,
-    user_password: Option<String>,
-    bootloader: Option<String>,
-    encryption: Option<bool>,
-    autologin: Option<bool>,
+struct TomlPartition {
+    mountpoint: Option<String>,
+    size: Option<String>,
+    filesystem: Option<String>,
+    encrypt: Option<bool>,
+    format: Option<bool>,
+    device: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlPackages {
-    desktop: Option<TomlDesktop>,
-    browser: Option<TomlBrowser>,
-    office: Option<TomlOffice>,
-    development: Option<TomlDevelopment>,
-    multimedia: Option<TomlMultimedia>,
-    gaming: Option<TomlGaming>,
-    virtualization: Option<TomlVirtualization>,
-    communication: Option<TomlCommunication>,
-    utility: Option<TomlUtility>,
+struct TomlAlongside {
+    enabled: Option<bool>,
+    partition: Option<String>,
+    size: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlDesktop {
-    kde: Option<bool>,
+struct TomlKeepHome {
+    partition: Option<String>,
+    root: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlBrowser {
-    firefox: Option<bool>,
-    whale: Option<bool>,
-    chrome: Option<bool>,
-    mullvad: Option<bool>,
+struct TomlLvm {
+    enabled: Option<bool>,
+    root_size: Option<String>,
+    home: Option<bool>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlOffice {
-    libreoffice: Option<bool>,
-    hoffice: Option<bool>,
-    texlive: Option<bool>,
+struct TomlLabels {
+    root: Option<String>,
+    esp: Option<String>,
+    home: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlDevelopment {
-    vscode: Option<bool>,
-    sublime: Option<bool>,
-    git: Option<bool>,
-    rust: Option<bool>,
-    julia: Option<bool>,
-    nodejs: Option<bool>,
-    github_cli: Option<bool>,
+struct TomlExtraDisk {
+    device: Option<String>,
+    filesystem: Option<String>,
+    mountpoint: Option<String>,
+    options: Option<String>,
+    format: Option<bool>,
+    games: Option<bool>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlMultimedia {
-    vlc: Option<bool>,
-    obs: Option<bool>,
-    freetv: Option<bool>,
-    ytdlp: Option<bool>,
-    freetube: Option<bool>,
+struct TomlEncryption {
+    detached_header: Option<String>,
+    encrypt_boot: Option<bool>,
+    ssh_unlock_key: Option<String>,
+    ssh_unlock_server: Option<String>,
+    keyfile: Option<String>,
+    tpm2: Option<bool>,
+    cipher: Option<String>,
+    key_size: Option<u32>,
+    pbkdf: Option<String>,
+    iter_time: Option<u32>,
+    pbkdf_memory: Option<u32>,
+    pbkdf_iterations: Option<u32>,
+    luks_version: Option<u8>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlGaming {
-    steam: Option<bool>,
-    unciv: Option<bool>,
-    snes9x: Option<bool>,
+struct TomlPower {
+    suspend_then_hibernate: Option<bool>,
+    hibernate_delay: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlVirtualization {
-    virtualbox: Option<bool>,
-    docker: Option<bool>,
+struct TomlSystem {
+    numlock: Option<bool>,
+    tty_autologin: Option<String>,
+    default_target: Option<String>,
+    session: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
-struct TomlCommunication {
-    teams: Option<bool>,
-    whatsapp: Option<bool>,
-    onenote: Option<bool>,
+struct TomlKiosk {
+    enabled: Option<bool>,
+    user: Option<String>,
+    app: Option<String>,
+    disable_usb_storage: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlSecurity {
+    preset: Option<String>,
+    firewall: Option<bool>,
+    apparmor: Option<bool>,
+    sysctl_hardening: Option<bool>,
+    auto_updates: Option<bool>,
+    disable_root_ssh: Option<bool>,
+    antivirus: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlHardware {
+    extra_drivers: Option<Vec<String>>,
+    skip_drivers: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlServices {
+    disk_monitoring: Option<bool>,
+    disk_monitoring_email: Option<String>,
+    fs_maintenance: Option<bool>,
+    pacman_cache_keep: Option<u32>,
+    mirror_refresh: Option<bool>,
+    mirror_countries: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlProvision {
+    ansible_pull: Option<TomlAnsiblePull>,
+    salt_master: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlContainer {
+    name: Option<String>,
+    distro: Option<String>,
+    release: Option<String>,
+    autostart: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlFile {
+    path: Option<String>,
+    mode: Option<String>,
+    owner: Option<String>,
+    content: Option<String>,
+    source: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlAnsiblePull {
+    repo: Option<String>,
+    playbook: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlHooks {
+    pre_partition: Option<Vec<TomlHook>>,
+    pre_pacstrap: Option<Vec<TomlHook>>,
+    post_pacstrap: Option<Vec<TomlHook>>,
+    post_configure: Option<Vec<TomlHook>>,
+    post_packages: Option<Vec<TomlHook>>,
+    post_bootloader: Option<Vec<TomlHook>>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlHook {
+    command: Option<String>,
+    chroot: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlInstall {
+    target_disk: Option<String>,
+    hostname: Option<String>,
+    username: Option<String>,
+    uid: Option<u32>,
+    root_password: Option<String>,
+    user_password: Option<String>,
+    bootloader: Option<String>,
+    encryption: Option<bool>,
+    autologin: Option<bool>,
+    generalize: Option<bool>,
+    removable_fallback: Option<bool>,
+    prompt_timeout: Option<u64>,
+    unattended: Option<bool>,
+    telemetry: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlPackages {
+    aur: Option<Vec<String>>,
+    aur_build_in_chroot: Option<bool>,
+    repos: Option<Vec<String>>,
+    multilib: Option<TomlBoolOrAuto>,
+    desktop: Option<TomlDesktop>,
+    browser: Option<TomlBrowser>,
+    office: Option<TomlOffice>,
+    development: Option<TomlDevelopment>,
+    multimedia: Option<TomlMultimedia>,
+    gaming: Option<TomlGaming>,
+    virtualization: Option<TomlVirtualization>,
+    communication: Option<TomlCommunication>,
+    utility: Option<TomlUtility>,
+    printing: Option<TomlPrinting>,
+    flatpak: Option<TomlFlatpak>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlDesktop {
+    kde: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlBrowser {
+    firefox: Option<bool>,
+    whale: Option<bool>,
+    chrome: Option<bool>,
+    mullvad: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlOffice {
+    libreoffice: Option<bool>,
+    hoffice: Option<bool>,
+    texlive: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlDevelopment {
+    vscode: Option<bool>,
+    sublime: Option<bool>,
+    git: Option<bool>,
+    rust: Option<bool>,
+    julia: Option<bool>,
+    nodejs: Option<bool>,
+    github_cli: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlMultimedia {
+    vlc: Option<bool>,
+    obs: Option<bool>,
+    freetv: Option<bool>,
+    ytdlp: Option<bool>,
+    freetube: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlGaming {
+    steam: Option<bool>,
+    unciv: Option<bool>,
+    snes9x: Option<bool>,
+    lutris: Option<bool>,
+    proton_ge: Option<bool>,
+    controllers: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlVirtualization {
+    virtualbox: Option<bool>,
+    docker: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlCommunication {
+    teams: Option<bool>,
+    whatsapp: Option<bool>,
+    onenote: Option<bool>,
 }
 
 #[derive(Deserialize, Default)]
@@ -347,6 +1291,18 @@ struct TomlUtility {
     conky: Option<bool>,
     vnc: Option<bool>,
     samba: Option<bool>,
+    flatpak: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlPrinting {
+    brands: Option<Vec<String>>,
+    discovery: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct TomlFlatpak {
+    apps: Option<Vec<String>>,
 }
 
 impl Config {
@@ -381,8 +1337,9 @@ impl Config {
                 cfg.locale.timezone = v;
             }
             if let Some(v) = l.keyboard {
-                cfg.locale.keyboards = v;
+                cfg.locale.keyboards = v.iter().map(|k| Keyboard::parse(k)).collect();
             }
+            cfg.locale.user_language = l.user_language.filter(|v| !v.is_empty());
         }
 
         // [input_method] section
@@ -407,16 +1364,352 @@ impl Config {
             if let Some(v) = d.swap {
                 cfg.disk.swap = SwapMode::from_str(&v);
             }
+            if let Some(v) = d.filesystem {
+                cfg.disk.filesystem = Filesystem::from_str(&v);
+            }
+            if let Some(v) = d.layout {
+                cfg.disk.layout = v;
+            }
+            if let Some(v) = d.snapshots {
+                cfg.disk.snapshots = v;
+            }
+            if let Some(v) = d.snapshot_tool {
+                cfg.disk.snapshot_tool = v;
+            }
+            if let Some(v) = d.home_split {
+                cfg.disk.home_split = v;
+            }
+            if let Some(v) = d.fstab {
+                cfg.disk.fstab = v;
+            }
+            if let Some(v) = d.esp_disk {
+                cfg.disk.esp_disk = v;
+            }
+            if let Some(v) = d.reuse_esp {
+                cfg.disk.reuse_esp = v;
+            }
+            if let Some(v) = d.bios_gpt {
+                cfg.disk.bios_gpt = v;
+            }
+            if let Some(v) = d.esp_size_mb {
+                cfg.disk.esp_size_mb = v;
+            }
+            if let Some(v) = d.secure_erase {
+                cfg.disk.secure_erase = v;
+            }
+            if let Some(v) = d.read_scan {
+                cfg.disk.read_scan = v;
+            }
+            if let Some(v) = d.min_write_mbps {
+                cfg.disk.min_write_mbps = v;
+            }
+            if let Some(e) = d.encryption {
+                if let Some(v) = e.detached_header {
+                    cfg.disk.encryption.detached_header = v;
+                }
+                if let Some(v) = e.encrypt_boot {
+                    cfg.disk.encryption.encrypt_boot = v;
+                }
+                if let Some(v) = e.ssh_unlock_key {
+                    cfg.disk.encryption.ssh_unlock_key = v;
+                }
+                if let Some(v) = e.ssh_unlock_server {
+                    cfg.disk.encryption.ssh_unlock_server = v;
+                }
+                if let Some(v) = e.keyfile {
+                    cfg.disk.encryption.keyfile = v;
+                }
+                if let Some(v) = e.tpm2 {
+                    cfg.disk.encryption.tpm2 = v;
+                }
+                if let Some(v) = e.cipher {
+                    cfg.disk.encryption.cipher = v;
+                }
+                if let Some(v) = e.key_size {
+                    cfg.disk.encryption.key_size = v;
+                }
+                if let Some(v) = e.pbkdf {
+                    cfg.disk.encryption.pbkdf = v;
+                }
+                if let Some(v) = e.iter_time {
+                    cfg.disk.encryption.iter_time = v;
+                }
+                if let Some(v) = e.pbkdf_memory {
+                    cfg.disk.encryption.pbkdf_memory = v;
+                }
+                if let Some(v) = e.pbkdf_iterations {
+                    cfg.disk.encryption.pbkdf_iterations = v;
+                }
+                if let Some(v) = e.luks_version {
+                    cfg.disk.encryption.luks_version = v;
+                }
+            }
+            if let Some(l) = d.lvm {
+                if let Some(v) = l.enabled {
+                    cfg.disk.lvm.enabled = v;
+                }
+                if let Some(v) = l.root_size {
+                    cfg.disk.lvm.root_size = v;
+                }
+                if let Some(v) = l.home {
+                    cfg.disk.lvm.home = v;
+                }
+            }
+            if let Some(l) = d.labels {
+                if let Some(v) = l.root {
+                    cfg.disk.labels.root = v;
+                }
+                if let Some(v) = l.esp {
+                    cfg.disk.labels.esp = v;
+                }
+                if let Some(v) = l.home {
+                    cfg.disk.labels.home = v;
+                }
+            }
+            if let Some(a) = d.alongside {
+                if let Some(v) = a.enabled {
+                    cfg.disk.alongside.enabled = v;
+                }
+                if let Some(v) = a.partition {
+                    cfg.disk.alongside.partition = v;
+                }
+                if let Some(v) = a.size {
+                    cfg.disk.alongside.size = v;
+                }
+            }
+            if let Some(k) = d.keep_home {
+                if let Some(v) = k.partition {
+                    cfg.disk.keep_home.partition = v;
+                }
+                if let Some(v) = k.root {
+                    cfg.disk.keep_home.root = v;
+                }
+            }
+            if let Some(parts) = d.partitions {
+                cfg.disk.partitions = parts
+                    .into_iter()
+                    .map(|x| {
+                        let mut mountpoint = x.mountpoint.unwrap_or_default();
+                        let filesystem = x.filesystem.unwrap_or_else(|| {
+                            match mountpoint.as_str() {
+                                "/boot/efi" => "vfat",
+                                "/" => "",
+                                "swap" => "swap",
+                                _ => "ext4",
+                            }
+                            .to_string()
+                        });
+                        if filesystem == "swap" {
+                            mountpoint.clear();
+                        }
+                        PartitionSpec {
+                            mountpoint,
+                            size: x.size.unwrap_or_else(|| "rest".to_string()),
+                            filesystem,
+                            encrypt: x.encrypt.unwrap_or(false),
+                            format: x.format.unwrap_or(true),
+                            device: x.device.unwrap_or_default(),
+                        }
+                    })
+                    .collect();
+            }
+            if let Some(extra) = d.extra {
+                cfg.disk.extra = extra
+                    .into_iter()
+                    .map(|x| {
+                        let games = x.games.unwrap_or(false);
+                        let default_mountpoint = if games { "/mnt/games" } else { "" };
+                        ExtraDisk {
+                            device: x.device.unwrap_or_default(),
+                            filesystem: x.filesystem.unwrap_or_else(|| "ext4".to_string()),
+                            mountpoint: x
+                                .mountpoint
+                                .unwrap_or_else(|| default_mountpoint.to_string()),
+                            // nofail: a missing data disk must not block boot
+                            options: x.options.unwrap_or_else(|| "defaults,nofail".to_string()),
+                            format: x.format.unwrap_or(false),
+                            games,
+                        }
+                    })
+                    .collect();
+            }
+        }
+
+        // [power] section
+        if let Some(p) = toml_root.power {
+            if let Some(v) = p.suspend_then_hibernate {
+                cfg.power.suspend_then_hibernate = v;
+            }
+            if let Some(v) = p.hibernate_delay {
+                cfg.power.hibernate_delay = v;
+            }
+        }
+
+        // [system] section
+        if let Some(sys) = toml_root.system {
+            if let Some(v) = sys.numlock {
+                cfg.system.numlock = v;
+            }
+            if let Some(v) = sys.tty_autologin {
+                cfg.system.tty_autologin = v;
+            }
+            if let Some(v) = sys.default_target {
+                cfg.system.default_target = v;
+            }
+            if let Some(v) = sys.session {
+                cfg.system.session = v;
+            }
+        }
+
+        // [kiosk] section
+        if let Some(k) = toml_root.kiosk {
+            if let Some(v) = k.enabled {
+                cfg.kiosk.enabled = v;
+            }
+            if let Some(v) = k.user {
+                cfg.kiosk.user = v;
+            }
+            if let Some(v) = k.app {
+                cfg.kiosk.app = v;
+            }
+            if let Some(v) = k.disable_usb_storage {
+                cfg.kiosk.disable_usb_storage = v;
+            }
+        }
+
+        // [hooks] section
+        if let Some(h) = toml_root.hooks {
+            let convert = |list: Option<Vec<TomlHook>>| -> Vec<Hook> {
+                list.unwrap_or_default()
+                    .into_iter()
+                    .map(|x| Hook {
+                        command: x.command.unwrap_or_default(),
+                        chroot: x.chroot.unwrap_or(false),
+                    })
+                    .collect()
+            };
+            cfg.hooks.pre_partition = convert(h.pre_partition);
+            cfg.hooks.pre_pacstrap = convert(h.pre_pacstrap);
+            cfg.hooks.post_pacstrap = convert(h.post_pacstrap);
+            cfg.hooks.post_configure = convert(h.post_configure);
+            cfg.hooks.post_packages = convert(h.post_packages);
+            cfg.hooks.post_bootloader = convert(h.post_bootloader);
+        }
+
+        // [hardware] section
+        if let Some(h) = toml_root.hardware {
+            if let Some(v) = h.extra_drivers {
+                cfg.hardware.extra_drivers = v;
+            }
+            if let Some(v) = h.skip_drivers {
+                cfg.hardware.skip_drivers = v;
+            }
+        }
+
+        // [services] section
+        if let Some(s) = toml_root.services {
+            if let Some(v) = s.disk_monitoring {
+                cfg.services.disk_monitoring = v;
+            }
+            if let Some(v) = s.disk_monitoring_email {
+                cfg.services.disk_monitoring_email = v;
+            }
+            if let Some(v) = s.fs_maintenance {
+                cfg.services.fs_maintenance = v;
+            }
+            if let Some(v) = s.pacman_cache_keep {
+                cfg.services.pacman_cache_keep = v;
+            }
+            if let Some(v) = s.mirror_refresh {
+                cfg.services.mirror_refresh = v;
+            }
+            if let Some(v) = s.mirror_countries {
+                cfg.services.mirror_countries = v;
+            }
+        }
+
+        // [provision] section
+        if let Some(p) = toml_root.provision {
+            if let Some(a) = p.ansible_pull {
+                if let Some(v) = a.repo {
+                    cfg.provision.ansible_repo = v;
+                }
+                if let Some(v) = a.playbook {
+                    cfg.provision.ansible_playbook = v;
+                }
+            }
+            if let Some(v) = p.salt_master {
+                cfg.provision.salt_master = v;
+            }
+        }
+
+        // [[containers]] entries
+        if let Some(containers) = toml_root.containers {
+            cfg.containers = containers
+                .into_iter()
+                .map(|c| ContainerConfig {
+                    name: c.name.unwrap_or_default(),
+                    distro: c.distro.unwrap_or_else(|| "arch".to_string()),
+                    release: c.release.unwrap_or_else(|| "stable".to_string()),
+                    autostart: c.autostart.unwrap_or(false),
+                })
+                .collect();
+        }
+
+        // [[files]] entries
+        if let Some(files) = toml_root.files {
+            cfg.files = files
+                .into_iter()
+                .map(|f| FileEntry {
+                    path: f.path.unwrap_or_default(),
+                    mode: f.mode.unwrap_or_default(),
+                    owner: f.owner.unwrap_or_default(),
+                    content: f.content,
+                    source: f.source.unwrap_or_default(),
+                })
+                .collect();
+        }
+
+        // [security] section: preset first, then individual overrides
+        if let Some(s) = toml_root.security {
+            if let Some(v) = s.preset {
+                cfg.security.preset = v;
+                cfg.security.apply_preset();
+            }
+            if let Some(v) = s.firewall {
+                cfg.security.firewall = v;
+            }
+            if let Some(v) = s.apparmor {
+                cfg.security.apparmor = v;
+            }
+            if let Some(v) = s.sysctl_hardening {
+                cfg.security.sysctl_hardening = v;
+            }
+            if let Some(v) = s.auto_updates {
+                cfg.security.auto_updates = v;
+            }
+            if let Some(v) = s.disable_root_ssh {
+                cfg.security.disable_root_ssh = v;
+            }
+            if let Some(v) = s.antivirus {
+                cfg.security.antivirus = v;
+            }
         }
 
         // [install] section
         if let Some(i) = toml_root.install {
+            if let Some(v) = i.target_disk {
+                cfg.install.target_disk = v;
+            }
             if let Some(v) = i.hostname {
                 cfg.install.hostname = v;
             }
             if let Some(v) = i.username {
                 cfg.install.username = v;
             }
+            if i.uid.is_some() {
+                cfg.install.uid = i.uid;
+            }
             if let Some(v) = i.root_password {
                 cfg.install.root_password = v;
             }
@@ -432,10 +1725,44 @@ impl Config {
             if let Some(v) = i.autologin {
                 cfg.install.autologin = v;
             }
+            if let Some(v) = i.generalize {
+                cfg.install.generalize = v;
+            }
+            if let Some(v) = i.removable_fallback {
+                cfg.install.removable_fallback = v;
+            }
+            if let Some(v) = i.prompt_timeout {
+                cfg.install.prompt_timeout = v;
+            }
+            if let Some(v) = i.unattended {
+                cfg.install.unattended = v;
+            }
+            if i.telemetry.is_some() {
+                cfg.install.telemetry = i.telemetry;
+            }
         }
 
         // [packages] sections
         if let Some(p) = toml_root.packages {
+            if let Some(v) = p.aur {
+                cfg.packages.aur = v;
+            }
+            if let Some(v) = p.aur_build_in_chroot {
+                cfg.packages.aur_build_in_chroot = v;
+            }
+            if let Some(v) = p.repos {
+                cfg.packages.repos = v;
+            }
+            match p.multilib {
+                Some(TomlBoolOrAuto::Bool(v)) => cfg.packages.multilib = Some(v),
+                Some(TomlBoolOrAuto::Auto(s)) if s == "auto" => cfg.packages.multilib = None,
+                Some(TomlBoolOrAuto::Auto(s)) => {
+                    return Err(format!(
+                        "[packages] multilib must be true, false or \"auto\", got \"{s}\""
+                    ));
+                }
+                None => {}
+            }
             if let Some(d) = p.desktop {
                 if let Some(v) = d.kde {
                     cfg.packages.kde = v;
@@ -516,6 +1843,15 @@ impl Config {
                 if let Some(v) = g.snes9x {
                     cfg.packages.snes9x = v;
                 }
+                if let Some(v) = g.lutris {
+                    cfg.packages.lutris = v;
+                }
+                if let Some(v) = g.proton_ge {
+                    cfg.packages.proton_ge = v;
+                }
+                if let Some(v) = g.controllers {
+                    cfg.packages.controllers = v;
+                }
             }
             if let Some(v) = p.virtualization {
                 if let Some(val) = v.virtualbox {
@@ -549,13 +1885,1103 @@ impl Config {
                 if let Some(v) = u.samba {
                     cfg.packages.samba = v;
                 }
+                if let Some(v) = u.flatpak {
+                    cfg.packages.flatpak = v;
+                }
+            }
+            if let Some(pr) = p.printing {
+                if let Some(v) = pr.brands {
+                    cfg.packages.printer_brands = v;
+                }
+                if let Some(v) = pr.discovery {
+                    cfg.packages.printer_discovery = v;
+                }
+            }
+            if let Some(f) = p.flatpak {
+                if let Some(v) = f.apps {
+                    cfg.packages.flatpak_apps = v;
+                }
             }
         }
 
+        // [disk] layout: applied last so the template's partition plan wins
+        // over the individual [disk]/[install] settings it covers
+        cfg.apply_layout();
+
         cfg.loaded_from_file = true;
         Ok(cfg)
     }
 
+    /// Layout templates accepted by [disk] layout
+    pub const LAYOUTS: [&'static str; 4] =
+        ["simple", "encrypted-lvm", "btrfs-snapshots", "dual-boot-alongside"];
+
+    /// Expand the [disk] layout template into filesystem, encryption, LVM
+    /// and dual-boot settings. Sizes already set are kept.
+    pub fn apply_layout(&mut self) {
+        let disk = &mut self.disk;
+        match disk.layout.as_str() {
+            "simple" => {
+                disk.filesystem = Filesystem::Ext4;
+                disk.lvm.enabled = false;
+                self.install.use_encryption = false;
+            }
+            "encrypted-lvm" => {
+                disk.filesystem = Filesystem::Ext4;
+                disk.lvm.enabled = true;
+                disk.lvm.home = true;
+                if disk.lvm.root_size.is_empty() {
+                    disk.lvm.root_size = "50G".to_string();
+                }
+                self.install.use_encryption = true;
+            }
+            "btrfs-snapshots" => {
+                disk.filesystem = Filesystem::Btrfs;
+                disk.snapshots = true;
+                disk.lvm.enabled = false;
+            }
+            "dual-boot-alongside" => {
+                disk.alongside.enabled = true;
+                if disk.alongside.size.is_empty() {
+                    disk.alongside.size = "60G".to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The wizard's answers as a config.toml for `generate-config`. The
+    /// target disk and the encryption passphrase are left out: both are
+    /// asked on the machine being installed.
+    pub fn to_toml(&self) -> String {
+        let s = |v: &str| toml::Value::from(v).to_string();
+        let list = |v: &[String]| toml::Value::from(v.to_vec()).to_string();
+        let keyboards: Vec<String> =
+            self.locale.keyboards.iter().map(|k| k.to_string()).collect();
+        let p = &self.packages;
+
+        let mut out = String::from("# Generated by blunux-installer generate-config\n");
+        out.push_str(&format!(
+            "\n[locale]\nlanguage = {}\ntimezone = {}\nkeyboard = {}\n",
+            list(&self.locale.languages),
+            s(&self.locale.timezone),
+            list(&keyboards)
+        ));
+        out.push_str(&format!(
+            "\n[input_method]\nenabled = {}\nengine = {}\n",
+            self.input_method.enabled,
+            s(&self.input_method.engine)
+        ));
+        out.push_str(&format!("\n[kernel]\ntype = {}\n", s(&self.kernel.type_)));
+
+        let i = &self.install;
+        out.push_str(&format!(
+            "\n[install]\nhostname = {}\nusername = {}\nroot_password = {}\n\
+             user_password = {}\nbootloader = {}\nencryption = {}\nautologin = {}\n",
+            s(&i.hostname),
+            s(&i.username),
+            s(&i.root_password),
+            s(&i.user_password),
+            s(&i.bootloader),
+            i.use_encryption,
+            i.autologin
+        ));
+
+        // "simple" would reset the filesystem chosen after it on load
+        let d = &self.disk;
+        out.push_str("\n[disk]\n");
+        if matches!(d.layout.as_str(), "encrypted-lvm" | "btrfs-snapshots") {
+            out.push_str(&format!("layout = {}\n", s(&d.layout)));
+        }
+        out.push_str(&format!(
+            "filesystem = {}\nswap = {}\n",
+            s(d.filesystem.fs_type()),
+            s(d.swap.as_toml())
+        ));
+        if d.filesystem == Filesystem::Btrfs {
+            out.push_str(&format!(
+                "snapshots = {}\nsnapshot_tool = {}\n",
+                d.snapshots,
+                s(&d.snapshot_tool)
+            ));
+        }
+
+        let groups: [(&str, Vec<(&str, bool)>); 8] = [
+            (
+                "browser",
+                vec![
+                    ("firefox", p.firefox),
+                    ("whale", p.whale),
+                    ("chrome", p.chrome),
+                    ("mullvad", p.mullvad),
+                ],
+            ),
+            (
+                "office",
+                vec![
+                    ("libreoffice", p.libreoffice),
+                    ("hoffice", p.hoffice),
+                    ("texlive", p.texlive),
+                ],
+            ),
+            (
+                "development",
+                vec![
+                    ("vscode", p.vscode),
+                    ("sublime", p.sublime),
+                    ("git", p.git),
+                    ("rust", p.rust),
+                    ("julia", p.julia),
+                    ("nodejs", p.nodejs),
+                    ("github_cli", p.github_cli),
+                ],
+            ),
+            (
+                "multimedia",
+                vec![
+                    ("vlc", p.vlc),
+                    ("obs", p.obs),
+                    ("freetv", p.freetv),
+                    ("ytdlp", p.ytdlp),
+                    ("freetube", p.freetube),
+                ],
+            ),
+            (
+                "gaming",
+                vec![
+                    ("steam", p.steam),
+                    ("unciv", p.unciv),
+                    ("snes9x", p.snes9x),
+                    ("lutris", p.lutris),
+                    ("proton_ge", p.proton_ge),
+                ],
+            ),
+            (
+                "virtualization",
+                vec![
+                    ("virtualbox", p.virtualbox),
+                    ("docker", p.docker),
+                ],
+            ),
+            (
+                "communication",
+                vec![
+                    ("teams", p.teams),
+                    ("whatsapp", p.whatsapp),
+                    ("onenote", p.onenote),
+                ],
+            ),
+            (
+                "utility",
+                vec![
+                    ("bluetooth", p.bluetooth),
+                    ("conky", p.conky),
+                    ("vnc", p.vnc),
+                    ("samba", p.samba),
+                    ("flatpak", p.flatpak),
+                ],
+            ),
+        ];
+        for (group, flags) in groups {
+            out.push_str(&format!("\n[packages.{group}]\n"));
+            for (name, on) in flags {
+                out.push_str(&format!("{name} = {on}\n"));
+            }
+        }
+        out
+    }
+
+    /// btrfs root with VirtualBox or Docker: their images get a nodatacow
+    /// subvolume so copy-on-write does not fragment them
+    pub fn btrfs_vm_subvolume(&self) -> bool {
+        self.disk.filesystem == Filesystem::Btrfs
+            && !self.disk.lvm.enabled
+            && (self.packages.virtualbox || self.packages.docker)
+    }
+
+    /// Effective ESP size in MB of the default layout: [disk] esp_size_mb,
+    /// or 1 GiB when NMBL copies kernel + initramfs onto the ESP or
+    /// linux-bore installs linux-cachyos next to the stock kernel
+    pub fn esp_size_mb(&self) -> u64 {
+        if self.disk.esp_size_mb > 0 {
+            self.disk.esp_size_mb
+        } else if self.install.bootloader == "nmbl" || self.kernel.type_ == "linux-bore" {
+            1024
+        } else {
+            512
+        }
+    }
+
+    /// Where the root keyfile goes: "initramfs", a key device, or "" for
+    /// none. GRUB unlocking an encrypted /boot always embeds one, so the
+    /// passphrase is not asked a second time.
+    pub fn root_keyfile(&self) -> &str {
+        let enc = &self.disk.encryption;
+        if !self.uses_luks() {
+            ""
+        } else if enc.keyfile.is_empty() && enc.encrypt_boot {
+            "initramfs"
+        } else {
+            &enc.keyfile
+        }
+    }
+
+    /// Root is opened by sd-encrypt from /etc/crypttab.initramfs rather
+    /// than by a cryptdevice= kernel parameter. LUKS + LVM always is,
+    /// unless encryptssh (busybox init) has to open it.
+    pub fn unlocks_root_from_crypttab(&self) -> bool {
+        let enc = &self.disk.encryption;
+        self.uses_luks()
+            && (!enc.detached_header.is_empty()
+                || enc.encrypt_boot
+                || enc.tpm2
+                || self.root_keyfile().starts_with("/dev/")
+                || (self.disk.lvm.enabled && enc.ssh_unlock_key.is_empty()))
+    }
+
+    /// True when the root partition is wrapped in a LUKS container.
+    /// bcachefs and ZFS use their own native encryption instead.
+    pub fn uses_luks(&self) -> bool {
+        self.install.use_encryption && !self.disk.filesystem.has_native_encryption()
+    }
+
+    /// Root or any [[disk.partitions]] entry is encrypted with the disk
+    /// encryption password
+    pub fn needs_encryption_password(&self) -> bool {
+        self.install.use_encryption || self.disk.partitions.iter().any(|p| p.encrypt)
+    }
+
+    /// Structural checks for [[disk.partitions]]; whether the layout fits
+    /// the firmware and disk is checked by `disk::check_partition_plan`
+    fn validate_partitions(&self) -> Result<(), String> {
+        let parts = &self.disk.partitions;
+        if parts.is_empty() {
+            return Ok(());
+        }
+
+        if parts.iter().filter(|p| p.mountpoint == "/").count() != 1 {
+            return Err("[[disk.partitions]] needs exactly one mountpoint = \"/\" entry".to_string());
+        }
+
+        // Either every entry names its partition or none does
+        let named = parts.iter().filter(|p| !p.device.is_empty()).count();
+        if named > 0 && named < parts.len() {
+            return Err("[[disk.partitions]] set device on every entry or on none".to_string());
+        }
+        for (i, p) in parts.iter().enumerate().filter(|(_, p)| !p.device.is_empty()) {
+            if !p.device.starts_with("/dev/") {
+                return Err(format!(
+                    "[[disk.partitions]] device must be a /dev path, got {}",
+                    p.device
+                ));
+            }
+            if parts[..i].iter().any(|q| q.device == p.device) {
+                return Err(format!("[[disk.partitions]] {} is used twice", p.device));
+            }
+        }
+
+        // Sizes only matter when the partitions are created
+        let keep_table = self.disk.keeps_partition_table();
+        let mut seen: Vec<&str> = Vec::new();
+        for (i, p) in parts.iter().enumerate() {
+            let last = i + 1 == parts.len();
+            if !keep_table && p.size_mib().is_none() && !(p.size == "rest" && last) {
+                return Err(format!(
+                    "[[disk.partitions]] invalid size \"{}\" (use e.g. \"512M\", \"50G\"; \"rest\" only on the last entry)",
+                    p.size
+                ));
+            }
+
+            if !p.format && p.encrypt {
+                return Err(format!(
+                    "[[disk.partitions]] {} cannot be encrypted without format = true",
+                    p.mountpoint
+                ));
+            }
+            if p.is_swap() {
+                continue;
+            }
+            if !p.mountpoint.starts_with('/') {
+                return Err(format!(
+                    "[[disk.partitions]] mountpoint must be absolute, got \"{}\"",
+                    p.mountpoint
+                ));
+            }
+            if seen.contains(&p.mountpoint.as_str()) {
+                return Err(format!("[[disk.partitions]] duplicate mountpoint {}", p.mountpoint));
+            }
+            seen.push(&p.mountpoint);
+
+            match p.mountpoint.as_str() {
+                "/" => {
+                    if !p.filesystem.is_empty() && p.filesystem != self.disk.filesystem.fs_type() {
+                        return Err(format!(
+                            "[[disk.partitions]] \"/\" is formatted as [disk] filesystem ({}), not {}",
+                            self.disk.filesystem.fs_type(),
+                            p.filesystem
+                        ));
+                    }
+                }
+                "/boot/efi" => {
+                    if p.filesystem != "vfat" && p.filesystem != "fat32" {
+                        return Err("[[disk.partitions]] /boot/efi must be vfat".to_string());
+                    }
+                }
+                _ => {
+                    if crate::disk::mkfs_command(&p.filesystem, "").is_none() {
+                        return Err(format!(
+                            "[[disk.partitions]] unsupported filesystem for {}: {}",
+                            p.mountpoint, p.filesystem
+                        ));
+                    }
+                }
+            }
+
+            let boot_path = ["/", "/boot", "/boot/efi"].contains(&p.mountpoint.as_str());
+            if p.encrypt && boot_path {
+                return Err(format!(
+                    "[[disk.partitions]] encrypt is for data partitions; {} follows [install] encryption",
+                    p.mountpoint
+                ));
+            }
+            // An encrypted root must not leave new data partitions in the clear
+            if self.install.use_encryption && !boot_path && !p.encrypt && p.format {
+                return Err(format!(
+                    "[[disk.partitions]] {} would not be encrypted; set encrypt = true",
+                    p.mountpoint
+                ));
+            }
+            if self.disk.lvm.enabled && self.disk.lvm.home && p.mountpoint == "/home" {
+                return Err("[[disk.partitions]] /home conflicts with [disk.lvm] home = true".to_string());
+            }
+        }
+
+        if parts.iter().any(|p| p.mountpoint == "/" && !p.format) {
+            if !self.force {
+                return Err(
+                    "[[disk.partitions]] refusing to install over the existing \"/\" filesystem (format = false); pass --force to allow it"
+                        .to_string(),
+                );
+            }
+            let fs = self.disk.filesystem;
+            if self.install.use_encryption
+                || self.disk.lvm.enabled
+                || !matches!(
+                    fs,
+                    Filesystem::Ext4 | Filesystem::Xfs | Filesystem::F2fs | Filesystem::Btrfs
+                )
+            {
+                return Err(
+                    "[[disk.partitions]] an existing \"/\" can only be reused as plain ext4, xfs, f2fs or btrfs"
+                        .to_string(),
+                );
+            }
+        }
+        if self.install.use_encryption && parts.iter().any(|p| p.is_swap()) {
+            return Err(
+                "[[disk.partitions]] swap partitions are not encrypted; use [disk] swap with encryption instead"
+                    .to_string(),
+            );
+        }
+        if parts.iter().any(|p| p.is_swap() && p.encrypt) {
+            return Err("[[disk.partitions]] encrypt is not supported for swap".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check option combinations that cannot produce a bootable system
+    pub fn validate(&self) -> Result<(), String> {
+        if self.disk.filesystem.requires_efistub() && self.install.bootloader != "nmbl" {
+            return Err(format!(
+                "{fs} root requires bootloader = \"nmbl\" (GRUB cannot read {fs})",
+                fs = self.disk.filesystem.fs_type()
+            ));
+        }
+        self.validate_partitions()?;
+        // UID_MIN..UID_MAX of the stock login.defs
+        if let Some(uid) = self.install.uid.filter(|uid| !(1000..60000).contains(uid)) {
+            return Err(format!("[install] uid must be between 1000 and 59999, got {uid}"));
+        }
+        if let Some(lang) = &self.locale.user_language {
+            if !lang.chars().all(|c| c.is_ascii_alphanumeric() || "_@".contains(c)) {
+                return Err(format!(
+                    "[locale] user_language \"{lang}\" must look like \"en_US\" (without .UTF-8)"
+                ));
+            }
+        }
+        for kb in &self.locale.keyboards {
+            if kb.layout.is_empty() || !kb.layout.chars().all(|c| c.is_ascii_lowercase()) {
+                return Err(format!(
+                    "[locale] keyboard \"{kb}\" must look like \"de\" or \"de-neo\""
+                ));
+            }
+        }
+        let layout = &self.disk.layout;
+        if !layout.is_empty() {
+            if !Self::LAYOUTS.contains(&layout.as_str()) {
+                return Err(format!(
+                    "[disk] unknown layout \"{layout}\" (available: {})",
+                    Self::LAYOUTS.join(", ")
+                ));
+            }
+            if !self.disk.partitions.is_empty() {
+                return Err("[disk] layout cannot be combined with [[disk.partitions]]".to_string());
+            }
+        }
+        let split = &self.disk.home_split;
+        if !split.is_empty() {
+            let valid = match split.strip_suffix('%') {
+                Some(p) => p.parse::<u8>().is_ok_and(|p| (1..=99).contains(&p)),
+                None => parse_size_mib(split).is_some(),
+            };
+            if !valid {
+                return Err(format!(
+                    "[disk] home_split must look like \"30%\" or \"80G\", got \"{split}\""
+                ));
+            }
+            if !self.disk.partitions.is_empty()
+                || self.disk.alongside.enabled
+                || !self.disk.keep_home.partition.is_empty()
+            {
+                return Err("[disk] home_split only applies to the default whole-disk layout"
+                    .to_string());
+            }
+            if self.disk.lvm.home {
+                return Err("[disk] home_split conflicts with [disk.lvm] home = true".to_string());
+            }
+        }
+        if self.disk.swap == SwapMode::Partition {
+            if !self.disk.partitions.is_empty()
+                || self.disk.alongside.enabled
+                || !self.disk.keep_home.partition.is_empty()
+            {
+                return Err("[disk] swap = \"partition\" only applies to the default whole-disk layout (use a swap entry in [[disk.partitions]])"
+                    .to_string());
+            }
+            if self.disk.lvm.enabled {
+                return Err(
+                    "[disk] swap = \"partition\" conflicts with [disk.lvm] (swap is a logical volume)"
+                        .to_string(),
+                );
+            }
+            if self.install.use_encryption {
+                return Err(
+                    "[disk] swap = \"partition\" would not be encrypted; use a swap file with encryption"
+                        .to_string(),
+                );
+            }
+        }
+        match self.disk.fstab.as_str() {
+            "uuid" => {}
+            "label" => {
+                // bcachefs is formatted without a label; kept partitions keep theirs
+                if self.disk.filesystem == Filesystem::Bcachefs {
+                    return Err("[disk] fstab = \"label\" is not supported with bcachefs".to_string());
+                }
+                if self.disk.alongside.enabled
+                    || !self.disk.keep_home.partition.is_empty()
+                    || self.disk.keeps_partition_table()
+                {
+                    return Err(
+                        "[disk] fstab = \"label\" needs every partition formatted by the installer"
+                            .to_string(),
+                    );
+                }
+            }
+            other => {
+                return Err(format!("[disk] fstab must be \"uuid\" or \"label\", got \"{other}\""));
+            }
+        }
+        let labels = &self.disk.labels;
+        let named = [("root", &labels.root), ("esp", &labels.esp), ("home", &labels.home)];
+        for (name, label) in named {
+            if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(format!(
+                    "[disk.labels] {name} \"{label}\" may only use letters, digits, '-' and '_'"
+                ));
+            }
+        }
+        // FAT labels are at most 11 characters; the others are cut to fit
+        if labels.esp.len() > 11 {
+            return Err(format!(
+                "[disk.labels] esp \"{}\" is longer than 11 characters",
+                labels.esp
+            ));
+        }
+        if !labels.root.is_empty() && labels.root.eq_ignore_ascii_case(&labels.home) {
+            return Err("[disk.labels] root and home need different labels".to_string());
+        }
+        let esp_disk = &self.disk.esp_disk;
+        if !esp_disk.is_empty() {
+            if !self.disk.partitions.is_empty()
+                || self.disk.alongside.enabled
+                || !self.disk.keep_home.partition.is_empty()
+            {
+                return Err("[disk] esp_disk only applies to the default whole-disk layout"
+                    .to_string());
+            }
+            if *esp_disk == self.install.target_disk {
+                return Err("[disk] esp_disk must be a different disk than the target".to_string());
+            }
+            if let Some(extra) = self.disk.extra.iter().find(|x| x.device.starts_with(esp_disk)) {
+                return Err(format!(
+                    "[[disk.extra]] device {} is on esp_disk {esp_disk}, which is wiped",
+                    extra.device
+                ));
+            }
+        }
+        let reuse_esp = &self.disk.reuse_esp;
+        if !reuse_esp.is_empty() {
+            if !self.disk.partitions.is_empty()
+                || self.disk.alongside.enabled
+                || !self.disk.keep_home.partition.is_empty()
+            {
+                return Err("[disk] reuse_esp only applies to the default whole-disk layout \
+                            (the other layouts keep the ESP of the target disk)"
+                    .to_string());
+            }
+            if !esp_disk.is_empty() {
+                return Err("[disk] esp_disk and reuse_esp cannot be combined".to_string());
+            }
+            if reuse_esp != "auto" && !reuse_esp.starts_with("/dev/") {
+                return Err(format!(
+                    "[disk] reuse_esp must be \"auto\" or a partition like /dev/nvme0n1p1, \
+                     got \"{reuse_esp}\""
+                ));
+            }
+            let target = &self.install.target_disk;
+            if !target.is_empty() && reuse_esp.starts_with(target.as_str()) {
+                return Err(format!(
+                    "[disk] reuse_esp {reuse_esp} is on the target disk, which is wiped"
+                ));
+            }
+        }
+        let esp_size = self.disk.esp_size_mb;
+        if esp_size != 0 && !(260..=4096).contains(&esp_size) {
+            // 260 MB: smallest FAT32 ESP on 4K-sector disks
+            return Err(format!("[disk] esp_size_mb must be between 260 and 4096, got {esp_size}"));
+        }
+        match self.disk.secure_erase.as_str() {
+            "none" => {}
+            "auto" | "discard" | "nvme-format" | "zero" => {
+                if self.disk.alongside.enabled
+                    || !self.disk.keep_home.partition.is_empty()
+                    || self.disk.keeps_partition_table()
+                {
+                    return Err(
+                        "[disk] secure_erase wipes the whole disk; it cannot keep partitions"
+                            .to_string(),
+                    );
+                }
+            }
+            other => {
+                return Err(format!(
+                    "[disk] secure_erase must be \"none\", \"auto\", \"discard\", \"nvme-format\" or \"zero\", got \"{other}\""
+                ));
+            }
+        }
+        if self.disk.bios_gpt
+            && (self.disk.alongside.enabled
+                || !self.disk.keep_home.partition.is_empty()
+                || self.disk.keeps_partition_table())
+        {
+            return Err("[disk] bios_gpt needs a new partition table (not alongside, keep_home \
+                        or kept partitions)"
+                .to_string());
+        }
+        if self.disk.snapshots && self.disk.filesystem != Filesystem::Btrfs {
+            return Err("[disk] snapshots requires filesystem = \"btrfs\"".to_string());
+        }
+        for name in &self.packages.repos {
+            if repos::find(name).is_none() {
+                let known: Vec<&str> = repos::KNOWN.iter().map(|r| r.name).collect();
+                return Err(format!(
+                    "[packages] repos: unknown repository \"{name}\" (known: {})",
+                    known.join(", ")
+                ));
+            }
+        }
+        if self.packages.multilib == Some(false)
+            && (self.packages.steam || self.packages.lutris)
+        {
+            return Err("Steam and Lutris need 32-bit libraries ([packages] multilib = false)"
+                .to_string());
+        }
+        if !["snapper", "timeshift"].contains(&self.disk.snapshot_tool.as_str()) {
+            return Err(format!(
+                "[disk] snapshot_tool must be \"snapper\" or \"timeshift\", got \"{}\"",
+                self.disk.snapshot_tool
+            ));
+        }
+        let alongside = &self.disk.alongside;
+        if alongside.enabled {
+            if parse_size_mib(&alongside.size).is_none() {
+                return Err(format!(
+                    "[disk.alongside] size must look like \"60G\", got \"{}\"",
+                    alongside.size
+                ));
+            }
+            if !self.disk.partitions.is_empty() {
+                return Err(
+                    "[disk.alongside] cannot be combined with [[disk.partitions]]".to_string(),
+                );
+            }
+            if !alongside.partition.is_empty()
+                && !self.install.target_disk.is_empty()
+                && crate::disk::parent_disk(&alongside.partition) != self.install.target_disk
+            {
+                return Err(format!(
+                    "[disk.alongside] partition {} is not on the target disk",
+                    alongside.partition
+                ));
+            }
+        }
+        let keep_home = &self.disk.keep_home;
+        if !keep_home.partition.is_empty() {
+            if alongside.enabled || !self.disk.partitions.is_empty() {
+                return Err(
+                    "[disk.keep_home] cannot be combined with [disk.alongside] or [[disk.partitions]]"
+                        .to_string(),
+                );
+            }
+            if self.disk.lvm.home {
+                return Err("[disk.keep_home] conflicts with [disk.lvm] home = true".to_string());
+            }
+            if !keep_home.root.is_empty() && keep_home.root == keep_home.partition {
+                return Err("[disk.keep_home] root and partition must differ".to_string());
+            }
+        }
+        let lvm = &self.disk.lvm;
+        if lvm.enabled {
+            if self.disk.filesystem != Filesystem::Ext4 {
+                return Err(format!(
+                    "[disk.lvm] requires filesystem = \"ext4\" ({} manages its own volumes)",
+                    self.disk.filesystem.fs_type()
+                ));
+            }
+            if lvm.home && lvm.root_size.is_empty() {
+                return Err("[disk.lvm] home = true needs a root_size (e.g. \"50G\")".to_string());
+            }
+            let valid_size = lvm
+                .root_size
+                .strip_suffix(['M', 'G', 'T'])
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if !lvm.root_size.is_empty() && !valid_size {
+                return Err(format!(
+                    "[disk.lvm] root_size must look like \"50G\", got \"{}\"",
+                    lvm.root_size
+                ));
+            }
+        }
+        if !self.disk.encryption.detached_header.is_empty() {
+            if !self.uses_luks() {
+                return Err(
+                    "[disk.encryption] detached_header requires LUKS encryption (encryption = true on ext4)"
+                        .to_string(),
+                );
+            }
+            if self.install.bootloader != "nmbl" {
+                return Err(
+                    "detached LUKS header requires bootloader = \"nmbl\" (GRUB cannot open it)"
+                        .to_string(),
+                );
+            }
+        }
+        if self.disk.encryption.encrypt_boot {
+            if !self.uses_luks() {
+                return Err(
+                    "[disk.encryption] encrypt_boot requires LUKS encryption (encryption = true on ext4)"
+                        .to_string(),
+                );
+            }
+            if self.install.bootloader == "nmbl" {
+                return Err(
+                    "encrypt_boot requires GRUB (EFISTUB boots from the unencrypted ESP)".to_string(),
+                );
+            }
+            if !self.disk.encryption.detached_header.is_empty() {
+                return Err("encrypt_boot cannot be combined with detached_header".to_string());
+            }
+        }
+        let keyfile = &self.disk.encryption.keyfile;
+        if !keyfile.is_empty() {
+            if !self.uses_luks() {
+                return Err(
+                    "[disk.encryption] keyfile requires LUKS encryption (encryption = true on ext4)"
+                        .to_string(),
+                );
+            }
+            if keyfile == "initramfs" {
+                // Anyone could read it from an unencrypted /boot
+                if !self.disk.encryption.encrypt_boot {
+                    return Err(
+                        "[disk.encryption] keyfile = \"initramfs\" requires encrypt_boot = true"
+                            .to_string(),
+                    );
+                }
+            } else if !keyfile.starts_with("/dev/") {
+                return Err(format!(
+                    "[disk.encryption] keyfile must be \"initramfs\" or a device, got \"{keyfile}\""
+                ));
+            } else if self.disk.encryption.encrypt_boot
+                || !self.disk.encryption.detached_header.is_empty()
+                || !self.disk.encryption.ssh_unlock_key.is_empty()
+            {
+                return Err("A keyfile device cannot be combined with encrypt_boot, \
+                            detached_header or ssh_unlock_key"
+                    .to_string());
+            }
+        }
+        if self.disk.encryption.tpm2 {
+            if !self.uses_luks() {
+                return Err(
+                    "[disk.encryption] tpm2 requires LUKS encryption (encryption = true on ext4)"
+                        .to_string(),
+                );
+            }
+            let enc = &self.disk.encryption;
+            if enc.encrypt_boot
+                || !enc.detached_header.is_empty()
+                || !enc.ssh_unlock_key.is_empty()
+                || !enc.keyfile.is_empty()
+            {
+                return Err("tpm2 cannot be combined with encrypt_boot, detached_header, \
+                            ssh_unlock_key or keyfile"
+                    .to_string());
+            }
+        }
+        let enc = &self.disk.encryption;
+        if !enc.cipher.is_empty()
+            && !enc.cipher.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ':')
+        {
+            return Err(format!(
+                "[disk.encryption] cipher \"{}\" is not a cryptsetup cipher spec \
+                 (e.g. aes-xts-plain64)",
+                enc.cipher
+            ));
+        }
+        if ![0, 128, 256, 512].contains(&enc.key_size) {
+            return Err(format!(
+                "[disk.encryption] key_size must be 128, 256 or 512, got {}",
+                enc.key_size
+            ));
+        }
+        if !["", "argon2id", "argon2i", "pbkdf2"].contains(&enc.pbkdf.as_str()) {
+            return Err(format!(
+                "[disk.encryption] pbkdf must be argon2id, argon2i or pbkdf2, got \"{}\"",
+                enc.pbkdf
+            ));
+        }
+        if enc.encrypt_boot && !enc.pbkdf.is_empty() && enc.pbkdf != "pbkdf2" {
+            return Err("encrypt_boot requires pbkdf = \"pbkdf2\" (GRUB cannot read argon2 keyslots)"
+                .to_string());
+        }
+        if enc.pbkdf_memory != 0 {
+            if enc.pbkdf == "pbkdf2" || enc.encrypt_boot {
+                return Err("[disk.encryption] pbkdf_memory only applies to argon2".to_string());
+            }
+            // cryptsetup's own limits: 32 KiB .. 4 GiB
+            if !(32..=4_194_304).contains(&enc.pbkdf_memory) {
+                return Err(format!(
+                    "[disk.encryption] pbkdf_memory must be 32-4194304 KiB, got {}",
+                    enc.pbkdf_memory
+                ));
+            }
+        }
+        if enc.iter_time != 0 && enc.pbkdf_iterations != 0 {
+            return Err("[disk.encryption] set either iter_time or pbkdf_iterations, not both"
+                .to_string());
+        }
+        match enc.luks_version {
+            2 => {}
+            1 => {
+                if !enc.pbkdf.is_empty() && enc.pbkdf != "pbkdf2" {
+                    return Err("luks_version = 1 only supports pbkdf = \"pbkdf2\"".to_string());
+                }
+                if enc.pbkdf_memory != 0 {
+                    return Err("[disk.encryption] pbkdf_memory only applies to argon2 \
+                                (LUKS1 uses pbkdf2)"
+                        .to_string());
+                }
+                if enc.tpm2 {
+                    return Err("tpm2 needs LUKS2 (systemd-cryptenroll stores the TPM key in \
+                                a LUKS2 token)"
+                        .to_string());
+                }
+            }
+            v => {
+                return Err(format!("[disk.encryption] luks_version must be 1 or 2, got {v}"));
+            }
+        }
+        for extra in &self.disk.extra {
+            if extra.device.is_empty() || !extra.mountpoint.starts_with('/') {
+                return Err(
+                    "[[disk.extra]] entries need a device and an absolute mountpoint".to_string(),
+                );
+            }
+            if ["/", "/boot", "/boot/efi"].contains(&extra.mountpoint.as_str()) {
+                return Err(format!(
+                    "[[disk.extra]] cannot be mounted at {}",
+                    extra.mountpoint
+                ));
+            }
+            // by-id links name the same disk as the resolved target
+            let device = std::fs::canonicalize(&extra.device)
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| extra.device.clone());
+            if !self.install.target_disk.is_empty()
+                && (device == self.install.target_disk
+                    || crate::disk::parent_disk(&device) == self.install.target_disk)
+            {
+                return Err(format!(
+                    "[[disk.extra]] device {} is on the target disk",
+                    extra.device
+                ));
+            }
+            if extra.games && !["ext4", "ntfs"].contains(&extra.filesystem.as_str()) {
+                return Err(format!(
+                    "[[disk.extra]] games drive must be ext4 or ntfs, got \"{}\"",
+                    extra.filesystem
+                ));
+            }
+        }
+        if self.disk.extra.iter().filter(|x| x.games).count() > 1 {
+            return Err("Only one [[disk.extra]] entry can be the games drive".to_string());
+        }
+
+        if !["graphical", "multi-user"].contains(&self.system.default_target.as_str()) {
+            return Err(format!(
+                "[system] default_target must be \"graphical\" or \"multi-user\", got \"{}\"",
+                self.system.default_target
+            ));
+        }
+        if !["wayland", "x11"].contains(&self.system.session.as_str()) {
+            return Err(format!(
+                "[system] session must be \"wayland\" or \"x11\", got \"{}\"",
+                self.system.session
+            ));
+        }
+        let tty = &self.system.tty_autologin;
+        let valid_tty = tty
+            .strip_prefix("tty")
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        if !tty.is_empty() && !valid_tty {
+            return Err(format!("[system] tty_autologin must look like \"tty2\", got \"{tty}\""));
+        }
+
+        if !["", "none", "kernel", "xpadneo"].contains(&self.packages.controllers.as_str()) {
+            return Err(format!(
+                "[packages.gaming] controllers must be \"none\", \"kernel\" or \"xpadneo\", got \"{}\"",
+                self.packages.controllers
+            ));
+        }
+
+        for brand in &self.packages.printer_brands {
+            if printer_driver_packages(brand).is_none() {
+                return Err(format!(
+                    "[packages.printing] unknown brand \"{brand}\" \
+                     (hp, brother, epson, samsung, canon, generic)"
+                ));
+            }
+        }
+
+        if !self.packages.flatpak_apps.is_empty() && !self.packages.flatpak {
+            return Err(
+                "[packages.flatpak] apps requires [packages.utility] flatpak = true".to_string(),
+            );
+        }
+
+        for point in HooksConfig::POINTS {
+            for hook in self.hooks.get(point) {
+                if hook.command.trim().is_empty() {
+                    return Err(format!("[[hooks.{point}]] entry has an empty command"));
+                }
+                // Nothing to chroot into until pacstrap has run
+                if hook.chroot && (point == "pre_partition" || point == "pre_pacstrap") {
+                    return Err(format!("[[hooks.{point}]] cannot use chroot = true"));
+                }
+            }
+        }
+
+        let preset = &self.security.preset;
+        if !preset.is_empty() && !SecurityConfig::PRESETS.contains(&preset.as_str()) {
+            return Err(format!(
+                "[security] unknown preset \"{preset}\" (available: {})",
+                SecurityConfig::PRESETS.join(", ")
+            ));
+        }
+
+        // Joined into one quoted reflector --country argument
+        if let Some(c) = self
+            .services
+            .mirror_countries
+            .iter()
+            .find(|c| c.trim().is_empty() || c.contains([',', '\'', '"']))
+        {
+            return Err(format!("[services] mirror_countries: invalid country \"{c}\""));
+        }
+
+        if !self.provision.ansible_repo.is_empty() && self.provision.ansible_playbook.is_empty() {
+            return Err("[provision] ansible_pull needs a playbook".to_string());
+        }
+
+        for (i, c) in self.containers.iter().enumerate() {
+            let valid_name = !c.name.is_empty()
+                && c.name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+                && !c.name.starts_with('-');
+            if !valid_name {
+                return Err(format!(
+                    "[[containers]] name \"{}\" must be letters, digits and '-'",
+                    c.name
+                ));
+            }
+            if !ContainerConfig::DISTROS.contains(&c.distro.as_str()) {
+                return Err(format!(
+                    "[[containers]] {}: unknown distro \"{}\" (available: {})",
+                    c.name,
+                    c.distro,
+                    ContainerConfig::DISTROS.join(", ")
+                ));
+            }
+            if self.containers[..i].iter().any(|o| o.name == c.name) {
+                return Err(format!("[[containers]] name \"{}\" is used twice", c.name));
+            }
+        }
+
+        for (i, f) in self.files.iter().enumerate() {
+            let absolute = f.path.starts_with('/') || f.in_home();
+            if !absolute || f.path.split('/').any(|c| c == "..") || f.path.contains('\'') {
+                return Err(format!(
+                    "[[files]] path \"{}\" must be absolute or start with ~/",
+                    f.path
+                ));
+            }
+            if f.content.is_some() && !f.source.is_empty() {
+                return Err(format!("[[files]] {}: set content or source, not both", f.path));
+            }
+            if !f.source.is_empty() && (!f.source.starts_with('/') || f.source.contains('\'')) {
+                return Err(format!("[[files]] {}: source must be an absolute path", f.path));
+            }
+            let octal = (3..=4).contains(&f.mode.len())
+                && f.mode.chars().all(|c| ('0'..='7').contains(&c));
+            if !f.mode.is_empty() && !octal {
+                return Err(format!(
+                    "[[files]] {}: mode \"{}\" must be octal, e.g. \"0644\"",
+                    f.path, f.mode
+                ));
+            }
+            let valid_owner = f.owner.split(':').count() <= 2
+                && f.owner.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || "_-:".contains(c)
+                });
+            if !valid_owner {
+                return Err(format!(
+                    "[[files]] {}: owner \"{}\" must be \"user\" or \"user:group\"",
+                    f.path, f.owner
+                ));
+            }
+            if self.files[..i].iter().any(|o| o.path == f.path) {
+                return Err(format!("[[files]] path \"{}\" is listed twice", f.path));
+            }
+        }
+
+        if self.kiosk.enabled {
+            if self.kiosk.app.is_empty() {
+                return Err("[kiosk] app must be set when kiosk mode is enabled".to_string());
+            }
+            if self.kiosk.user.is_empty() || self.kiosk.user == self.install.username {
+                return Err(
+                    "[kiosk] user must be a separate account from [install] username".to_string(),
+                );
+            }
+            if self.system.tty_autologin == "tty1" {
+                return Err("[system] tty_autologin cannot use tty1 in kiosk mode".to_string());
+            }
+        }
+
+        if self.power.suspend_then_hibernate {
+            if self.disk.swap != SwapMode::Suspend && self.disk.swap != SwapMode::Partition {
+                return Err(
+                    "[power] suspend_then_hibernate requires [disk] swap = \"suspend\" or \"partition\" (RAM-sized swap)"
+                        .to_string(),
+                );
+            }
+            if self.disk.swap == SwapMode::Suspend && !self.disk.filesystem.supports_swapfile() {
+                return Err(format!(
+                    "[power] suspend_then_hibernate needs a swapfile, which {} does not support",
+                    self.disk.filesystem.fs_type()
+                ));
+            }
+        }
+
+        let enc = &self.disk.encryption;
+        if !enc.ssh_unlock_key.is_empty() {
+            if !self.uses_luks() {
+                return Err(
+                    "[disk.encryption] ssh_unlock_key requires LUKS encryption (encryption = true on ext4)"
+                        .to_string(),
+                );
+            }
+            if self.install.bootloader != "nmbl" {
+                return Err(
+                    "SSH unlock requires bootloader = \"nmbl\" (GRUB would ask for the passphrase on the console)"
+                        .to_string(),
+                );
+            }
+            if !enc.detached_header.is_empty() || enc.encrypt_boot {
+                return Err(
+                    "SSH unlock cannot be combined with detached_header or encrypt_boot".to_string(),
+                );
+            }
+            match enc.ssh_unlock_server.as_str() {
+                "dropbear" => {}
+                "tinyssh" => {
+                    if !enc.ssh_unlock_key.starts_with("ssh-ed25519 ") {
+                        return Err("tinyssh only accepts ssh-ed25519 keys".to_string());
+                    }
+                }
+                other => {
+                    return Err(format!(
+                        "Unknown ssh_unlock_server \"{other}\" (use \"dropbear\" or \"tinyssh\")"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Effective [packages.gaming] controllers mode
+    pub fn controller_support(&self) -> &str {
+        match self.packages.controllers.as_str() {
+            "" if self.packages.steam || self.packages.snes9x || self.packages.lutris => "kernel",
+            "" => "none",
+            mode => mode,
+        }
+    }
+
+    /// AUR packages to install: [packages] aur plus those implied by other
+    /// options (kime engine, linux-bore kernel)
+    pub fn aur_packages(&self) -> Vec<String> {
+        let mut aur = self.packages.aur.clone();
+        let mut implied = Vec::new();
+        if self.controller_support() == "xpadneo" {
+            // Bluetooth (xpadneo) and USB wireless dongle (xone) Xbox pads
+            implied.extend_from_slice(&["xpadneo-dkms", "xone-dkms"]);
+        }
+        if self.input_method.enabled && self.input_method.engine == "kime" {
+            implied.push("kime-git");
+        }
+        if self.kernel.type_ == "linux-bore" {
+            implied.extend_from_slice(&["linux-cachyos", "linux-cachyos-headers"]);
+        }
+        for pkg in implied {
+            if !aur.iter().any(|p| p == pkg) {
+                aur.push(pkg.to_string());
+            }
+        }
+        aur
+    }
+
     /// Get list of script-installable packages based on config
     pub fn get_script_package_list(&self) -> Vec<String> {
         let mut scripts = Vec::new();
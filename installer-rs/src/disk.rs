@@ -1,20 +1,112 @@
+use crate::config::{
+    self, AlongsideConfig, Config, DiskConfig, EncryptionConfig, ExtraDisk, Filesystem,
+    KeepHomeConfig, PartitionSpec, SwapMode,
+};
+use crate::repos;
 use crate::tui;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::Path;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// SMART data, read scans and write speed of the target
+pub mod health;
+/// Typed lsblk/blkid queries
+pub mod probe;
+/// Typed partition table edits
+pub mod table;
+
+use table::{Flag, Label, Pos};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PartitionScheme {
     GptUefi,
+    /// GPT on a BIOS machine; GRUB embeds its core image in a 1 MiB
+    /// bios_grub partition
+    GptBios,
     MbrBios,
 }
 
+impl PartitionScheme {
+    pub fn is_gpt(self) -> bool {
+        self != PartitionScheme::MbrBios
+    }
+}
+
+/// Partition of a custom layout other than the ESP and root
+#[derive(Debug, Clone)]
+pub struct DataPartition {
+    pub device: String,
+    /// Empty for swap
+    pub mountpoint: String,
+    pub filesystem: String,
+    /// LUKS container opened as /dev/mapper/`mapper_name()`
+    pub encrypt: bool,
+    /// Existing filesystem that is mounted but never formatted
+    pub keep: bool,
+}
+
+impl DataPartition {
+    /// dm-crypt name, e.g. "crypt-home" for /home
+    pub fn mapper_name(&self) -> String {
+        format!("crypt-{}", self.mountpoint.trim_start_matches('/').replace('/', "-"))
+    }
+
+    /// Device holding the filesystem (the opened mapping when encrypted)
+    pub fn fs_device(&self) -> String {
+        if self.encrypt {
+            format!("/dev/mapper/{}", self.mapper_name())
+        } else {
+            self.device.clone()
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PartitionLayout {
     pub efi_partition: String,
     pub root_partition: String,
     pub scheme: PartitionScheme,
+    pub data_partitions: Vec<DataPartition>,
+    /// ESP shared with another OS: mounted, never formatted
+    pub keep_efi: bool,
+    /// Existing root filesystem reused (--force): mounted, never formatted
+    pub keep_root: bool,
+    /// Target is an SSD: noatime, discards through LUKS, fstrim.timer
+    pub ssd: bool,
+    /// Target is eMMC or an SD card: less frequent journal commits
+    pub flash: bool,
 }
 
+/// Name of the pool created for root-on-ZFS installs
+pub const ZFS_POOL: &str = "zroot";
+
+/// Dataset mounted at / (also used for the kernel `zfs=` parameter)
+pub const ZFS_ROOT_DATASET: &str = "zroot/ROOT/default";
+
+/// Volume group created for [disk.lvm] installs
+pub const LVM_VG: &str = "blunux";
+
+/// Logical volume mounted at /
+pub const LVM_ROOT: &str = "/dev/blunux/root";
+
+/// Where the detached LUKS header device is mounted during installation
+pub const KEYS_MOUNT: &str = "/run/blunux-keys";
+
+/// File name of the detached LUKS header on the header device
+pub const DETACHED_HEADER_FILE: &str = "blunux-root.header";
+
+/// Root keyfile enrolled by `format_partitions`, kept in the live system's
+/// RAM until the installer copies it to the initramfs or the key device
+pub const ROOT_KEYFILE: &str = "/run/blunux-cryptroot.key";
+
+/// File name of the root keyfile on a [disk.encryption] keyfile device
+pub const KEYFILE_NAME: &str = "blunux-root.key";
+
 /// Execute a command and capture stdout
 fn exec(cmd: &str) -> String {
     Command::new("sh")
@@ -35,43 +127,180 @@ fn run_cmd(cmd: &str) -> bool {
 
 /// Get list of available disks
 pub fn get_disks() -> Vec<tui::DiskInfo> {
-    let output = exec("lsblk -d -n -o NAME,SIZE,MODEL,TYPE 2>/dev/null");
-    let mut disks = Vec::new();
-
-    for line in output.lines() {
-        if line.is_empty() {
-            continue;
-        }
+    probe::disks()
+        .into_iter()
+        .map(|d| tui::DiskInfo {
+            size: d.size_human(),
+            model: d
+                .model
+                .as_deref()
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .unwrap_or("Unknown")
+                .to_string(),
+            serial: d.serial.as_deref().unwrap_or("").trim().to_string(),
+            transport: d.tran.clone().unwrap_or_default(),
+            rotational: d.rota,
+            removable: d.rm,
+            device: d.path,
+        })
+        .collect()
+}
 
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 2 {
-            continue;
-        }
+/// One disk as `--list-disks` reports it: what the disk step and the
+/// preflight checks look at
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskReport {
+    pub path: String,
+    pub model: String,
+    pub serial: String,
+    pub wwn: String,
+    pub size_mib: u64,
+    /// "nvme", "sata", "usb", "mmc", ... (empty if unknown)
+    pub transport: String,
+    pub ssd: bool,
+    /// eMMC or SD card
+    pub flash: bool,
+    pub removable: bool,
+    /// The running live system boots from this disk
+    pub live_medium: bool,
+    /// RAID arrays and LVM volume groups active on the disk
+    pub active_stacks: Vec<String>,
+    pub partitions: Vec<PartitionReport>,
+    /// Windows and Linux installs dual-boot-alongside could shrink
+    pub existing_os: Vec<ExistingOs>,
+    pub smart_warnings: Vec<String>,
+}
 
-        let name = parts[0];
-        let size = parts[1];
+#[derive(Debug, Clone, Serialize)]
+pub struct PartitionReport {
+    pub path: String,
+    pub size_mib: u64,
+    pub fs_type: String,
+    /// GPT type GUID or MBR type code
+    pub part_type: String,
+}
 
-        // Last token is type, everything between size and type is model
-        let type_ = parts.last().unwrap_or(&"");
+/// Inspect every disk the installer could install to
+pub fn disk_reports() -> Vec<DiskReport> {
+    let live = live_medium_disks();
+    probe::disks()
+        .into_iter()
+        .map(|d| {
+            let partitions = probe::partitions(&d.path)
+                .into_iter()
+                .map(|p| PartitionReport {
+                    size_mib: p.size_mib(),
+                    fs_type: p.fstype.unwrap_or_default(),
+                    part_type: p.parttype.unwrap_or_default(),
+                    path: p.path,
+                })
+                .collect();
+            DiskReport {
+                model: d.model.as_deref().unwrap_or_default().trim().to_string(),
+                serial: d.serial.as_deref().unwrap_or_default().trim().to_string(),
+                wwn: d.wwn.clone().unwrap_or_default(),
+                size_mib: d.size_mib(),
+                transport: d.tran.clone().unwrap_or_default(),
+                ssd: !d.rota,
+                flash: is_flash_card(&d.path),
+                removable: d.rm || d.tran.as_deref() == Some("usb"),
+                live_medium: live.contains(&d.path),
+                active_stacks: active_stacks(&d.path),
+                partitions,
+                existing_os: detect_existing_os(&d.path),
+                smart_warnings: health::smart_warnings(&d.path),
+                path: d.path,
+            }
+        })
+        .collect()
+}
 
-        if *type_ != "disk" {
-            continue;
-        }
+/// Resolve [install] target_disk to a disk node, so one config.toml fits
+/// machines whose disks enumerate differently:
+/// - a path, including /dev/disk/by-id/ links
+/// - "serial:S2RBNX0J123456" or "wwn:0x5002538e40a1b2c3"
+/// - "model:Samsung SSD 980", matched case-insensitively as a substring
+/// - "largest": the largest disk that is neither removable nor the live medium
+pub fn resolve_target_disk(spec: &str) -> Result<String, String> {
+    let disks = probe::disks();
+    let field = |v: &Option<String>| v.as_deref().unwrap_or_default().trim().to_lowercase();
+    let matches: Vec<&probe::BlockDevice> = if spec.starts_with("/dev/") {
+        let node = fs::canonicalize(spec).map_err(|e| format!("target_disk {spec}: {e}"))?;
+        let node = node.to_string_lossy();
+        disks.iter().filter(|d| d.path == node).collect()
+    } else if let Some(serial) = spec.strip_prefix("serial:") {
+        disks.iter().filter(|d| field(&d.serial) == serial.trim().to_lowercase()).collect()
+    } else if let Some(wwn) = spec.strip_prefix("wwn:") {
+        let wwn = wwn.trim().to_lowercase();
+        let wwn = wwn.strip_prefix("0x").unwrap_or(&wwn);
+        disks
+            .iter()
+            .filter(|d| !wwn.is_empty() && field(&d.wwn).trim_start_matches("0x") == wwn)
+            .collect()
+    } else if let Some(model) = spec.strip_prefix("model:") {
+        let model = model.trim().to_lowercase();
+        disks.iter().filter(|d| !model.is_empty() && field(&d.model).contains(&model)).collect()
+    } else if spec == "largest" {
+        let live = live_medium_disks();
+        disks
+            .iter()
+            .filter(|d| !d.rm && d.tran.as_deref() != Some("usb") && !live.contains(&d.path))
+            // Compressed RAM swap, as large as the RAM on live ISOs
+            .filter(|d| !d.path.starts_with("/dev/zram"))
+            .max_by_key(|d| d.size)
+            .into_iter()
+            .collect()
+    } else {
+        return Err(format!(
+            "target_disk \"{spec}\" must be a /dev path, serial:, wwn:, model: or \"largest\""
+        ));
+    };
 
-        let model = if parts.len() > 3 {
-            parts[2..parts.len() - 1].join(" ")
-        } else {
-            "Unknown".to_string()
-        };
+    match matches.as_slice() {
+        [disk] => Ok(disk.path.clone()),
+        [] => Err(format!("target_disk \"{spec}\" matches no disk")),
+        many => Err(format!(
+            "target_disk \"{spec}\" matches several disks: {}",
+            many.iter().map(|d| d.path.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
 
-        disks.push(tui::DiskInfo {
-            device: format!("/dev/{name}"),
-            size: size.to_string(),
-            model,
-        });
+/// Create the disk image `path` as a sparse file of `size_mib` MiB (0 keeps
+/// the size of an existing image) and attach it as a loop device whose
+/// partitions show up as /dev/loopNpM; returns the loop device. A new size
+/// never overwrites an existing non-empty file unless `force` is set.
+pub fn attach_image(path: &str, size_mib: u64, force: bool) -> Result<String, String> {
+    let existing = fs::metadata(path).map_or(0, |m| m.len());
+    if size_mib > 0 && existing > 0 && !force {
+        return Err(format!(
+            "{path} already exists - leave out --size to reuse it, or pass --force to overwrite it"
+        ));
+    }
+    if size_mib == 0 && existing == 0 {
+        return Err(format!("{path} is empty or missing - give its size with --size"));
+    }
+    if size_mib > 0 {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("{path}: {e}"))?;
+        file.set_len(size_mib * 1024 * 1024).map_err(|e| format!("{path}: {e}"))?;
     }
+    let device = exec(&format!("losetup --find --show --partscan {path}"));
+    let device = device.trim();
+    if !device.starts_with("/dev/loop") {
+        return Err(format!("Could not attach {path} as a loop device"));
+    }
+    Ok(device.to_string())
+}
 
-    disks
+/// Detach the loop device of a disk image once it is unmounted
+pub fn detach_image(device: &str) {
+    run_cmd(&format!("losetup -d {device} 2>/dev/null"));
 }
 
 /// Check if system booted in UEFI mode
@@ -79,219 +308,2705 @@ pub fn is_uefi() -> bool {
     Path::new("/sys/firmware/efi").exists()
 }
 
-/// Wipe and partition disk
-pub fn partition_disk(disk: &str, scheme: PartitionScheme) -> Option<PartitionLayout> {
-    let mut layout = PartitionLayout {
-        efi_partition: String::new(),
-        root_partition: String::new(),
-        scheme,
-    };
+/// Device node of partition `n` on `disk`
+fn partition_device(disk: &str, n: usize) -> String {
+    if disk.contains("nvme") || disk.contains("mmcblk") || disk.contains("loop") {
+        format!("{disk}p{n}")
+    } else {
+        format!("{disk}{n}")
+    }
+}
 
-    // First, unmount any existing partitions on this disk
-    tui::print_info(&format!(
-        "Checking for mounted partitions on {disk}..."
-    ));
+/// Disk a partition device name belongs to; the inverse of `partition_device`
+pub fn parent_disk(partition: &str) -> &str {
+    let disk = partition.trim_end_matches(|c: char| c.is_ascii_digit());
+    if partition.contains("nvme") || partition.contains("mmcblk") || partition.contains("loop") {
+        disk.strip_suffix('p').unwrap_or(disk)
+    } else {
+        disk
+    }
+}
 
-    let partitions = exec(&format!(
-        "lsblk -ln -o NAME {disk} 2>/dev/null | tail -n +2"
-    ));
-    for part_name in partitions.lines() {
-        let part_name = part_name.trim();
-        if !part_name.is_empty() {
-            let part_dev = format!("/dev/{part_name}");
-            run_cmd(&format!("umount -f {part_dev} 2>/dev/null"));
-            run_cmd(&format!("swapoff {part_dev} 2>/dev/null"));
+/// Largest disk an MBR partition table can address (2 TiB of 512-byte sectors)
+const MBR_MAX_MIB: u64 = 2 * 1024 * 1024;
+
+/// Partition table for `disk`: GPT on UEFI; on BIOS, GPT with a bios_grub
+/// partition when [disk] bios_gpt is set or the disk is too large for MBR.
+/// Installs that keep the existing table stay on MBR.
+pub fn partition_scheme(disk: &str, cfg: &DiskConfig) -> PartitionScheme {
+    if is_uefi() {
+        PartitionScheme::GptUefi
+    } else if cfg.alongside.enabled
+        || !cfg.keep_home.partition.is_empty()
+        || cfg.keeps_partition_table()
+    {
+        PartitionScheme::MbrBios
+    } else if cfg.bios_gpt || disk_size_mib(disk) > MBR_MAX_MIB {
+        PartitionScheme::GptBios
+    } else {
+        PartitionScheme::MbrBios
+    }
+}
+
+/// Create the 1 MiB bios_grub partition (number `num`) at the start of
+/// `disk`, where GRUB embeds its core image on GPT
+fn create_bios_grub_partition(disk: &str, num: usize) -> bool {
+    let created = table::mkpart(disk, "", Pos::Mib(1), Pos::Mib(2))
+        .and_then(|_| table::set_flag(disk, num, Flag::BiosGrub));
+    if let Err(e) = created {
+        tui::print_error(&format!("Failed to create BIOS boot partition: {e}"));
+        return false;
+    }
+    // Some BIOSes only boot a disk whose protective MBR is marked active
+    if let Err(e) = table::set_pmbr_boot(disk) {
+        tui::print_warning(&e);
+    }
+    true
+}
+
+/// Size of `disk` in MiB (0 if unknown)
+fn disk_size_mib(disk: &str) -> u64 {
+    exec(&format!("blockdev --getsize64 {disk} 2>/dev/null"))
+        .trim()
+        .parse::<u64>()
+        .unwrap_or(0)
+        / (1024 * 1024)
+}
+
+/// End of the root partition for [disk] home_split
+fn root_end(split: &str, start_mib: u64) -> Pos {
+    match split.strip_suffix('%') {
+        Some(p) => Pos::Percent(p.parse().unwrap_or(50)),
+        None => Pos::Mib(start_mib + config::parse_size_mib(split).unwrap_or(0)),
+    }
+}
+
+/// Root partition from `start_mib` up to `end` of the default layout, or
+/// up to [disk] home_split followed by the /home partition
+fn create_root_and_home(disk: &str, start_mib: u64, split: &str, end: Pos) -> bool {
+    let root_end = if split.is_empty() { end } else { root_end(split, start_mib) };
+    if let Err(e) = table::mkpart(disk, "ext4", Pos::Mib(start_mib), root_end) {
+        tui::print_error(&format!("Failed to create root partition: {e}"));
+        return false;
+    }
+    if !split.is_empty() {
+        if let Err(e) = table::mkpart(disk, "ext4", root_end, end) {
+            tui::print_error(&format!("Failed to create home partition: {e}"));
+            return false;
         }
     }
+    true
+}
 
-    // Close any LUKS devices
-    run_cmd("cryptsetup close cryptroot 2>/dev/null");
-    run_cmd("sleep 1");
+/// Smallest root partition left next to a swap partition
+const MIN_ROOT_MIB: u64 = 8 * 1024;
 
-    // Wipe existing partition table
-    tui::print_info(&format!("Wiping disk: {disk}"));
-    if !run_cmd(&format!("wipefs -af {disk} 2>/dev/null")) {
-        tui::print_warning("Could not wipe disk signatures");
+/// Swap size for `swap` on this machine; Err when the RAM it follows
+/// cannot be read
+fn swap_size_mib(swap: &SwapMode) -> Result<u64, String> {
+    if *swap == SwapMode::None {
+        Ok(0)
+    } else {
+        Ok(swap.size_mb(ram_mb()?))
     }
+}
 
-    run_cmd(&format!("partprobe {disk} 2>/dev/null"));
-    run_cmd("sleep 1");
+/// Size of the [disk] swap = "partition" swap partition (0 for other modes)
+fn swap_partition_mib(cfg: &DiskConfig) -> Result<u64, String> {
+    if cfg.swap == SwapMode::Partition {
+        swap_size_mib(&cfg.swap)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Space the default layout leaves for the root filesystem on `disk`;
+/// None for layouts that keep or shrink existing partitions
+pub fn default_root_space_mib(disk: &str, config: &Config) -> Option<u64> {
+    let cfg = &config.disk;
+    if !cfg.partitions.is_empty() || cfg.alongside.enabled || !cfg.keep_home.partition.is_empty() {
+        return None;
+    }
+    let disk_mib = disk_size_mib(disk);
+    if disk_mib == 0 {
+        return None;
+    }
+    let esp_end = if cfg.esp_on_target() { config.esp_size_mb() + 1 } else { 1 };
+    let rest = disk_mib.saturating_sub(esp_end + swap_partition_mib(cfg).ok()?);
+    let root = match cfg.home_split.strip_suffix('%') {
+        Some(p) => disk_mib * p.parse::<u64>().unwrap_or(100) / 100,
+        None => config::parse_size_mib(&cfg.home_split).unwrap_or(rest),
+    };
+    Some(root.min(rest))
+}
 
-    let is_nvme = disk.contains("nvme") || disk.contains("mmcblk");
+/// Check a [[disk.partitions]] layout (or the [disk] home_split of the
+/// default layout) against the firmware and disk size, without touching
+/// the disk
+pub fn check_partition_plan(
+    disk: &str,
+    config: &Config,
+    scheme: PartitionScheme,
+) -> Result<(), String> {
+    let cfg = &config.disk;
+    let specs = &cfg.partitions;
+    // End of the default layout's ESP (or bios_grub), with the 1 MiB gap
+    let esp_end = config.esp_size_mb() + 1;
+    if !cfg.esp_disk.is_empty() {
+        if scheme != PartitionScheme::GptUefi {
+            return Err("[disk] esp_disk needs UEFI boot; this machine boots in BIOS mode"
+                .to_string());
+        }
+        if disk_size_mib(&cfg.esp_disk) < esp_end + 2 {
+            return Err(format!("[disk] esp_disk {} is missing or too small", cfg.esp_disk));
+        }
+    }
+    if !cfg.reuse_esp.is_empty() {
+        if scheme != PartitionScheme::GptUefi {
+            return Err("[disk] reuse_esp needs UEFI boot; this machine boots in BIOS mode"
+                .to_string());
+        }
+        let Some(esp) = reused_esp(config) else {
+            return Err(format!(
+                "[disk] reuse_esp: no EFI system partition found ({})",
+                cfg.reuse_esp
+            ));
+        };
+        let needed = shared_esp_needed_mib(config);
+        let avail = esp_free_mib(&esp);
+        if avail < needed {
+            return Err(format!(
+                "The shared EFI partition {esp} has {avail} MiB free, {needed} MiB needed"
+            ));
+        }
+    }
+    let swap_mib = swap_partition_mib(cfg)?;
+    let disk_mib = disk_size_mib(disk);
+    if swap_mib > 0 && disk_mib > 0 && swap_mib + esp_end + MIN_ROOT_MIB > disk_mib {
+        return Err(format!(
+            "A {swap_mib} MiB swap partition leaves too little room for the system on {disk}"
+        ));
+    }
+    if let Some(root_mib) = config::parse_size_mib(&cfg.home_split) {
+        // Leave at least 1 GiB for /home
+        if disk_mib > 0 && root_mib + esp_end + 1024 + swap_mib > disk_mib {
+            return Err(format!(
+                "[disk] home_split {} leaves no room for /home on {disk}",
+                cfg.home_split
+            ));
+        }
+    }
+    if specs.is_empty() {
+        return Ok(());
+    }
 
+    let has_esp = specs.iter().any(|p| p.mountpoint == "/boot/efi");
     match scheme {
-        PartitionScheme::GptUefi => {
-            tui::print_info("Creating GPT partition table...");
+        PartitionScheme::GptUefi if !has_esp => {
+            return Err("UEFI boot needs a /boot/efi partition in [[disk.partitions]]".to_string());
+        }
+        PartitionScheme::GptBios | PartitionScheme::MbrBios if has_esp => {
+            return Err("This machine boots in BIOS mode; remove the /boot/efi partition".to_string());
+        }
+        PartitionScheme::MbrBios if specs.len() > 4 => {
+            return Err("BIOS (MBR) installs support at most 4 partitions".to_string());
+        }
+        _ => {}
+    }
+    if cfg.keeps_partition_table() {
+        return check_existing_partitions(disk, cfg);
+    }
 
-            if !run_cmd(&format!("parted -s {disk} mklabel gpt")) {
-                tui::print_error("Failed to create GPT partition table");
-                return None;
-            }
+    let disk_mib = disk_size_mib(disk);
+    // 1 MiB alignment gap at the start (2 with bios_grub), 1 MiB for the
+    // backup GPT at the end
+    let gap_mib = if scheme == PartitionScheme::GptBios { 3 } else { 2 };
+    let fixed_mib: u64 = specs.iter().filter_map(|p| p.size_mib()).sum::<u64>() + gap_mib;
+    if disk_mib > 0 && fixed_mib > disk_mib {
+        return Err(format!(
+            "[[disk.partitions]] need {fixed_mib} MiB but {disk} has only {disk_mib} MiB"
+        ));
+    }
+    Ok(())
+}
 
-            // Create EFI partition (512MB)
-            if !run_cmd(&format!(
-                "parted -s {disk} mkpart primary fat32 1MiB 513MiB"
-            )) {
-                tui::print_error("Failed to create EFI partition");
-                return None;
-            }
+/// Create the declared partitions in order and record their devices
+fn create_custom_partitions(
+    disk: &str,
+    specs: &[PartitionSpec],
+    layout: &mut PartitionLayout,
+) -> bool {
+    let label = if layout.scheme.is_gpt() { Label::Gpt } else { Label::Msdos };
+    tui::print_info("Creating custom partition layout...");
+    if let Err(e) = table::mklabel(disk, label) {
+        tui::print_error(&format!("Failed to create partition table: {e}"));
+        return false;
+    }
 
-            // Set ESP flag
-            run_cmd(&format!("parted -s {disk} set 1 esp on"));
+    // bios_grub goes into the 1-2 MiB gap after the declared partitions so
+    // their numbers match [[disk.partitions]]
+    let bios_grub = layout.scheme == PartitionScheme::GptBios;
+    let mut start_mib = if bios_grub { 2 } else { 1 };
+    for (i, spec) in specs.iter().enumerate() {
+        let num = i + 1;
+        let end = match spec.size_mib() {
+            Some(mib) => Pos::Mib(start_mib + mib),
+            None => Pos::End,
+        };
+        let fs_hint = match spec.mountpoint.as_str() {
+            _ if spec.is_swap() => "linux-swap",
+            "/boot/efi" => "fat32",
+            _ => "ext4",
+        };
+        if let Err(e) = table::mkpart(disk, fs_hint, Pos::Mib(start_mib), end) {
+            tui::print_error(&format!("Failed to create partition {num} ({}): {e}", spec.size));
+            return false;
+        }
+        start_mib += spec.size_mib().unwrap_or(0);
 
-            // Create root partition (rest of disk)
-            if !run_cmd(&format!(
-                "parted -s {disk} mkpart primary ext4 513MiB 100%"
-            )) {
-                tui::print_error("Failed to create root partition");
-                return None;
-            }
+        let flag = match spec.mountpoint.as_str() {
+            "/boot/efi" => Some(Flag::Esp),
+            "/" if layout.scheme == PartitionScheme::MbrBios => Some(Flag::Boot),
+            _ => None,
+        };
+        if let Some(Err(e)) = flag.map(|f| table::set_flag(disk, num, f)) {
+            tui::print_error(&e);
+            return false;
+        }
+        record_partition(layout, spec, partition_device(disk, num));
+    }
+    !bios_grub || create_bios_grub_partition(disk, specs.len() + 1)
+}
 
-            if is_nvme {
-                layout.efi_partition = format!("{disk}p1");
-                layout.root_partition = format!("{disk}p2");
-            } else {
-                layout.efi_partition = format!("{disk}1");
-                layout.root_partition = format!("{disk}2");
-            }
+/// Put a [[disk.partitions]] entry's device where the layout expects it
+fn record_partition(layout: &mut PartitionLayout, spec: &PartitionSpec, device: String) {
+    match spec.mountpoint.as_str() {
+        "/boot/efi" => {
+            layout.efi_partition = device;
+            layout.keep_efi = !spec.format;
         }
-        PartitionScheme::MbrBios => {
-            tui::print_info("Creating MBR partition table...");
+        "/" => {
+            layout.root_partition = device;
+            layout.keep_root = !spec.format;
+        }
+        _ => layout.data_partitions.push(DataPartition {
+            device,
+            mountpoint: spec.mountpoint.clone(),
+            filesystem: spec.filesystem.clone(),
+            encrypt: spec.encrypt,
+            keep: !spec.format,
+        }),
+    }
+}
 
-            if !run_cmd(&format!("parted -s {disk} mklabel msdos")) {
-                tui::print_error("Failed to create MBR partition table");
-                return None;
-            }
+/// Filesystem type blkid reports for a [[disk.partitions]] entry
+fn blkid_type<'a>(spec: &'a PartitionSpec, root_fs: &'a Filesystem) -> &'a str {
+    match spec.filesystem.as_str() {
+        "" => root_fs.fs_type(),
+        "fat32" => "vfat",
+        "ntfs3" => "ntfs",
+        fs => fs,
+    }
+}
 
-            if !run_cmd(&format!(
-                "parted -s {disk} mkpart primary ext4 1MiB 100%"
-            )) {
-                tui::print_error("Failed to create root partition");
-                return None;
-            }
+/// Partition used for entry `i` of [[disk.partitions]] in manual mode: its
+/// device, or partition i + 1 of `disk`
+fn spec_device(disk: &str, i: usize, spec: &PartitionSpec) -> String {
+    if spec.device.is_empty() {
+        partition_device(disk, i + 1)
+    } else {
+        spec.device.clone()
+    }
+}
 
-            run_cmd(&format!("parted -s {disk} set 1 boot on"));
+/// Manual mode: each [[disk.partitions]] entry must be an existing
+/// partition of the target disk, and kept entries must already hold their filesystem
+fn check_existing_partitions(disk: &str, cfg: &DiskConfig) -> Result<(), String> {
+    let live = live_medium_disks();
+    for (i, spec) in cfg.partitions.iter().enumerate() {
+        let device = spec_device(disk, i, spec);
+        let target = if spec.is_swap() { "swap" } else { &spec.mountpoint };
+        if probe::tree(&device).is_none_or(|d| d.kind != "part") {
+            return Err(format!(
+                "[[disk.partitions]] entry {} ({target}) needs an existing partition {device}",
+                i + 1
+            ));
+        }
+        if backing_disks(&device).iter().any(|d| live.contains(d)) {
+            return Err(format!("{device} ({target}) is on the disk the installer runs from"));
+        }
+        // lsblk follows /dev/disk/by-* links that a name comparison would miss
+        let parent = exec(&format!("lsblk -ndo PKNAME {device} 2>/dev/null"));
+        if format!("/dev/{}", parent.trim()) != disk {
+            return Err(format!("{device} ({target}) is not a partition of {disk}"));
+        }
+        if spec.format {
+            continue;
+        }
+        let found = probe::fs_type(&device);
+        let expected = blkid_type(spec, &cfg.filesystem);
+        if found != expected {
+            let found = if found.is_empty() { "no filesystem" } else { found.as_str() };
+            return Err(format!(
+                "[[disk.partitions]] {device} ({target}) has {found}, expected {expected}; set format = true"
+            ));
+        }
+    }
+    Ok(())
+}
 
-            if is_nvme {
-                layout.root_partition = format!("{disk}p1");
-            } else {
-                layout.root_partition = format!("{disk}1");
-            }
+/// Manual mode: keep the partition table, wipe the entries that are
+/// formatted and leave the others untouched
+fn reuse_custom_partitions(disk: &str, specs: &[PartitionSpec], layout: &mut PartitionLayout) {
+    tui::print_info(&format!("Keeping the partition table of {disk}..."));
+    for (i, spec) in specs.iter().enumerate() {
+        let device = spec_device(disk, i, spec);
+        if spec.format {
+            // Old signatures would confuse blkid/genfstab after mkfs
+            run_cmd(&format!("wipefs -af {device} 2>/dev/null"));
         }
+        record_partition(layout, spec, device);
     }
+}
 
-    // Wait for kernel to recognize partitions
-    run_cmd(&format!("partprobe {disk}"));
-    run_cmd("sleep 2");
+/// Temporary read-only mount used to inspect existing partitions
+const PROBE_MOUNT: &str = "/run/blunux-probe";
 
-    tui::print_success("Partitioning complete");
-    Some(layout)
+/// GPT partition type GUID of an EFI system partition
+const ESP_TYPE_GUID: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+
+/// An installed OS found on the target disk that can be shrunk
+#[derive(Debug, Clone, Serialize)]
+pub struct ExistingOs {
+    pub partition: String,
+    /// "Windows" or the os-release PRETTY_NAME
+    pub name: String,
+    /// "ntfs" or "ext4"
+    pub fs_type: String,
+    pub size_mib: u64,
+    /// Smallest size the filesystem can be shrunk to (used space + margin)
+    pub min_size_mib: u64,
+    /// Separate /home partition from the install's fstab (Linux only)
+    pub home_partition: Option<String>,
 }
 
-/// Format partitions
-pub fn format_partitions(
-    layout: &PartitionLayout,
-    use_encryption: bool,
-    encryption_password: &str,
-) -> bool {
-    // Format EFI partition if UEFI
-    if layout.scheme == PartitionScheme::GptUefi {
-        tui::print_info("Formatting EFI partition...");
-        if !run_cmd(&format!("mkfs.fat -F32 {}", layout.efi_partition)) {
-            tui::print_error("Failed to format EFI partition");
-            return false;
-        }
+impl ExistingOs {
+    /// Largest amount of space that can be taken from this partition
+    pub fn free_mib(&self) -> u64 {
+        self.size_mib.saturating_sub(self.min_size_mib)
     }
+}
 
-    // Format root partition
-    if use_encryption {
-        tui::print_info("Setting up encryption on root partition...");
+/// Partition device nodes of `disk`, in table order
+fn list_partitions(disk: &str) -> Vec<String> {
+    probe::partitions(disk).into_iter().map(|p| p.path).collect()
+}
 
-        let cmd = format!(
-            "echo -n '{}' | cryptsetup luksFormat --type luks2 {} -",
-            encryption_password, layout.root_partition
-        );
-        if !run_cmd(&cmd) {
-            tui::print_error("Failed to encrypt root partition");
-            return false;
-        }
+/// Mount `part` read-only at PROBE_MOUNT, run `f` on the mount and unmount
+fn with_probe_mount<T>(part: &str, f: impl FnOnce(&Path) -> T) -> Option<T> {
+    run_cmd(&format!("mkdir -p {PROBE_MOUNT}"));
+    if !run_cmd(&format!("mount -o ro {part} {PROBE_MOUNT} 2>/dev/null")) {
+        return None;
+    }
+    let result = f(Path::new(PROBE_MOUNT));
+    run_cmd(&format!("umount {PROBE_MOUNT}"));
+    Some(result)
+}
 
-        let cmd = format!(
-            "echo -n '{}' | cryptsetup open {} cryptroot -",
-            encryption_password, layout.root_partition
-        );
-        if !run_cmd(&cmd) {
-            tui::print_error("Failed to open encrypted partition");
-            return false;
-        }
+/// Device of the /home entry in the fstab of the install mounted at `root`
+fn fstab_home_device(root: &Path) -> Option<String> {
+    let fstab = fs::read_to_string(root.join("etc/fstab")).ok()?;
+    let spec = fstab.lines().find_map(|l| {
+        let f: Vec<&str> = l.split_whitespace().collect();
+        (f.len() >= 2 && !f[0].starts_with('#') && f[1] == "/home").then(|| f[0].to_string())
+    })?;
+    if spec.starts_with("/dev/") {
+        return Some(spec);
+    }
+    // UUID=, PARTUUID=, LABEL=
+    probe::find_by_tag(&spec)
+}
 
-        if !run_cmd("mkfs.ext4 -F /dev/mapper/cryptroot") {
-            tui::print_error("Failed to format encrypted root partition");
-            return false;
-        }
-    } else {
-        tui::print_info("Formatting root partition...");
-        if !run_cmd(&format!("mkfs.ext4 -F {}", layout.root_partition)) {
-            tui::print_error("Failed to format root partition");
-            return false;
+/// Used MiB of the filesystem mounted at `path`
+fn used_mib(path: &Path) -> u64 {
+    exec(&format!("df -B1M --output=used {} | tail -n 1", path.display()))
+        .trim()
+        .parse()
+        .unwrap_or(u64::MAX)
+}
+
+/// Find Windows (NTFS) and Linux (ext4) installs on `disk`. Partitions that
+/// cannot be mounted read-only (e.g. hibernated Windows) are skipped, as
+/// they cannot be resized safely either.
+pub fn detect_existing_os(disk: &str) -> Vec<ExistingOs> {
+    let mut found = Vec::new();
+    for p in probe::partitions(disk) {
+        let fs_type = p.fstype.as_deref().unwrap_or_default();
+        if !["ntfs", "ext4"].contains(&fs_type) {
+            continue;
         }
+        let (part, size_mib) = (p.path.as_str(), p.size_mib());
+
+        let probe = with_probe_mount(part, |root| {
+            let name = if fs_type == "ntfs" {
+                root.join("Windows/System32")
+                    .exists()
+                    .then(|| "Windows".to_string())
+            } else {
+                fs::read_to_string(root.join("etc/os-release")).ok().map(|s| {
+                    s.lines()
+                        .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+                        .unwrap_or("Linux")
+                        .trim_matches('"')
+                        .to_string()
+                })
+            };
+            let home = if fs_type == "ext4" { fstab_home_device(root) } else { None };
+            (name, used_mib(root), home)
+        });
+        let Some((Some(name), used, home_partition)) = probe else {
+            continue;
+        };
+
+        // Leave the other OS 10% + 2 GiB of headroom for updates
+        let min_size_mib = used.saturating_add(used / 10 + 2048);
+        found.push(ExistingOs {
+            partition: part.to_string(),
+            name,
+            fs_type: fs_type.to_string(),
+            size_mib,
+            min_size_mib,
+            home_partition,
+        });
     }
+    found
+}
 
-    tui::print_success("Formatting complete");
-    true
+/// EFI system partition on `disk`, if any
+pub fn find_esp(disk: &str) -> Option<String> {
+    probe::partitions(disk)
+        .into_iter()
+        .find(|p| p.parttype.as_deref() == Some(ESP_TYPE_GUID))
+        .map(|p| p.path)
 }
 
-/// Mount partitions for installation
-pub fn mount_partitions(layout: &PartitionLayout, mount_point: &str) -> bool {
-    run_cmd(&format!("mkdir -p {mount_point}"));
+/// The ESP [disk] reuse_esp names: the given partition if it really is an
+/// ESP, or for "auto" the first ESP on a disk other than the target
+pub fn reused_esp(config: &Config) -> Option<String> {
+    let reuse = &config.disk.reuse_esp;
+    let target = &config.install.target_disk;
+    probe::disks()
+        .into_iter()
+        .filter(|d| d.path != *target)
+        .flat_map(|d| probe::partitions(&d.path))
+        .filter(|p| p.parttype.as_deref() == Some(ESP_TYPE_GUID))
+        .map(|p| p.path)
+        .find(|p| reuse == "auto" || p == reuse)
+}
 
-    // Mount root partition
-    let root_dev = if Path::new("/dev/mapper/cryptroot").exists() {
-        "/dev/mapper/cryptroot".to_string()
+/// Free space a shared ESP needs: NMBL copies kernel and initramfs onto it
+pub fn shared_esp_needed_mib(config: &Config) -> u64 {
+    if config.install.bootloader == "nmbl" {
+        200
     } else {
-        layout.root_partition.clone()
+        16
+    }
+}
+
+/// Free space on the filesystem of `esp` in MiB (0 if it cannot be mounted)
+fn esp_free_mib(esp: &str) -> u64 {
+    with_probe_mount(esp, |root| {
+        exec(&format!("df -B1M --output=avail {} | tail -n 1", root.display()))
+            .trim()
+            .parse::<u64>()
+            .unwrap_or(0)
+    })
+    .unwrap_or(0)
+}
+
+/// Pick the partition to shrink for [disk.alongside] and check that the
+/// requested space, a free partition slot and the shared ESP are available.
+/// Only mounts partitions read-only.
+pub fn check_alongside_plan(
+    disk: &str,
+    cfg: &AlongsideConfig,
+    scheme: PartitionScheme,
+    esp_needed_mib: u64,
+) -> Result<ExistingOs, String> {
+    let found = detect_existing_os(disk);
+    let os = if cfg.partition.is_empty() {
+        found.into_iter().max_by_key(|o| o.size_mib)
+    } else {
+        found.into_iter().find(|o| o.partition == cfg.partition)
+    };
+    let Some(os) = os else {
+        return Err(format!(
+            "No resizable Windows or Linux installation found on {disk} \
+             (hibernated Windows must be shut down fully)"
+        ));
     };
 
-    tui::print_info("Mounting root partition...");
-    if !run_cmd(&format!("mount {root_dev} {mount_point}")) {
-        tui::print_error("Failed to mount root partition");
-        return false;
+    let want = config::parse_size_mib(&cfg.size).unwrap_or(0);
+    if want > os.free_mib() {
+        return Err(format!(
+            "{} ({}) can give up at most {} MiB, {} requested",
+            os.partition,
+            os.name,
+            os.free_mib(),
+            cfg.size
+        ));
     }
 
-    // Mount EFI partition if UEFI
-    if layout.scheme == PartitionScheme::GptUefi {
-        tui::print_info("Mounting EFI partition...");
-        run_cmd(&format!("mkdir -p {mount_point}/boot/efi"));
-        if !run_cmd(&format!(
-            "mount {} {mount_point}/boot/efi",
-            layout.efi_partition
-        )) {
-            tui::print_error("Failed to mount EFI partition");
+    match scheme {
+        PartitionScheme::GptUefi => {
+            let Some(esp) = find_esp(disk) else {
+                return Err(format!("No EFI system partition found on {disk}"));
+            };
+            let avail = esp_free_mib(&esp);
+            if avail < esp_needed_mib {
+                return Err(format!(
+                    "The shared EFI partition {esp} has {avail} MiB free, {esp_needed_mib} MiB needed"
+                ));
+            }
+        }
+        PartitionScheme::MbrBios if list_partitions(disk).len() >= 4 => {
+            return Err(format!("{disk} has no free primary partition slot"));
+        }
+        PartitionScheme::GptBios | PartitionScheme::MbrBios => {}
+    }
+    Ok(os)
+}
+
+/// Shrink the filesystem and partition of `os` to `new_mib`
+fn shrink_partition(disk: &str, os: &ExistingOs, new_mib: u64) -> bool {
+    let part = &os.partition;
+    tui::print_info(&format!("Shrinking {part} ({}) to {new_mib} MiB...", os.name));
+
+    let fs_ok = if os.fs_type == "ntfs" {
+        // ntfsresize sizes are decimal; pass bytes to avoid unit confusion
+        let bytes = new_mib * 1024 * 1024;
+        if let Err(e) = check_ntfs_resize(part, bytes) {
+            tui::print_error(&e);
             return false;
         }
+        run_cmd(&format!("echo y | ntfsresize --no-progress-bar --size {bytes} {part}"))
+    } else {
+        run_cmd(&format!("e2fsck -f -y {part}")) && run_cmd(&format!("resize2fs {part} {new_mib}M"))
+    };
+    if !fs_ok {
+        tui::print_error(&format!("Failed to shrink the filesystem on {part}"));
+        return false;
     }
 
-    tui::print_success("Partitions mounted");
+    let name = part.trim_start_matches("/dev/");
+    let num = exec(&format!("cat /sys/class/block/{name}/partition"));
+    // Keep the start, only move the end
+    let resized = num.trim().parse().map_err(|_| format!("{part} is not a partition"));
+    if let Err(e) = resized.and_then(|num| table::resize(disk, num, new_mib)) {
+        tui::print_error(&format!("Failed to resize partition {part}: {e}"));
+        return false;
+    }
+    if let Err(e) = table::reread(disk) {
+        tui::print_warning(&e);
+    }
+    run_cmd("sleep 1");
     true
 }
 
-/// Unmount partitions
+/// Dry run of the NTFS shrink. ntfsresize refuses volumes Windows marked
+/// dirty or left hibernated; only Windows can repair those safely.
+fn check_ntfs_resize(part: &str, bytes: u64) -> Result<(), String> {
+    let out = Command::new("ntfsresize")
+        .args(["--no-action", "--no-progress-bar", "--size", &bytes.to_string(), part])
+        .output()
+        .map_err(|e| format!("Failed to run ntfsresize: {e}"))?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let lower = text.to_lowercase();
+    if lower.contains("chkdsk") || lower.contains("hibernat") || lower.contains("unclean") {
+        return Err(format!(
+            "{part} needs checking before it can be shrunk: run chkdsk /f in Windows, \
+             shut Windows down fully (not hibernate) and retry"
+        ));
+    }
+    let reason = text.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("").trim();
+    Err(format!("ntfsresize cannot shrink {part}: {reason}"))
+}
+
+/// [disk.alongside]: shrink an existing OS partition and create the Blunux
+/// root partition in the freed space, sharing the existing ESP
+fn partition_alongside(disk: &str, cfg: &AlongsideConfig, layout: &mut PartitionLayout) -> bool {
+    let os = match check_alongside_plan(disk, cfg, layout.scheme, 0) {
+        Ok(os) => os,
+        Err(e) => {
+            tui::print_error(&e);
+            return false;
+        }
+    };
+    let want = config::parse_size_mib(&cfg.size).unwrap_or(0);
+
+    let start_mib = partition_start_mib(&os.partition);
+    let new_mib = os.size_mib - want;
+    if start_mib == 0 || !shrink_partition(disk, &os, new_mib) {
+        return false;
+    }
+
+    // The freed space is exactly the tail of the old partition
+    let before = list_partitions(disk);
+    let (free_start, free_end) = (start_mib + new_mib + 1, start_mib + os.size_mib);
+    tui::print_info(&format!("Creating Blunux partition ({free_start}-{free_end} MiB)..."));
+    if let Err(e) = table::mkpart(disk, "ext4", Pos::Mib(free_start), Pos::Mib(free_end)) {
+        tui::print_error(&format!("Failed to create root partition: {e}"));
+        return false;
+    }
+    if let Err(e) = table::reread(disk) {
+        tui::print_warning(&e);
+    }
+    run_cmd("sleep 2");
+
+    let Some(root) = list_partitions(disk).into_iter().find(|p| !before.contains(p)) else {
+        tui::print_error("New root partition not found");
+        return false;
+    };
+    layout.root_partition = root;
+    if layout.scheme == PartitionScheme::GptUefi {
+        layout.efi_partition = find_esp(disk).unwrap_or_default();
+        layout.keep_efi = true;
+    }
+    tui::print_success(&format!(
+        "Installing alongside {} on {}",
+        os.name, layout.root_partition
+    ));
+    true
+}
+
+/// Resolve the partitions of a [disk.keep_home] reinstall: returns the root
+/// partition to reformat and the filesystem of the kept /home
+pub fn check_keep_home_plan(
+    disk: &str,
+    cfg: &KeepHomeConfig,
+    scheme: PartitionScheme,
+) -> Result<(String, String), String> {
+    let partitions = list_partitions(disk);
+    if !partitions.contains(&cfg.partition) {
+        return Err(format!("[disk.keep_home] {} is not a partition of {disk}", cfg.partition));
+    }
+    let home_fs = probe::fs_type(&cfg.partition);
+    if home_fs.is_empty() || home_fs == "crypto_LUKS" {
+        return Err(format!(
+            "[disk.keep_home] {} has no plain filesystem (encrypted homes are not supported)",
+            cfg.partition
+        ));
+    }
+
+    let root = if cfg.root.is_empty() {
+        detect_existing_os(disk)
+            .into_iter()
+            .find(|o| o.home_partition.as_deref() == Some(cfg.partition.as_str()))
+            .map(|o| o.partition)
+            .ok_or_else(|| {
+                format!(
+                    "No installation mounting {} at /home found; set [disk.keep_home] root",
+                    cfg.partition
+                )
+            })?
+    } else {
+        cfg.root.clone()
+    };
+    if !partitions.contains(&root) || root == cfg.partition {
+        return Err(format!("[disk.keep_home] invalid root partition {root}"));
+    }
+
+    if scheme == PartitionScheme::GptUefi && find_esp(disk).is_none() {
+        return Err(format!("No EFI system partition found on {disk}"));
+    }
+    Ok((root, home_fs))
+}
+
+/// [disk.keep_home]: keep the partition table, reformat root and the ESP,
+/// and mount the existing /home untouched
+fn reuse_partitions(disk: &str, cfg: &KeepHomeConfig, layout: &mut PartitionLayout) -> bool {
+    let (root, home_fs) = match check_keep_home_plan(disk, cfg, layout.scheme) {
+        Ok(plan) => plan,
+        Err(e) => {
+            tui::print_error(&e);
+            return false;
+        }
+    };
+
+    // Old signatures on root would confuse blkid/genfstab after mkfs
+    run_cmd(&format!("wipefs -af {root} 2>/dev/null"));
+    layout.root_partition = root;
+    if layout.scheme == PartitionScheme::GptUefi {
+        layout.efi_partition = find_esp(disk).unwrap_or_default();
+    }
+    layout.data_partitions.push(DataPartition {
+        device: cfg.partition.clone(),
+        mountpoint: "/home".to_string(),
+        filesystem: home_fs,
+        encrypt: false,
+        keep: true,
+    });
+    tui::print_success(&format!(
+        "Reinstalling to {}, keeping /home on {}",
+        layout.root_partition, cfg.partition
+    ));
+    true
+}
+
+/// Size of a block device in MiB (0 if unknown)
+fn device_size_mib(device: &str) -> u64 {
+    probe::size_mib(device)
+}
+
+/// One row of the partition plan preview
+#[derive(Debug, Clone, Default)]
+pub struct PlanRow {
+    /// "ESP", "root (LUKS)", "Windows (shrunk)", ...
+    pub label: String,
+    /// Device node, empty while the partition number is not known yet
+    pub device: String,
+    /// Offset on the disk; None for logical volumes
+    pub start_mib: Option<u64>,
+    pub size_mib: u64,
+    pub filesystem: String,
+    pub mountpoint: String,
+}
+
+impl PlanRow {
+    fn new(label: &str, device: String, start_mib: Option<u64>, size_mib: u64) -> Self {
+        Self { label: label.to_string(), device, start_mib, size_mib, ..Default::default() }
+    }
+
+    fn fs(mut self, filesystem: &str, mountpoint: &str) -> Self {
+        self.filesystem = filesystem.to_string();
+        self.mountpoint = mountpoint.to_string();
+        self
+    }
+}
+
+/// Start of partition `part` on its disk in MiB (0 if unknown)
+fn partition_start_mib(part: &str) -> u64 {
+    let name = part.trim_start_matches("/dev/");
+    exec(&format!("cat /sys/class/block/{name}/start"))
+        .trim()
+        .parse::<u64>()
+        .unwrap_or(0)
+        * 512
+        / (1024 * 1024)
+}
+
+/// Filesystem of the separate /home partition of the default layout
+fn home_fs(cfg: &DiskConfig) -> &str {
+    match cfg.filesystem {
+        Filesystem::Xfs | Filesystem::F2fs | Filesystem::Btrfs => cfg.filesystem.fs_type(),
+        _ => "ext4",
+    }
+}
+
+/// The partitions `partition_disk` will create (or keep), in disk order,
+/// for the plan preview. Only reads the disk.
+pub fn plan_preview(config: &Config) -> Vec<PlanRow> {
+    let cfg = &config.disk;
+    let disk = &config.install.target_disk;
+    let scheme = partition_scheme(disk, cfg);
+    let disk_mib = disk_size_mib(disk);
+    let root_label = if config.uses_luks() { "root (LUKS)" } else { "root" };
+    let root_fs = cfg.filesystem.fs_type();
+
+    if cfg.alongside.enabled {
+        let want = config::parse_size_mib(&cfg.alongside.size).unwrap_or(0);
+        let Ok(os) = check_alongside_plan(disk, &cfg.alongside, scheme, 0) else {
+            return Vec::new();
+        };
+        let start = partition_start_mib(&os.partition);
+        let shrunk = os.size_mib.saturating_sub(want);
+        let os_label = format!("{} (shrunk)", os.name);
+        return vec![
+            PlanRow::new(&os_label, os.partition.clone(), Some(start), shrunk).fs(&os.fs_type, ""),
+            PlanRow::new(root_label, String::new(), Some(start + shrunk + 1), want)
+                .fs(root_fs, "/"),
+        ];
+    }
+
+    if !cfg.keep_home.partition.is_empty() {
+        let Ok((root, home_fs)) = check_keep_home_plan(disk, &cfg.keep_home, scheme) else {
+            return Vec::new();
+        };
+        let mut plan = Vec::new();
+        if let Some(esp) = find_esp(disk).filter(|_| scheme == PartitionScheme::GptUefi) {
+            let (start, size) = (partition_start_mib(&esp), device_size_mib(&esp));
+            plan.push(PlanRow::new("ESP", esp, Some(start), size).fs("vfat", "/boot/efi"));
+        }
+        let (start, size) = (partition_start_mib(&root), device_size_mib(&root));
+        plan.push(PlanRow::new(root_label, root, Some(start), size).fs(root_fs, "/"));
+        let home = cfg.keep_home.partition.clone();
+        let (start, size) = (partition_start_mib(&home), device_size_mib(&home));
+        plan.push(PlanRow::new("home (kept)", home, Some(start), size).fs(&home_fs, "/home"));
+        return plan;
+    }
+
+    if !cfg.partitions.is_empty() {
+        let keep_table = cfg.keeps_partition_table();
+        let fixed: u64 = cfg.partitions.iter().filter_map(|p| p.size_mib()).sum();
+        let bios_grub = scheme == PartitionScheme::GptBios && !keep_table;
+        let mut start = if bios_grub { 2 } else { 1 };
+        let mut plan: Vec<PlanRow> = Vec::new();
+        for (i, p) in cfg.partitions.iter().enumerate() {
+            let label = match p.mountpoint.as_str() {
+                _ if p.is_swap() => "swap".to_string(),
+                "/boot/efi" => "ESP".to_string(),
+                "/" => root_label.to_string(),
+                mp if p.encrypt => format!("{} (LUKS)", mp.trim_start_matches('/')),
+                mp => mp.trim_start_matches('/').to_string(),
+            };
+            let label = if p.format { label } else { format!("{label} (kept)") };
+            let device = spec_device(disk, i, p);
+            let (row_start, size) = if keep_table {
+                (partition_start_mib(&device), device_size_mib(&device))
+            } else {
+                (start, p.size_mib().unwrap_or(disk_mib.saturating_sub(fixed + 2)))
+            };
+            start += size;
+            let filesystem = match p.mountpoint.as_str() {
+                "/" => root_fs,
+                "/boot/efi" => "vfat",
+                _ => &p.filesystem,
+            };
+            plan.push(
+                PlanRow::new(&label, device, Some(row_start), size).fs(filesystem, &p.mountpoint),
+            );
+        }
+        if bios_grub {
+            let device = partition_device(disk, cfg.partitions.len() + 1);
+            plan.insert(0, PlanRow::new("BIOS boot", device, Some(1), 1));
+        }
+        return plan;
+    }
+
+    let mut plan = Vec::new();
+    let mut num = 1;
+    if scheme == PartitionScheme::GptBios {
+        plan.push(PlanRow::new("BIOS boot", partition_device(disk, num), Some(1), 1));
+        num += 1;
+    }
+    // An ESP on [disk] esp_disk or a reused one is not part of this disk
+    let esp_mib = if scheme == PartitionScheme::GptUefi && cfg.esp_on_target() {
+        config.esp_size_mb()
+    } else {
+        0
+    };
+    if esp_mib > 0 {
+        plan.push(
+            PlanRow::new("ESP", partition_device(disk, num), Some(1), esp_mib)
+                .fs("vfat", "/boot/efi"),
+        );
+        num += 1;
+    }
+    let root_start = match scheme {
+        _ if esp_mib > 0 => esp_mib + 1,
+        PartitionScheme::GptBios => 2,
+        _ => 1,
+    };
+    let Ok(swap_mib) = swap_partition_mib(cfg) else {
+        return Vec::new();
+    };
+    let avail = disk_mib.saturating_sub(esp_mib + 1 + swap_mib);
+    let split = &cfg.home_split;
+    let root_mib = match split.strip_suffix('%') {
+        Some(p) => (disk_mib * p.parse::<u64>().unwrap_or(100) / 100).saturating_sub(esp_mib + 1),
+        None => config::parse_size_mib(split).unwrap_or(avail),
+    };
+
+    let root_device = partition_device(disk, num);
+    if cfg.lvm.enabled {
+        // The LUKS/LVM container, then the logical volumes inside it
+        plan.push(
+            PlanRow::new("LVM (LUKS)", root_device, Some(root_start), root_mib)
+                .fs("crypto_LUKS", ""),
+        );
+        let lv = |name: &str| format!("/dev/mapper/{LVM_VG}-{name}");
+        let Ok(swap) = swap_size_mib(&cfg.swap) else {
+            return Vec::new();
+        };
+        let swap = swap.min(root_mib);
+        let rest = root_mib - swap;
+        let lv_root = config::parse_size_mib(&cfg.lvm.root_size).unwrap_or(rest).min(rest);
+        if swap > 0 {
+            plan.push(PlanRow::new("swap (LVM)", lv("swap"), None, swap).fs("swap", ""));
+        }
+        plan.push(
+            PlanRow::new(&format!("{root_label} (LVM)"), lv("root"), None, lv_root)
+                .fs(root_fs, "/"),
+        );
+        if cfg.lvm.home {
+            plan.push(
+                PlanRow::new("home (LVM)", lv("home"), None, rest - lv_root).fs("ext4", "/home"),
+            );
+        }
+    } else {
+        plan.push(
+            PlanRow::new(root_label, root_device, Some(root_start), root_mib).fs(root_fs, "/"),
+        );
+    }
+    num += 1;
+    if !split.is_empty() {
+        let home = if config.install.use_encryption { "home (LUKS)" } else { "home" };
+        plan.push(
+            PlanRow::new(
+                home,
+                partition_device(disk, num),
+                Some(root_start + root_mib),
+                avail.saturating_sub(root_mib),
+            )
+            .fs(home_fs(cfg), "/home"),
+        );
+        num += 1;
+    }
+    if swap_mib > 0 {
+        let start = disk_mib.saturating_sub(swap_mib);
+        plan.push(
+            PlanRow::new("swap", partition_device(disk, num), Some(start), swap_mib).fs("swap", ""),
+        );
+    }
+    plan
+}
+
+/// Partitions on the target disk now, with what the plan does to each:
+/// "erased", "shrunk", "reformatted" or "kept"
+pub fn current_partitions(config: &Config) -> Vec<(PlanRow, &'static str)> {
+    let cfg = &config.disk;
+    let disk = &config.install.target_disk;
+    let scheme = partition_scheme(disk, cfg);
+    // Partitions the plan changes; everything else is kept (or erased with
+    // the whole disk)
+    let mut changed: Vec<(String, &'static str)> = Vec::new();
+    let whole_disk = if cfg.alongside.enabled {
+        if let Ok(os) = check_alongside_plan(disk, &cfg.alongside, scheme, 0) {
+            changed.push((os.partition, "shrunk"));
+        }
+        false
+    } else if !cfg.keep_home.partition.is_empty() {
+        if let Ok((root, _)) = check_keep_home_plan(disk, &cfg.keep_home, scheme) {
+            changed.push((root, "reformatted"));
+        }
+        if let Some(esp) = find_esp(disk).filter(|_| scheme == PartitionScheme::GptUefi) {
+            changed.push((esp, "reformatted"));
+        }
+        false
+    } else if cfg.keeps_partition_table() {
+        for (i, spec) in cfg.partitions.iter().enumerate().filter(|(_, p)| p.format) {
+            changed.push((spec_device(disk, i, spec), "reformatted"));
+        }
+        false
+    } else {
+        true
+    };
+
+    probe::partitions(disk)
+        .into_iter()
+        .map(|p| {
+            let fate = match changed.iter().find(|(dev, _)| *dev == p.path) {
+                Some((_, fate)) => fate,
+                None if whole_disk => "erased",
+                None => "kept",
+            };
+            let row = PlanRow {
+                start_mib: Some(partition_start_mib(&p.path)),
+                size_mib: p.size_mib(),
+                filesystem: p.fstype.clone().unwrap_or_default(),
+                device: p.path,
+                ..Default::default()
+            };
+            (row, fate)
+        })
+        .collect()
+}
+
+/// Unmount, swapoff and close everything on `disk` so it can be repartitioned
+fn release_disk(disk: &str) {
+    tui::print_info(&format!(
+        "Checking for mounted partitions on {disk}..."
+    ));
+
+    // Partitions and whatever is stacked on them (LUKS mappings, LVs, md
+    // arrays), innermost first: reversed pre-order puts every device
+    // before the one it sits on
+    if let Some(tree) = probe::tree(disk) {
+        let mut stack: Vec<&probe::BlockDevice> = tree.walk().into_iter().skip(1).collect();
+        stack.reverse();
+        // An array or VG shows up under each of its members
+        let mut seen: Vec<String> = Vec::new();
+        for dev in &stack {
+            run_cmd(&format!("umount -f {} 2>/dev/null", dev.path));
+            run_cmd(&format!("swapoff {} 2>/dev/null", dev.path));
+        }
+        for dev in &stack {
+            if seen.contains(&dev.path) {
+                continue;
+            }
+            seen.push(dev.path.clone());
+            let path = &dev.path;
+            match dev.kind.as_str() {
+                "lvm" => {
+                    let vg = exec(&format!("lvs --noheadings -o vg_name {path} 2>/dev/null"));
+                    let vg = vg.trim().to_string();
+                    if !vg.is_empty() && !seen.contains(&vg) {
+                        tui::print_info(&format!("Deactivating volume group {vg}"));
+                        run_cmd(&format!("vgchange -an {vg} 2>/dev/null"));
+                        seen.push(vg);
+                    }
+                }
+                "crypt" => {
+                    tui::print_info(&format!("Closing LUKS mapping {path}"));
+                    run_cmd(&format!("cryptsetup close {path} 2>/dev/null"));
+                }
+                kind if kind.starts_with("raid") => {
+                    tui::print_info(&format!("Stopping RAID array {path}"));
+                    run_cmd(&format!("mdadm --stop {path} 2>/dev/null"));
+                }
+                "dm" | "mpath" => {
+                    run_cmd(&format!("dmsetup remove {path} 2>/dev/null"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Volumes of a previous install whose devices lsblk did not list
+    // (e.g. a detached LUKS header elsewhere)
+    run_cmd(&format!("swapoff /dev/{LVM_VG}/swap 2>/dev/null"));
+    run_cmd(&format!("vgchange -an {LVM_VG} 2>/dev/null"));
+    run_cmd("cryptsetup close cryptroot 2>/dev/null");
+    run_cmd("for m in /dev/mapper/crypt-*; do cryptsetup close \"$m\"; done 2>/dev/null");
+    run_cmd("sleep 1");
+
+    // Anything left keeps the kernel from re-reading the partition table
+    let left: Vec<String> = probe::tree(disk)
+        .map(|tree| {
+            tree.walk()
+                .into_iter()
+                .filter(|d| d.kind != "disk" && d.kind != "part")
+                .map(|d| format!("{} ({})", d.path, d.kind))
+                .collect()
+        })
+        .unwrap_or_default();
+    if !left.is_empty() {
+        tui::print_warning(&format!("Still active on {disk}: {}", left.join(", ")));
+    }
+}
+
+/// Whole disks underneath a block device, through partitions, LUKS and LVM
+fn backing_disks(device: &str) -> Vec<String> {
+    exec(&format!("lsblk -nlso PATH,TYPE {device} 2>/dev/null"))
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .filter(|(_, kind)| kind.trim() == "disk")
+        .map(|(path, _)| path.to_string())
+        .collect()
+}
+
+/// Whether `disk` is removable media or attached over USB
+pub fn is_removable(disk: &str) -> bool {
+    probe::tree(disk).is_some_and(|d| d.rm || d.tran.as_deref() == Some("usb"))
+}
+
+/// Disks the running system lives on: the archiso boot medium, or the
+/// root filesystem when the installer runs from an installed system.
+/// Empty with copytoram or a network boot, when nothing is mounted from a disk.
+pub fn live_medium_disks() -> Vec<String> {
+    let mut disks: Vec<String> = ["/run/archiso/bootmnt", "/run/archiso/img_dev", "/"]
+        .iter()
+        .filter_map(|mp| {
+            let source = exec(&format!("findmnt -no SOURCE {mp} 2>/dev/null"));
+            // btrfs reports the subvolume as /dev/sda2[/@]
+            let source = source.trim().split('[').next().unwrap_or("").to_string();
+            source.starts_with("/dev/").then_some(source)
+        })
+        .flat_map(|source| backing_disks(&source))
+        .collect();
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+/// RAID arrays and foreign LVM volume groups active on `disk`. LVs of a
+/// previous Blunux install are not listed; release_disk deactivates them.
+pub fn active_stacks(disk: &str) -> Vec<String> {
+    let Some(tree) = probe::tree(disk) else {
+        return Vec::new();
+    };
+    let own_lv = format!("/dev/mapper/{LVM_VG}-");
+    let mut stacks: Vec<String> = tree
+        .walk()
+        .into_iter()
+        .filter(|d| d.kind.starts_with("raid") || (d.kind == "lvm" && !d.path.starts_with(&own_lv)))
+        .map(|d| format!("{} ({})", d.path, d.kind))
+        .collect();
+    stacks.dedup();
+    stacks
+}
+
+/// The kernel's rotational flag of `disk` (None if unknown)
+fn rotational(disk: &str) -> Option<bool> {
+    probe::tree(disk).map(|d| d.rota)
+}
+
+/// Whether `disk` is a spinning hard disk, where discard does not erase
+fn is_rotational(disk: &str) -> bool {
+    rotational(disk) == Some(true)
+}
+
+/// Whether `disk` is an SSD/NVMe drive that benefits from TRIM
+pub fn is_ssd(disk: &str) -> bool {
+    rotational(disk) == Some(false)
+}
+
+/// eMMC or SD card: slow random writes and limited write endurance
+pub fn is_flash_card(disk: &str) -> bool {
+    disk.starts_with("/dev/mmcblk")
+        || probe::tree(disk).is_some_and(|d| d.tran.as_deref() == Some("mmc"))
+}
+
+/// Destroy the old contents of `disk` with [disk] secure_erase `mode`.
+/// "auto" formats NVMe namespaces, discards SSDs and zero-fills hard disks.
+fn secure_erase(disk: &str, mode: &str) -> bool {
+    let mode = match mode {
+        "auto" if disk.contains("nvme") => "nvme-format",
+        "auto" if is_rotational(disk) => "zero",
+        "auto" => "discard",
+        mode => mode,
+    };
+    match mode {
+        "nvme-format" => {
+            tui::print_info(&format!("Secure erase: formatting NVMe namespace {disk}..."));
+            // User-data erase; drives that refuse it can still discard
+            if run_cmd(&format!("nvme format {disk} --ses=1 --force")) {
+                return true;
+            }
+            tui::print_warning("nvme format failed, falling back to discard");
+            secure_erase(disk, "discard")
+        }
+        "discard" => {
+            tui::print_info(&format!("Secure erase: discarding all blocks of {disk}..."));
+            if run_cmd(&format!("blkdiscard -f -s {disk} 2>/dev/null")) {
+                return true;
+            }
+            // Plain TRIM: the drive returns zeroes or garbage, but the
+            // flash cells may only be cleared later by garbage collection
+            tui::print_warning("Secure discard is not supported, using a plain discard");
+            run_cmd(&format!("blkdiscard -f {disk}"))
+        }
+        _ => {
+            tui::print_info(&format!(
+                "Secure erase: overwriting {disk} with zeroes (this takes hours on large disks)..."
+            ));
+            run_cmd(&format!("shred -v -n 0 -z {disk}"))
+        }
+    }
+}
+
+/// Where partition tables and LUKS headers are saved before a disk is
+/// modified. It lives in the live system's RAM: the installer copies it into
+/// the installed system, and asks for a copy elsewhere before rebooting.
+pub const BACKUP_DIR: &str = "/root/blunux-backup";
+
+/// Backups of one disk, one timestamped directory per install attempt,
+/// e.g. /root/blunux-backup/nvme0n1/20260101-120000
+fn backup_dir(disk: &str) -> String {
+    format!("{BACKUP_DIR}/{}", disk.trim_start_matches("/dev/").replace('/', "-"))
+}
+
+/// New directory for this attempt's backup of `disk`; earlier attempts
+/// are never overwritten
+fn new_backup_dir(disk: &str) -> Option<String> {
+    let base = format!("{}/{}", backup_dir(disk), exec("date +%Y%m%d-%H%M%S").trim());
+    fs::create_dir_all(backup_dir(disk)).ok()?;
+    (0..100)
+        .map(|n| if n == 0 { base.clone() } else { format!("{base}-{n}") })
+        .find(|dir| fs::create_dir(dir).is_ok())
+}
+
+/// Most recent backup of `disk`
+fn latest_backup_dir(disk: &str) -> Option<String> {
+    let mut dirs: Vec<String> = fs::read_dir(backup_dir(disk))
+        .ok()?
+        .flatten()
+        .filter(|e| e.path().join("partition-table.sfdisk").is_file())
+        .filter_map(|e| e.path().to_str().map(str::to_string))
+        .collect();
+    // Timestamps sort chronologically
+    dirs.sort();
+    dirs.pop()
+}
+
+/// Save the partition table (sfdisk -d) and the LUKS headers on `disk`
+/// before it is modified
+fn backup_partition_table(disk: &str) {
+    let table = exec(&format!("sfdisk -d {disk} 2>/dev/null"));
+    if table.trim().is_empty() {
+        tui::print_info(&format!("{disk} has no partition table to back up"));
+        return;
+    }
+    let Some(dir) = new_backup_dir(disk) else {
+        tui::print_warning(&format!("Could not create a backup directory for {disk}"));
+        return;
+    };
+    if fs::write(format!("{dir}/partition-table.sfdisk"), table).is_err() {
+        tui::print_warning(&format!("Could not back up the partition table of {disk}"));
+        return;
+    }
+
+    for part in list_partitions(disk) {
+        if probe::fs_type(&part) != "crypto_LUKS" {
+            continue;
+        }
+        let name = part.trim_start_matches("/dev/");
+        if !run_cmd(&format!(
+            "cryptsetup luksHeaderBackup {part} --header-backup-file {dir}/{name}.luks-header"
+        )) {
+            tui::print_warning(&format!("Could not back up the LUKS header of {part}"));
+        }
+    }
+    tui::print_success(&format!(
+        "Partition table of {disk} backed up to {dir} (undo: --restore-partition-table {disk})"
+    ));
+    tui::print_warning(&format!(
+        "{dir} is in RAM and lost on reboot - copy it to a USB stick if you may need it"
+    ));
+}
+
+/// Rescue command: write back the partition table and LUKS headers saved
+/// by the last install attempt on `disk`. Filesystems that were already
+/// reformatted cannot be recovered this way.
+pub fn restore_partition_table(disk: &str) -> bool {
+    let Some(dir) = latest_backup_dir(disk) else {
+        tui::print_error(&format!("No partition table backup for {disk} in {BACKUP_DIR}"));
+        return false;
+    };
+    let table = format!("{dir}/partition-table.sfdisk");
+
+    release_disk(disk);
+    tui::print_info(&format!("Restoring partition table of {disk} from {table}..."));
+    if !run_cmd(&format!("sfdisk --no-reread {disk} < {table}")) {
+        tui::print_error("Failed to restore the partition table");
+        return false;
+    }
+    if let Err(e) = table::reread(disk) {
+        tui::print_warning(&e);
+    }
+    run_cmd("sleep 2");
+
+    let headers = fs::read_dir(&dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut ok = true;
+    for header in headers {
+        let Some(name) = header.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(part) = name.strip_suffix(".luks-header") else {
+            continue;
+        };
+        tui::print_info(&format!("Restoring LUKS header of /dev/{part}..."));
+        if !run_cmd(&format!(
+            "cryptsetup luksHeaderRestore --batch-mode /dev/{part} --header-backup-file {}",
+            header.display()
+        )) {
+            tui::print_error(&format!("Failed to restore the LUKS header of /dev/{part}"));
+            ok = false;
+        }
+    }
+
+    if ok {
+        tui::print_success(&format!("Partition table of {disk} restored"));
+    }
+    ok
+}
+
+/// Wipe and partition disk ([[disk.partitions]] replaces the default layout
+/// when set); with [disk.alongside] only the freed space is used
+pub fn partition_disk(
+    disk: &str,
+    scheme: PartitionScheme,
+    config: &Config,
+) -> Option<PartitionLayout> {
+    let cfg = &config.disk;
+    let specs = &cfg.partitions;
+    let mut layout = PartitionLayout {
+        efi_partition: String::new(),
+        root_partition: String::new(),
+        scheme,
+        data_partitions: Vec::new(),
+        keep_efi: false,
+        keep_root: false,
+        ssd: is_ssd(disk),
+        flash: is_flash_card(disk),
+    };
+
+    // Refuse an impossible layout before anything is wiped
+    if let Err(e) = check_partition_plan(disk, config, scheme) {
+        tui::print_error(&e);
+        return None;
+    }
+
+    // Undo path for everything below: --restore-partition-table
+    for d in [disk, cfg.esp_disk.as_str()].into_iter().filter(|d| !d.is_empty()) {
+        release_disk(d);
+        backup_partition_table(d);
+    }
+
+    if cfg.alongside.enabled {
+        return partition_alongside(disk, &cfg.alongside, &mut layout).then_some(layout);
+    }
+    if !cfg.keep_home.partition.is_empty() {
+        return reuse_partitions(disk, &cfg.keep_home, &mut layout).then_some(layout);
+    }
+    if cfg.keeps_partition_table() {
+        reuse_custom_partitions(disk, specs, &mut layout);
+        tui::print_success("Partitioning complete");
+        return Some(layout);
+    }
+
+    if cfg.secure_erase != "none" {
+        for d in [disk, cfg.esp_disk.as_str()].into_iter().filter(|d| !d.is_empty()) {
+            if !secure_erase(d, &cfg.secure_erase) {
+                tui::print_error(&format!("Secure erase of {d} failed"));
+                return None;
+            }
+        }
+    }
+
+    // Wipe existing partition table
+    tui::print_info(&format!("Wiping disk: {disk}"));
+    if !run_cmd(&format!("wipefs -af {disk} 2>/dev/null")) {
+        tui::print_warning("Could not wipe disk signatures");
+    }
+
+    // Nothing left to find; a failure here shows up at mklabel
+    let _ = table::reread(disk);
+    run_cmd("sleep 1");
+
+    let split = &cfg.home_split;
+    // Root is the first partition unless the ESP or bios_grub precedes it
+    let esp_elsewhere = !cfg.esp_on_target();
+    let (root_start, root_num) = match scheme {
+        PartitionScheme::GptUefi if !esp_elsewhere => (config.esp_size_mb() + 1, 2),
+        PartitionScheme::GptBios => (2, 2),
+        _ => (1, 1),
+    };
+    // A swap partition takes the end of the disk
+    let swap_mib = match swap_partition_mib(cfg) {
+        Ok(mib) => mib,
+        Err(e) => {
+            tui::print_error(&format!("Cannot size the swap partition: {e}"));
+            return None;
+        }
+    };
+    let disk_end = if swap_mib > 0 { Pos::FromEnd(swap_mib) } else { Pos::End };
+
+    if !specs.is_empty() {
+        if !create_custom_partitions(disk, specs, &mut layout) {
+            return None;
+        }
+        if scheme.is_gpt() {
+            set_disk_guid(disk, &config.install.hostname);
+        }
+        if let Err(e) = table::reread(disk) {
+            tui::print_error(&e);
+            return None;
+        }
+        run_cmd("sleep 2");
+        if let Err(e) = verify_partitions(disk) {
+            tui::print_error(&e);
+            return None;
+        }
+        tui::print_success("Partitioning complete");
+        return Some(layout);
+    }
+
+    match scheme {
+        PartitionScheme::GptUefi => {
+            tui::print_info("Creating GPT partition table...");
+
+            if let Err(e) = table::mklabel(disk, Label::Gpt) {
+                tui::print_error(&format!("Failed to create GPT partition table: {e}"));
+                return None;
+            }
+
+            if !cfg.reuse_esp.is_empty() {
+                // Found and checked by check_partition_plan; kept as it is
+                layout.efi_partition = reused_esp(config).unwrap_or_default();
+                layout.keep_efi = true;
+                tui::print_info(&format!("Using existing EFI partition {}", layout.efi_partition));
+            } else {
+                // Create EFI partition ([disk] esp_size_mb), on [disk] esp_disk when set
+                let esp_disk = if esp_elsewhere { cfg.esp_disk.as_str() } else { disk };
+                if esp_elsewhere {
+                    tui::print_info(&format!("Creating EFI partition on {esp_disk}..."));
+                    run_cmd(&format!("wipefs -af {esp_disk} 2>/dev/null"));
+                    if let Err(e) = table::mklabel(esp_disk, Label::Gpt) {
+                        tui::print_error(&format!("Failed to partition {esp_disk}: {e}"));
+                        return None;
+                    }
+                }
+                let esp_end = Pos::Mib(config.esp_size_mb() + 1);
+                let esp = table::mkpart(esp_disk, "fat32", Pos::Mib(1), esp_end)
+                    .and_then(|_| table::set_flag(esp_disk, 1, Flag::Esp));
+                if let Err(e) = esp {
+                    tui::print_error(&format!("Failed to create EFI partition: {e}"));
+                    return None;
+                }
+                layout.efi_partition = partition_device(esp_disk, 1);
+                if esp_elsewhere {
+                    set_disk_guid(esp_disk, &format!("{}:esp", config.install.hostname));
+                    if let Err(e) = table::reread(esp_disk) {
+                        tui::print_warning(&e);
+                    }
+                }
+            }
+
+            // Create root partition (rest of disk, or up to the /home split)
+            if !create_root_and_home(disk, root_start, split, disk_end) {
+                return None;
+            }
+            layout.root_partition = partition_device(disk, root_num);
+        }
+        PartitionScheme::GptBios => {
+            tui::print_info("Creating GPT partition table (BIOS boot)...");
+
+            if let Err(e) = table::mklabel(disk, Label::Gpt) {
+                tui::print_error(&format!("Failed to create GPT partition table: {e}"));
+                return None;
+            }
+            if !create_bios_grub_partition(disk, 1)
+                || !create_root_and_home(disk, root_start, split, disk_end)
+            {
+                return None;
+            }
+            layout.root_partition = partition_device(disk, root_num);
+        }
+        PartitionScheme::MbrBios => {
+            tui::print_info("Creating MBR partition table...");
+
+            if let Err(e) = table::mklabel(disk, Label::Msdos) {
+                tui::print_error(&format!("Failed to create MBR partition table: {e}"));
+                return None;
+            }
+            if !create_root_and_home(disk, 1, split, disk_end) {
+                return None;
+            }
+            if let Err(e) = table::set_flag(disk, 1, Flag::Boot) {
+                tui::print_error(&e);
+                return None;
+            }
+            layout.root_partition = partition_device(disk, root_num);
+        }
+    }
+
+    // Separate /home after root; encrypted along with root
+    let mut num = root_num + 1;
+    if !split.is_empty() {
+        let filesystem = match cfg.filesystem {
+            Filesystem::Xfs | Filesystem::F2fs | Filesystem::Btrfs => cfg.filesystem.fs_type(),
+            _ => "ext4",
+        };
+        layout.data_partitions.push(DataPartition {
+            device: partition_device(disk, num),
+            mountpoint: "/home".to_string(),
+            filesystem: filesystem.to_string(),
+            encrypt: config.install.use_encryption,
+            keep: false,
+        });
+        num += 1;
+    }
+
+    // Swap partition last, activated by mount_partitions for genfstab
+    if swap_mib > 0 {
+        if let Err(e) = table::mkpart(disk, "linux-swap", disk_end, Pos::End) {
+            tui::print_error(&format!("Failed to create swap partition: {e}"));
+            return None;
+        }
+        layout.data_partitions.push(DataPartition {
+            device: partition_device(disk, num),
+            mountpoint: String::new(),
+            filesystem: "swap".to_string(),
+            encrypt: false,
+            keep: false,
+        });
+    }
+
+    if scheme.is_gpt() {
+        set_disk_guid(disk, &config.install.hostname);
+    }
+
+    // Wait for kernel to recognize partitions
+    if let Err(e) = table::reread(disk) {
+        tui::print_error(&e);
+        return None;
+    }
+    run_cmd("sleep 2");
+
+    // mkfs and GRUB fail far less clearly on a partition the kernel has
+    // not picked up
+    let verify_disks = [disk, cfg.esp_disk.as_str()];
+    for d in verify_disks.into_iter().filter(|d| !d.is_empty()) {
+        if let Err(e) = verify_partitions(d) {
+            tui::print_error(&e);
+            return None;
+        }
+    }
+
+    tui::print_success("Partitioning complete");
+    Some(layout)
+}
+
+/// Check the freshly written table of `disk`: every partition starts on a
+/// 1 MiB boundary, and the kernel has a device node with the same start
+/// and size as the table
+fn verify_partitions(disk: &str) -> Result<(), String> {
+    const MIB: u64 = 1024 * 1024;
+    let sector = table::sector_size(disk);
+    let extents = table::extents(disk)?;
+    if extents.is_empty() {
+        return Err(format!("{disk}: the new partition table has no partitions"));
+    }
+    run_cmd("udevadm settle");
+
+    for extent in extents {
+        let device = partition_device(disk, extent.num);
+        let name = device.trim_start_matches("/dev/");
+        let (start, size) = (extent.start * sector, extent.size * sector);
+        if start % MIB != 0 {
+            return Err(format!(
+                "{device} starts at byte {start}, not on a 1 MiB boundary \
+                 (slow writes, and some firmware will not boot it)"
+            ));
+        }
+
+        // sysfs counts 512-byte sectors whatever the logical sector size
+        let sysfs = |attr: &str| {
+            fs::read_to_string(format!("/sys/class/block/{name}/{attr}"))
+                .ok()
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .map(|sectors| sectors * 512)
+        };
+        let (Some(kernel_start), Some(kernel_size)) = (sysfs("start"), sysfs("size")) else {
+            return Err(format!(
+                "The kernel does not know {device} yet - the partition table of {disk} \
+                 was not re-read (reboot and try again)"
+            ));
+        };
+        if (kernel_start, kernel_size) != (start, size) {
+            return Err(format!(
+                "The kernel sees {device} at {} MiB with {} MiB, the partition table says \
+                 {} MiB with {} MiB - reboot and try again",
+                kernel_start / MIB,
+                kernel_size / MIB,
+                start / MIB,
+                size / MIB
+            ));
+        }
+        if !Path::new(&device).exists() {
+            return Err(format!("{device} has no device node (is udev running?)"));
+        }
+    }
+    Ok(())
+}
+
+/// One formatting task run by `run_format_jobs`
+type FormatJob<'a> = Box<dyn FnOnce() -> bool + Send + 'a>;
+
+/// Filesystems created at the same time; more would only contend for the
+/// same disks
+const FORMAT_JOBS: usize = 4;
+
+/// Format partitions. LUKS containers of data partitions are set up one at
+/// a time (argon2 is memory-hard), then the filesystems are created in
+/// parallel.
+pub fn format_partitions(layout: &PartitionLayout, cfg: &Config) -> bool {
+    let encryption_password = &cfg.install.encryption_password;
+
+    for part in &layout.data_partitions {
+        if part.keep {
+            tui::print_info(&format!("Keeping {} ({})", part.device, part.mountpoint));
+            continue;
+        }
+        if part.encrypt {
+            tui::print_info(&format!("Encrypting {} ({})...", part.device, part.mountpoint));
+            let format = format!(
+                "echo -n '{encryption_password}' | cryptsetup luksFormat {}{} -",
+                luks_format_opts(&cfg.disk.encryption),
+                part.device
+            );
+            let open = format!(
+                "echo -n '{encryption_password}' | cryptsetup open {}{} {} -",
+                luks_discard_opt(layout, &cfg.disk.encryption),
+                part.device,
+                part.mapper_name()
+            );
+            if !run_cmd(&format) || !run_cmd(&open) {
+                tui::print_error(&format!("Failed to encrypt {}", part.device));
+                return false;
+            }
+        }
+    }
+
+    let mut jobs: Vec<FormatJob> = Vec::new();
+
+    // Format EFI partition if UEFI (unless another OS boots from it)
+    if layout.scheme == PartitionScheme::GptUefi && !layout.keep_efi {
+        jobs.push(Box::new(|| {
+            tui::print_info("Formatting EFI partition...");
+            let ok = run_cmd(&format!(
+                "mkfs.fat -F32 -n {} {}",
+                esp_fs_label(cfg),
+                layout.efi_partition
+            ));
+            if !ok {
+                tui::print_error("Failed to format EFI partition");
+            }
+            ok
+        }));
+    }
+
+    for part in layout.data_partitions.iter().filter(|p| !p.keep) {
+        jobs.push(Box::new(move || {
+            let device = part.fs_device();
+            tui::print_info(&format!("Formatting {device} ({})...", part.filesystem));
+            let cmd = if part.filesystem == "swap" {
+                Some(format!("mkswap {device}"))
+            } else {
+                mkfs_command(&part.filesystem, &device)
+            };
+            let ok = cmd.is_some_and(|c| run_cmd(&c));
+            if !ok {
+                tui::print_error(&format!("Failed to format {}", part.device));
+            } else {
+                set_fs_label(&part.filesystem, &device, &data_fs_label(part, cfg));
+            }
+            ok
+        }));
+    }
+
+    if layout.keep_root {
+        tui::print_warning(&format!(
+            "Reusing the existing root filesystem on {} (not formatted)",
+            layout.root_partition
+        ));
+    } else {
+        jobs.push(Box::new(|| format_root(layout, cfg)));
+    }
+
+    if !run_format_jobs(jobs) {
+        return false;
+    }
+    tui::print_success("Formatting complete");
+    true
+}
+
+/// Run formatting jobs on a small thread pool; true only if all succeed
+fn run_format_jobs(jobs: Vec<FormatJob>) -> bool {
+    let queue = Mutex::new(jobs.into_iter());
+    let failed = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for _ in 0..FORMAT_JOBS {
+            s.spawn(|| loop {
+                let job = queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+                let Some(job) = job else {
+                    break;
+                };
+                if !job() {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+    if failed > 0 {
+        tui::print_error(&format!("{failed} partition(s) could not be formatted"));
+    }
+    failed == 0
+}
+
+/// Format the root partition: LUKS, LVM, bcachefs or ZFS as configured
+fn format_root(layout: &PartitionLayout, cfg: &Config) -> bool {
+    let filesystem = cfg.disk.filesystem;
+    let use_encryption = cfg.install.use_encryption;
+    let encryption_password = &cfg.install.encryption_password;
+
+    if filesystem == Filesystem::Bcachefs {
+        return format_bcachefs(&layout.root_partition, use_encryption, encryption_password);
+    }
+    if filesystem == Filesystem::Zfs {
+        return create_zfs_pool(&layout.root_partition, use_encryption, encryption_password);
+    }
+
+    if use_encryption {
+        tui::print_info("Setting up encryption on root partition...");
+
+        // Detached header: the LUKS header lives on a removable device instead
+        let detached_header = &cfg.disk.encryption.detached_header;
+        let header_opt = if detached_header.is_empty() {
+            String::new()
+        } else {
+            match mount_header_device(detached_header) {
+                Some(path) => format!("--header {path} "),
+                None => return false,
+            }
+        };
+
+        let cmd = format!(
+            "echo -n '{}' | cryptsetup luksFormat {}{}{} -",
+            encryption_password,
+            luks_format_opts(&cfg.disk.encryption),
+            header_opt,
+            layout.root_partition
+        );
+        if !run_cmd(&cmd) {
+            tui::print_error("Failed to encrypt root partition");
+            return false;
+        }
+
+        let cmd = format!(
+            "echo -n '{}' | cryptsetup open {}{}{} cryptroot -",
+            encryption_password,
+            luks_discard_opt(layout, &cfg.disk.encryption),
+            header_opt,
+            layout.root_partition
+        );
+        if !run_cmd(&cmd) {
+            tui::print_error("Failed to open encrypted partition");
+            return false;
+        }
+
+        if !cfg.root_keyfile().is_empty()
+            && !enroll_root_keyfile(&layout.root_partition, &header_opt, encryption_password)
+        {
+            tui::print_warning("Keyfile enrollment failed - root will only open with the passphrase");
+        }
+
+        if cfg.disk.encryption.tpm2 {
+            if !has_tpm2() {
+                tui::print_warning("No TPM 2.0 found - root will only open with the passphrase");
+            } else if enroll_tpm2(&layout.root_partition, encryption_password) {
+                tui::print_success("TPM2 enrolled - root will unlock automatically on this machine");
+            } else {
+                tui::print_warning("TPM2 enrollment failed - root will only open with the passphrase");
+            }
+        }
+
+        if cfg.disk.lvm.enabled {
+            if !setup_lvm("/dev/mapper/cryptroot", cfg) {
+                return false;
+            }
+        } else if !mkfs_root("/dev/mapper/cryptroot", cfg, &root_fs_label(cfg)) {
+            tui::print_error("Failed to format encrypted root partition");
+            return false;
+        }
+    } else if cfg.disk.lvm.enabled {
+        if !setup_lvm(&layout.root_partition, cfg) {
+            return false;
+        }
+    } else {
+        tui::print_info("Formatting root partition...");
+        if !mkfs_root(&layout.root_partition, cfg, &root_fs_label(cfg)) {
+            tui::print_error("Failed to format root partition");
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Btrfs subvolumes created on the root filesystem and where they are mounted
+pub const BTRFS_SUBVOLUMES: [(&str, &str); 3] =
+    [("@", "/"), ("@home", "/home"), ("@snapshots", "/.snapshots")];
+
+/// Subvolume for VM disk images and container data, created with
+/// virtualbox or docker. Its files are nodatacow (chattr +C): btrfs applies
+/// the nodatacow mount option to the whole filesystem, not per subvolume.
+pub const BTRFS_VM_SUBVOLUME: (&str, &str) = ("@vmimages", "/var/lib/vmimages");
+
+/// Mount options for btrfs subvolumes
+const BTRFS_MOUNT_OPTIONS: &str = "compress=zstd,noatime";
+
+/// luksFormat flags for the [disk.encryption] header version, cipher and
+/// keyslot settings; unset values are left to cryptsetup
+fn luks_format_opts(enc: &EncryptionConfig) -> String {
+    let mut opts = format!("--type luks{} ", enc.luks_version);
+    if !enc.cipher.is_empty() {
+        opts.push_str(&format!("--cipher {} ", enc.cipher));
+    }
+    if enc.key_size != 0 {
+        opts.push_str(&format!("--key-size {} ", enc.key_size));
+    }
+    // GRUB can only unlock LUKS2 keyslots that use PBKDF2, not argon2;
+    // LUKS1 has no other key derivation
+    let pbkdf = if enc.encrypt_boot || enc.luks_version == 1 {
+        "pbkdf2"
+    } else {
+        enc.pbkdf.as_str()
+    };
+    if !pbkdf.is_empty() {
+        opts.push_str(&format!("--pbkdf {pbkdf} "));
+    }
+    if enc.iter_time != 0 {
+        opts.push_str(&format!("--iter-time {} ", enc.iter_time));
+    }
+    if enc.pbkdf_memory != 0 {
+        opts.push_str(&format!("--pbkdf-memory {} ", enc.pbkdf_memory));
+    }
+    if enc.pbkdf_iterations != 0 {
+        opts.push_str(&format!("--pbkdf-force-iterations {} ", enc.pbkdf_iterations));
+    }
+    opts
+}
+
+/// Cipher and keyslot parameters cryptsetup actually used for `device`,
+/// from the LUKS2 JSON metadata (Null if the header is not on the device)
+pub fn luks_parameters(device: &str) -> serde_json::Value {
+    let dump = exec(&format!("cryptsetup luksDump --dump-json-metadata {device} 2>/dev/null"));
+    let Ok(meta) = serde_json::from_str::<serde_json::Value>(&dump) else {
+        return serde_json::Value::Null;
+    };
+    let keyslot = &meta["keyslots"]["0"];
+    let kdf = &keyslot["kdf"];
+    serde_json::json!({
+        "cipher": meta["segments"]["0"]["encryption"],
+        "key_size": keyslot["key_size"].as_u64().map(|bytes| bytes * 8),
+        "pbkdf": kdf["type"],
+        "iterations": kdf.get("iterations").unwrap_or(&kdf["time"]),
+        "memory_kib": kdf["memory"],
+        "parallel": kdf["cpus"],
+    })
+}
+
+/// cryptsetup open flags letting TRIM through the LUKS mapping on SSDs.
+/// --persistent stores the flag in the LUKS2 header, so every later unlock
+/// (encrypt/sd-encrypt hooks, crypttab) keeps it without extra options.
+/// LUKS1 has no such flag; the installed system passes it at every unlock.
+fn luks_discard_opt(layout: &PartitionLayout, enc: &EncryptionConfig) -> &'static str {
+    match (layout.ssd, enc.luks_version) {
+        (false, _) => "",
+        (true, 1) => "--allow-discards ",
+        (true, _) => "--allow-discards --persistent ",
+    }
+}
+
+/// Extra mount options for a filesystem on an SSD target. Periodic fstrim
+/// covers TRIM, except on btrfs whose async discard is cheap.
+fn ssd_mount_options(layout: &PartitionLayout, fs_type: &str) -> &'static str {
+    match fs_type {
+        _ if !layout.ssd => "",
+        "btrfs" => "discard=async",
+        "ext4" | "xfs" | "f2fs" => "noatime",
+        _ => "",
+    }
+}
+
+/// ext4 commits its journal every 5 s by default; once a minute spares
+/// eMMC and SD cards most of those small writes
+fn flash_mount_options(layout: &PartitionLayout, fs_type: &str) -> &'static str {
+    match fs_type {
+        "ext4" if layout.flash => "commit=60",
+        _ => "",
+    }
+}
+
+/// `-o ...` argument of mount for `base` plus the SSD and flash options of
+/// `fs_type` (empty if there are none); genfstab copies them into fstab
+fn mount_opts(layout: &PartitionLayout, fs_type: &str, base: &str) -> String {
+    let opts: Vec<&str> =
+        [base, ssd_mount_options(layout, fs_type), flash_mount_options(layout, fs_type)]
+        .into_iter()
+        .filter(|o| !o.is_empty())
+        .collect();
+    if opts.is_empty() {
+        String::new()
+    } else {
+        format!("-o {} ", opts.join(","))
+    }
+}
+
+/// Create the root filesystem on `device` (ext4/xfs/f2fs, or btrfs with
+/// its subvolumes) and label it
+fn mkfs_root(device: &str, cfg: &Config, label: &str) -> bool {
+    let filesystem = cfg.disk.filesystem;
+    let fs_type = filesystem.fs_type();
+    if !mkfs_command(fs_type, device).is_some_and(|c| run_cmd(&c)) {
+        return false;
+    }
+    set_fs_label(fs_type, device, label);
+    if filesystem != Filesystem::Btrfs {
+        return true;
+    }
+    run_cmd(&format!("mkdir -p {PROBE_MOUNT}"));
+    if !run_cmd(&format!("mount {device} {PROBE_MOUNT}")) {
+        return false;
+    }
+    let mut created = BTRFS_SUBVOLUMES
+        .iter()
+        .all(|(subvol, _)| run_cmd(&format!("btrfs subvolume create {PROBE_MOUNT}/{subvol}")));
+    if created && cfg.btrfs_vm_subvolume() {
+        let (subvol, _) = BTRFS_VM_SUBVOLUME;
+        created = run_cmd(&format!("btrfs subvolume create {PROBE_MOUNT}/{subvol}"))
+            && run_cmd(&format!("chattr +C {PROBE_MOUNT}/{subvol}"));
+    }
+    run_cmd(&format!("umount {PROBE_MOUNT}"));
+    created
+}
+
+/// Create the volume group on `pv` with swap, root and (optionally) home
+/// logical volumes, and format them
+fn setup_lvm(pv: &str, cfg: &Config) -> bool {
+    let lvm = &cfg.disk.lvm;
+    tui::print_info(&format!("Creating LVM volume group {LVM_VG} on {pv}..."));
+
+    if !run_cmd(&format!("pvcreate -ff -y {pv}")) || !run_cmd(&format!("vgcreate {LVM_VG} {pv}"))
+    {
+        tui::print_error("Failed to create LVM volume group");
+        return false;
+    }
+
+    // Swap first so a "100%FREE" root or home does not leave it without space
+    let swap_mb = match swap_size_mib(&cfg.disk.swap) {
+        Ok(mb) => mb,
+        Err(e) => {
+            tui::print_error(&format!("Cannot size the swap volume: {e}"));
+            return false;
+        }
+    };
+    if swap_mb > 0 {
+        if !run_cmd(&format!("lvcreate -y -L {swap_mb}M -n swap {LVM_VG}")) {
+            tui::print_error("Failed to create swap logical volume");
+            return false;
+        }
+        run_cmd(&format!("mkswap -L BLX-SWAP /dev/{LVM_VG}/swap"));
+    }
+
+    let root_size = if lvm.root_size.is_empty() {
+        "-l 100%FREE".to_string()
+    } else {
+        format!("-L {}", lvm.root_size)
+    };
+    if !run_cmd(&format!("lvcreate -y {root_size} -n root {LVM_VG}"))
+        || !run_cmd(&format!("mkfs.ext4 -F -L '{}' {LVM_ROOT}", root_fs_label(cfg)))
+    {
+        tui::print_error("Failed to create root logical volume");
+        return false;
+    }
+
+    if lvm.home
+        && (!run_cmd(&format!("lvcreate -y -l 100%FREE -n home {LVM_VG}"))
+            || !run_cmd(&format!(
+                "mkfs.ext4 -F -L '{}' /dev/{LVM_VG}/home",
+                home_fs_label(cfg, "ext4")
+            )))
+    {
+        tui::print_error("Failed to create home logical volume");
+        return false;
+    }
+
+    tui::print_success("LVM volumes created");
+    true
+}
+
+/// Generate ROOT_KEYFILE and add it as a second keyslot of the root container
+fn enroll_root_keyfile(device: &str, header_opt: &str, password: &str) -> bool {
+    let _ = fs::remove_file(ROOT_KEYFILE);
+    run_cmd(&format!(
+        "(umask 077 && dd bs=512 count=4 if=/dev/random of={ROOT_KEYFILE} iflag=fullblock) 2>/dev/null"
+    )) && run_cmd(&format!(
+        "echo -n '{password}' | cryptsetup luksAddKey {header_opt}--key-file=- {device} {ROOT_KEYFILE}"
+    ))
+}
+
+/// A TPM 2.0 chip is present (systemd-cryptenroll cannot use 1.2)
+pub fn has_tpm2() -> bool {
+    fs::read_to_string("/sys/class/tpm/tpm0/tpm_version_major")
+        .is_ok_and(|v| v.trim() == "2")
+}
+
+/// Add a TPM2 token bound to PCR 7 (Secure Boot state) to the root container
+fn enroll_tpm2(device: &str, password: &str) -> bool {
+    run_cmd(&format!(
+        "PASSWORD='{password}' systemd-cryptenroll --tpm2-device=auto --tpm2-pcrs=7 {device}"
+    ))
+}
+
+/// Mount the device holding the detached LUKS header and return the header path
+fn mount_header_device(device: &str) -> Option<String> {
+    if !Path::new(device).exists() {
+        tui::print_error(&format!("Detached header device not found: {device}"));
+        return None;
+    }
+
+    run_cmd(&format!("mkdir -p {KEYS_MOUNT}"));
+    let already_mounted = run_cmd(&format!("findmnt -n {KEYS_MOUNT} >/dev/null 2>&1"));
+    if !already_mounted && !run_cmd(&format!("mount {device} {KEYS_MOUNT}")) {
+        tui::print_error(&format!("Failed to mount detached header device {device}"));
+        return None;
+    }
+
+    let header = format!("{KEYS_MOUNT}/{DETACHED_HEADER_FILE}");
+    if Path::new(&header).exists() {
+        // Keep the previous header instead of overwriting it; it may still guard data
+        run_cmd(&format!("mv -f {header} {header}.old"));
+    }
+
+    tui::print_warning(&format!(
+        "LUKS header will be stored on {device} - without it the disk cannot be unlocked!"
+    ));
+    Some(header)
+}
+
+/// Format root as bcachefs with zstd compression and optional native encryption
+fn format_bcachefs(partition: &str, use_encryption: bool, encryption_password: &str) -> bool {
+    if use_encryption {
+        tui::print_info("Formatting root partition (bcachefs, encrypted)...");
+
+        // bcachefs reads the passphrase (and its confirmation) from stdin
+        let cmd = format!(
+            "printf '%s\\n%s\\n' '{pw}' '{pw}' | bcachefs format -f --compression=zstd --encrypted {partition}",
+            pw = encryption_password
+        );
+        if !run_cmd(&cmd) {
+            tui::print_error("Failed to format encrypted bcachefs partition");
+            return false;
+        }
+
+        // Load the key into the session keyring so the filesystem can be mounted
+        let cmd = format!(
+            "echo '{encryption_password}' | bcachefs unlock -k session {partition}"
+        );
+        if !run_cmd(&cmd) {
+            tui::print_error("Failed to unlock bcachefs partition");
+            return false;
+        }
+    } else {
+        tui::print_info("Formatting root partition (bcachefs)...");
+        if !run_cmd(&format!(
+            "bcachefs format -f --compression=zstd {partition}"
+        )) {
+            tui::print_error("Failed to format bcachefs partition");
+            return false;
+        }
+    }
+
+    tui::print_success("Formatting complete");
+    true
+}
+
+/// Make the ZFS kernel module available in the live environment.
+/// Adds the archzfs repository to the host pacman.conf (pacstrap reuses it)
+/// and installs zfs-dkms/zfs-utils if the module is not already loadable.
+pub fn prepare_zfs_live_env() -> bool {
+    // Even with a loadable module, pacstrap needs the repository for zfs-utils
+    if let Err(e) = repos::add("", &repos::ARCHZFS) {
+        tui::print_error(&e);
+        return false;
+    }
+    if run_cmd("modprobe zfs 2>/dev/null") {
+        return true;
+    }
+
+    tui::print_info("Installing ZFS support into the live environment...");
+
+    if !run_cmd("pacman -Sy --noconfirm --needed linux-headers zfs-dkms zfs-utils") {
+        tui::print_error("Failed to install zfs-dkms/zfs-utils in the live environment");
+        return false;
+    }
+
+    if !run_cmd("modprobe zfs") {
+        tui::print_error("ZFS kernel module could not be loaded");
+        return false;
+    }
+
+    tui::print_success("ZFS support ready");
+    true
+}
+
+/// Create the root pool and its datasets, imported with altroot /mnt
+fn create_zfs_pool(partition: &str, use_encryption: bool, encryption_password: &str) -> bool {
+    tui::print_info(&format!("Creating ZFS pool {ZFS_POOL}..."));
+
+    // Pool properties recommended for Linux root pools
+    let mut opts = String::from(
+        "-o ashift=12 -o autotrim=on \
+         -O acltype=posixacl -O xattr=sa -O relatime=on -O compression=lz4 \
+         -O dnodesize=auto -O normalization=formD -O mountpoint=none -O canmount=off",
+    );
+    let mut cmd_prefix = String::new();
+    if use_encryption {
+        opts.push_str(" -O encryption=aes-256-gcm -O keyformat=passphrase -O keylocation=prompt");
+        cmd_prefix = format!("echo '{encryption_password}' | ");
+    }
+
+    run_cmd("mkdir -p /etc/zfs");
+    if !run_cmd(&format!(
+        "{cmd_prefix}zpool create -f {opts} -R /mnt {ZFS_POOL} {partition}"
+    )) {
+        tui::print_error("Failed to create ZFS pool");
+        return false;
+    }
+
+    let datasets = [
+        ("zroot/ROOT", "-o canmount=off -o mountpoint=none"),
+        (ZFS_ROOT_DATASET, "-o canmount=noauto -o mountpoint=/"),
+        ("zroot/home", "-o mountpoint=/home"),
+        ("zroot/var", "-o canmount=off -o mountpoint=/var"),
+        ("zroot/var/log", ""),
+        ("zroot/var/cache", "-o com.sun:auto-snapshot=false"),
+    ];
+    for (name, props) in &datasets {
+        if !run_cmd(&format!("zfs create {props} {name}")) {
+            tui::print_error(&format!("Failed to create dataset {name}"));
+            return false;
+        }
+    }
+
+    run_cmd(&format!("zpool set bootfs={ZFS_ROOT_DATASET} {ZFS_POOL}"));
+    run_cmd(&format!("zpool set cachefile=/etc/zfs/zpool.cache {ZFS_POOL}"));
+
+    // canmount=noauto datasets are not mounted on creation
+    if !run_cmd(&format!("zfs mount {ZFS_ROOT_DATASET}")) || !run_cmd("zfs mount -a") {
+        tui::print_error("Failed to mount ZFS datasets");
+        return false;
+    }
+
+    tui::print_success("ZFS pool and datasets created");
+    true
+}
+
+/// Mount partitions for installation
+pub fn mount_partitions(layout: &PartitionLayout, mount_point: &str) -> bool {
+    run_cmd(&format!("mkdir -p {mount_point}"));
+
+    // Mount root partition
+    let root_dev = if Path::new(LVM_ROOT).exists() {
+        LVM_ROOT.to_string()
+    } else if Path::new("/dev/mapper/cryptroot").exists() {
+        "/dev/mapper/cryptroot".to_string()
+    } else {
+        layout.root_partition.clone()
+    };
+
+    // A ZFS root is already mounted under the pool altroot
+    let zfs_root_mounted = run_cmd(&format!(
+        "findmnt -n -t zfs {mount_point} >/dev/null 2>&1"
+    ));
+    let btrfs = probe::fs_type(&root_dev) == "btrfs";
+    if !zfs_root_mounted {
+        tui::print_info("Mounting root partition...");
+        let opts = if btrfs {
+            mount_opts(layout, "btrfs", &format!("subvol=@,{BTRFS_MOUNT_OPTIONS}"))
+        } else {
+            mount_opts(layout, &probe::fs_type(&root_dev), "")
+        };
+        if !run_cmd(&format!("mount {opts}{root_dev} {mount_point}")) {
+            tui::print_error("Failed to mount root partition");
+            return false;
+        }
+    }
+    if btrfs {
+        // A separate /home partition takes the place of @home
+        let separate_home = layout.data_partitions.iter().any(|p| p.mountpoint == "/home");
+        for (subvol, target) in BTRFS_SUBVOLUMES.iter().skip(1) {
+            if *target == "/home" && separate_home {
+                continue;
+            }
+            run_cmd(&format!("mkdir -p {mount_point}{target}"));
+            let base = format!("subvol={subvol},{BTRFS_MOUNT_OPTIONS}");
+            let opts = mount_opts(layout, "btrfs", &base);
+            if !run_cmd(&format!("mount {opts}{root_dev} {mount_point}{target}")) {
+                tui::print_error(&format!("Failed to mount btrfs subvolume {subvol}"));
+                return false;
+            }
+        }
+
+        // Only there when mkfs_root created it
+        let (subvol, target) = BTRFS_VM_SUBVOLUME;
+        let subvolumes = exec(&format!("btrfs subvolume list {mount_point}"));
+        if subvolumes.lines().any(|l| l.ends_with(&format!(" path {subvol}"))) {
+            run_cmd(&format!("mkdir -p {mount_point}{target}"));
+            let base = format!("subvol={subvol},{BTRFS_MOUNT_OPTIONS}");
+            let opts = mount_opts(layout, "btrfs", &base);
+            if !run_cmd(&format!("mount {opts}{root_dev} {mount_point}{target}")) {
+                tui::print_error(&format!("Failed to mount btrfs subvolume {subvol}"));
+                return false;
+            }
+        }
+    }
+
+    // Custom layout partitions, parents before children (/boot before
+    // /boot/efi); swap is activated so genfstab picks it up
+    let mut data: Vec<&DataPartition> = layout.data_partitions.iter().collect();
+    data.sort_by_key(|p| p.mountpoint.matches('/').count());
+    for part in data {
+        if part.filesystem == "swap" {
+            run_cmd(&format!("swapon {}", part.device));
+            continue;
+        }
+        run_cmd(&format!("mkdir -p {mount_point}{}", part.mountpoint));
+        let opts = mount_opts(layout, &part.filesystem, "");
+        if !run_cmd(&format!(
+            "mount {opts}{} {mount_point}{}",
+            part.fs_device(),
+            part.mountpoint
+        )) {
+            tui::print_error(&format!("Failed to mount {} at {}", part.device, part.mountpoint));
+            return false;
+        }
+    }
+
+    // LVM home and swap volumes (swapon so genfstab picks the swap up)
+    let lvm_home = format!("/dev/{LVM_VG}/home");
+    if Path::new(&lvm_home).exists() {
+        run_cmd(&format!("mkdir -p {mount_point}/home"));
+        let opts = mount_opts(layout, "ext4", "");
+        if !run_cmd(&format!("mount {opts}{lvm_home} {mount_point}/home")) {
+            tui::print_error("Failed to mount home volume");
+            return false;
+        }
+    }
+    let lvm_swap = format!("/dev/{LVM_VG}/swap");
+    if Path::new(&lvm_swap).exists() {
+        run_cmd(&format!("swapon {lvm_swap}"));
+    }
+
+    // Mount EFI partition if UEFI
+    if layout.scheme == PartitionScheme::GptUefi {
+        tui::print_info("Mounting EFI partition...");
+        run_cmd(&format!("mkdir -p {mount_point}/boot/efi"));
+        if !run_cmd(&format!(
+            "mount {} {mount_point}/boot/efi",
+            layout.efi_partition
+        )) {
+            tui::print_error("Failed to mount EFI partition");
+            return false;
+        }
+    }
+
+    tui::print_success("Partitions mounted");
+    true
+}
+
+/// Unmount partitions
 pub fn unmount_partitions(mount_point: &str) -> bool {
     run_cmd(&format!("umount -R {mount_point} 2>/dev/null"));
+    run_cmd(&format!("swapoff /dev/{LVM_VG}/swap 2>/dev/null"));
+    run_cmd(&format!("vgchange -an {LVM_VG} 2>/dev/null"));
     run_cmd("cryptsetup close cryptroot 2>/dev/null");
+    run_cmd(&format!("umount {KEYS_MOUNT} 2>/dev/null"));
+    run_cmd(&format!("zfs umount -a 2>/dev/null; zpool export {ZFS_POOL} 2>/dev/null"));
     true
 }
 
+/// Create a swap file of `size_mb` MiB at `path`, calling `progress` with
+/// the MiB allocated so far. Swap files must not be sparse: the blocks are
+/// reserved with fallocate (ext4, and XFS since 4.18, accept that for swap),
+/// or written as zeros where the filesystem cannot preallocate.
+pub fn write_swap_file(
+    path: &str,
+    size_mb: u64,
+    mut progress: impl FnMut(u64),
+) -> std::io::Result<()> {
+    use nix::errno::Errno;
+    use nix::fcntl::{fallocate, FallocateFlags};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::PermissionsExt;
+
+    const MIB: i64 = 1024 * 1024;
+    // fallocate is fast, but a few GiB still take a moment on slow disks
+    const STEP_MIB: u64 = 1024;
+
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    let mut done = 0;
+    while done < size_mb {
+        let len = STEP_MIB.min(size_mb - done);
+        let (offset, len_bytes) = (done as i64 * MIB, len as i64 * MIB);
+        match fallocate(file.as_raw_fd(), FallocateFlags::empty(), offset, len_bytes) {
+            Ok(()) => {
+                done += len;
+                progress(done);
+            }
+            Err(Errno::EOPNOTSUPP) if done == 0 => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if done == size_mb {
+        return file.sync_all();
+    }
+
+    let chunk = vec![0u8; 1024 * 1024];
+    for written in 1..=size_mb {
+        file.write_all(&chunk)?;
+        // Redrawing every MiB would flood a serial console
+        if written % 64 == 0 || written == size_mb {
+            progress(written);
+        }
+    }
+    file.sync_all()
+}
+
+/// Label of the ESP created by the installer, [disk.labels] esp or BLX-EFI
+fn esp_fs_label(cfg: &Config) -> String {
+    let label = &cfg.disk.labels.esp;
+    fs_label(if label.is_empty() { "BLX-EFI" } else { label }, "vfat")
+}
+
+/// Root filesystem label, [disk.labels] root or BLX-ROOT-<hostname>, cut
+/// to the filesystem's limit
+pub fn root_fs_label(cfg: &Config) -> String {
+    let label = match cfg.disk.labels.root.as_str() {
+        "" => format!("BLX-ROOT-{}", cfg.install.hostname),
+        label => label.to_string(),
+    };
+    fs_label(&label, cfg.disk.filesystem.fs_type())
+}
+
+/// Label of the /home filesystem, [disk.labels] home or BLX-HOME
+fn home_fs_label(cfg: &Config, fs_type: &str) -> String {
+    let label = &cfg.disk.labels.home;
+    fs_label(if label.is_empty() { "BLX-HOME" } else { label }, fs_type)
+}
+
+/// Label of an installer-created data partition, e.g. BLX-HOME or BLX-SWAP
+fn data_fs_label(part: &DataPartition, cfg: &Config) -> String {
+    if part.mountpoint == "/home" {
+        return home_fs_label(cfg, &part.filesystem);
+    }
+    let name = if part.filesystem == "swap" {
+        "swap".to_string()
+    } else {
+        part.mountpoint.trim_start_matches('/').replace('/', "-")
+    };
+    fs_label(&format!("BLX-{}", name.to_uppercase()), &part.filesystem)
+}
+
+/// Cut a label to what `fs_type` can store (FAT labels are upper case)
+fn fs_label(label: &str, fs_type: &str) -> String {
+    let max = match fs_type {
+        "vfat" | "fat32" | "exfat" => 11,
+        "xfs" => 12,
+        "ext4" | "swap" => 16,
+        "ntfs" | "ntfs3" => 32,
+        _ => 64,
+    };
+    let label: String = label.chars().take(max).collect();
+    if matches!(fs_type, "vfat" | "fat32") {
+        label.to_uppercase()
+    } else {
+        label
+    }
+}
+
+/// Set the label of a freshly created filesystem; a failure only warns
+fn set_fs_label(fs_type: &str, device: &str, label: &str) {
+    let cmd = match fs_type {
+        "ext4" => format!("e2label {device} '{label}'"),
+        "xfs" => format!("xfs_admin -L '{label}' {device}"),
+        "btrfs" => format!("btrfs filesystem label {device} '{label}'"),
+        "f2fs" => format!("f2fslabel {device} '{label}'"),
+        "vfat" | "fat32" => format!("fatlabel {device} '{label}'"),
+        "exfat" => format!("exfatlabel {device} '{label}'"),
+        "ntfs" | "ntfs3" => format!("ntfslabel {device} '{label}'"),
+        "swap" => format!("swaplabel -L '{label}' {device}"),
+        _ => return,
+    };
+    if !run_cmd(&format!("{cmd} >/dev/null 2>&1")) {
+        tui::print_warning(&format!("Could not label {device} as {label}"));
+    }
+}
+
+/// Give a freshly created GPT a disk GUID derived from `seed` (the hostname,
+/// plus the disk's role), so a machine's config always produces the same GUID
+fn set_disk_guid(disk: &str, seed: &str) {
+    let hash = exec(&format!("printf '%s' 'blunux:{seed}' | sha256sum"));
+    let hex: Vec<char> = hash.chars().take(32).collect();
+    if hex.len() < 32 || !hex.iter().all(|c| c.is_ascii_hexdigit()) {
+        return;
+    }
+    let part = |range: std::ops::Range<usize>| hex[range].iter().collect::<String>();
+    // RFC 9562 version 8 (custom) with the RFC variant bits
+    let variant = u8::from_str_radix(&part(16..17), 16).unwrap_or(0) & 0x3 | 0x8;
+    let guid = format!(
+        "{}-{}-8{}-{variant:x}{}-{}",
+        part(0..8),
+        part(8..12),
+        part(13..16),
+        part(17..20),
+        part(20..32)
+    );
+    if let Err(e) = table::set_disk_guid(disk, &guid) {
+        tui::print_warning(&format!("Could not set the disk GUID of {disk}: {e}"));
+    }
+}
+
 /// Generate fstab
-pub fn generate_fstab(mount_point: &str) -> bool {
+pub fn generate_fstab(mount_point: &str, by_label: bool) -> bool {
     tui::print_info("Generating fstab...");
+    let tag = if by_label { "-L" } else { "-U" };
+    // ZFS datasets are mounted by zfs-mount.service, not fstab; genfstab
+    // also lists the active live swap file, which is gone after the install
     run_cmd(&format!(
-        "genfstab -U {mount_point} >> {mount_point}/etc/fstab"
+        "genfstab {tag} {mount_point} | awk '$3 != \"zfs\" && $1 != \"/{LIVE_SWAP_FILE}\"' \
+         >> {mount_point}/etc/fstab"
     ))
 }
 
-/// Get total system RAM in MB
-pub fn get_ram_mb() -> u64 {
-    let output = exec("free -m | awk '/^Mem:/ {print $2}'");
-    output.trim().parse::<u64>().unwrap_or(4096)
+/// mkfs invocation for a filesystem name, or None if unsupported
+pub fn mkfs_command(fs_type: &str, device: &str) -> Option<String> {
+    let cmd = match fs_type {
+        "ext4" => format!("mkfs.ext4 -F {device}"),
+        "xfs" => format!("mkfs.xfs -f {device}"),
+        "btrfs" => format!("mkfs.btrfs -f {device}"),
+        "f2fs" => format!("mkfs.f2fs -f {device}"),
+        "vfat" | "fat32" => format!("mkfs.fat -F32 {device}"),
+        "exfat" => format!("mkfs.exfat {device}"),
+        "ntfs" | "ntfs3" => format!("mkfs.ntfs -Q -F {device}"),
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+/// Userspace tools package needed to maintain a filesystem in the target
+pub fn fs_tools_package(fs_type: &str) -> Option<&'static str> {
+    match fs_type {
+        "xfs" => Some("xfsprogs"),
+        "f2fs" => Some("f2fs-tools"),
+        "exfat" => Some("exfatprogs"),
+        "btrfs" => Some("btrfs-progs"),
+        "vfat" | "fat32" => Some("dosfstools"),
+        "ntfs" | "ntfs3" => Some("ntfs-3g"),
+        _ => None,
+    }
+}
+
+/// fstab type for a [[disk.extra]] filesystem name: the in-kernel NTFS
+/// driver, and vfat for FAT32
+fn fstab_type(filesystem: &str) -> &str {
+    match filesystem {
+        "ntfs" => "ntfs3",
+        "fat32" => "vfat",
+        fs => fs,
+    }
+}
+
+/// Format (if requested) extra data disks and add them to the target fstab
+pub fn setup_extra_disks(extra: &[ExtraDisk], mount_point: &str) -> bool {
+    let fstab_path = format!("{mount_point}/etc/fstab");
+    let mut fstab = String::new();
+
+    for disk in extra {
+        if !Path::new(&disk.device).exists() {
+            tui::print_error(&format!("Extra disk not found: {}", disk.device));
+            return false;
+        }
+
+        if disk.format {
+            let Some(mkfs) = mkfs_command(&disk.filesystem, &disk.device) else {
+                tui::print_error(&format!(
+                    "Unsupported filesystem for {}: {}",
+                    disk.device, disk.filesystem
+                ));
+                return false;
+            };
+            tui::print_info(&format!(
+                "Formatting {} as {}...",
+                disk.device, disk.filesystem
+            ));
+            run_cmd(&format!("umount -f {} 2>/dev/null", disk.device));
+            if !run_cmd(&mkfs) {
+                tui::print_error(&format!("Failed to format {}", disk.device));
+                return false;
+            }
+        }
+
+        let uuid = probe::uuid(&disk.device);
+        if uuid.is_empty() {
+            tui::print_error(&format!(
+                "{} has no filesystem (set format = true to create one)",
+                disk.device
+            ));
+            return false;
+        }
+
+        let fs_type = fstab_type(&disk.filesystem);
+        run_cmd(&format!("mkdir -p {mount_point}{}", disk.mountpoint));
+        fstab.push_str(&format!(
+            "UUID={uuid}  {}  {fs_type}  {}  0 2\n",
+            disk.mountpoint, disk.options
+        ));
+        tui::print_success(&format!("{} -> {}", disk.device, disk.mountpoint));
+    }
+
+    if fstab.is_empty() {
+        return true;
+    }
+
+    OpenOptions::new()
+        .append(true)
+        .open(&fstab_path)
+        .and_then(|mut f| f.write_all(format!("\n# Extra data disks\n{fstab}").as_bytes()))
+        .is_ok()
+}
+
+/// MemTotal of a /proc/meminfo dump, in MB
+fn parse_mem_total_mb(meminfo: &str) -> Result<u64, String> {
+    let line = meminfo
+        .lines()
+        .find(|l| l.starts_with("MemTotal:"))
+        .ok_or("/proc/meminfo has no MemTotal line")?;
+    let mut fields = line.split_whitespace().skip(1);
+    let kb = fields
+        .next()
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| format!("Unreadable /proc/meminfo line: {line}"))?;
+    match fields.next() {
+        Some("kB") if kb > 0 => Ok(kb / 1024),
+        _ => Err(format!("Unexpected /proc/meminfo line: {line}")),
+    }
+}
+
+/// Total system RAM in MB, from /proc/meminfo
+pub fn ram_mb() -> Result<u64, String> {
+    let meminfo = fs::read_to_string("/proc/meminfo")
+        .map_err(|e| format!("Cannot read /proc/meminfo: {e}"))?;
+    parse_mem_total_mb(&meminfo)
+}
+
+/// Below this much RAM the live system gets swap while installing: pacstrap
+/// next to the tmpfs-backed live root can run it out of memory
+pub const LOW_RAM_MB: u64 = 4096;
+
+/// Swap file on the target used when zram is unavailable
+const LIVE_SWAP_FILE: &str = ".blunux-live-swap";
+const LIVE_SWAP_FILE_MB: u64 = 2048;
+
+/// Swap added to the live system for the duration of the install
+pub enum LiveSwap {
+    /// /dev/zramN, compressed in RAM
+    Zram(String),
+    /// Swap file on the mounted target root
+    File(String),
+}
+
+/// On low-RAM machines without swap, add swap to the live system: zram of
+/// half the RAM, or else a swap file on the target root (ext4/XFS only).
+/// None when it is not needed or could not be set up.
+pub fn enable_live_swap(mount_point: &str, cfg: &Config) -> Option<LiveSwap> {
+    let ram = ram_mb().ok()?;
+    let swap_kib: u64 = exec("awk 'NR > 1 { s += $3 } END { print s + 0 }' /proc/swaps")
+        .trim()
+        .parse()
+        .unwrap_or(0);
+    if ram >= LOW_RAM_MB || swap_kib / 1024 >= ram / 2 {
+        return None;
+    }
+    tui::print_info(&format!("Only {ram} MB of RAM - adding swap for the install"));
+
+    let zram = exec(&format!(
+        "modprobe zram 2>/dev/null; zramctl --find --size {}M --algorithm zstd 2>/dev/null",
+        ram / 2
+    ));
+    let zram = zram.trim();
+    if zram.starts_with("/dev/zram") {
+        if run_cmd(&format!("mkswap {zram} >/dev/null && swapon -p 100 {zram}")) {
+            tui::print_success(&format!("{} MB zram swap enabled ({zram})", ram / 2));
+            return Some(LiveSwap::Zram(zram.to_string()));
+        }
+        run_cmd(&format!("zramctl --reset {zram}"));
+    }
+
+    if !cfg.disk.filesystem.supports_swapfile() {
+        tui::print_warning("No zram and no swap file on this filesystem - continuing without swap");
+        return None;
+    }
+    let path = format!("{mount_point}/{LIVE_SWAP_FILE}");
+    let enabled = write_swap_file(&path, LIVE_SWAP_FILE_MB, |_| {}).is_ok()
+        && run_cmd(&format!("mkswap {path} >/dev/null && swapon {path}"));
+    if !enabled {
+        let _ = fs::remove_file(&path);
+        tui::print_warning("Could not add swap - continuing without it");
+        return None;
+    }
+    tui::print_success(&format!("{LIVE_SWAP_FILE_MB} MB swap file enabled on the target"));
+    Some(LiveSwap::File(path))
+}
+
+/// Undo `enable_live_swap`; must run before the target is unmounted
+pub fn disable_live_swap(swap: &LiveSwap) {
+    match swap {
+        LiveSwap::Zram(device) => {
+            run_cmd(&format!("swapoff {device} && zramctl --reset {device}"));
+        }
+        LiveSwap::File(path) => {
+            run_cmd(&format!("swapoff {path}"));
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_total_in_mb() {
+        let meminfo = "MemTotal:       16303992 kB\nMemFree:         1211604 kB\n";
+        assert_eq!(parse_mem_total_mb(meminfo), Ok(15921));
+    }
+
+    #[test]
+    fn mem_total_missing() {
+        let meminfo = "MemFree:         1211604 kB\nMemAvailable:    8303992 kB\n";
+        assert!(parse_mem_total_mb(meminfo).is_err());
+    }
+
+    #[test]
+    fn mem_total_unexpected_unit() {
+        assert!(parse_mem_total_mb("MemTotal:       16303992 MB\n").is_err());
+        assert!(parse_mem_total_mb("MemTotal:       16303992\n").is_err());
+    }
+
+    #[test]
+    fn mem_total_garbage() {
+        assert!(parse_mem_total_mb("MemTotal:       lots kB\n").is_err());
+        assert!(parse_mem_total_mb("MemTotal:       0 kB\n").is_err());
+        assert!(parse_mem_total_mb("").is_err());
+        assert!(parse_mem_total_mb("\u{0}\u{1}garbage").is_err());
+    }
+
+    #[test]
+    fn size_units() {
+        use crate::config::parse_size_mib;
+        assert_eq!(parse_size_mib("512M"), Some(512));
+        assert_eq!(parse_size_mib("50G"), Some(50 * 1024));
+        assert_eq!(parse_size_mib("2T"), Some(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn size_garbage() {
+        use crate::config::parse_size_mib;
+        assert_eq!(parse_size_mib("50\u{413}"), None);
+        assert_eq!(parse_size_mib("\u{413}"), None);
+        assert_eq!(parse_size_mib("99999999999999T"), None);
+        assert_eq!(parse_size_mib("18014398509481984G"), None);
+        assert_eq!(parse_size_mib("50"), None);
+        assert_eq!(parse_size_mib("G"), None);
+        assert_eq!(parse_size_mib(""), None);
+        assert_eq!(parse_size_mib("-5G"), None);
+    }
 }
@@ -0,0 +1,142 @@
+use super::probe;
+use crate::tui;
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// ATA attributes whose raw value counts damaged sectors; any non-zero
+/// value means the drive is already remapping or losing data
+const ATA_SECTOR_ATTRIBUTES: [(u64, &str); 4] = [
+    (5, "reallocated sectors"),
+    (187, "uncorrectable errors"),
+    (197, "sectors pending reallocation"),
+    (198, "offline uncorrectable sectors"),
+];
+
+/// Bytes read at each sample of `read_scan`
+const SAMPLE_BYTES: usize = 1 << 20;
+
+/// Even a slow USB hard disk reads 1 MiB in well under this
+const SLOW_READ: Duration = Duration::from_secs(1);
+
+/// Problems smartctl reports for `disk`; empty if it looks healthy or
+/// smartctl is missing or cannot read it (USB bridges, virtual disks)
+pub fn smart_warnings(disk: &str) -> Vec<String> {
+    // The exit status is a bit mask that is non-zero for healthy disks too
+    let Ok(output) = Command::new("smartctl").args(["-j", "-H", "-A", disk]).output() else {
+        return Vec::new();
+    };
+    let Ok(report) = serde_json::from_slice::<Value>(&output.stdout) else {
+        return Vec::new();
+    };
+
+    let mut warnings = Vec::new();
+    if report["smart_status"]["passed"] == Value::Bool(false) {
+        warnings.push("S.M.A.R.T. overall health check FAILED".to_string());
+    }
+
+    let table = report["ata_smart_attributes"]["table"].as_array();
+    for attr in table.into_iter().flatten() {
+        let id = attr["id"].as_u64().unwrap_or(0);
+        let raw = attr["raw"]["value"].as_u64().unwrap_or(0);
+        if let Some((_, what)) = ATA_SECTOR_ATTRIBUTES.iter().find(|(a, _)| *a == id) {
+            if raw > 0 {
+                warnings.push(format!("{raw} {what}"));
+            }
+        }
+    }
+
+    let nvme = &report["nvme_smart_health_information_log"];
+    if nvme.is_object() {
+        let field = |name: &str| nvme[name].as_u64().unwrap_or(0);
+        if field("critical_warning") != 0 {
+            warnings.push(format!("NVMe critical warning 0x{:02x}", field("critical_warning")));
+        }
+        if field("media_errors") > 0 {
+            warnings.push(format!("{} NVMe media errors", field("media_errors")));
+        }
+        if field("percentage_used") >= 100 {
+            warnings.push(format!("{}% of rated endurance used", field("percentage_used")));
+        }
+        if field("available_spare") < field("available_spare_threshold") {
+            warnings.push(format!("only {}% spare blocks left", field("available_spare")));
+        }
+    }
+    warnings
+}
+
+/// Read `samples` evenly spaced 1 MiB blocks across `disk`, bypassing the
+/// page cache. A disk with bad sectors fails some of them, or takes
+/// seconds per block while the drive retries; returns how many were slow.
+pub fn read_scan(disk: &str, samples: u64) -> Result<u64, String> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(disk)
+        .map_err(|e| format!("{disk}: {e}"))?;
+    // SAMPLE_BYTES is 1 MiB, so blocks are MiB
+    let blocks = probe::size_mib(disk);
+    if blocks == 0 {
+        return Ok(0);
+    }
+    let samples = samples.min(blocks);
+
+    // O_DIRECT needs a buffer aligned to the logical block size
+    let mut buf = vec![0u8; SAMPLE_BYTES + 4096];
+    let offset = buf.as_ptr().align_offset(4096);
+    let buf = &mut buf[offset..offset + SAMPLE_BYTES];
+
+    let mut failed = Vec::new();
+    let mut slow = 0;
+    for i in 0..samples {
+        // Spread over the disk, including the last block
+        let block = if samples > 1 { i * (blocks - 1) / (samples - 1) } else { 0 };
+        let start = Instant::now();
+        if file.read_exact_at(buf, block * SAMPLE_BYTES as u64).is_err() {
+            failed.push(block);
+        } else if start.elapsed() > SLOW_READ {
+            slow += 1;
+        }
+        tui::print_progress(&format!("Read scan of {disk}"), i + 1, samples);
+    }
+
+    match failed.as_slice() {
+        [] => Ok(slow),
+        [first, ..] => Err(format!(
+            "{disk}: {} of {samples} sampled blocks could not be read (first at {first} MiB)",
+            failed.len()
+        )),
+    }
+}
+
+/// Sequential write speed of the filesystem mounted at `dir` in MB/s:
+/// 64 MiB written and synced. The data is pseudo-random so compressing
+/// filesystems (btrfs, ZFS) cannot skip it.
+pub fn write_speed_mbps(dir: &str) -> Result<f64, String> {
+    const CHUNKS: usize = 64;
+    let path = format!("{dir}/.blunux-write-test");
+    let mut buf = vec![0u8; SAMPLE_BYTES];
+    let mut x: u64 = 0x9E37_79B9_7F4A_7C15;
+    for chunk in buf.chunks_exact_mut(8) {
+        // xorshift64
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        chunk.copy_from_slice(&x.to_le_bytes());
+    }
+
+    let start = Instant::now();
+    let result = fs::File::create(&path).and_then(|mut file| {
+        for _ in 0..CHUNKS {
+            file.write_all(&buf)?;
+        }
+        file.sync_all()
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+    let _ = fs::remove_file(&path);
+    result.map_err(|e| format!("Write test on {dir}: {e}"))?;
+    Ok((CHUNKS * SAMPLE_BYTES) as f64 / 1e6 / elapsed.max(1e-3))
+}
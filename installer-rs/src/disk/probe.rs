@@ -0,0 +1,213 @@
+use serde::{Deserialize, Deserializer};
+use std::process::Command;
+
+/// Columns requested from lsblk; each is a field of [`BlockDevice`]
+const LSBLK_COLUMNS: &str = "PATH,TYPE,SIZE,MODEL,SERIAL,WWN,TRAN,ROTA,RM,FSTYPE,PARTTYPE";
+
+/// One node of the lsblk tree
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDevice {
+    /// Device node, e.g. "/dev/nvme0n1p2"
+    pub path: String,
+    /// "disk", "part", "crypt", "lvm", "rom", "loop", ...
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Size in bytes
+    #[serde(deserialize_with = "bytes")]
+    pub size: u64,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    /// World Wide Name, e.g. "0x5002538e40a1b2c3" or "eui.0025385b71b0a1c2"
+    pub wwn: Option<String>,
+    /// Transport of whole disks: "nvme", "sata", "usb", "mmc", ...
+    pub tran: Option<String>,
+    /// Spinning disk (kernel rotational flag)
+    #[serde(default, deserialize_with = "flag")]
+    pub rota: bool,
+    /// Removable media, e.g. USB sticks and SD cards
+    #[serde(default, deserialize_with = "flag")]
+    pub rm: bool,
+    pub fstype: Option<String>,
+    pub parttype: Option<String>,
+    #[serde(default)]
+    pub children: Vec<BlockDevice>,
+}
+
+impl BlockDevice {
+    pub fn size_mib(&self) -> u64 {
+        self.size / (1024 * 1024)
+    }
+
+    /// Size for display, e.g. "476.9G"
+    pub fn size_human(&self) -> String {
+        const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+        let mut size = self.size as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{}B", self.size)
+        } else {
+            format!("{size:.1}{}", UNITS[unit])
+        }
+    }
+
+    /// This device and everything below it, depth first
+    pub fn walk(&self) -> Vec<&BlockDevice> {
+        let mut all = vec![self];
+        for child in &self.children {
+            all.extend(child.walk());
+        }
+        all
+    }
+}
+
+#[derive(Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<BlockDevice>,
+}
+
+/// util-linux before 2.33 prints sizes as strings even with -b
+fn bytes<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Number(u64),
+        Text(String),
+    }
+    Ok(match Size::deserialize(d)? {
+        Size::Number(n) => n,
+        Size::Text(s) => s.trim().parse().unwrap_or(0),
+    })
+}
+
+/// Booleans are "0"/"1" strings before util-linux 2.33 as well
+fn flag<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Text(String),
+    }
+    Ok(match Option::<Flag>::deserialize(d)? {
+        Some(Flag::Bool(b)) => b,
+        Some(Flag::Text(s)) => s.trim() == "1",
+        None => false,
+    })
+}
+
+/// lsblk tree of `device` (all devices when None); empty on any error
+pub fn lsblk(device: Option<&str>) -> Vec<BlockDevice> {
+    let mut cmd = Command::new("lsblk");
+    cmd.args(["-J", "-b", "-o", LSBLK_COLUMNS]);
+    if let Some(device) = device {
+        cmd.arg(device);
+    }
+    let Ok(output) = cmd.output() else {
+        return Vec::new();
+    };
+    serde_json::from_slice::<LsblkOutput>(&output.stdout)
+        .map(|o| o.blockdevices)
+        .unwrap_or_default()
+}
+
+/// Whole disks, in lsblk order
+pub fn disks() -> Vec<BlockDevice> {
+    lsblk(None)
+        .into_iter()
+        .filter(|d| d.kind == "disk" && !is_emmc_hw_partition(&d.path))
+        .collect()
+}
+
+/// eMMC hardware boot partitions (mmcblk0boot0/1) and the RPMB area: lsblk
+/// lists them as disks, but they are tiny, usually read-only and hold the
+/// SBC's firmware
+pub fn is_emmc_hw_partition(device: &str) -> bool {
+    device.starts_with("/dev/mmcblk") && (device.contains("boot") || device.ends_with("rpmb"))
+}
+
+/// Partitions of `disk`, in table order
+pub fn partitions(disk: &str) -> Vec<BlockDevice> {
+    lsblk(Some(disk))
+        .iter()
+        .flat_map(|d| d.walk())
+        .filter(|d| d.kind == "part")
+        .cloned()
+        .map(|mut d| {
+            d.children.clear();
+            d
+        })
+        .collect()
+}
+
+/// `device` and everything stacked on it (partitions, LUKS mappings, LVs)
+pub fn tree(device: &str) -> Option<BlockDevice> {
+    lsblk(Some(device)).into_iter().next()
+}
+
+/// Size of a block device in MiB (0 if unknown)
+pub fn size_mib(device: &str) -> u64 {
+    tree(device).map_or(0, |d| d.size_mib())
+}
+
+/// Identifiers blkid reads from a device's superblock and partition entry
+#[derive(Debug, Clone, Default)]
+pub struct BlkidInfo {
+    pub uuid: String,
+    pub partuuid: String,
+    /// Filesystem/container type, e.g. "ext4", "crypto_LUKS", "swap"
+    pub fs_type: String,
+    pub label: String,
+}
+
+/// Parse `blkid -o export` output (KEY=value lines, backslash escapes)
+fn parse_export(output: &str) -> BlkidInfo {
+    let mut info = BlkidInfo::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            unescaped.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+        }
+        match key {
+            "UUID" => info.uuid = unescaped,
+            "PARTUUID" => info.partuuid = unescaped,
+            "TYPE" => info.fs_type = unescaped,
+            "LABEL" => info.label = unescaped,
+            _ => {}
+        }
+    }
+    info
+}
+
+/// blkid identifiers of `device`; all fields empty when it has none
+pub fn blkid(device: &str) -> BlkidInfo {
+    Command::new("blkid")
+        .args(["-o", "export", device])
+        .output()
+        .map(|o| parse_export(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default()
+}
+
+/// Filesystem UUID of `device` (empty if none)
+pub fn uuid(device: &str) -> String {
+    blkid(device).uuid
+}
+
+/// Filesystem or container type of `device` (empty if none)
+pub fn fs_type(device: &str) -> String {
+    blkid(device).fs_type
+}
+
+/// Device carrying a UUID=, PARTUUID= or LABEL= tag, e.g. from an fstab
+pub fn find_by_tag(tag: &str) -> Option<String> {
+    let output = Command::new("blkid").args(["-o", "device", "-t", tag]).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let device = stdout.lines().next()?.trim();
+    (!device.is_empty()).then(|| device.to_string())
+}
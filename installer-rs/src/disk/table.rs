@@ -0,0 +1,637 @@
+use gptman::{GPTPartitionEntry, GPT};
+use mbrman::{MBRPartitionEntry, BOOT_ACTIVE, BOOT_INACTIVE, CHS, MBR};
+use nix::errno::Errno;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+
+const MIB: u64 = 1024 * 1024;
+
+/// GPT partition type GUIDs
+const GUID_LINUX_FS: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+const GUID_LINUX_SWAP: &str = "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F";
+const GUID_BASIC_DATA: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+const GUID_ESP: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+const GUID_BIOS_BOOT: &str = "21686148-6449-6E6F-744E-656564454649";
+
+/// GPT attribute bit 2: legacy BIOS bootable
+const GPT_LEGACY_BOOTABLE: u64 = 1 << 2;
+
+/// MBR partition type of a GPT protective MBR
+const MBR_PROTECTIVE: u8 = 0xee;
+
+/// Partition table type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Label {
+    Gpt,
+    Msdos,
+}
+
+/// Partition flags the installer sets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    /// EFI system partition (GPT)
+    Esp,
+    /// Active partition (MBR)
+    Boot,
+    /// GRUB core image partition on GPT/BIOS
+    BiosGrub,
+}
+
+/// A partition boundary
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pos {
+    /// MiB from the start of the disk
+    Mib(u64),
+    /// Percent of the disk
+    Percent(u8),
+    /// MiB back from the end of the disk
+    FromEnd(u64),
+    /// The end of the disk
+    End,
+}
+
+impl Pos {
+    /// Byte offset on a disk of `disk_bytes`; percentages and positions
+    /// from the end are rounded down to a 1 MiB boundary
+    fn offset(self, disk_bytes: u64) -> u64 {
+        let align = |bytes: u64| bytes / MIB * MIB;
+        match self {
+            Pos::Mib(mib) => mib * MIB,
+            Pos::Percent(p) => align(disk_bytes * u64::from(p.min(100)) / 100),
+            Pos::FromEnd(mib) => align(disk_bytes.saturating_sub(mib * MIB)),
+            Pos::End => disk_bytes,
+        }
+    }
+}
+
+impl fmt::Display for Pos {
+    /// parted-style notation for messages; negative positions count from the end
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pos::Mib(mib) => write!(f, "{mib}MiB"),
+            Pos::Percent(p) => write!(f, "{p}%"),
+            Pos::FromEnd(mib) => write!(f, "-{mib}MiB"),
+            Pos::End => write!(f, "100%"),
+        }
+    }
+}
+
+/// A partition as the on-disk table records it, in logical sectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    pub num: usize,
+    pub start: u64,
+    pub size: u64,
+}
+
+/// A partition table read from a disk
+enum Table {
+    Gpt(GPT),
+    Mbr(Box<MBR>),
+}
+
+/// GUID in its on-disk layout: the first three fields are little-endian
+fn guid_bytes(guid: &str) -> [u8; 16] {
+    let hex: Vec<u8> = guid
+        .split('-')
+        .enumerate()
+        .flat_map(|(i, field)| {
+            let mut bytes: Vec<u8> = (0..field.len())
+                .step_by(2)
+                .map(|j| u8::from_str_radix(&field[j..j + 2], 16).unwrap_or(0))
+                .collect();
+            if i < 3 {
+                bytes.reverse();
+            }
+            bytes
+        })
+        .collect();
+    let mut out = [0u8; 16];
+    out.copy_from_slice(&hex[..16]);
+    out
+}
+
+/// Random bytes for disk signatures and partition GUIDs
+fn random_bytes<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(|e| format!("/dev/urandom: {e}"))?;
+    Ok(bytes)
+}
+
+/// Random (version 4) GUID in its on-disk layout
+fn random_guid() -> Result<[u8; 16], String> {
+    let mut guid = random_bytes::<16>()?;
+    guid[7] = guid[7] & 0x0f | 0x40;
+    guid[8] = guid[8] & 0x3f | 0x80;
+    Ok(guid)
+}
+
+fn open(disk: &str) -> Result<File, String> {
+    OpenOptions::new().read(true).write(true).open(disk).map_err(|e| format!("{disk}: {e}"))
+}
+
+fn disk_bytes(file: &mut File, disk: &str) -> Result<u64, String> {
+    file.seek(SeekFrom::End(0)).map_err(|e| format!("{disk}: {e}"))
+}
+
+/// The table on `disk`: GPT when the MBR is a protective one, else MBR
+fn read_table(file: &mut File, disk: &str) -> Result<Table, String> {
+    let ss = sector_size(disk);
+    let mut mbr = [0u8; 512];
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.read_exact(&mut mbr))
+        .map_err(|e| format!("{disk}: {e}"))?;
+    let protective = (0..4).any(|i| mbr[446 + 16 * i + 4] == MBR_PROTECTIVE);
+    if protective {
+        let mut gpt = GPT::read_from(file, ss).map_err(|e| format!("{disk}: GPT: {e}"))?;
+        // The disk (or image) may have grown since the table was written
+        gpt.header.update_from(file, ss).map_err(|e| format!("{disk}: GPT: {e}"))?;
+        return Ok(Table::Gpt(gpt));
+    }
+    let ss = u32::try_from(ss).map_err(|_| format!("{disk}: unsupported sector size"))?;
+    MBR::read_from(file, ss)
+        .map(|mbr| Table::Mbr(Box::new(mbr)))
+        .map_err(|_| format!("{disk} has no partition table"))
+}
+
+/// Write `table` back to `disk` and flush it past the page cache
+fn write_table(file: &mut File, disk: &str, table: &mut Table) -> Result<(), String> {
+    match table {
+        Table::Gpt(gpt) => gpt.write_into(file).map(drop).map_err(|e| e.to_string()),
+        Table::Mbr(mbr) => mbr.write_into(file).map_err(|e| e.to_string()),
+    }
+    .and_then(|_| file.sync_all().map_err(|e| e.to_string()))
+    .map_err(|e| format!("Writing the partition table of {disk}: {e}"))
+}
+
+/// Zero `len` bytes at `offset`
+fn zero(file: &mut File, offset: u64, len: u64) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&vec![0u8; len as usize])
+}
+
+/// Replace the partition table of `disk` with an empty one. The first and
+/// last MiB are cleared first, so no stale GPT (or its backup) survives.
+pub fn mklabel(disk: &str, label: Label) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let bytes = disk_bytes(&mut file, disk)?;
+    let ss = sector_size(disk);
+    let edge = MIB.min(bytes / 2);
+    zero(&mut file, 0, edge)
+        .and_then(|_| zero(&mut file, bytes - edge, edge))
+        .map_err(|e| format!("Clearing the old partition table of {disk}: {e}"))?;
+
+    let mut table = match label {
+        Label::Gpt => {
+            GPT::write_protective_mbr_into(&mut file, ss).map_err(|e| format!("{disk}: {e}"))?;
+            let gpt = GPT::new_from(&mut file, ss, random_guid()?)
+                .map_err(|e| format!("{disk}: {e}"))?;
+            Table::Gpt(gpt)
+        }
+        Label::Msdos => {
+            let ss = u32::try_from(ss).map_err(|_| format!("{disk}: unsupported sector size"))?;
+            let mbr = MBR::new_from(&mut file, ss, random_bytes::<4>()?)
+                .map_err(|e| format!("{disk}: {e}"))?;
+            Table::Mbr(Box::new(mbr))
+        }
+    };
+    write_table(&mut file, disk, &mut table)
+}
+
+/// First and last sector of a partition from `start` up to (not
+/// including) `end`, clipped to the usable area
+fn sector_range(
+    start: Pos,
+    end: Pos,
+    disk_bytes: u64,
+    ss: u64,
+    last_usable: u64,
+) -> Result<(u64, u64), String> {
+    let first = start.offset(disk_bytes) / ss;
+    let last = (end.offset(disk_bytes) / ss).saturating_sub(1).min(last_usable);
+    if first > last {
+        return Err(format!("empty partition {start} - {end}"));
+    }
+    Ok((first, last))
+}
+
+/// Append a partition from `start` to `end` in the first free slot;
+/// `fs_hint` only sets the type code ("fat32", "linux-swap", "ext4", or ""
+/// for a Linux filesystem)
+pub fn mkpart(disk: &str, fs_hint: &str, start: Pos, end: Pos) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let bytes = disk_bytes(&mut file, disk)?;
+    let ss = sector_size(disk);
+    let mut table = read_table(&mut file, disk)?;
+    let context = |e: String| format!("{disk}: partition {start} - {end}: {e}");
+
+    match &mut table {
+        Table::Gpt(gpt) => {
+            let (first, last) =
+                sector_range(start, end, bytes, ss, gpt.header.last_usable_lba).map_err(context)?;
+            let type_guid = match fs_hint {
+                "fat32" => GUID_BASIC_DATA,
+                "linux-swap" => GUID_LINUX_SWAP,
+                _ => GUID_LINUX_FS,
+            };
+            let Some((slot, _)) = gpt.iter().find(|(_, p)| p.is_unused()) else {
+                return Err(context("no free partition entry".to_string()));
+            };
+            gpt[slot] = GPTPartitionEntry {
+                partition_type_guid: guid_bytes(type_guid),
+                unique_partition_guid: random_guid()?,
+                starting_lba: first,
+                ending_lba: last,
+                attribute_bits: 0,
+                partition_name: "primary".into(),
+            };
+        }
+        Table::Mbr(mbr) => {
+            let last_usable = bytes / ss - 1;
+            let (first, last) =
+                sector_range(start, end, bytes, ss, last_usable).map_err(context)?;
+            let overlaps = mbr.iter().filter(|(_, p)| p.is_used()).any(|(_, p)| {
+                let (p_first, p_last) =
+                    (u64::from(p.starting_lba), u64::from(p.starting_lba) + u64::from(p.sectors));
+                first < p_last && p_first <= last
+            });
+            if overlaps {
+                return Err(context("overlaps an existing partition".to_string()));
+            }
+            let (Ok(starting_lba), Ok(sectors)) =
+                (u32::try_from(first), u32::try_from(last - first + 1))
+            else {
+                return Err(context("beyond the 2 TiB an MBR can address".to_string()));
+            };
+            let Some(slot) = (1..=4).find(|&i| mbr[i].is_unused()) else {
+                return Err(context("no free primary partition slot".to_string()));
+            };
+            mbr[slot] = MBRPartitionEntry {
+                boot: BOOT_INACTIVE,
+                first_chs: CHS::empty(),
+                sys: match fs_hint {
+                    "fat32" => 0x0c,
+                    "linux-swap" => 0x82,
+                    _ => 0x83,
+                },
+                last_chs: CHS::empty(),
+                starting_lba,
+                sectors,
+            };
+        }
+    }
+    write_table(&mut file, disk, &mut table)
+}
+
+/// Move the end of partition `num` so it is `size_mib` long; its start and
+/// contents stay where they are
+pub fn resize(disk: &str, num: usize, size_mib: u64) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let ss = sector_size(disk);
+    let sectors = size_mib * MIB / ss;
+    let mut table = read_table(&mut file, disk)?;
+    let missing = || format!("{disk} has no partition {num}");
+    match &mut table {
+        Table::Gpt(gpt) => {
+            let slot = u32::try_from(num).map_err(|_| missing())?;
+            let entry = gpt.iter_mut().find(|(i, p)| *i == slot && p.is_used()).ok_or_else(missing)?;
+            entry.1.ending_lba = entry.1.starting_lba + sectors - 1;
+        }
+        Table::Mbr(mbr) => {
+            let entry = mbr.get_mut(num).filter(|p| p.is_used()).ok_or_else(missing)?;
+            entry.sectors = u32::try_from(sectors)
+                .map_err(|_| format!("{disk}: {size_mib} MiB is more than an MBR can address"))?;
+        }
+    }
+    write_table(&mut file, disk, &mut table)
+}
+
+/// Turn `flag` on for partition `num`
+pub fn set_flag(disk: &str, num: usize, flag: Flag) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let mut table = read_table(&mut file, disk)?;
+    let missing = || format!("{disk} has no partition {num}");
+    match &mut table {
+        Table::Gpt(gpt) => {
+            let slot = u32::try_from(num).map_err(|_| missing())?;
+            let (_, entry) =
+                gpt.iter_mut().find(|(i, p)| *i == slot && p.is_used()).ok_or_else(missing)?;
+            match flag {
+                Flag::Esp => entry.partition_type_guid = guid_bytes(GUID_ESP),
+                Flag::BiosGrub => entry.partition_type_guid = guid_bytes(GUID_BIOS_BOOT),
+                Flag::Boot => entry.attribute_bits |= GPT_LEGACY_BOOTABLE,
+            }
+        }
+        Table::Mbr(mbr) => {
+            if mbr.get(num).is_none_or(|p| p.is_unused()) {
+                return Err(missing());
+            }
+            match flag {
+                Flag::Esp => mbr[num].sys = 0xef,
+                Flag::BiosGrub => {
+                    return Err(format!("{disk}: bios_grub needs a GPT partition table"));
+                }
+                Flag::Boot => {
+                    // Only one partition can be active
+                    for (i, p) in mbr.iter_mut() {
+                        p.boot = if i == num { BOOT_ACTIVE } else { BOOT_INACTIVE };
+                    }
+                }
+            }
+        }
+    }
+    write_table(&mut file, disk, &mut table)
+}
+
+/// Mark the protective MBR of a GPT disk active
+pub fn set_pmbr_boot(disk: &str) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let ss = sector_size(disk);
+    GPT::write_bootable_protective_mbr_into(&mut file, ss)
+        .map_err(|e| e.to_string())
+        .and_then(|_| file.sync_all().map_err(|e| e.to_string()))
+        .map_err(|e| format!("{disk}: protective MBR: {e}"))
+}
+
+/// Set the GPT disk GUID ("8f3c...-..." notation)
+pub fn set_disk_guid(disk: &str, guid: &str) -> Result<(), String> {
+    let mut file = open(disk)?;
+    let mut table = read_table(&mut file, disk)?;
+    let Table::Gpt(gpt) = &mut table else {
+        return Err(format!("{disk} has no GPT"));
+    };
+    gpt.header.disk_guid = guid_bytes(guid);
+    write_table(&mut file, disk, &mut table)
+}
+
+/// Partitions in the table of `disk`, numbered like their device nodes
+pub fn extents(disk: &str) -> Result<Vec<Extent>, String> {
+    let mut file = File::open(disk).map_err(|e| format!("{disk}: {e}"))?;
+    let extents = match read_table(&mut file, disk)? {
+        Table::Gpt(gpt) => gpt
+            .iter()
+            .filter(|(_, p)| p.is_used())
+            .map(|(i, p)| Extent {
+                num: i as usize,
+                start: p.starting_lba,
+                size: p.ending_lba - p.starting_lba + 1,
+            })
+            .collect(),
+        Table::Mbr(mbr) => mbr
+            .iter()
+            .filter(|(_, p)| p.is_used() && !p.is_extended())
+            .map(|(i, p)| Extent {
+                num: i,
+                start: u64::from(p.starting_lba),
+                size: u64::from(p.sectors),
+            })
+            .collect(),
+    };
+    Ok(extents)
+}
+
+/// Logical sector size of `disk` in bytes, the unit of `Extent`
+pub fn sector_size(disk: &str) -> u64 {
+    let name = disk.trim_start_matches("/dev/");
+    fs::read_to_string(format!("/sys/block/{name}/queue/logical_block_size"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(512)
+}
+
+nix::ioctl_none!(blkrrpart, 0x12, 95);
+
+/// struct blkpg_partition from <linux/blkpg.h>; offsets in bytes
+#[repr(C)]
+struct BlkpgPartition {
+    start: i64,
+    length: i64,
+    pno: libc::c_int,
+    devname: [libc::c_char; 64],
+    volname: [libc::c_char; 64],
+}
+
+/// struct blkpg_ioctl_arg from <linux/blkpg.h>
+#[repr(C)]
+struct BlkpgIoctlArg {
+    op: libc::c_int,
+    flags: libc::c_int,
+    datalen: libc::c_int,
+    data: *mut libc::c_void,
+}
+
+const BLKPG_ADD_PARTITION: libc::c_int = 1;
+const BLKPG_DEL_PARTITION: libc::c_int = 2;
+const BLKPG_RESIZE_PARTITION: libc::c_int = 3;
+
+nix::ioctl_write_ptr_bad!(blkpg, 0x1269, BlkpgIoctlArg);
+
+/// One BLKPG operation on partition `pno` of the open disk `file`
+fn blkpg_op(file: &File, op: libc::c_int, pno: usize, start: u64, length: u64) -> nix::Result<()> {
+    let mut part = BlkpgPartition {
+        start: start as i64,
+        length: length as i64,
+        pno: pno as libc::c_int,
+        devname: [0; 64],
+        volname: [0; 64],
+    };
+    let arg = BlkpgIoctlArg {
+        op,
+        flags: 0,
+        datalen: std::mem::size_of::<BlkpgPartition>() as libc::c_int,
+        data: (&mut part as *mut BlkpgPartition).cast(),
+    };
+    // SAFETY: arg points to a live blkpg_partition of the advertised size
+    unsafe { blkpg(file.as_raw_fd(), &arg) }.map(drop)
+}
+
+/// Partitions the kernel currently knows on `disk`: number -> (start, size)
+/// in bytes (sysfs counts 512-byte sectors whatever the sector size)
+fn kernel_partitions(disk: &str) -> Vec<(usize, u64, u64)> {
+    let name = disk.trim_start_matches("/dev/");
+    let read = |path: std::path::PathBuf| -> Option<u64> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+    fs::read_dir(format!("/sys/block/{name}"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| {
+                    let dir = e.path();
+                    let num = read(dir.join("partition"))? as usize;
+                    Some((num, read(dir.join("start"))? * 512, read(dir.join("size"))? * 512))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// While a partition of `disk` is in use the kernel refuses BLKRRPART;
+/// update the other partitions one by one with BLKPG instead
+fn update_partitions(file: &File, disk: &str) -> Result<(), String> {
+    let ss = sector_size(disk);
+    let wanted: Vec<(usize, u64, u64)> = extents(disk)?
+        .into_iter()
+        .map(|e| (e.num, e.start * ss, e.size * ss))
+        .collect();
+    let known = kernel_partitions(disk);
+    let mut busy = Vec::new();
+
+    for &(num, start, size) in &known {
+        let op = match wanted.iter().find(|w| w.0 == num) {
+            Some(&(_, w_start, w_size)) if (w_start, w_size) == (start, size) => continue,
+            // Only the end can move while the partition stays registered
+            Some(&(_, w_start, w_size)) if w_start == start => {
+                blkpg_op(file, BLKPG_RESIZE_PARTITION, num, start, w_size)
+            }
+            _ => blkpg_op(file, BLKPG_DEL_PARTITION, num, 0, 0),
+        };
+        if op.is_err() {
+            busy.push(num);
+        }
+    }
+    for &(num, start, size) in &wanted {
+        let registered = kernel_partitions(disk).iter().any(|k| k.0 == num);
+        if !registered && blkpg_op(file, BLKPG_ADD_PARTITION, num, start, size).is_err() {
+            busy.push(num);
+        }
+    }
+    if busy.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{disk} is busy: partition(s) {busy:?} could not be updated"
+        ))
+    }
+}
+
+/// Make the kernel re-read the partition table of `disk` (BLKRRPART), or
+/// update its partitions one by one with BLKPG when one of them is in use
+pub fn reread(disk: &str) -> Result<(), String> {
+    let file = File::open(disk).map_err(|e| format!("{disk}: {e}"))?;
+    // SAFETY: BLKRRPART takes no argument and fd is an open block device
+    match unsafe { blkrrpart(file.as_raw_fd()) } {
+        Ok(_) => Ok(()),
+        Err(Errno::EBUSY) => update_partitions(&file, disk),
+        Err(e) => Err(format!("Re-reading the partition table of {disk}: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sparse disk image in the temp directory, removed on drop
+    struct Image(String);
+
+    impl Image {
+        fn new(name: &str, mib: u64) -> Image {
+            let path = std::env::temp_dir()
+                .join(format!("blunux-table-{}-{name}.img", std::process::id()))
+                .display()
+                .to_string();
+            File::create(&path).and_then(|f| f.set_len(mib * MIB)).expect("create image");
+            Image(path)
+        }
+    }
+
+    impl Drop for Image {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let disk = 1000 * MIB + 12345;
+        assert_eq!(Pos::Mib(513).offset(disk), 513 * MIB);
+        assert_eq!(Pos::Percent(50).offset(disk), 500 * MIB);
+        assert_eq!(Pos::FromEnd(100).offset(disk), 900 * MIB);
+        assert_eq!(Pos::End.offset(disk), disk);
+    }
+
+    #[test]
+    fn ranges() {
+        let disk = 100 * MIB;
+        let last = disk / 512 - 34;
+        assert_eq!(sector_range(Pos::Mib(1), Pos::Mib(2), disk, 512, last), Ok((2048, 4095)));
+        assert_eq!(sector_range(Pos::Mib(1), Pos::End, disk, 512, last), Ok((2048, last)));
+        assert!(sector_range(Pos::Mib(2), Pos::Mib(2), disk, 512, last).is_err());
+    }
+
+    #[test]
+    fn guid_layout() {
+        assert_eq!(
+            guid_bytes(GUID_ESP),
+            [
+                0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9,
+                0x3e, 0xc9, 0x3b
+            ]
+        );
+    }
+
+    #[test]
+    fn gpt_layout() {
+        let image = Image::new("gpt", 64);
+        let disk = image.0.as_str();
+        mklabel(disk, Label::Gpt).unwrap();
+        mkpart(disk, "fat32", Pos::Mib(1), Pos::Mib(33)).unwrap();
+        set_flag(disk, 1, Flag::Esp).unwrap();
+        mkpart(disk, "ext4", Pos::Mib(33), Pos::FromEnd(8)).unwrap();
+        mkpart(disk, "linux-swap", Pos::FromEnd(8), Pos::End).unwrap();
+
+        let parts = extents(disk).unwrap();
+        let last = 64 * MIB / 512 - 34;
+        assert_eq!(
+            parts,
+            [
+                Extent { num: 1, start: 2048, size: 32 * 2048 },
+                Extent { num: 2, start: 33 * 2048, size: 23 * 2048 },
+                Extent { num: 3, start: 56 * 2048, size: last - 56 * 2048 + 1 },
+            ]
+        );
+        let mut file = File::open(disk).unwrap();
+        let Table::Gpt(gpt) = read_table(&mut file, disk).unwrap() else {
+            panic!("not a GPT");
+        };
+        assert_eq!(gpt[1].partition_type_guid, guid_bytes(GUID_ESP));
+        assert_eq!(gpt[3].partition_type_guid, guid_bytes(GUID_LINUX_SWAP));
+
+        // Overlapping partitions are refused
+        assert!(mkpart(disk, "ext4", Pos::Mib(10), Pos::Mib(20)).is_err());
+        resize(disk, 2, 10).unwrap();
+        assert_eq!(extents(disk).unwrap()[1].size, 10 * 2048);
+    }
+
+    #[test]
+    fn mbr_layout() {
+        let image = Image::new("mbr", 64);
+        let disk = image.0.as_str();
+        // A new MBR must not leave the old GPT readable
+        mklabel(disk, Label::Gpt).unwrap();
+        mklabel(disk, Label::Msdos).unwrap();
+        mkpart(disk, "ext4", Pos::Mib(1), Pos::Percent(50)).unwrap();
+        mkpart(disk, "linux-swap", Pos::Percent(50), Pos::End).unwrap();
+        set_flag(disk, 1, Flag::Boot).unwrap();
+        assert!(set_flag(disk, 1, Flag::BiosGrub).is_err());
+        assert!(set_flag(disk, 3, Flag::Boot).is_err());
+
+        assert_eq!(
+            extents(disk).unwrap(),
+            [
+                Extent { num: 1, start: 2048, size: 31 * 2048 },
+                Extent { num: 2, start: 32 * 2048, size: 32 * 2048 },
+            ]
+        );
+        let mut file = File::open(disk).unwrap();
+        let Table::Mbr(mbr) = read_table(&mut file, disk).unwrap() else {
+            panic!("not an MBR");
+        };
+        assert!(mbr[1].is_active() && !mbr[2].is_active());
+        assert_eq!((mbr[1].sys, mbr[2].sys), (0x83, 0x82));
+    }
+}
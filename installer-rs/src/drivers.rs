@@ -0,0 +1,96 @@
+use crate::tui;
+use serde::Deserialize;
+use std::fs;
+
+/// Rules shipped with the installer
+const BUILTIN_RULES: &str = include_str!("../drivers.toml");
+
+/// Rules on the ISO that extend or replace the built-in ones
+pub const RULES_OVERRIDE: &str = "/etc/blunux/drivers.toml";
+
+/// One hardware pattern and what to install when it is present
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    pub name: String,
+    /// Patterns for `lspci -nn` lines; "a&b" needs both on one line
+    pub pci: Vec<String>,
+    /// Patterns for `lsusb` lines
+    pub usb: Vec<String>,
+    pub packages: Vec<String>,
+    /// `packages` are AUR packages, installed with the [packages] aur list
+    pub aur: bool,
+    pub services: Vec<String>,
+    pub kernel_params: Vec<String>,
+    /// In-kernel modules that grab the device before the packaged driver;
+    /// blacklisted (and kept out of the initramfs) when the packages are installed
+    pub blacklist: Vec<String>,
+    /// Modules loaded from the initramfs (mkinitcpio MODULES), e.g. for
+    /// early KMS; added only when the packages are installed
+    pub modules: Vec<String>,
+    /// Printed when the rule matches
+    pub note: String,
+    /// Only meaningful in RULES_OVERRIDE: drop the built-in rule of this name
+    pub disabled: bool,
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+/// Whether any line of `devices` matches any of `patterns`
+fn matches_any(patterns: &[String], devices: &str) -> bool {
+    devices.lines().any(|line| {
+        let line = line.to_lowercase();
+        patterns.iter().any(|p| {
+            let p = p.to_lowercase();
+            !p.is_empty() && p.split('&').all(|part| line.contains(part.trim()))
+        })
+    })
+}
+
+impl Rule {
+    /// `pci` / `usb` are the raw lspci -nn / lsusb outputs
+    pub fn matches(&self, pci: &str, usb: &str) -> bool {
+        matches_any(&self.pci, pci) || matches_any(&self.usb, usb)
+    }
+
+    /// Rules without packages count as installed
+    pub fn is_installed(&self, installed: &[String]) -> bool {
+        self.packages.is_empty() || self.packages.iter().any(|p| installed.contains(p))
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<Rule>, String> {
+    toml::from_str::<RulesFile>(content)
+        .map(|f| f.rule)
+        .map_err(|e| e.to_string())
+}
+
+/// Built-in rules merged with RULES_OVERRIDE (same name replaces, new
+/// names are appended). A broken override is reported and ignored.
+pub fn load_rules() -> Vec<Rule> {
+    let mut rules = parse(BUILTIN_RULES).expect("built-in drivers.toml is valid");
+
+    let Ok(content) = fs::read_to_string(RULES_OVERRIDE) else {
+        return rules;
+    };
+    let overrides = match parse(&content) {
+        Ok(o) => o,
+        Err(e) => {
+            tui::print_warning(&format!("Ignoring {RULES_OVERRIDE}: {e}"));
+            return rules;
+        }
+    };
+    tui::print_info(&format!("Using driver rules from {RULES_OVERRIDE}"));
+    for rule in overrides {
+        match rules.iter().position(|r| r.name == rule.name) {
+            Some(i) => rules[i] = rule,
+            None => rules.push(rule),
+        }
+    }
+    rules.retain(|r| !r.disabled);
+    rules
+}
@@ -0,0 +1,253 @@
+use crate::disk::{self, probe};
+use crate::drivers;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Hardware inventory of the machine being installed, written to the
+/// install report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Survey {
+    /// "uefi" or "bios"
+    pub firmware: String,
+    pub dmi: Dmi,
+    pub cpu: Cpu,
+    /// None when /proc/meminfo could not be read
+    pub ram_mb: Option<u64>,
+    pub gpus: Vec<PciDevice>,
+    pub network: Vec<NetInterface>,
+    pub storage: Vec<StorageDevice>,
+    /// Driver packages the installer picked for this hardware
+    pub drivers: Vec<String>,
+}
+
+/// System identification from /sys/class/dmi/id (empty on boards without DMI)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Dmi {
+    pub sys_vendor: String,
+    pub product_name: String,
+    pub product_version: String,
+    pub board_vendor: String,
+    pub board_name: String,
+    pub bios_version: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Cpu {
+    pub vendor: String,
+    pub model: String,
+    /// Logical CPUs (threads)
+    pub threads: usize,
+}
+
+/// One PCI function as lspci -vmm describes it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PciDevice {
+    pub slot: String,
+    pub class: String,
+    pub vendor: String,
+    pub device: String,
+    /// "vendor:device" PCI IDs, e.g. "10de:2484"
+    pub id: String,
+    /// Kernel driver bound to the device (empty if none)
+    pub driver: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NetInterface {
+    pub name: String,
+    pub mac: String,
+    pub driver: String,
+    pub wireless: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageDevice {
+    pub path: String,
+    pub model: String,
+    pub size_mib: u64,
+    /// "nvme", "sata", "usb", ... (empty if unknown)
+    pub transport: String,
+    pub ssd: bool,
+}
+
+impl Survey {
+    /// One-line description for the progress output
+    pub fn summary(&self) -> String {
+        let machine = format!("{} {}", self.dmi.sys_vendor, self.dmi.product_name);
+        let machine = machine.trim();
+        let machine = if machine.is_empty() { "Unknown machine" } else { machine };
+        let mut parts = vec![machine.to_string()];
+        if !self.cpu.model.is_empty() {
+            parts.push(format!("{} ({} threads)", self.cpu.model, self.cpu.threads));
+        }
+        if let Some(ram) = self.ram_mb {
+            parts.push(format!("{ram} MB RAM"));
+        }
+        parts.extend(self.gpus.iter().map(|g| g.device.clone()));
+        parts.join(", ")
+    }
+}
+
+/// Collect the inventory; every probe degrades to empty values on failure
+pub fn survey() -> Survey {
+    Survey {
+        firmware: if disk::is_uefi() { "uefi" } else { "bios" }.to_string(),
+        dmi: dmi(),
+        cpu: cpu(),
+        ram_mb: disk::ram_mb().ok(),
+        gpus: pci_devices().into_iter().filter(|d| d.class.starts_with("03")).collect(),
+        network: network_interfaces(),
+        storage: storage_devices(),
+        drivers: Vec::new(),
+    }
+}
+
+/// Packages drivers.toml picks for this machine, as the install would
+/// before [hardware] extra_drivers / skip_drivers apply
+pub fn driver_packages() -> Vec<String> {
+    let output = |cmd: &mut Command| {
+        cmd.output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+            .unwrap_or_default()
+    };
+    let pci = output(Command::new("lspci").arg("-nn"));
+    let usb = output(&mut Command::new("lsusb"));
+    let mut packages: Vec<String> = Vec::new();
+    for rule in drivers::load_rules().into_iter().filter(|r| r.matches(&pci, &usb)) {
+        for pkg in rule.packages {
+            if !packages.contains(&pkg) {
+                packages.push(pkg);
+            }
+        }
+    }
+    packages
+}
+
+/// Trimmed contents of a sysfs attribute (empty if missing)
+fn read_attr(path: impl AsRef<Path>) -> String {
+    fs::read_to_string(path).map(|s| s.trim().to_string()).unwrap_or_default()
+}
+
+/// Name of the driver a sysfs device is bound to (empty if none)
+fn driver_name(device: &Path) -> String {
+    fs::read_link(device.join("driver"))
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default()
+}
+
+fn dmi() -> Dmi {
+    let id = Path::new("/sys/class/dmi/id");
+    Dmi {
+        sys_vendor: read_attr(id.join("sys_vendor")),
+        product_name: read_attr(id.join("product_name")),
+        product_version: read_attr(id.join("product_version")),
+        board_vendor: read_attr(id.join("board_vendor")),
+        board_name: read_attr(id.join("board_name")),
+        bios_version: read_attr(id.join("bios_version")),
+    }
+}
+
+fn cpu() -> Cpu {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    let field = |key: &str| {
+        cpuinfo
+            .lines()
+            .filter_map(|l| l.split_once(':'))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    Cpu {
+        vendor: field("vendor_id"),
+        model: field("model name"),
+        threads: cpuinfo.lines().filter(|l| l.starts_with("processor")).count(),
+    }
+}
+
+/// Split an lspci -nn name such as "NVIDIA Corporation [10de]" into
+/// the name and the bracketed ID
+fn split_id(value: &str) -> (String, String) {
+    match value.rsplit_once(" [") {
+        Some((name, id)) => (name.to_string(), id.trim_end_matches(']').to_string()),
+        None => (value.to_string(), String::new()),
+    }
+}
+
+/// Parse `lspci -vmm -nn -k` records (blank-line separated "Key:\tvalue")
+fn parse_lspci(output: &str) -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for record in output.split("\n\n") {
+        let mut dev = PciDevice::default();
+        let (mut vendor_id, mut device_id) = (String::new(), String::new());
+        for (key, value) in record.lines().filter_map(|l| l.split_once(':')) {
+            let value = value.trim();
+            match key {
+                "Slot" => dev.slot = value.to_string(),
+                "Class" => {
+                    let (name, id) = split_id(value);
+                    dev.class = format!("{id} {name}");
+                }
+                "Vendor" => (dev.vendor, vendor_id) = split_id(value),
+                "Device" => (dev.device, device_id) = split_id(value),
+                "Driver" => dev.driver = value.to_string(),
+                _ => {}
+            }
+        }
+        if dev.slot.is_empty() {
+            continue;
+        }
+        dev.id = format!("{vendor_id}:{device_id}");
+        devices.push(dev);
+    }
+    devices
+}
+
+/// All PCI functions; empty when lspci is unavailable
+fn pci_devices() -> Vec<PciDevice> {
+    Command::new("lspci")
+        .args(["-vmm", "-nn", "-k"])
+        .output()
+        .map(|o| parse_lspci(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default()
+}
+
+/// Physical network interfaces (those backed by a device, so not lo,
+/// bridges or tunnels)
+fn network_interfaces() -> Vec<NetInterface> {
+    let Ok(entries) = fs::read_dir("/sys/class/net") else {
+        return Vec::new();
+    };
+    let mut interfaces: Vec<NetInterface> = entries
+        .flatten()
+        .filter(|e| e.path().join("device").exists())
+        .map(|e| {
+            let path = e.path();
+            NetInterface {
+                name: e.file_name().to_string_lossy().into_owned(),
+                mac: read_attr(path.join("address")),
+                driver: driver_name(&path.join("device")),
+                wireless: path.join("wireless").exists() || path.join("phy80211").exists(),
+            }
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces
+}
+
+/// Whole disks, skipping optical drives, loop devices and zram
+fn storage_devices() -> Vec<StorageDevice> {
+    probe::disks()
+        .into_iter()
+        .filter(|d| !d.path.starts_with("/dev/zram"))
+        .map(|d| StorageDevice {
+            ssd: !d.rota,
+            transport: d.tran.clone().unwrap_or_default(),
+            size_mib: d.size_mib(),
+            model: d.model.clone().unwrap_or_default().trim().to_string(),
+            path: d.path,
+        })
+        .collect()
+}
@@ -1,16 +1,66 @@
-use crate::config::{Config, SwapMode};
+use crate::abort;
+use crate::config::{self, Config, FileEntry, Filesystem, Keyboard, SwapMode};
 use crate::disk::{self, PartitionLayout, PartitionScheme};
+use crate::drivers;
+use crate::hardware::{self, Survey};
+use crate::repos::{self, Repo};
 use crate::tui;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 
+/// LSM order with AppArmor enabled (Arch kernels do not enable it by default)
+const APPARMOR_LSM: &str = "lsm=landlock,lockdown,yama,integrity,apparmor,bpf";
+
+/// Queued first-boot tasks; each script is removed once it succeeds
+const FIRSTBOOT_DIR: &str = "/usr/local/lib/blunux/firstboot.d";
+
+/// Marker left in a home once blunux-firstlogin has run for it
+const FIRSTLOGIN_STAMP: &str = ".local/state/blunux/firstlogin-done";
+
+/// Root space a first-boot script package (install-packages.sh) may take
+const SCRIPT_PACKAGE_MIB: u64 = 1024;
+
+/// Free space kept on root for the package cache, logs and updates
+const ROOT_HEADROOM_MIB: u64 = 4096;
+
+/// Largest small/file swap file on eMMC or an SD card
+const FLASH_SWAP_MAX_MB: u64 = 2048;
+
+/// Install report (config summary + hardware survey) inside the target
+const INSTALL_REPORT: &str = "/var/log/blunux/install-report.json";
+
+/// Partition table backups (disk::BACKUP_DIR) inside the target
+const PARTITION_BACKUPS: &str = "/var/backups/blunux-partition-tables";
+
+const EFIVARS: &str = "/sys/firmware/efi/efivars";
+
+/// Removable-media boot path on the ESP, tried by firmware without entries
+const NMBL_REMOVABLE_PATH: &str = "/boot/efi/EFI/BOOT/BOOTX64.EFI";
+
+/// mkinitcpio call (in the chroot) that writes kernel + initramfs +
+/// /etc/kernel/cmdline as one UKI to the removable-media path
+fn nmbl_uki_command(kernel: &str) -> String {
+    format!(
+        "mkinitcpio -k /boot/vmlinuz-{kernel} --cmdline /etc/kernel/cmdline -U {NMBL_REMOVABLE_PATH}"
+    )
+}
+
 pub struct Installer {
     config: Config,
     error_message: String,
     mount_point: String,
     partition_layout: PartitionLayout,
+    hardware: Survey,
+    /// Kernel parameters requested by matched driver rules
+    driver_params: Vec<String>,
+    /// AUR packages requested by matched driver rules
+    driver_aur: Vec<String>,
+    /// Step the install is in (or failed in), e.g. "Installing packages"
+    step: String,
+    /// Swap added to a low-memory live system until the install ends
+    live_swap: Option<disk::LiveSwap>,
 }
 
 impl Installer {
@@ -23,7 +73,17 @@ impl Installer {
                 efi_partition: String::new(),
                 root_partition: String::new(),
                 scheme: PartitionScheme::GptUefi,
+                data_partitions: Vec::new(),
+                keep_efi: false,
+                keep_root: false,
+                ssd: false,
+                flash: false,
             },
+            hardware: Survey::default(),
+            driver_params: Vec::new(),
+            driver_aur: Vec::new(),
+            step: String::new(),
+            live_swap: None,
         }
     }
 
@@ -31,7 +91,35 @@ impl Installer {
         &self.error_message
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// English name of the current (after a failure: the failed) step
+    pub fn current_step(&self) -> &str {
+        &self.step
+    }
+
+    /// Hardware survey of this machine; taken now if the install stopped
+    /// before the driver step
+    pub fn hardware(&self) -> Survey {
+        if self.hardware.firmware.is_empty() {
+            hardware::survey()
+        } else {
+            self.hardware.clone()
+        }
+    }
+
+    fn start_step(&mut self, step: i32, total: i32, msg: &str) {
+        tui::print_step(step, total, msg);
+        self.step = msg.split(" / ").next().unwrap_or(msg).to_string();
+    }
+
     fn run_command(&self, cmd: &str) -> bool {
+        // Nothing more touches the target once the installation is aborted
+        if abort::requested() {
+            return false;
+        }
         Command::new("sh")
             .args(["-c", cmd])
             .status()
@@ -65,47 +153,358 @@ impl Installer {
             .is_ok()
     }
 
+    /// Check everything that can be verified without touching the target
+    /// disk, so a bad config or unreachable mirror fails before any wipe
+    pub fn preflight(&mut self) -> bool {
+        tui::print_info("Checking installation plan / 설치 계획 확인 중...");
+        let mut problems: Vec<String> = Vec::new();
+
+        let target = &self.config.install.target_disk;
+        if !self.run_command(&format!("test -b {target}")) {
+            problems.push(format!("Target disk {target} is not a block device"));
+        }
+        let esp_disk = &self.config.disk.esp_disk;
+        if !esp_disk.is_empty() && !self.run_command(&format!("test -b {esp_disk}")) {
+            problems.push(format!("[disk] esp_disk {esp_disk} is not a block device"));
+        }
+        // Never overridable: wiping the medium we run from cannot succeed
+        let live = disk::live_medium_disks();
+        for disk in [target, esp_disk].into_iter().filter(|d| live.contains(d)) {
+            problems.push(format!("{disk} holds the running installer - choose another disk"));
+        }
+        if disk::probe::is_emmc_hw_partition(target) {
+            problems.push(format!(
+                "{target} is an eMMC boot/RPMB area - install to the main device instead"
+            ));
+        }
+        if disk::is_removable(target) {
+            // Installing to a USB drive is legitimate, just rarely intended
+            tui::print_warning(&format!("{target} is a removable/USB disk"));
+        }
+        for stack in disk::active_stacks(target) {
+            let msg = format!("{target} is in use by {stack} - stop it first");
+            self.disk_check_problem(&mut problems, msg);
+        }
+        if self.config.uses_luks() && self.config.disk.encryption.tpm2 && !disk::has_tpm2() {
+            // Not fatal: the passphrase still unlocks root
+            tui::print_warning("[disk.encryption] tpm2 is set but this machine has no TPM 2.0");
+        }
+        if self.config.needs_encryption_password() && self.config.disk.encryption.luks_version == 1
+        {
+            // PBKDF2 is cheap to run on GPUs, unlike argon2
+            tui::print_warning(
+                "LUKS1: keyslots use PBKDF2 and the header has no backup copy - \
+                 use a long passphrase and keep a header backup (cryptsetup luksHeaderBackup)",
+            );
+        }
+
+        // Swap and hibernation sizes are derived from RAM; never guess them
+        if self.config.disk.swap != SwapMode::None {
+            if let Err(e) = disk::ram_mb() {
+                problems.push(format!("{e} (needed to size swap)"));
+            }
+        }
+
+        let scheme = disk::partition_scheme(target, &self.config.disk);
+        if let Err(e) = disk::check_partition_plan(target, &self.config, scheme) {
+            problems.push(e);
+        }
+        if self.config.disk.alongside.enabled {
+            // NMBL copies kernel and initramfs onto the (often 100 MiB) shared ESP
+            let esp_needed = disk::shared_esp_needed_mib(&self.config);
+            match disk::check_alongside_plan(target, &self.config.disk.alongside, scheme, esp_needed)
+            {
+                Ok(os) => tui::print_info(&format!(
+                    "{} on {} will be shrunk by {}",
+                    os.name, os.partition, self.config.disk.alongside.size
+                )),
+                Err(e) => problems.push(e),
+            }
+        }
+        if !self.config.disk.reuse_esp.is_empty() {
+            if let Some(esp) = disk::reused_esp(&self.config) {
+                tui::print_info(&format!("Boot files go to the existing EFI partition {esp}"));
+            }
+        }
+        if !self.config.disk.keep_home.partition.is_empty() {
+            match disk::check_keep_home_plan(target, &self.config.disk.keep_home, scheme) {
+                Ok((root, _)) => tui::print_info(&format!(
+                    "{root} will be reformatted, /home on {} is kept",
+                    self.config.disk.keep_home.partition
+                )),
+                Err(e) => problems.push(e),
+            }
+        }
+
+        self.check_disk_health(&mut problems);
+
+        if self.config.install.root_password.is_empty()
+            || self.config.install.user_password.is_empty()
+        {
+            problems.push("Root and user passwords must not be empty".to_string());
+        }
+        if self.config.needs_encryption_password()
+            && self.config.install.encryption_password.is_empty()
+        {
+            problems.push("Encryption is enabled but no encryption password is set".to_string());
+        }
+
+        let timezone = &self.config.locale.timezone;
+        if !Path::new(&format!("/usr/share/zoneinfo/{timezone}")).is_file() {
+            problems.push(format!("Unknown timezone: {timezone}"));
+        }
+
+        // Same list locale-gen reads on the target
+        let supported = fs::read_to_string("/usr/share/i18n/SUPPORTED").unwrap_or_default();
+        if !supported.is_empty() {
+            for lang in self.config.locale.generated_languages() {
+                let entry = format!("{lang}.UTF-8 UTF-8");
+                if !supported.lines().any(|l| l.trim() == entry) {
+                    problems.push(format!("Unsupported locale: {lang}.UTF-8"));
+                }
+            }
+        }
+
+        // Only the first keyboard goes to the console
+        let keymaps = self.exec_output("localectl list-keymaps 2>/dev/null");
+        if let Some(kb) = self.config.locale.keyboards.first() {
+            let keymap = kb.console_keymap();
+            if !keymaps.is_empty() && !keymaps.lines().any(|k| k == keymap) {
+                problems.push(format!("No console keymap {keymap} for keyboard {kb}"));
+            }
+        }
+        let layouts = self.exec_output("localectl list-x11-keymap-layouts 2>/dev/null");
+        for kb in self.config.locale.keyboards.iter().filter(|_| !layouts.is_empty()) {
+            if !layouts.lines().any(|l| l == kb.layout) {
+                problems.push(format!("Unknown keyboard layout: {}", kb.layout));
+                continue;
+            }
+            let variants = self.exec_output(&format!(
+                "localectl list-x11-keymap-variants {} 2>/dev/null",
+                kb.layout
+            ));
+            if !kb.variant.is_empty() && !variants.lines().any(|v| v == kb.variant) {
+                problems.push(format!("Unknown variant {} of layout {}", kb.variant, kb.layout));
+            }
+        }
+
+        // archzfs has to be configured before zfs-dkms can be resolved
+        if self.config.disk.filesystem == Filesystem::Zfs && !disk::prepare_zfs_live_env() {
+            problems.push("ZFS is not available in the live environment".to_string());
+        }
+        // Packages from [packages] repos only resolve once those repos are in
+        // the live pacman.conf; pacstrap reuses the same setup later
+        for repo in self.extra_repos() {
+            if let Err(e) = repos::add("", repo) {
+                problems.push(e);
+            }
+        }
+
+        let packages = self.pacstrap_packages();
+        if !self.run_command("pacman -Sy --noconfirm >/dev/null 2>&1") {
+            problems.push("Could not sync package databases (no network?)".to_string());
+        } else {
+            let errors = self.exec_output(&format!(
+                "pacman -Sp --print-format %n {} 2>&1 >/dev/null",
+                packages.join(" ")
+            ));
+            for line in errors.lines() {
+                if let Some(pkg) = line.strip_prefix("error: target not found: ") {
+                    problems.push(format!("Package not found in repositories: {pkg}"));
+                }
+            }
+        }
+
+        // An unreadable RAM size was reported above
+        let needed_mib = self.estimated_root_mib(&packages);
+        if let (Some(space_mib), Ok(needed_mib)) =
+            (disk::default_root_space_mib(target, &self.config), needed_mib)
+        {
+            if needed_mib > space_mib {
+                let msg = format!(
+                    "The selected packages need about {} GiB but root gets only {} GiB on {target}",
+                    needed_mib.div_ceil(1024),
+                    space_mib / 1024
+                );
+                self.disk_check_problem(&mut problems, msg);
+            }
+        }
+        if !problems.is_empty() {
+            for p in &problems {
+                tui::print_error(p);
+            }
+            self.error_message = format!("{} pre-installation check(s) failed", problems.len());
+            return false;
+        }
+
+        tui::print_success(&format!(
+            "Plan OK: {} packages resolvable, nothing written to {target} yet",
+            packages.len()
+        ));
+        true
+    }
+
+    /// SMART data only warns, the disk may still be usable for a reinstall;
+    /// unreadable blocks in the read scan would fail the install midway
+    fn check_disk_health(&self, problems: &mut Vec<String>) {
+        let target = &self.config.install.target_disk;
+        let warnings = disk::health::smart_warnings(target);
+        for w in &warnings {
+            tui::print_warning(&format!("{target}: {w}"));
+        }
+        if !warnings.is_empty() {
+            tui::print_warning(&format!("{target} may be failing - back up and replace it"));
+        }
+        if self.config.disk.read_scan > 0 {
+            match disk::health::read_scan(target, self.config.disk.read_scan) {
+                Ok(0) => tui::print_success(&format!("Read scan of {target} passed")),
+                Ok(slow) => tui::print_warning(&format!(
+                    "{target}: {slow} sampled blocks took over a second to read"
+                )),
+                Err(e) => self.disk_check_problem(problems, e),
+            }
+        }
+    }
+
+    /// Disk checks that --skip-disk-checks turns into warnings
+    fn disk_check_problem(&self, problems: &mut Vec<String>, msg: String) {
+        if self.config.skip_disk_checks {
+            tui::print_warning(&format!("{msg} (ignored: --skip-disk-checks)"));
+        } else {
+            problems.push(format!("{msg} (override with --skip-disk-checks)"));
+        }
+    }
+
+    /// Rough size of the installed root: pacstrap packages at ~3x their
+    /// download size, first-boot script packages, a swap file and headroom
+    fn estimated_root_mib(&self, packages: &[String]) -> Result<u64, String> {
+        let sizes = self.exec_output(&format!(
+            "pacman -Sp --print-format %s {} 2>/dev/null",
+            packages.join(" ")
+        ));
+        let download_mib =
+            sizes.lines().filter_map(|l| l.trim().parse::<u64>().ok()).sum::<u64>() / (1024 * 1024);
+        let scripts_mib = self.config.get_script_package_list().len() as u64 * SCRIPT_PACKAGE_MIB;
+        let swap_mib = match self.config.disk.swap {
+            SwapMode::None | SwapMode::Partition => 0,
+            _ => self.config.disk.swap.size_mb(disk::ram_mb()?),
+        };
+        Ok(download_mib * 3 + scripts_mib + swap_mib + ROOT_HEADROOM_MIB)
+    }
+
+    /// Run the [[hooks.<point>]] commands in order; the first failure
+    /// aborts the installation
+    fn run_hooks(&mut self, point: &str) -> bool {
+        let hooks = self.config.hooks.get(point).to_vec();
+        for (i, hook) in hooks.iter().enumerate() {
+            let context = if hook.chroot { "chroot" } else { "host" };
+            tui::print_info(&format!("Hook {point} #{} ({context}): {}", i + 1, hook.command));
+
+            // Passed as separate arguments so the command needs no extra quoting
+            let status = if hook.chroot {
+                Command::new("arch-chroot")
+                    .args([self.mount_point.as_str(), "sh", "-c", &hook.command])
+                    .status()
+            } else {
+                Command::new("sh")
+                    .args(["-c", &hook.command])
+                    .env("BLUNUX_TARGET", &self.mount_point)
+                    .status()
+            };
+            if !status.map(|s| s.success()).unwrap_or(false) {
+                self.error_message = format!("Hook {point} #{} failed: {}", i + 1, hook.command);
+                return false;
+            }
+        }
+        true
+    }
+
     /// Run the full installation
     pub fn install(&mut self) -> bool {
-        let total_steps = 10;
+        let ok = self.install_steps();
+        // Steps that failed return before unmounting
+        self.disable_live_swap();
+        ok
+    }
+
+    fn disable_live_swap(&mut self) {
+        if let Some(swap) = self.live_swap.take() {
+            disk::disable_live_swap(&swap);
+        }
+    }
+
+    fn install_steps(&mut self) -> bool {
+        let snapshots =
+            self.config.disk.snapshots && self.config.disk.filesystem == Filesystem::Btrfs;
+        let total_steps = if snapshots { 11 } else { 10 };
+
+        if !self.run_hooks("pre_partition") {
+            return false;
+        }
 
         // Step 1: Prepare disk
-        tui::print_step(1, total_steps, "Preparing disk / 디스크 준비 중...");
+        self.start_step(1, total_steps, "Preparing disk / 디스크 준비 중...");
         if !self.prepare_disk() {
             return false;
         }
+        // Needs the target mounted for its swap file fallback
+        self.live_swap = disk::enable_live_swap(&self.mount_point, &self.config);
+
+        if !self.run_hooks("pre_pacstrap") {
+            return false;
+        }
 
         // Step 2: Install base system
-        tui::print_step(2, total_steps, "Installing base system / 기본 시스템 설치 중...");
+        self.start_step(2, total_steps, "Installing base system / 기본 시스템 설치 중...");
         if !self.install_base_system() {
             return false;
         }
 
         // Step 3: Generate fstab
-        tui::print_step(3, total_steps, "Generating fstab / fstab 생성 중...");
-        if !disk::generate_fstab(&self.mount_point) {
+        self.start_step(3, total_steps, "Generating fstab / fstab 생성 중...");
+        if self.partition_layout.keep_root {
+            // genfstab appends; entries of the old install would be duplicated
+            let fstab = format!("{}/etc/fstab", self.mount_point);
+            self.run_command(&format!("mv {fstab} {fstab}.old 2>/dev/null"));
+        }
+        if !disk::generate_fstab(&self.mount_point, self.config.disk.fstab == "label") {
             self.error_message = "Failed to generate fstab".to_string();
             return false;
         }
+        if !self.config.disk.extra.is_empty()
+            && !disk::setup_extra_disks(&self.config.disk.extra, &self.mount_point)
+        {
+            self.error_message = "Failed to set up extra data disks".to_string();
+            return false;
+        }
+        if !self.run_hooks("post_pacstrap") {
+            return false;
+        }
 
         // Step 4: Configure system (includes swap setup from config.toml)
-        tui::print_step(4, total_steps, "Configuring system / 시스템 설정 중...");
+        self.start_step(4, total_steps, "Configuring system / 시스템 설정 중...");
         if !self.configure_system() {
             return false;
         }
+        if !self.run_hooks("post_configure") {
+            return false;
+        }
 
         // Step 5: Detect and install hardware drivers
-        tui::print_step(5, total_steps, "Detecting hardware drivers / 하드웨어 드라이버 감지 중...");
+        self.start_step(5, total_steps, "Detecting hardware drivers / 하드웨어 드라이버 감지 중...");
         self.detect_and_install_drivers();
 
         // Step 6: Install packages
-        tui::print_step(6, total_steps, "Installing packages / 패키지 설치 중...");
+        self.start_step(6, total_steps, "Installing packages / 패키지 설치 중...");
         if !self.install_packages() {
             return false;
         }
+        if !self.run_hooks("post_packages") {
+            return false;
+        }
 
         // Step 7: Configure locale and input method
-        tui::print_step(7, total_steps, "Configuring locale / 로케일 설정 중...");
+        self.start_step(7, total_steps, "Configuring locale / 로케일 설정 중...");
         if !self.configure_locale() {
             return false;
         }
@@ -114,19 +513,28 @@ impl Installer {
         }
 
         // Step 8: Configure users
-        tui::print_step(8, total_steps, "Configuring users / 사용자 설정 중...");
+        self.start_step(8, total_steps, "Configuring users / 사용자 설정 중...");
         if !self.configure_users() {
             return false;
         }
 
         // Step 9: Install bootloader
-        tui::print_step(9, total_steps, "Installing bootloader / 부트로더 설치 중...");
+        self.start_step(9, total_steps, "Installing bootloader / 부트로더 설치 중...");
         if !self.install_bootloader() {
             return false;
         }
+        if !self.run_hooks("post_bootloader") {
+            return false;
+        }
+
+        // Step 10 (btrfs snapshots only): after the bootloader for grub-btrfs
+        if snapshots {
+            self.start_step(10, total_steps, "Configuring snapshots / 스냅샷 설정 중...");
+            self.configure_snapshots();
+        }
 
-        // Step 10: Finalize
-        tui::print_step(10, total_steps, "Finalizing / 마무리 중...");
+        // Final step: Finalize
+        self.start_step(total_steps, total_steps, "Finalizing / 마무리 중...");
         if !self.finalize() {
             return false;
         }
@@ -135,13 +543,9 @@ impl Installer {
     }
 
     fn prepare_disk(&mut self) -> bool {
-        let scheme = if disk::is_uefi() {
-            PartitionScheme::GptUefi
-        } else {
-            PartitionScheme::MbrBios
-        };
-
-        let layout = match disk::partition_disk(&self.config.install.target_disk, scheme) {
+        let target = &self.config.install.target_disk;
+        let scheme = disk::partition_scheme(target, &self.config.disk);
+        let layout = match disk::partition_disk(target, scheme, &self.config) {
             Some(l) => l,
             None => {
                 self.error_message = "Failed to partition disk".to_string();
@@ -151,11 +555,7 @@ impl Installer {
 
         self.partition_layout = layout.clone();
 
-        if !disk::format_partitions(
-            &self.partition_layout,
-            self.config.install.use_encryption,
-            &self.config.install.encryption_password,
-        ) {
+        if !disk::format_partitions(&self.partition_layout, &self.config) {
             self.error_message = "Failed to format partitions".to_string();
             return false;
         }
@@ -215,6 +615,112 @@ impl Installer {
             "man-pages".to_string(),
         ];
 
+        match self.config.disk.filesystem {
+            Filesystem::Btrfs => {
+                packages.push("btrfs-progs".to_string());
+                if self.config.disk.snapshots {
+                    if self.config.disk.snapshot_tool == "timeshift" {
+                        packages.push("timeshift".to_string());
+                        packages.push("cronie".to_string());
+                    } else {
+                        packages.push("snapper".to_string());
+                        packages.push("snap-pac".to_string());
+                    }
+                    if self.boots_with_grub() {
+                        packages.push("grub-btrfs".to_string());
+                        packages.push("inotify-tools".to_string());
+                    }
+                }
+            }
+            Filesystem::Xfs => packages.push("xfsprogs".to_string()),
+            Filesystem::F2fs => packages.push("f2fs-tools".to_string()),
+            Filesystem::Bcachefs => packages.push("bcachefs-tools".to_string()),
+            Filesystem::Zfs => {
+                packages.push("zfs-dkms".to_string());
+                packages.push("zfs-utils".to_string());
+            }
+            Filesystem::Ext4 => {}
+        }
+
+        if self.config.disk.lvm.enabled {
+            packages.push("lvm2".to_string());
+        }
+
+        if self.config.kiosk.enabled {
+            packages.push("cage".to_string());
+        }
+
+        if self.config.packages.flatpak {
+            packages.push("flatpak".to_string());
+        }
+
+        if !self.config.provision.ansible_repo.is_empty() {
+            packages.push("ansible".to_string());
+        }
+        if self.config.security.firewall {
+            packages.push("ufw".to_string());
+        }
+        if self.config.security.apparmor {
+            packages.push("apparmor".to_string());
+        }
+        if self.config.security.antivirus {
+            packages.push("clamav".to_string());
+        }
+        for brand in &self.config.packages.printer_brands {
+            for pkg in config::printer_driver_packages(brand).unwrap_or_default() {
+                if !packages.iter().any(|p| p == pkg) {
+                    packages.push(pkg.to_string());
+                }
+            }
+        }
+        if self.config.packages.printer_discovery {
+            packages.push("avahi".to_string());
+            packages.push("nss-mdns".to_string());
+        }
+        if self.config.services.disk_monitoring {
+            packages.push("smartmontools".to_string());
+            packages.push("libnotify".to_string());
+        }
+        if self.config.services.pacman_cache_keep > 0 {
+            packages.push("pacman-contrib".to_string());
+        }
+        if self.config.services.mirror_refresh {
+            packages.push("reflector".to_string());
+        }
+        if !self.config.provision.salt_master.is_empty() {
+            packages.push("salt".to_string());
+        }
+        // Container bootstrappers, kept installed for creating more later
+        if self.config.containers.iter().any(|c| c.distro == "arch") {
+            packages.push("arch-install-scripts".to_string());
+        }
+        if self.config.containers.iter().any(|c| c.distro == "debian") {
+            packages.push("debootstrap".to_string());
+            packages.push("debian-archive-keyring".to_string());
+        }
+
+        for extra in &self.config.disk.extra {
+            if let Some(pkg) = disk::fs_tools_package(&extra.filesystem) {
+                if !packages.iter().any(|p| p == pkg) {
+                    packages.push(pkg.to_string());
+                }
+            }
+        }
+
+        if self.config.uses_luks() && self.config.disk.encryption.tpm2 {
+            // sd-encrypt only bundles the TPM2 libraries when they are installed
+            packages.push("tpm2-tss".to_string());
+        }
+
+        if self.config.uses_luks() && !self.config.disk.encryption.ssh_unlock_key.is_empty() {
+            packages.push("mkinitcpio-netconf".to_string());
+            packages.push("mkinitcpio-utils".to_string());
+            packages.push(format!(
+                "mkinitcpio-{}",
+                self.config.disk.encryption.ssh_unlock_server
+            ));
+        }
+
         if self.config.install.bootloader != "nmbl" {
             packages.push("grub".to_string());
             packages.push("os-prober".to_string());
@@ -224,7 +730,7 @@ impl Installer {
     }
 
     fn get_desktop_packages(&self) -> Vec<String> {
-        vec![
+        let mut packages = vec![
             "xorg-server".to_string(),
             "xorg-xinit".to_string(),
             "wayland".to_string(),
@@ -257,7 +763,12 @@ impl Installer {
             "wireplumber".to_string(),
             "cups".to_string(),
             "print-manager".to_string(),
-        ]
+        ];
+        if !self.wayland_session() {
+            // Plasma's X11 session is a separate package since 6.4
+            packages.push("plasma-x11-session".to_string());
+        }
+        packages
     }
 
     fn get_font_packages(&self) -> Vec<String> {
@@ -309,39 +820,23 @@ impl Installer {
                     "fcitx5-qt".to_string(),
                 ]);
 
-                let has_lang = |prefix: &str| -> bool {
-                    self.config
-                        .locale
-                        .languages
-                        .iter()
-                        .any(|l| l.contains(prefix))
-                };
-
-                if has_lang("ko") {
+                if self.has_language("ko") {
                     packages.push("fcitx5-hangul".to_string());
                 }
-                if has_lang("ja") {
+                if self.has_language("ja") {
                     packages.push("fcitx5-mozc".to_string());
                 }
-                if has_lang("zh") {
+                if self.has_language("zh") {
                     packages.push("fcitx5-chinese-addons".to_string());
                 }
             }
             "ibus" => {
                 packages.push("ibus".to_string());
 
-                let has_lang = |prefix: &str| -> bool {
-                    self.config
-                        .locale
-                        .languages
-                        .iter()
-                        .any(|l| l.contains(prefix))
-                };
-
-                if has_lang("ko") {
+                if self.has_language("ko") {
                     packages.push("ibus-hangul".to_string());
                 }
-                if has_lang("ja") {
+                if self.has_language("ja") {
                     packages.push("ibus-mozc".to_string());
                 }
             }
@@ -351,22 +846,98 @@ impl Installer {
         packages
     }
 
-    fn install_base_system(&mut self) -> bool {
+    /// Everything passed to pacstrap
+    fn pacstrap_packages(&self) -> Vec<String> {
         let mut all_packages = Vec::new();
         all_packages.extend(self.get_base_packages());
         all_packages.extend(self.get_desktop_packages());
         all_packages.extend(self.get_font_packages());
         all_packages.extend(self.get_input_method_packages());
+        all_packages
+    }
+
+    /// [packages] repos, plus archzfs for root on ZFS
+    fn extra_repos(&self) -> Vec<&'static Repo> {
+        let mut extra: Vec<&'static Repo> =
+            self.config.packages.repos.iter().filter_map(|n| repos::find(n)).collect();
+        if self.config.disk.filesystem == Filesystem::Zfs
+            && !extra.iter().any(|r| r.name == repos::ARCHZFS.name)
+        {
+            extra.push(&repos::ARCHZFS);
+        }
+        extra
+    }
+
+    /// Write test on the mounted target before pacstrap spends half an hour
+    /// on it; false if the user stops at a slow disk
+    fn check_write_speed(&self) -> bool {
+        let min = self.config.disk.min_write_mbps;
+        if min == 0 {
+            return true;
+        }
+        tui::print_info("Testing target write speed...");
+        let mbps = match disk::health::write_speed_mbps(&self.mount_point) {
+            Ok(mbps) => mbps,
+            Err(e) => {
+                tui::print_warning(&e);
+                return true;
+            }
+        };
+        if mbps >= min as f64 {
+            tui::print_success(&format!("Target writes at {mbps:.0} MB/s"));
+            return true;
+        }
+        tui::print_warning(&format!(
+            "{} writes at only {mbps:.1} MB/s (expected at least {min}) - \
+             a USB 2 stick or a failing disk? The install may take hours.",
+            self.config.install.target_disk
+        ));
+        tui::confirm("Continue anyway?", true)
+    }
+
+    fn install_base_system(&mut self) -> bool {
+        if !self.check_write_speed() {
+            self.error_message = "Installation cancelled: target disk is too slow".to_string();
+            return false;
+        }
 
-        let pkg_list = all_packages.join(" ");
-        let cmd = format!("pacstrap -K {} {}", self.mount_point, pkg_list);
+        // pacstrap resolves packages with the live system's pacman.conf
+        for repo in self.extra_repos() {
+            if let Err(e) = repos::add("", repo) {
+                self.error_message = e;
+                return false;
+            }
+        }
 
         tui::print_info("Installing packages with pacstrap...");
         tui::print_info("This may take several minutes...");
 
-        if !self.run_command(&cmd) {
-            self.error_message = "pacstrap failed".to_string();
-            return false;
+        // Run directly rather than through run_command, so an abort can
+        // stop pacstrap and everything it started (pacman, gpg, hooks)
+        let mut pacstrap = Command::new("pacstrap");
+        pacstrap.arg("-K").arg(&self.mount_point).args(self.pacstrap_packages());
+        match abort::run(&mut pacstrap) {
+            Ok(true) => {}
+            Ok(false) => {
+                self.error_message = "pacstrap failed".to_string();
+                return false;
+            }
+            Err(e) => {
+                self.error_message = format!("pacstrap stopped: {e}");
+                return false;
+            }
+        }
+
+        // -K gave the target a fresh keyring: pin the keys there as well
+        for repo in self.extra_repos() {
+            if let Err(e) = repos::add(&self.mount_point, repo) {
+                self.error_message = e;
+                return false;
+            }
+            tui::print_success(&format!(
+                "[{}] enabled with pinned key {}",
+                repo.name, repo.fingerprint
+            ));
         }
 
         true
@@ -402,6 +973,13 @@ impl Installer {
         self.run_chroot("systemctl enable bluetooth 2>/dev/null || true");
         self.run_chroot("systemctl enable sddm");
         self.run_chroot("systemctl enable cups 2>/dev/null || true");
+        if self.config.packages.printer_discovery {
+            // Driverless (IPP Everywhere/AirPrint) printers are found over mDNS
+            self.run_chroot("systemctl enable avahi-daemon.service");
+            self.run_chroot(
+                "sed -i '/^hosts:/ {/mdns_minimal/! s/resolve/mdns_minimal [NOTFOUND=return] resolve/}' /etc/nsswitch.conf",
+            );
+        }
 
         // Mask conflicting network services (systemd-networkd conflicts with NM)
         self.run_chroot("systemctl mask systemd-networkd.service 2>/dev/null || true");
@@ -425,39 +1003,1028 @@ impl Installer {
         // SWAP CONFIGURATION - Uses [disk] swap from config.toml
         // This is the FIX for the hardcoded 8GB swap problem
         // =====================================================
-        self.setup_swap();
+        if !self.setup_swap() {
+            return false;
+        }
+        self.configure_power();
+
+        self.configure_portable_initramfs();
+        self.configure_filesystem_support();
+        self.configure_trim();
+        self.configure_flash_storage();
+        self.configure_passphrase_keymap();
+        self.configure_detached_header();
+        self.configure_encrypted_boot();
+        self.configure_keyfile_device();
+        self.configure_tpm_unlock();
+        self.configure_ssh_unlock();
+        self.configure_lvm();
+        self.configure_hibernation();
+        self.configure_encrypted_partitions();
+        self.configure_security();
+        self.configure_disk_monitoring();
+        self.configure_fs_maintenance();
+        self.configure_pacman_cache();
+        self.configure_mirror_refresh();
+        self.configure_game_controllers();
 
         true
     }
 
-    /// Copy WiFi connections from the live session to the installed system
-    /// This ensures the user's WiFi connection persists after reboot
-    fn copy_wifi_connections(&self) {
-        let live_nm_dir = "/etc/NetworkManager/system-connections";
-        let target_nm_dir = format!("{}/etc/NetworkManager/system-connections", self.mount_point);
+    /// [security] options: firewall, AppArmor, sysctl hardening,
+    /// automatic updates, sshd root login and antivirus
+    fn configure_security(&self) {
+        let sec = &self.config.security;
+        let mp = &self.mount_point;
+
+        if sec.firewall {
+            // ufw cannot load rules inside the chroot; enable it for the next boot
+            self.run_chroot("sed -i 's/^ENABLED=.*/ENABLED=yes/' /etc/ufw/ufw.conf");
+            self.run_chroot("sed -i 's/^DEFAULT_INPUT_POLICY=.*/DEFAULT_INPUT_POLICY=\"DROP\"/' /etc/default/ufw");
+            self.run_chroot("systemctl enable ufw.service");
+            tui::print_success("Firewall enabled (ufw, incoming denied)");
+        }
 
-        // Create target directory
-        self.run_command(&format!("mkdir -p {target_nm_dir}"));
+        if sec.apparmor {
+            // The lsm= kernel parameter is added by install_bootloader
+            self.run_chroot("systemctl enable apparmor.service");
+            tui::print_success("AppArmor enabled");
+        }
 
-        // Copy all connection files from live session
-        self.run_command(&format!(
-            "cp -f {live_nm_dir}/*.nmconnection {target_nm_dir}/ 2>/dev/null || true"
-        ));
+        if sec.sysctl_hardening {
+            let sysctl = "\
+# Blunux sysctl hardening\n\
+kernel.kptr_restrict = 2\n\
+kernel.dmesg_restrict = 1\n\
+kernel.kexec_load_disabled = 1\n\
+kernel.unprivileged_bpf_disabled = 1\n\
+kernel.yama.ptrace_scope = 1\n\
+net.core.bpf_jit_harden = 2\n\
+fs.protected_fifos = 2\n\
+fs.protected_regular = 2\n\
+net.ipv4.tcp_syncookies = 1\n\
+net.ipv4.conf.all.rp_filter = 1\n\
+net.ipv4.conf.default.rp_filter = 1\n\
+net.ipv4.conf.all.accept_redirects = 0\n\
+net.ipv4.conf.default.accept_redirects = 0\n\
+net.ipv4.conf.all.send_redirects = 0\n\
+net.ipv4.conf.all.accept_source_route = 0\n\
+net.ipv6.conf.all.accept_redirects = 0\n\
+net.ipv6.conf.default.accept_redirects = 0\n";
+            self.run_command(&format!("mkdir -p {mp}/etc/sysctl.d"));
+            self.write_file(&format!("{mp}/etc/sysctl.d/90-blunux-hardening.conf"), sysctl);
+            tui::print_success("Sysctl hardening applied");
+        }
 
-        // Fix permissions (NM requires 600 for connection files)
-        self.run_command(&format!("chmod 600 {target_nm_dir}/*.nmconnection 2>/dev/null || true"));
+        if sec.auto_updates {
+            let service = "[Unit]\n\
+                           Description=Blunux automatic system update\n\
+                           Wants=network-online.target\n\
+                           After=network-online.target\n\
+                           \n\
+                           [Service]\n\
+                           Type=oneshot\n\
+                           ExecStart=/usr/bin/pacman -Syu --noconfirm\n";
+            let timer = "[Unit]\n\
+                         Description=Daily Blunux automatic system update\n\
+                         \n\
+                         [Timer]\n\
+                         OnCalendar=daily\n\
+                         RandomizedDelaySec=1h\n\
+                         Persistent=true\n\
+                         \n\
+                         [Install]\n\
+                         WantedBy=timers.target\n";
+            self.write_file(&format!("{mp}/etc/systemd/system/blunux-autoupdate.service"), service);
+            self.write_file(&format!("{mp}/etc/systemd/system/blunux-autoupdate.timer"), timer);
+            self.run_chroot("systemctl enable blunux-autoupdate.timer");
+            tui::print_success("Automatic updates enabled (daily)");
+        }
 
-        tui::print_info("Copied WiFi connections from live session to installed system");
+        if sec.disable_root_ssh {
+            // Applies once openssh is installed; Arch's sshd_config includes this dir
+            self.run_command(&format!("mkdir -p {mp}/etc/ssh/sshd_config.d"));
+            self.write_file(
+                &format!("{mp}/etc/ssh/sshd_config.d/10-blunux-no-root.conf"),
+                "PermitRootLogin no\n",
+            );
+            tui::print_success("Root login over SSH disabled");
+        }
+
+        if sec.antivirus {
+            self.configure_antivirus();
+        }
     }
 
-    /// Complete WiFi management setup for the installed system
-    /// Sets up NetworkManager config, polkit rules, DNS, and wpa_supplicant
-    fn setup_wifi_management(&self) {
-        // ---------------------------------------------------
-        // 1. NetworkManager main configuration
-        // ---------------------------------------------------
-        let nm_conf_dir = format!("{}/etc/NetworkManager/conf.d", self.mount_point);
-        self.run_command(&format!("mkdir -p {nm_conf_dir}"));
+    /// Controller access for Steam Input and Bluetooth settings Xbox pads need
+    fn configure_game_controllers(&self) {
+        let mode = self.config.controller_support();
+        if mode == "none" {
+            return;
+        }
+        let mp = &self.mount_point;
+
+        // hidraw access for the logged-in user (USB and Bluetooth), which
+        // Steam Input and emulators use instead of the evdev devices
+        let mut rules = String::from("# Blunux: game controllers (auto-generated by installer)\n");
+        for (vendor, name) in [
+            ("054c", "Sony DualShock/DualSense"),
+            ("045e", "Microsoft Xbox"),
+            ("057e", "Nintendo Switch Pro/Joy-Con"),
+            ("28de", "Valve Steam Controller/Deck"),
+        ] {
+            let upper = vendor.to_uppercase();
+            rules.push_str(&format!(
+                "# {name}\n\
+                 KERNEL==\"hidraw*\", ATTRS{{idVendor}}==\"{vendor}\", MODE=\"0660\", TAG+=\"uaccess\"\n\
+                 KERNEL==\"hidraw*\", KERNELS==\"*{upper}:*\", MODE=\"0660\", TAG+=\"uaccess\"\n"
+            ));
+        }
+        self.run_command(&format!("mkdir -p {mp}/etc/udev/rules.d"));
+        self.write_file(&format!("{mp}/etc/udev/rules.d/70-blunux-controllers.rules"), &rules);
+
+        // Xbox Wireless pads drop their Bluetooth link with ERTM enabled
+        self.run_command(&format!("mkdir -p {mp}/etc/modprobe.d"));
+        self.write_file(
+            &format!("{mp}/etc/modprobe.d/blunux-xbox-bluetooth.conf"),
+            "options bluetooth disable_ertm=Y\n",
+        );
+        // Faster reconnects and re-pairing for controllers that forget keys
+        self.run_chroot(
+            "sed -i -e 's/^#\\?FastConnectable *=.*/FastConnectable = true/' \
+             -e 's/^#\\?JustWorksRepairing *=.*/JustWorksRepairing = always/' /etc/bluetooth/main.conf",
+        );
+
+        let driver = if mode == "xpadneo" {
+            "xpadneo/xone (AUR)"
+        } else {
+            "in-kernel xpad/hid-playstation"
+        };
+        tui::print_success(&format!("Game controller support configured ({driver})"));
+    }
+
+    /// [services] disk_monitoring: smartd with short/long self-tests and
+    /// warnings forwarded to every logged-in desktop (and optionally mail)
+    fn configure_disk_monitoring(&self) {
+        let services = &self.config.services;
+        if !services.disk_monitoring {
+            return;
+        }
+        let mp = &self.mount_point;
+
+        let notify = "#!/bin/bash\n\
+                      # smartd warning handler (auto-generated by Blunux installer)\n\
+                      for bus in /run/user/*/bus; do\n\
+                      \x20   uid=$(basename \"$(dirname \"$bus\")\")\n\
+                      \x20   user=$(id -nu \"$uid\" 2>/dev/null) || continue\n\
+                      \x20   runuser -u \"$user\" -- env DBUS_SESSION_BUS_ADDRESS=\"unix:path=$bus\" \\\n\
+                      \x20       notify-send -u critical -i drive-harddisk \\\n\
+                      \x20       \"Disk problem: $SMARTD_DEVICE\" \"$SMARTD_MESSAGE\"\n\
+                      done\n\
+                      if [ -n \"$SMARTD_ADDRESS\" ] && command -v mail >/dev/null; then\n\
+                      \x20   echo \"$SMARTD_FULLMESSAGE\" | mail -s \"$SMARTD_SUBJECT\" \"$SMARTD_ADDRESS\"\n\
+                      fi\n";
+        let notify_path = format!("{mp}/usr/local/bin/blunux-smartd-notify");
+        self.run_command(&format!("mkdir -p {mp}/usr/local/bin"));
+        self.write_file(&notify_path, notify);
+        self.run_command(&format!("chmod 755 {notify_path}"));
+
+        // <nomailer> still runs the -M exec handler, just without an address
+        let mail_to = if services.disk_monitoring_email.is_empty() {
+            "<nomailer>"
+        } else {
+            &services.disk_monitoring_email
+        };
+        let smartd_conf = format!(
+            "# Blunux: monitor all disks, short test daily 02:00, long test Saturdays 03:00\n\
+             DEVICESCAN -a -o on -S on -n standby,q -s (S/../.././02|L/../../6/03) -W 4,45,55 \
+             -m {mail_to} -M exec /usr/local/bin/blunux-smartd-notify\n"
+        );
+        self.write_file(&format!("{mp}/etc/smartd.conf"), &smartd_conf);
+        self.run_chroot("systemctl enable smartd.service");
+
+        tui::print_success("Disk monitoring enabled (smartd)");
+    }
+
+    /// [services] fs_maintenance: the scrub timers the filesystem packages
+    /// ship. Weekly fstrim is already on for SSDs (configure_trim).
+    fn configure_fs_maintenance(&self) {
+        if !self.config.services.fs_maintenance {
+            return;
+        }
+        let mut timers: Vec<String> = Vec::new();
+
+        // Every btrfs filesystem mounted by the installer: root and data partitions
+        let mut btrfs_mounts: Vec<&str> = Vec::new();
+        if self.config.disk.filesystem == Filesystem::Btrfs && !self.config.disk.lvm.enabled {
+            btrfs_mounts.push("/");
+        }
+        for part in &self.partition_layout.data_partitions {
+            if part.filesystem == "btrfs" && !part.mountpoint.is_empty() {
+                btrfs_mounts.push(&part.mountpoint);
+            }
+        }
+        for mount in btrfs_mounts {
+            let unit = self.exec_output(&format!("systemd-escape -p '{mount}'"));
+            timers.push(format!("btrfs-scrub@{}.timer", unit.trim()));
+        }
+
+        match self.config.disk.filesystem {
+            Filesystem::Zfs => timers.push(format!("zfs-scrub-monthly@{}.timer", disk::ZFS_POOL)),
+            // e2scrub checks a snapshot, so it only works on logical volumes
+            _ if self.config.disk.lvm.enabled => timers.push("e2scrub_all.timer".to_string()),
+            _ => {}
+        }
+
+        if timers.is_empty() {
+            tui::print_info("No scrub timer for this filesystem - skipping fs maintenance");
+            return;
+        }
+        for timer in &timers {
+            self.run_chroot(&format!("systemctl enable {timer}"));
+        }
+        tui::print_success(&format!("Filesystem maintenance: {}", timers.join(", ")));
+    }
+
+    /// [services] pacman_cache_keep: weekly paccache run keeping that many
+    /// versions of each installed package and none of removed ones
+    fn configure_pacman_cache(&self) {
+        let keep = self.config.services.pacman_cache_keep;
+        if keep == 0 {
+            return;
+        }
+        let dropin = format!(
+            "[Service]\nExecStart=\nExecStart=/usr/bin/paccache -rk{keep}\n\
+             ExecStart=/usr/bin/paccache -ruk0\n"
+        );
+        self.apply_files(&[FileEntry::file(
+            "/etc/systemd/system/paccache.service.d/keep.conf",
+            "",
+            &dropin,
+        )]);
+        self.run_chroot("systemctl enable paccache.timer");
+        tui::print_success(&format!("Pacman cache cleanup: weekly, keeping {keep} versions"));
+    }
+
+    /// [services] mirror_refresh: reflector.timer rewrites the mirrorlist
+    /// weekly with the fastest recently synced HTTPS mirrors
+    fn configure_mirror_refresh(&self) {
+        let services = &self.config.services;
+        if !services.mirror_refresh {
+            return;
+        }
+        let mut conf = String::from(
+            "# Mirror ranking for reflector.timer (generated by Blunux installer)\n\
+             --save /etc/pacman.d/mirrorlist\n\
+             --protocol https\n\
+             --age 12\n\
+             --latest 20\n\
+             --fastest 10\n\
+             --sort rate\n",
+        );
+        if !services.mirror_countries.is_empty() {
+            conf.push_str(&format!("--country '{}'\n", services.mirror_countries.join(",")));
+        }
+        self.apply_files(&[FileEntry::file("/etc/xdg/reflector/reflector.conf", "", &conf)]);
+        self.run_chroot("systemctl enable reflector.timer");
+        tui::print_success("Mirror list refreshed weekly by reflector.timer");
+    }
+
+    /// clamd + clamonacc on-access scanning of /home, with signatures
+    /// fetched by a first-boot freshclam run and kept fresh by the daemon
+    fn configure_antivirus(&self) {
+        let clamd_conf = format!("{}/etc/clamav/clamd.conf", self.mount_point);
+        let current = fs::read_to_string(&clamd_conf).unwrap_or_default();
+
+        let mut onacc = String::from("\n# Blunux: on-access scanning (clamonacc)\n");
+        if !current.lines().any(|l| l.starts_with("LocalSocket ")) {
+            onacc.push_str("LocalSocket /run/clamav/clamd.ctl\n");
+        }
+        onacc.push_str(
+            "OnAccessIncludePath /home\n\
+             OnAccessExcludeUname clamav\n\
+             OnAccessExtraScanning yes\n\
+             OnAccessPrevention no\n",
+        );
+        self.append_file(&clamd_conf, &onacc);
+
+        // clamd refuses to start without signatures, which need the network
+        self.queue_firstboot(
+            "40-clamav-signatures",
+            "#!/bin/bash\nset -e\nfreshclam\n\
+             systemctl restart clamav-daemon.service clamav-clamonacc.service\n",
+        );
+        self.run_chroot("systemctl enable clamav-freshclam.service");
+        self.run_chroot("systemctl enable clamav-daemon.service");
+        self.run_chroot("systemctl enable clamav-clamonacc.service");
+
+        tui::print_success("ClamAV enabled (on-access scanning of /home)");
+    }
+
+    /// Embed an SSH server in the initramfs so a headless machine can be
+    /// unlocked remotely (netconf + dropbear/tinyssh + encryptssh hooks)
+    fn configure_ssh_unlock(&self) {
+        let enc = &self.config.disk.encryption;
+        if enc.ssh_unlock_key.is_empty() || !self.config.uses_luks() {
+            return;
+        }
+
+        tui::print_info(&format!(
+            "Configuring SSH unlock in initramfs ({})...",
+            enc.ssh_unlock_server
+        ));
+
+        // Both mkinitcpio hooks read the authorized key from /etc/<server>/root_key
+        let key_dir = format!("{}/etc/{}", self.mount_point, enc.ssh_unlock_server);
+        self.run_command(&format!("mkdir -p {key_dir}"));
+        self.write_file(
+            &format!("{key_dir}/root_key"),
+            &format!("{}\n", enc.ssh_unlock_key.trim()),
+        );
+        self.run_command(&format!("chmod 600 {key_dir}/root_key"));
+
+        // encryptssh is a busybox-init hook and replaces encrypt
+        self.use_busybox_initramfs();
+        self.add_mkinitcpio_hook("netconf");
+        self.add_mkinitcpio_hook(&enc.ssh_unlock_server);
+        self.add_mkinitcpio_hook("encryptssh");
+        self.run_chroot("mkinitcpio -P");
+
+        tui::print_success("Root can be unlocked over SSH at boot (ip=dhcp)");
+    }
+
+    /// Inverse of use_systemd_initramfs, for hooks that need busybox init
+    fn use_busybox_initramfs(&self) {
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        self.run_command(&format!(
+            "sed -i '/^HOOKS=/ {{ s/\\bsystemd\\b/udev/; s/\\bsd-vconsole\\b/keymap consolefont/; s/ sd-encrypt\\b//; s/ encrypt\\b//; }}' {conf}"
+        ));
+    }
+
+    /// Encrypted /boot: GRUB asks for the passphrase, then the initramfs
+    /// unlocks root with an embedded keyfile so it is only typed once
+    fn configure_encrypted_boot(&self) {
+        if !self.config.disk.encryption.encrypt_boot || !self.config.uses_luks() {
+            return;
+        }
+
+        tui::print_info("Configuring encrypted /boot...");
+
+        let Some(keyfile) = self.enroll_initramfs_keyfile() else {
+            tui::print_warning("Keyfile enrollment failed - passphrase will be asked twice");
+            return;
+        };
+
+        let root_uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+        self.write_initramfs_crypttab(
+            "Root unlocked with the initramfs keyfile",
+            &format!("UUID={root_uuid}"),
+            &keyfile,
+            &format!("luks{}", self.luks1_discard(",discard")),
+        );
+
+        self.use_systemd_initramfs();
+        self.add_mkinitcpio_hook("sd-encrypt");
+        self.run_chroot("mkinitcpio -P");
+        // The images now contain the keyfile
+        self.run_chroot("chmod 600 /boot/initramfs-*.img");
+
+        tui::print_success("Encrypted /boot configured");
+    }
+
+    /// /etc/crypttab for [[disk.partitions]] entries with encrypt = true.
+    /// With an encrypted root they unlock from a keyfile stored on it;
+    /// otherwise systemd asks for the password during boot.
+    fn configure_encrypted_partitions(&self) {
+        let encrypted: Vec<&disk::DataPartition> = self
+            .partition_layout
+            .data_partitions
+            .iter()
+            .filter(|p| p.encrypt)
+            .collect();
+        if encrypted.is_empty() {
+            return;
+        }
+        let mp = &self.mount_point;
+        let key_dir = format!("{mp}/etc/cryptsetup-keys.d");
+        self.run_command(&format!("mkdir -p {key_dir} && chmod 700 {key_dir}"));
+
+        let mut crypttab = String::from("\n# Encrypted data partitions (generated by Blunux installer)\n");
+        for part in encrypted {
+            let name = part.mapper_name();
+            let uuid = disk::probe::uuid(&part.device);
+
+            // systemd-cryptsetup looks up /etc/cryptsetup-keys.d/<name>.key
+            // by itself when the key field is "none"
+            if self.config.install.use_encryption {
+                let keyfile = format!("{key_dir}/{name}.key");
+                let enrolled = self.run_command(&format!(
+                    "dd bs=512 count=4 if=/dev/random of={keyfile} iflag=fullblock 2>/dev/null"
+                )) && self.run_command(&format!(
+                    "echo -n '{}' | cryptsetup luksAddKey --key-file=- {} {keyfile}",
+                    self.config.install.encryption_password, part.device
+                ));
+                self.run_command(&format!("chmod 000 {keyfile}"));
+                if !enrolled {
+                    tui::print_warning(&format!(
+                        "{} will ask for its password at boot (keyfile setup failed)",
+                        part.mountpoint
+                    ));
+                }
+            }
+            let discard = self.luks1_discard(",discard");
+            crypttab.push_str(&format!("{name} UUID={uuid} none luks{discard}\n"));
+        }
+        self.append_file(&format!("{mp}/etc/crypttab"), &crypttab);
+        tui::print_success("Encrypted data partitions added to /etc/crypttab");
+    }
+
+    /// Copy the keyfile enrolled by `format_partitions` into the target
+    /// and embed it in the initramfs via FILES. Returns the keyfile path
+    /// inside the target.
+    fn enroll_initramfs_keyfile(&self) -> Option<String> {
+        let keyfile = "/etc/cryptsetup-keys.d/cryptroot.key";
+        let host_keyfile = format!("{}{keyfile}", self.mount_point);
+        if !Path::new(disk::ROOT_KEYFILE).exists() {
+            return None;
+        }
+
+        self.run_command(&format!(
+            "mkdir -p {}/etc/cryptsetup-keys.d && chmod 700 {}/etc/cryptsetup-keys.d",
+            self.mount_point, self.mount_point
+        ));
+        if !self.run_command(&format!("cp {} {host_keyfile}", disk::ROOT_KEYFILE)) {
+            return None;
+        }
+        self.run_command(&format!("chmod 000 {host_keyfile}"));
+
+        self.add_mkinitcpio_file(keyfile);
+        Some(keyfile.to_string())
+    }
+
+    /// [disk.encryption] tpm2: let sd-encrypt try the TPM2 token enrolled by
+    /// `format_partitions` before asking for the passphrase
+    fn configure_tpm_unlock(&self) {
+        if !self.config.disk.encryption.tpm2 || !self.config.uses_luks() {
+            return;
+        }
+
+        tui::print_info("Configuring TPM2 unlock...");
+        let root_uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+        self.write_initramfs_crypttab(
+            "Root unlocked by the TPM, passphrase as fallback",
+            &format!("UUID={root_uuid}"),
+            "none",
+            "tpm2-device=auto",
+        );
+
+        self.use_systemd_initramfs();
+        self.add_mkinitcpio_hook("sd-encrypt");
+        self.run_chroot("mkinitcpio -P");
+
+        // Firmware updates and Secure Boot changes alter PCR 7
+        tui::print_success(
+            "Root unlocks with the TPM - after firmware or Secure Boot changes, re-enroll with \
+             systemd-cryptenroll --wipe-slot=tpm2 --tpm2-device=auto --tpm2-pcrs=7",
+        );
+    }
+
+    /// [disk.encryption] keyfile device: store the root keyfile on it and
+    /// let sd-encrypt try it before asking for the passphrase
+    fn configure_keyfile_device(&self) {
+        let key_dev = self.config.root_keyfile();
+        if !key_dev.starts_with("/dev/") {
+            return;
+        }
+        if !Path::new(disk::ROOT_KEYFILE).exists() {
+            tui::print_warning("No keyfile was enrolled - root will ask for the passphrase");
+            return;
+        }
+
+        tui::print_info(&format!("Storing the root keyfile on {key_dev}..."));
+        let stored = self.run_command(&format!("mkdir -p {}", disk::KEYS_MOUNT))
+            && self.run_command(&format!("mount {key_dev} {}", disk::KEYS_MOUNT));
+        let stored = stored
+            && self.run_command(&format!(
+                "cp {} {}/{} && chmod 400 {}/{}",
+                disk::ROOT_KEYFILE,
+                disk::KEYS_MOUNT,
+                disk::KEYFILE_NAME,
+                disk::KEYS_MOUNT,
+                disk::KEYFILE_NAME
+            ));
+        self.run_command(&format!("umount {} 2>/dev/null", disk::KEYS_MOUNT));
+        if !stored {
+            tui::print_warning(&format!(
+                "Could not write the keyfile to {key_dev} - root will ask for the passphrase"
+            ));
+            return;
+        }
+
+        let root_uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+        let key = disk::probe::blkid(key_dev);
+        // Without the key device the passphrase prompt follows after 10 s
+        self.write_initramfs_crypttab(
+            &format!("Root unlocked with the keyfile on {key_dev}"),
+            &format!("UUID={root_uuid}"),
+            &format!("/{}:UUID={}", disk::KEYFILE_NAME, key.uuid),
+            &format!("luks,keyfile-timeout=10s{}", self.luks1_discard(",discard")),
+        );
+
+        self.use_systemd_initramfs();
+        self.add_mkinitcpio_hook("sd-encrypt");
+        if !key.fs_type.is_empty() {
+            self.add_mkinitcpio_module(&key.fs_type);
+        }
+        self.run_chroot("mkinitcpio -P");
+
+        tui::print_success(&format!("Root will unlock with the keyfile on {key_dev}"));
+    }
+
+    /// crypttab.initramfs + sd-encrypt so the initramfs opens root with the
+    /// LUKS header stored on the removable device
+    fn configure_detached_header(&self) {
+        let header_dev = &self.config.disk.encryption.detached_header;
+        if header_dev.is_empty() || !self.config.uses_luks() {
+            return;
+        }
+
+        tui::print_info("Configuring detached LUKS header unlock...");
+
+        let root_partuuid = disk::probe::blkid(&self.partition_layout.root_partition).partuuid;
+        let header = disk::probe::blkid(header_dev);
+        let (header_uuid, header_fstype) = (header.uuid, header.fs_type);
+
+        // Without the header the partition has no LUKS UUID, so refer to it by PARTUUID
+        self.write_initramfs_crypttab(
+            "Root with detached LUKS header",
+            &format!("PARTUUID={root_partuuid}"),
+            "none",
+            &format!(
+                "header=/{}:UUID={header_uuid}{}",
+                disk::DETACHED_HEADER_FILE,
+                self.luks1_discard(",discard")
+            ),
+        );
+
+        // The header option is only understood by systemd-cryptsetup
+        self.use_systemd_initramfs();
+        self.add_mkinitcpio_hook("sd-encrypt");
+        if !header_fstype.is_empty() {
+            self.add_mkinitcpio_module(&header_fstype);
+        }
+        self.run_chroot("mkinitcpio -P");
+
+        tui::print_success(&format!("Root will unlock with the header on {header_dev}"));
+    }
+
+    /// /etc/crypttab.initramfs for sd-encrypt: the single line opening root
+    /// as cryptroot from `source` with `key` ("none" to ask) and `opts`
+    fn write_initramfs_crypttab(&self, comment: &str, source: &str, key: &str, opts: &str) {
+        let crypttab = format!(
+            "# {comment} (generated by Blunux installer)\n\
+             cryptroot {source} {key} {opts}\n"
+        );
+        self.write_file(
+            &format!("{}/etc/crypttab.initramfs", self.mount_point),
+            &crypttab,
+        );
+    }
+
+    /// Switch busybox-style HOOKS (udev, keymap, consolefont) to their systemd equivalents
+    fn use_systemd_initramfs(&self) {
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        self.run_command(&format!(
+            "sed -i '/^HOOKS=/ {{ s/\\budev\\b/systemd/; s/\\bkeymap\\b/sd-vconsole/; s/ consolefont//; s/ encrypt\\b//; }}' {conf}"
+        ));
+    }
+
+    /// Add a file to mkinitcpio.conf FILES so it is copied into the initramfs
+    fn add_mkinitcpio_file(&self, path: &str) {
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        let content = fs::read_to_string(&conf).unwrap_or_default();
+        let already_present = content.lines().any(|l| {
+            l.starts_with("FILES=") && l.split(['(', ')', ' ']).any(|f| f == path)
+        });
+        if already_present {
+            return;
+        }
+        self.run_command(&format!(
+            "sed -i '/^FILES=/ {{ s|()|({path})|; t; s|)$| {path})|; }}' {conf}"
+        ));
+    }
+
+    /// Add a kernel module to mkinitcpio.conf MODULES
+    fn add_mkinitcpio_module(&self, module: &str) {
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        let content = fs::read_to_string(&conf).unwrap_or_default();
+        let already_present = content.lines().any(|l| {
+            l.starts_with("MODULES=") && l.split(['(', ')', ' ']).any(|m| m == module)
+        });
+        if already_present {
+            return;
+        }
+        self.run_command(&format!(
+            "sed -i '/^MODULES=/ {{ s/()/({module})/; t; s/)$/ {module})/; }}' {conf}"
+        ));
+    }
+
+    /// Snapper config for the root subvolume. /.snapshots is already the
+    /// @snapshots subvolume, so the config is written directly instead of
+    /// `snapper create-config` (which wants to create that subvolume itself).
+    fn configure_snapper(&self) {
+        let mp = &self.mount_point;
+        self.run_command(&format!("mkdir -p {mp}/etc/snapper/configs {mp}/etc/conf.d"));
+        self.run_command(&format!(
+            "cp {mp}/usr/share/snapper/config-templates/default {mp}/etc/snapper/configs/root"
+        ));
+        // Keep a bounded number of hourly/daily snapshots
+        self.run_command(&format!(
+            "sed -i -e 's/^TIMELINE_LIMIT_HOURLY=.*/TIMELINE_LIMIT_HOURLY=\"5\"/' \
+             -e 's/^TIMELINE_LIMIT_DAILY=.*/TIMELINE_LIMIT_DAILY=\"7\"/' \
+             -e 's/^TIMELINE_LIMIT_MONTHLY=.*/TIMELINE_LIMIT_MONTHLY=\"0\"/' \
+             -e 's/^TIMELINE_LIMIT_YEARLY=.*/TIMELINE_LIMIT_YEARLY=\"0\"/' \
+             {mp}/etc/snapper/configs/root"
+        ));
+        self.write_file(&format!("{mp}/etc/conf.d/snapper"), "SNAPPER_CONFIGS=\"root\"\n");
+        self.run_command(&format!("chmod 750 {mp}/.snapshots"));
+        self.run_chroot("systemctl enable snapper-timeline.timer snapper-cleanup.timer");
+        tui::print_success("Snapper snapshots configured (hourly + before/after pacman)");
+    }
+
+    /// Timeshift in btrfs mode: hourly/daily schedule driven by cron, plus a
+    /// snapshot before every pacman transaction
+    fn configure_timeshift(&self) {
+        let mp = &self.mount_point;
+        let fs_uuid = self.exec_output(&format!("findmnt -no UUID {mp}"));
+        let settings = format!(
+            r#"{{
+  "backup_device_uuid" : "{}",
+  "parent_device_uuid" : "",
+  "do_first_run" : "false",
+  "btrfs_mode" : "true",
+  "include_btrfs_home_for_backup" : "false",
+  "include_btrfs_home_for_restore" : "false",
+  "stop_cron_emails" : "true",
+  "schedule_monthly" : "false",
+  "schedule_weekly" : "false",
+  "schedule_daily" : "true",
+  "schedule_hourly" : "true",
+  "schedule_boot" : "false",
+  "count_monthly" : "0",
+  "count_weekly" : "0",
+  "count_daily" : "7",
+  "count_hourly" : "5",
+  "count_boot" : "0"
+}}
+"#,
+            fs_uuid.trim()
+        );
+        self.run_command(&format!("mkdir -p {mp}/etc/timeshift {mp}/etc/cron.d"));
+        self.write_file(&format!("{mp}/etc/timeshift/timeshift.json"), &settings);
+        // Timeshift itself only installs this cron job from its GUI
+        self.write_file(
+            &format!("{mp}/etc/cron.d/timeshift-hourly"),
+            "SHELL=/bin/bash\nPATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin\n\
+             0 * * * * root timeshift --check --scripted\n",
+        );
+        self.run_chroot("systemctl enable cronie.service");
+
+        let hook = "[Trigger]\n\
+                    Operation = Upgrade\n\
+                    Operation = Install\n\
+                    Operation = Remove\n\
+                    Type = Package\n\
+                    Target = *\n\
+                    \n\
+                    [Action]\n\
+                    Description = Creating Timeshift snapshot before transaction...\n\
+                    When = PreTransaction\n\
+                    Exec = /usr/bin/timeshift --create --scripted --tags O --comments pacman\n";
+        self.run_command(&format!("mkdir -p {mp}/etc/pacman.d/hooks"));
+        self.write_file(&format!("{mp}/etc/pacman.d/hooks/00-timeshift.hook"), hook);
+        tui::print_success("Timeshift snapshots configured (hourly + before pacman)");
+    }
+
+    /// [disk] snapshots: snapshot tool, and grub-btrfs so snapshots show up
+    /// as boot entries. Runs after the bootloader so grub.cfg can be rebuilt.
+    fn configure_snapshots(&self) {
+        if !self.config.disk.snapshots || self.config.disk.filesystem != Filesystem::Btrfs {
+            return;
+        }
+        if self.config.disk.snapshot_tool == "timeshift" {
+            self.configure_timeshift();
+        } else {
+            self.configure_snapper();
+        }
+
+        if !self.boots_with_grub() {
+            tui::print_info("Booting into snapshots needs GRUB - skipping grub-btrfs (NMBL)");
+            return;
+        }
+        if self.config.disk.snapshot_tool == "timeshift" {
+            // grub-btrfsd watches /.snapshots unless told to follow Timeshift
+            let dropin = format!("{}/etc/systemd/system/grub-btrfsd.service.d", self.mount_point);
+            self.run_command(&format!("mkdir -p {dropin}"));
+            self.write_file(
+                &format!("{dropin}/timeshift.conf"),
+                "[Service]\nExecStart=\nExecStart=/usr/bin/grub-btrfsd --syslog --timeshift-auto\n",
+            );
+        }
+        self.run_chroot("systemctl enable grub-btrfsd.service");
+        self.run_chroot("grub-mkconfig -o /boot/grub/grub.cfg");
+        tui::print_success("grub-btrfs: snapshots are listed in the GRUB menu");
+    }
+
+    /// GRUB is the boot loader (NMBL falls back to it on BIOS machines)
+    fn boots_with_grub(&self) -> bool {
+        self.config.install.bootloader != "nmbl" || !disk::is_uefi()
+    }
+
+    /// Weekly TRIM on SSD targets; discards pass through LUKS because the
+    /// containers were opened with --allow-discards --persistent (LUKS1:
+    /// `luks1_discard` options at every unlock)
+    fn configure_trim(&self) {
+        if !self.partition_layout.ssd {
+            return;
+        }
+        if self.config.disk.filesystem == Filesystem::Zfs {
+            self.run_command(&format!("zpool set autotrim=on {}", disk::ZFS_POOL));
+        }
+        self.run_chroot("systemctl enable fstrim.timer");
+        tui::print_success("SSD detected: weekly fstrim enabled");
+    }
+
+    /// The root passphrase is typed in the initramfs, which uses the US
+    /// keymap unless the console keymap and font from vconsole.conf are
+    /// built in. The hooks must come before `block` so they run before any
+    /// unlock hook; use_systemd_initramfs turns them into sd-vconsole.
+    fn configure_passphrase_keymap(&self) {
+        if !self.config.install.use_encryption {
+            return;
+        }
+        let added = if self.has_mkinitcpio_hook("systemd") {
+            self.insert_mkinitcpio_hook("keyboard", "block")
+                | self.insert_mkinitcpio_hook("sd-vconsole", "block")
+        } else {
+            self.insert_mkinitcpio_hook("keyboard", "block")
+                | self.insert_mkinitcpio_hook("keymap", "block")
+                | self.insert_mkinitcpio_hook("consolefont", "block")
+        };
+        if added {
+            self.run_chroot("mkinitcpio -P");
+        }
+        let keymap = self
+            .config
+            .locale
+            .keyboards
+            .first()
+            .map_or("us".to_string(), |kb| kb.console_keymap());
+        tui::print_success(&format!("Passphrase prompt at boot uses the {keymap} keymap"));
+    }
+
+    /// eMMC and SD cards wear out under small writes: cap the journal and
+    /// flush it to disk less often
+    fn configure_flash_storage(&self) {
+        if !self.partition_layout.flash {
+            return;
+        }
+        self.apply_files(&[FileEntry::file(
+            "/etc/systemd/journald.conf.d/flash.conf",
+            "",
+            "[Journal]\nSystemMaxUse=64M\nSyncIntervalSec=15m\n",
+        )]);
+        tui::print_success("eMMC/SD card: journal capped at 64 MB and synced every 15 min");
+    }
+
+    /// --image: the image boots on other hardware, so the initramfs must not
+    /// be cut down to the build machine's modules by autodetect. NMBL has no
+    /// fallback initramfs to rescue it.
+    fn configure_portable_initramfs(&self) {
+        if self.config.image.is_empty() {
+            return;
+        }
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        self.run_command(&format!("sed -i '/^HOOKS=/ s/ autodetect\\b//' {conf}"));
+        self.run_chroot("mkinitcpio -P");
+        tui::print_success("Initramfs includes all modules (no autodetect) for the image");
+    }
+
+    /// Initramfs hooks and services needed to boot from a non-ext4 root
+    fn configure_filesystem_support(&self) {
+        match self.config.disk.filesystem {
+            Filesystem::Ext4 => {}
+            Filesystem::Xfs | Filesystem::F2fs => {
+                // Keep the root filesystem driver in the initramfs even if
+                // autodetect misses it when run from the chroot
+                let fs_type = self.config.disk.filesystem.fs_type();
+                self.add_mkinitcpio_module(fs_type);
+                self.run_chroot("mkinitcpio -P");
+                tui::print_success(&format!("{fs_type} root support added to initramfs"));
+            }
+            Filesystem::Btrfs => {}
+            Filesystem::Bcachefs => {
+                // bcachefs needs its initramfs hook to assemble (and unlock) the root
+                self.add_mkinitcpio_hook("bcachefs");
+                self.run_chroot("mkinitcpio -P");
+            }
+            Filesystem::Zfs => {
+                tui::print_info("Configuring ZFS boot support...");
+
+                // The pool is tied to the host id it was imported with
+                self.run_command("zgenhostid -f $(hostid) 2>/dev/null || true");
+                self.run_command(&format!("cp /etc/hostid {}/etc/hostid", self.mount_point));
+                self.run_command(&format!("mkdir -p {}/etc/zfs", self.mount_point));
+                self.run_command(&format!(
+                    "cp /etc/zfs/zpool.cache {}/etc/zfs/zpool.cache",
+                    self.mount_point
+                ));
+
+                for unit in ["zfs-import-cache", "zfs-import.target", "zfs-mount", "zfs.target"] {
+                    self.run_chroot(&format!("systemctl enable {unit}"));
+                }
+
+                self.add_mkinitcpio_hook("zfs");
+                self.run_chroot("mkinitcpio -P");
+                tui::print_success("ZFS boot support configured");
+            }
+        }
+    }
+
+    /// lvm2 initramfs hook. With LUKS the volume group sits inside
+    /// cryptroot: unless an earlier step already set up sd-encrypt (or
+    /// encryptssh), open it from crypttab.initramfs. Hooks go in right
+    /// before `filesystems`, so lvm2 lands after the unlock hook.
+    fn configure_lvm(&self) {
+        if !self.config.disk.lvm.enabled {
+            return;
+        }
+        if self.config.unlocks_root_from_crypttab() && !self.has_mkinitcpio_hook("sd-encrypt") {
+            let root_uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+            self.write_initramfs_crypttab(
+                "LUKS container holding the LVM volume group",
+                &format!("UUID={root_uuid}"),
+                "none",
+                &format!("luks{}", self.luks1_discard(",discard")),
+            );
+            self.use_systemd_initramfs();
+            self.add_mkinitcpio_hook("sd-encrypt");
+        }
+        self.add_mkinitcpio_hook("lvm2");
+        self.run_chroot("mkinitcpio -P");
+        tui::print_success("LVM boot support configured");
+    }
+
+    /// Insert a hook into mkinitcpio.conf HOOKS, right before `filesystems`
+    fn add_mkinitcpio_hook(&self, hook: &str) {
+        self.insert_mkinitcpio_hook(hook, "filesystems");
+    }
+
+    /// Insert a hook into mkinitcpio.conf HOOKS right before `before`.
+    /// Returns false if it was already there.
+    fn insert_mkinitcpio_hook(&self, hook: &str, before: &str) -> bool {
+        if self.has_mkinitcpio_hook(hook) {
+            return false;
+        }
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        self.run_command(&format!(
+            "sed -i '/^HOOKS=/ s/ {before}\\b/ {hook} {before}/' {conf}"
+        ))
+    }
+
+    fn has_mkinitcpio_hook(&self, hook: &str) -> bool {
+        let conf = format!("{}/etc/mkinitcpio.conf", self.mount_point);
+        let content = fs::read_to_string(&conf).unwrap_or_default();
+        content.lines().any(|l| {
+            l.starts_with("HOOKS=") && l.split(['(', ')', ' ']).any(|h| h == hook)
+        })
+    }
+
+    /// Copy WiFi connections from the live session to the installed system
+    /// This ensures the user's WiFi connection persists after reboot
+    fn copy_wifi_connections(&self) {
+        let live_nm_dir = "/etc/NetworkManager/system-connections";
+        let target_nm_dir = format!("{}/etc/NetworkManager/system-connections", self.mount_point);
+
+        // Create target directory
+        self.run_command(&format!("mkdir -p {target_nm_dir}"));
+
+        // Copy all connection files from live session
+        self.run_command(&format!(
+            "cp -f {live_nm_dir}/*.nmconnection {target_nm_dir}/ 2>/dev/null || true"
+        ));
+
+        // Networks joined with iwctl on the live ISO live in iwd's own format;
+        // the installed system uses NetworkManager, so convert them
+        let imported = self.import_iwd_networks(&target_nm_dir);
+
+        // Fix permissions (NM requires 600 for connection files)
+        self.run_command(&format!("chmod 600 {target_nm_dir}/*.nmconnection 2>/dev/null || true"));
+
+        tui::print_info("Copied WiFi connections from live session to installed system");
+        if imported > 0 {
+            tui::print_info(&format!("Converted {imported} iwd network(s) to NetworkManager profiles"));
+        }
+    }
+
+    /// Write a NetworkManager keyfile for each /var/lib/iwd PSK/open network
+    /// that has no NetworkManager profile yet. Returns the number written.
+    fn import_iwd_networks(&self, target_nm_dir: &str) -> usize {
+        let Ok(entries) = fs::read_dir("/var/lib/iwd") else {
+            return 0;
+        };
+
+        // SSIDs already covered by copied NetworkManager profiles
+        let existing = self.exec_output(&format!(
+            "grep -h '^ssid=' {target_nm_dir}/*.nmconnection 2>/dev/null"
+        ));
+
+        let mut imported = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let (Some(stem), Some(ext)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) else {
+                continue;
+            };
+            if ext != "psk" && ext != "open" {
+                // 802.1X needs certificates and identities; leave it to the user
+                continue;
+            }
+
+            // Names that are not plain alphanumerics are stored as "=<hex>"
+            let ssid = match stem.strip_prefix('=') {
+                Some(hex) => {
+                    let bytes: Vec<u8> = (0..hex.len() / 2)
+                        .filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+                        .collect();
+                    String::from_utf8_lossy(&bytes).to_string()
+                }
+                None => stem.to_string(),
+            };
+            if ssid.is_empty() || existing.lines().any(|l| l == format!("ssid={ssid}")) {
+                continue;
+            }
+
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            let value = |key: &str| {
+                content
+                    .lines()
+                    .find_map(|l| l.trim().strip_prefix(key))
+                    .map(|v| v.trim().to_string())
+            };
+            let hidden = value("Hidden=").as_deref() == Some("true");
+            let autoconnect = value("AutoConnect=").as_deref() != Some("false");
+
+            let mut security = String::new();
+            if ext == "psk" {
+                // iwd may keep only the derived key; NM accepts either form
+                let Some(psk) = value("Passphrase=").or_else(|| value("PreSharedKey=")) else {
+                    continue;
+                };
+                security = format!("\n[wifi-security]\nkey-mgmt=wpa-psk\npsk={psk}\n");
+            }
+
+            let uuid = self.exec_output("cat /proc/sys/kernel/random/uuid");
+            let profile = format!(
+                "[connection]\n\
+                 id={ssid}\n\
+                 uuid={uuid}\n\
+                 type=wifi\n\
+                 autoconnect={autoconnect}\n\
+                 \n\
+                 [wifi]\n\
+                 mode=infrastructure\n\
+                 ssid={ssid}\n\
+                 hidden={hidden}\n\
+                 {security}\
+                 \n\
+                 [ipv4]\n\
+                 method=auto\n\
+                 \n\
+                 [ipv6]\n\
+                 addr-gen-mode=default\n\
+                 method=auto\n"
+            );
+
+            // File names cannot contain '/', which SSIDs may
+            let file_name = ssid.replace('/', "_");
+            if self.write_file(&format!("{target_nm_dir}/{file_name}.nmconnection"), &profile) {
+                imported += 1;
+            }
+        }
+        imported
+    }
+
+    /// Complete WiFi management setup for the installed system
+    /// Sets up NetworkManager config, polkit rules, DNS, and wpa_supplicant
+    fn setup_wifi_management(&self) {
+        // ---------------------------------------------------
+        // 1. NetworkManager main configuration
+        // ---------------------------------------------------
+        let nm_conf_dir = format!("{}/etc/NetworkManager/conf.d", self.mount_point);
+        self.run_command(&format!("mkdir -p {nm_conf_dir}"));
 
         // Main NM config: keyfile plugin + WiFi-friendly defaults
         // wpa_supplicant is used automatically (iwd.service is masked)
@@ -534,9 +2101,45 @@ nameserver 1.1.1.1\n";
 
     /// Configure swap based on [disk] swap setting from config.toml
     /// Previously hardcoded to 8GB - now dynamically calculated from RAM
-    fn setup_swap(&self) {
+    fn setup_swap(&mut self) -> bool {
         let swap_mode = &self.config.disk.swap;
 
+        // Swapfiles on ZFS datasets can deadlock; bcachefs has no swapfile support
+        let needs_swapfile = !matches!(swap_mode, SwapMode::None | SwapMode::Partition);
+        if !self.config.disk.filesystem.supports_swapfile() && needs_swapfile {
+            tui::print_warning(&format!(
+                "Swap files are not supported on {} - skipping swap setup",
+                self.config.disk.filesystem.fs_type()
+            ));
+            return true;
+        }
+
+        // The swap logical volume was created and added to fstab already
+        if self.config.disk.lvm.enabled {
+            if *swap_mode != SwapMode::None {
+                tui::print_info(&format!("Swap: /dev/{}/swap logical volume", disk::LVM_VG));
+            }
+            return true;
+        }
+
+        // A swap partition from [[disk.partitions]] replaces the swap file
+        if self.config.disk.partitions.iter().any(|p| p.is_swap()) {
+            tui::print_info("Swap: partition from [[disk.partitions]]");
+            return true;
+        }
+
+        // Swap sizes follow RAM; a guessed size may not hold a hibernation image
+        let ram_mb = match swap_mode {
+            SwapMode::Small | SwapMode::Suspend | SwapMode::File => match disk::ram_mb() {
+                Ok(ram_mb) => ram_mb,
+                Err(e) => {
+                    self.error_message = format!("Cannot size the swap file: {e}");
+                    return false;
+                }
+            },
+            _ => 0,
+        };
+
         match swap_mode {
             SwapMode::None => {
                 tui::print_info("Swap: none (as configured in config.toml [disk] swap = \"none\")");
@@ -544,8 +2147,7 @@ nameserver 1.1.1.1\n";
             }
             SwapMode::Small => {
                 // RAM / 2
-                let ram_mb = disk::get_ram_mb();
-                let swap_mb = ram_mb / 2;
+                let swap_mb = self.flash_swap_mb(swap_mode.size_mb(ram_mb));
                 tui::print_info(&format!(
                     "Swap: small ({swap_mb} MB = RAM/2, from config.toml [disk] swap = \"small\")"
                 ));
@@ -553,8 +2155,7 @@ nameserver 1.1.1.1\n";
             }
             SwapMode::Suspend => {
                 // RAM * 1 for hibernation support
-                let ram_mb = disk::get_ram_mb();
-                let swap_mb = ram_mb;
+                let swap_mb = swap_mode.size_mb(ram_mb);
                 tui::print_info(&format!(
                     "Swap: suspend ({swap_mb} MB = RAM size, from config.toml [disk] swap = \"suspend\")"
                 ));
@@ -562,121 +2163,753 @@ nameserver 1.1.1.1\n";
             }
             SwapMode::File => {
                 // Fixed reasonable default: min(RAM, 8GB)
-                let ram_mb = disk::get_ram_mb();
-                let swap_mb = ram_mb.min(8192);
+                let swap_mb = self.flash_swap_mb(swap_mode.size_mb(ram_mb));
                 tui::print_info(&format!(
                     "Swap: file ({swap_mb} MB, from config.toml [disk] swap = \"file\")"
                 ));
-                self.create_swap_file(swap_mb);
+                self.create_swap_file(swap_mb);
+            }
+            SwapMode::Partition => {
+                // Created by partition_disk and added to fstab by genfstab (UUID)
+                tui::print_info(
+                    "Swap: partition (RAM size, from config.toml [disk] swap = \"partition\")",
+                );
+            }
+        }
+        true
+    }
+
+    /// Swapping wears out eMMC and SD cards quickly, so a swap file there
+    /// only catches memory spikes; suspend keeps its RAM-sized file
+    fn flash_swap_mb(&self, swap_mb: u64) -> u64 {
+        if self.partition_layout.flash && swap_mb > FLASH_SWAP_MAX_MB {
+            tui::print_info(&format!(
+                "eMMC/SD card: swap file limited to {FLASH_SWAP_MAX_MB} MB"
+            ));
+            return FLASH_SWAP_MAX_MB;
+        }
+        swap_mb
+    }
+
+    /// resume= (plus resume_offset= for a swap file) kernel parameters for
+    /// hibernating to the RAM-sized swap of swap = "suspend" or "partition"
+    fn resume_params(&self) -> Option<String> {
+        if !matches!(self.config.disk.swap, SwapMode::Suspend | SwapMode::Partition) {
+            return None;
+        }
+        let swap_partition =
+            self.partition_layout.data_partitions.iter().find(|p| p.filesystem == "swap");
+        if let Some(part) = swap_partition {
+            let uuid = disk::probe::uuid(&part.device);
+            return Some(format!("resume=UUID={uuid}"));
+        }
+        if self.config.disk.lvm.enabled {
+            return Some(format!("resume=/dev/{}/swap", disk::LVM_VG));
+        }
+
+        let swapfile = format!("{}/swapfile", self.mount_point);
+        if !Path::new(&swapfile).exists() {
+            return None;
+        }
+        // Physical block of the swap file's first extent
+        let offset = self.exec_output(&format!(
+            "filefrag -v {swapfile} | awk '$1 == \"0:\" {{ sub(/\\.\\.$/, \"\", $4); print $4 }}'"
+        ));
+        if offset.is_empty() {
+            return None;
+        }
+        let device = if self.config.uses_luks() {
+            "/dev/mapper/cryptroot".to_string()
+        } else {
+            let uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+            format!("UUID={uuid}")
+        };
+        Some(format!("resume={device} resume_offset={offset}"))
+    }
+
+    /// Resume from swap after hibernation. The systemd initramfs resumes
+    /// from resume= on its own; busybox-style HOOKS need `resume` after
+    /// encrypt/lvm2. The kernel parameters are set with the bootloader.
+    fn configure_hibernation(&self) {
+        let Some(params) = self.resume_params() else {
+            return;
+        };
+        if self.has_mkinitcpio_hook("udev") {
+            self.add_mkinitcpio_hook("resume");
+            self.run_chroot("mkinitcpio -P");
+        }
+        tui::print_success(&format!("Hibernation configured ({params})"));
+    }
+
+    /// Suspend-then-hibernate via systemd sleep.conf and logind lid handling
+    fn configure_power(&self) {
+        if !self.config.power.suspend_then_hibernate {
+            return;
+        }
+
+        let sleep_dir = format!("{}/etc/systemd/sleep.conf.d", self.mount_point);
+        self.run_command(&format!("mkdir -p {sleep_dir}"));
+        let sleep_conf = format!(
+            "[Sleep]\n\
+             AllowSuspendThenHibernate=yes\n\
+             HibernateDelaySec={}\n",
+            self.config.power.hibernate_delay
+        );
+        self.write_file(&format!("{sleep_dir}/10-blunux.conf"), &sleep_conf);
+
+        let logind_dir = format!("{}/etc/systemd/logind.conf.d", self.mount_point);
+        self.run_command(&format!("mkdir -p {logind_dir}"));
+        let logind_conf = "[Login]\n\
+                           HandleLidSwitch=suspend-then-hibernate\n\
+                           HandleLidSwitchExternalPower=suspend\n\
+                           HandleLidSwitchDocked=ignore\n";
+        self.write_file(&format!("{logind_dir}/10-blunux-lid.conf"), logind_conf);
+
+        tui::print_success(&format!(
+            "Suspend-then-hibernate configured (hibernate after {})",
+            self.config.power.hibernate_delay
+        ));
+    }
+
+    /// Create a swap file of the given size in MB; it only goes into fstab
+    /// once `verify_swap_file` accepted it
+    fn create_swap_file(&self, size_mb: u64) {
+        if size_mb == 0 {
+            return;
+        }
+
+        let swapfile = format!("{}/swapfile", self.mount_point);
+
+        let written = disk::write_swap_file(&swapfile, size_mb, |done| {
+            tui::print_progress(&format!("Creating {size_mb} MB swap file"), done, size_mb);
+        });
+        if let Err(e) = written {
+            println!();
+            tui::print_error(&format!("Failed to create swap file: {e}"));
+            let _ = fs::remove_file(&swapfile);
+            return;
+        }
+        if !self.run_chroot("mkswap /swapfile") {
+            tui::print_error("Failed to format swap file");
+            let _ = fs::remove_file(&swapfile);
+            return;
+        }
+        // A broken swap file in fstab fails swap.target on every boot
+        if let Err(e) = self.verify_swap_file(&swapfile, size_mb) {
+            tui::print_error(&format!("Swap file check failed: {e} - continuing without swap"));
+            let _ = fs::remove_file(&swapfile);
+            return;
+        }
+
+        // Add swap to fstab
+        let fstab_path = format!("{}/etc/fstab", self.mount_point);
+        self.append_file(&fstab_path, "\n# Swap file\n/swapfile none swap defaults 0 0\n");
+
+        let size_display = if size_mb >= 1024 {
+            format!("{:.1} GB", size_mb as f64 / 1024.0)
+        } else {
+            format!("{size_mb} MB")
+        };
+        tui::print_success(&format!("{size_display} swap file created and configured"));
+    }
+
+    /// Check the swap file `create_swap_file` wrote: full size with every
+    /// block allocated, a swap signature, and the kernel accepts it
+    fn verify_swap_file(&self, swapfile: &str, size_mb: u64) -> Result<(), String> {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = fs::metadata(swapfile).map_err(|e| e.to_string())?;
+        let expected = size_mb * 1024 * 1024;
+        if meta.len() != expected {
+            return Err(format!("{} bytes instead of {expected}", meta.len()));
+        }
+        // st_blocks counts 512-byte units; holes make swapon refuse the file
+        if meta.blocks() * 512 < expected {
+            return Err("the file has holes".to_string());
+        }
+        let signature = self.exec_output(&format!("blkid -p -o value -s TYPE {swapfile}"));
+        if signature != "swap" {
+            return Err("no swap signature after mkswap".to_string());
+        }
+        // Enabled just long enough to see it listed
+        let enabled = self.run_chroot(
+            "sh -c 'swapon /swapfile && swapon --show=NAME --noheadings | grep -qx /swapfile; \
+             ok=$?; swapoff /swapfile 2>/dev/null; exit $ok'",
+        );
+        if !enabled {
+            return Err("swapon refused the file".to_string());
+        }
+        Ok(())
+    }
+
+    fn install_packages(&self) -> bool {
+        // Additional packages from config (already done in base system)
+        self.install_flatpak_apps();
+        self.install_gaming_extras();
+        self.install_containers();
+        self.configure_provisioning();
+        true
+    }
+
+    /// [packages.gaming] lutris (wine tree from multilib) and GE-Proton for
+    /// the created user, downloaded now or on first boot when offline
+    fn install_gaming_extras(&self) {
+        let pkgs = &self.config.packages;
+
+        if pkgs.lutris {
+            self.enable_multilib();
+            tui::print_info("Installing Lutris and Wine...");
+            let wine = "lutris wine-staging winetricks wine-gecko wine-mono \
+                        lib32-gnutls lib32-libpulse lib32-alsa-plugins lib32-openal \
+                        lib32-sdl2 lib32-vulkan-icd-loader lib32-gst-plugins-base-libs \
+                        giflib lib32-giflib gamemode lib32-gamemode";
+            if self.run_chroot(&format!("pacman -S --noconfirm --needed {wine}")) {
+                tui::print_success("Lutris installed");
+            } else {
+                tui::print_warning("Lutris installation failed - install it later with pacman");
+            }
+        }
+
+        if pkgs.proton_ge {
+            let user = &self.config.install.username;
+            let steam_dir = format!("/home/{user}/.local/share/Steam");
+            let script = format!(
+                "#!/bin/bash\n\
+                 set -e\n\
+                 # Latest GE-Proton release for Steam (auto-generated by Blunux installer)\n\
+                 URL=$(curl -fsSL https://api.github.com/repos/GloriousEggroll/proton-ge-custom/releases/latest \\\n\
+                 \x20   | grep -o '\"browser_download_url\": *\"[^\"]*\\.tar\\.gz\"' | cut -d'\"' -f4)\n\
+                 [ -n \"$URL\" ]\n\
+                 mkdir -p {steam_dir}/compatibilitytools.d\n\
+                 curl -fsSL \"$URL\" | tar -xz -C {steam_dir}/compatibilitytools.d\n\
+                 chown -R {user}:{user} /home/{user}/.local\n"
+            );
+            let script_path = format!("{}/root/blunux-proton-ge.sh", self.mount_point);
+            self.write_file(&script_path, &script);
+            let ok = self.run_chroot("bash /root/blunux-proton-ge.sh");
+            self.run_command(&format!("rm -f {script_path}"));
+            if ok {
+                tui::print_success("GE-Proton installed for Steam");
+            } else {
+                tui::print_warning("GE-Proton download failed - queued for first boot");
+                self.queue_firstboot("70-proton-ge", &script);
+            }
+        }
+    }
+
+    /// [[containers]]: nspawn settings plus a bootstrapped root filesystem
+    /// under /var/lib/machines; bootstrapping is retried on first boot when
+    /// the mirrors are unreachable
+    fn install_containers(&self) {
+        let mp = &self.mount_point;
+        for c in &self.config.containers {
+            let dir = format!("/var/lib/machines/{}", c.name);
+
+            // Share the host network so NetworkManager needs no extra setup
+            self.run_command(&format!("mkdir -p {mp}/etc/systemd/nspawn"));
+            self.write_file(
+                &format!("{mp}/etc/systemd/nspawn/{}.nspawn", c.name),
+                "[Exec]
+Boot=yes
+
+[Network]
+VirtualEthernet=no
+",
+            );
+            if c.autostart {
+                self.run_chroot(&format!(
+                    "systemctl enable machines.target systemd-nspawn@{}.service",
+                    c.name
+                ));
+            }
+
+            let bootstrap = if c.distro == "debian" {
+                format!(
+                    "debootstrap --include=systemd,dbus {} {dir} https://deb.debian.org/debian",
+                    c.release
+                )
+            } else {
+                format!("pacstrap -c {dir} base")
+            };
+            tui::print_info(&format!("Bootstrapping {} container '{}'...", c.distro, c.name));
+            if self.run_chroot(&format!("sh -c 'mkdir -p {dir} && {bootstrap}'")) {
+                tui::print_success(&format!(
+                    "Container '{}' ready (machinectl start {})",
+                    c.name, c.name
+                ));
+                continue;
             }
+
+            tui::print_warning(&format!(
+                "Container '{}' bootstrap failed (offline?) - queued for first boot",
+                c.name
+            ));
+            self.run_command(&format!("rm -rf {mp}{dir}"));
+            self.queue_firstboot(
+                &format!("55-container-{}", c.name),
+                &format!("#!/bin/bash\nset -e\nrm -rf {dir}\nmkdir -p {dir}\n{bootstrap}\n"),
+            );
         }
     }
 
-    /// Create a swap file of the given size in MB
-    fn create_swap_file(&self, size_mb: u64) {
-        if size_mb == 0 {
+    /// [provision]: point salt-minion at its master and/or run ansible-pull
+    /// once the installed system is online
+    fn configure_provisioning(&self) {
+        let provision = &self.config.provision;
+
+        if !provision.salt_master.is_empty() {
+            let minion_dir = format!("{}/etc/salt/minion.d", self.mount_point);
+            self.run_command(&format!("mkdir -p {minion_dir}"));
+            self.write_file(
+                &format!("{minion_dir}/10-blunux-master.conf"),
+                &format!("master: {}\n", provision.salt_master),
+            );
+            self.run_chroot("systemctl enable salt-minion.service");
+            tui::print_success(&format!("salt-minion will connect to {}", provision.salt_master));
+        }
+
+        if !provision.ansible_repo.is_empty() {
+            // Last in the first-boot queue so Flatpak/AUR tasks have run
+            self.queue_firstboot(
+                "90-ansible-pull",
+                &format!(
+                    "#!/bin/bash\nset -e\n\
+                     ansible-pull --url '{}' --directory /var/lib/blunux/ansible '{}'\n",
+                    provision.ansible_repo, provision.ansible_playbook
+                ),
+            );
+            tui::print_success(&format!(
+                "ansible-pull queued for first boot: {} ({})",
+                provision.ansible_repo, provision.ansible_playbook
+            ));
+        }
+    }
+
+    /// Install [packages.flatpak] apps from Flathub, or queue them for the
+    /// first boot when the chroot has no network
+    fn install_flatpak_apps(&self) {
+        let apps = &self.config.packages.flatpak_apps;
+        if !self.config.packages.flatpak || apps.is_empty() {
             return;
         }
 
-        let swapfile = format!("{}/swapfile", self.mount_point);
+        let remote_add = "flatpak remote-add --if-not-exists flathub \
+                          https://dl.flathub.org/repo/flathub.flatpakrepo";
+        let install = format!(
+            "flatpak install -y --noninteractive --system flathub {}",
+            apps.join(" ")
+        );
+
+        tui::print_info(&format!("Installing {} Flatpak app(s)...", apps.len()));
+        if self.run_chroot(remote_add) && self.run_chroot(&install) {
+            tui::print_success("Flatpak apps installed");
+            return;
+        }
 
-        tui::print_info(&format!("Creating {size_mb} MB swap file..."));
+        tui::print_warning("Flatpak install failed (offline?) - queued for first boot");
+        self.queue_firstboot(
+            "50-flatpak-apps",
+            &format!("#!/bin/bash\nset -e\n{remote_add}\n{install}\n"),
+        );
+    }
 
-        // Create swap file using dd
-        self.run_command(&format!(
-            "dd if=/dev/zero of={swapfile} bs=1M count={size_mb} status=progress"
+    /// Build AUR packages as the created user, either right now in the chroot
+    /// ([packages] aur_build_in_chroot) or from the first-boot queue
+    fn install_aur_packages(&self) {
+        let mut aur = self.config.aur_packages();
+        for pkg in &self.driver_aur {
+            if !aur.contains(pkg) {
+                aur.push(pkg.clone());
+            }
+        }
+        if aur.is_empty() {
+            return;
+        }
+
+        let username = &self.config.install.username;
+        let mut script = format!(
+            r#"#!/bin/bash
+# AUR package installation (auto-generated by Blunux installer)
+set -e
+
+AUR_USER="{username}"
+
+# makepkg refuses to run as root; let the user call pacman while building
+echo "$AUR_USER ALL=(ALL) NOPASSWD: ALL" > /etc/sudoers.d/99-blunux-aur
+chmod 440 /etc/sudoers.d/99-blunux-aur
+trap 'rm -f /etc/sudoers.d/99-blunux-aur' EXIT
+
+if ! command -v yay &> /dev/null; then
+    runuser -u "$AUR_USER" -- bash -c '
+        BUILDDIR=$(mktemp -d)
+        git clone https://aur.archlinux.org/yay-bin.git "$BUILDDIR/yay-bin"
+        cd "$BUILDDIR/yay-bin"
+        makepkg -si --noconfirm
+        rm -rf "$BUILDDIR"'
+fi
+
+runuser -u "$AUR_USER" -- yay -S --noconfirm --needed {}
+"#,
+            aur.join(" ")
+        );
+
+        // Kernels from the AUR need a boot entry of their own. Package names
+        // say little (linux-*-firmware, -headers, -tools), so a package is a
+        // kernel when its build left /boot/vmlinuz-<pkg> behind.
+        script.push_str(&format!(
+            "\n# Register the new kernel(s) with the boot loader\n\
+             new_kernel=\n\
+             for k in {}; do\n\
+             \x20   [ -f \"/boot/vmlinuz-$k\" ] || continue\n",
+            aur.join(" ")
+        ));
+        if self.config.install.bootloader == "nmbl" && disk::is_uefi() {
+            let params = self.nmbl_kernel_params();
+            let (efi_disk, efi_part_num) = self.efi_disk_and_part();
+            script.push_str(&format!(
+                "\x20   cp \"/boot/vmlinuz-$k\" \"/boot/efi/EFI/Blunux/vmlinuz-$k\"\n\
+                 \x20   cp \"/boot/initramfs-$k.img\" \"/boot/efi/EFI/Blunux/initramfs-$k.img\"\n\
+                 \x20   efibootmgr --create --disk {efi_disk} --part {efi_part_num} \
+                 --label \"Blunux ($k)\" --loader \"\\EFI\\Blunux\\vmlinuz-$k\" \
+                 --unicode \"{params} initrd=\\EFI\\Blunux\\initramfs-$k.img\"\n\
+                 \x20   echo \"cp /boot/vmlinuz-$k /boot/efi/EFI/Blunux/vmlinuz-$k\" >> /usr/local/bin/nmbl-update\n\
+                 \x20   echo \"cp /boot/initramfs-$k.img /boot/efi/EFI/Blunux/initramfs-$k.img\" >> /usr/local/bin/nmbl-update\n\
+                 \x20   sed -i \"/^Target = /a Target = $k\" /etc/pacman.d/hooks/99-nmbl-kernel-update.hook\n\
+                 done\n"
+            ));
+        } else {
+            script.push_str(
+                "\x20   new_kernel=1\n\
+                 done\n\
+                 [ -z \"$new_kernel\" ] || grub-mkconfig -o /boot/grub/grub.cfg\n",
+            );
+        }
+
+        if self.config.packages.aur_build_in_chroot {
+            tui::print_info(&format!("Building AUR packages in chroot: {}", aur.join(", ")));
+            let script_path = format!("{}/root/blunux-aur.sh", self.mount_point);
+            self.write_file(&script_path, &script);
+            let ok = self.run_chroot("bash /root/blunux-aur.sh");
+            self.run_command(&format!("rm -f {script_path}"));
+            if ok {
+                tui::print_success("AUR packages installed");
+                return;
+            }
+            tui::print_warning("AUR build failed - queued for first boot instead");
+        }
+
+        self.queue_firstboot("60-aur-packages", &script);
+        tui::print_info(&format!(
+            "AUR packages queued for first boot: {}",
+            aur.join(", ")
         ));
-        self.run_command(&format!("chmod 600 {swapfile}"));
-        self.run_chroot("mkswap /swapfile");
+    }
 
-        // Add swap to fstab
-        let fstab_path = format!("{}/etc/fstab", self.mount_point);
-        self.append_file(&fstab_path, "\n# Swap file\n/swapfile none swap defaults 0 0\n");
+    /// Queue a script to run as root on first boot (after network-online),
+    /// installing the runner service on first use
+    fn queue_firstboot(&self, name: &str, script: &str) {
+        let task_dir = format!("{}{FIRSTBOOT_DIR}", self.mount_point);
+        self.run_command(&format!("mkdir -p {task_dir}"));
+        let task_path = format!("{task_dir}/{name}.sh");
+        self.write_file(&task_path, script);
+        self.run_command(&format!("chmod 755 {task_path}"));
+
+        let runner_path = format!("{}/usr/local/bin/blunux-firstboot", self.mount_point);
+        if Path::new(&runner_path).exists() {
+            return;
+        }
 
-        let size_display = if size_mb >= 1024 {
-            format!("{:.1} GB", size_mb as f64 / 1024.0)
+        let runner = format!(
+            "#!/bin/bash\n\
+             # Blunux first-boot tasks (auto-generated by installer)\n\
+             # Failed tasks are kept and retried on the next boot\n\
+             for task in {FIRSTBOOT_DIR}/*.sh; do\n\
+             \x20   [ -e \"$task\" ] || continue\n\
+             \x20   echo \"Running $task\"\n\
+             \x20   if bash \"$task\"; then\n\
+             \x20       rm -f \"$task\"\n\
+             \x20   else\n\
+             \x20       echo \"WARNING: $task failed - will retry on next boot\"\n\
+             \x20   fi\n\
+             done\n"
+        );
+        self.run_command(&format!("mkdir -p {}/usr/local/bin", self.mount_point));
+        self.write_file(&runner_path, &runner);
+        self.run_command(&format!("chmod 755 {runner_path}"));
+
+        let service = format!(
+            "[Unit]\n\
+             Description=Blunux first-boot tasks\n\
+             Wants=network-online.target\n\
+             After=network-online.target\n\
+             ConditionDirectoryNotEmpty={FIRSTBOOT_DIR}\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart=/usr/local/bin/blunux-firstboot\n\
+             TimeoutStartSec=infinity\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n"
+        );
+        self.write_file(
+            &format!("{}/etc/systemd/system/blunux-firstboot.service", self.mount_point),
+            &service,
+        );
+        self.run_chroot("systemctl enable blunux-firstboot.service");
+        self.run_chroot("systemctl enable NetworkManager-wait-online.service 2>/dev/null || true");
+    }
+
+    fn wayland_session(&self) -> bool {
+        self.config.system.session == "wayland"
+    }
+
+    /// KWin starts the input method itself on Wayland (Plasma's virtual
+    /// keyboard setting); `desktop` is a file in /usr/share/applications
+    fn set_kwin_input_method(&self, desktop: &str) {
+        let kwinrc_path = format!("{}/etc/skel/.config/kwinrc", self.mount_point);
+        let kwinrc_content =
+            format!("[Wayland]\nInputMethod[$e]=/usr/share/applications/{desktop}\n");
+        if Path::new(&kwinrc_path).exists() {
+            self.append_file(&kwinrc_path, &format!("\n{kwinrc_content}"));
         } else {
-            format!("{size_mb} MB")
-        };
-        tui::print_success(&format!("{size_display} swap file created and configured"));
+            self.run_command(&format!("mkdir -p {}/etc/skel/.config", self.mount_point));
+            self.write_file(&kwinrc_path, &kwinrc_content);
+        }
     }
 
-    fn install_packages(&self) -> bool {
-        // Additional packages from config (already done in base system)
-        true
+    fn has_language(&self, prefix: &str) -> bool {
+        self.config.locale.languages.iter().any(|l| l.contains(prefix))
     }
 
-    /// Detect hardware via lspci and install appropriate GPU/WiFi drivers
-    fn detect_and_install_drivers(&self) {
-        // Read lspci output from the host (hardware is the same)
-        let lspci_output = self.exec_output("lspci -nn 2>/dev/null");
-        let lspci_lower = lspci_output.to_lowercase();
+    /// fcitx5 profile with the first keyboard layout plus the engines
+    /// installed for the configured languages, and their addons enabled.
+    /// fcitx5 ships its own XDG autostart entry.
+    fn configure_fcitx5(&self) {
+        tui::print_info("Configuring fcitx5 input method...");
 
-        let mut driver_packages: Vec<String> = Vec::new();
+        let keyboard = match self.config.locale.keyboards.first() {
+            Some(kb) if kb.variant.is_empty() => format!("keyboard-{}", kb.layout),
+            Some(kb) => format!("keyboard-{}-{}", kb.layout, kb.variant),
+            None => "keyboard-us".to_string(),
+        };
+        let mut engines = Vec::new();
+        let mut addons = Vec::new();
+        if self.has_language("ko") {
+            engines.push("hangul");
+            addons.push("hangul");
+        }
+        if self.has_language("ja") {
+            engines.push("mozc");
+            addons.push("mozc");
+        }
+        if self.has_language("zh") {
+            engines.push("pinyin");
+            addons.push("pinyin");
+        }
+
+        let default_im = engines.first().copied().unwrap_or(keyboard.as_str());
+        let mut profile = format!(
+            "[Groups/0]\nName=Default\nDefault Layout={}\nDefaultIM={default_im}\n\n\
+             [Groups/0/Items/0]\nName={keyboard}\nLayout=\n",
+            keyboard.trim_start_matches("keyboard-")
+        );
+        for (i, engine) in engines.iter().enumerate() {
+            profile.push_str(&format!("\n[Groups/0/Items/{}]\nName={engine}\nLayout=\n", i + 1));
+        }
+        profile.push_str("\n[GroupOrder]\n0=Default\n");
 
-        // ── GPU Detection ──────────────────────────────────────
-        let has_nvidia = lspci_lower.contains("nvidia");
-        let has_amd_gpu = lspci_lower.contains("[amd/ati]")
-            || lspci_lower.contains("radeon")
-            || (lspci_lower.contains("amd") && lspci_lower.contains("vga"));
-        let has_intel_gpu = lspci_lower.contains("intel")
-            && (lspci_lower.contains("vga") || lspci_lower.contains("display"));
+        // Ctrl+Space and the Hangul key switch, like the kime setup
+        let config = format!(
+            "[Hotkey/TriggerKeys]\n0=Control+space\n1=Hangul\n\n\
+             [Behavior]\nEnabledAddons={}\n",
+            addons.join(":")
+        );
 
-        if has_nvidia {
-            tui::print_info("Detected NVIDIA GPU - installing drivers...");
-            driver_packages.extend_from_slice(&[
-                "nvidia".to_string(),
-                "nvidia-utils".to_string(),
-                "nvidia-settings".to_string(),
-                "lib32-nvidia-utils".to_string(),
-                "libva-nvidia-driver".to_string(),
-            ]);
+        self.apply_files(&[
+            FileEntry::file("/etc/skel/.config/fcitx5/profile", "", &profile),
+            FileEntry::file("/etc/skel/.config/fcitx5/config", "", &config),
+        ]);
+        if self.wayland_session() {
+            self.set_kwin_input_method("org.fcitx.Fcitx5.desktop");
         }
+        self.install_firstlogin();
+        tui::print_success(&format!("fcitx5 configured ({})", engines.join(", ")));
+    }
 
-        if has_amd_gpu {
-            tui::print_info("Detected AMD/ATI GPU - installing drivers...");
-            driver_packages.extend_from_slice(&[
-                "xf86-video-amdgpu".to_string(),
-                "vulkan-radeon".to_string(),
-                "lib32-vulkan-radeon".to_string(),
-                "libva-mesa-driver".to_string(),
-                "lib32-libva-mesa-driver".to_string(),
-                "mesa-vdpau".to_string(),
-            ]);
+    /// System-wide dconf default for the preloaded engines, so the language
+    /// engines show up without ibus-setup, plus autostart on X11
+    fn configure_ibus(&self) {
+        tui::print_info("Configuring ibus input method...");
+
+        let mut engines = vec!["'xkb:us::eng'"];
+        if self.has_language("ko") {
+            engines.push("'hangul'");
+        }
+        if self.has_language("ja") {
+            engines.push("'mozc-jp'");
         }
+        let engines = engines.join(", ");
+
+        let autostart = "[Desktop Entry]\n\
+                         Type=Application\n\
+                         Name=IBus\n\
+                         Exec=ibus-daemon -drxR\n\
+                         Terminal=false\n\
+                         X-GNOME-Autostart-enabled=true\n";
+        let dconf = format!(
+            "[desktop/ibus/general]\n\
+             preload-engines=[{engines}]\n\
+             engines-order=[{engines}]\n"
+        );
 
-        if has_intel_gpu {
-            tui::print_info("Detected Intel GPU - installing drivers...");
-            driver_packages.extend_from_slice(&[
-                "vulkan-intel".to_string(),
-                "lib32-vulkan-intel".to_string(),
-                "intel-media-driver".to_string(),
-            ]);
+        self.apply_files(&[FileEntry::file("/etc/dconf/db/ibus.d/10-blunux", "", &dconf)]);
+        self.run_chroot("dconf update");
+        if self.wayland_session() {
+            self.set_kwin_input_method("org.freedesktop.IBus.Panel.Wayland.Gtk3.desktop");
+        } else {
+            let path = "/etc/skel/.config/autostart/ibus-daemon.desktop";
+            self.apply_files(&[FileEntry::file(path, "", autostart)]);
         }
+        self.install_firstlogin();
+        tui::print_success(&format!("ibus configured ({engines})"));
+    }
 
-        if !has_nvidia && !has_amd_gpu && !has_intel_gpu {
-            tui::print_info("No dedicated GPU detected - using mesa software rendering");
+    /// Per-user counterpart of blunux-firstboot: a global systemd user unit
+    /// that copies /etc/skel files a home does not have yet, once per home
+    /// (so again if the home is recreated)
+    fn install_firstlogin(&self) {
+        let script_path = format!("{}/usr/local/bin/blunux-firstlogin", self.mount_point);
+        if Path::new(&script_path).exists() {
+            return;
         }
 
-        // ── WiFi / Network Detection ───────────────────────────
-        let has_broadcom = lspci_lower.contains("broadcom")
-            && (lspci_lower.contains("wireless") || lspci_lower.contains("network")
-                || lspci_lower.contains("bcm43"));
+        let script = format!(
+            "#!/bin/bash\n\
+             # Blunux first-login setup (auto-generated by installer)\n\
+             # Homes created before the installer filled /etc/skel miss its files\n\
+             cp -rn /etc/skel/. \"$HOME\"/\n\
+             mkdir -p \"$(dirname \"$HOME/{FIRSTLOGIN_STAMP}\")\"\n\
+             touch \"$HOME/{FIRSTLOGIN_STAMP}\"\n"
+        );
+        self.run_command(&format!("mkdir -p {}/usr/local/bin", self.mount_point));
+        self.write_file(&script_path, &script);
+        self.run_command(&format!("chmod 755 {script_path}"));
+
+        let service = format!(
+            "[Unit]\n\
+             Description=Blunux first-login setup\n\
+             ConditionPathExists=!%h/{FIRSTLOGIN_STAMP}\n\
+             Before=graphical-session-pre.target\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart=/usr/local/bin/blunux-firstlogin\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n"
+        );
+        let unit_dir = format!("{}/etc/systemd/user", self.mount_point);
+        self.run_command(&format!("mkdir -p {unit_dir}"));
+        self.write_file(&format!("{unit_dir}/blunux-firstlogin.service"), &service);
+        self.run_chroot("systemctl --global enable blunux-firstlogin.service");
+    }
+
+    /// Detect hardware via lspci/lsusb and install the drivers the rules table
+    /// (drivers.toml) asks for
+    fn detect_and_install_drivers(&mut self) {
+        self.hardware = hardware::survey();
+        tui::print_info(&format!("Hardware: {}", self.hardware.summary()));
+
+        // Read lspci/lsusb output from the host (hardware is the same)
+        let lspci_output = self.exec_output("lspci -nn 2>/dev/null");
+        let lsusb_output = self.exec_output("lsusb 2>/dev/null");
 
-        if has_broadcom {
-            tui::print_info("Detected Broadcom wireless - installing driver...");
-            driver_packages.push("broadcom-wl-dkms".to_string());
+        let mut driver_packages: Vec<String> = Vec::new();
+        let mut services: Vec<String> = Vec::new();
+        // Units shipped by AUR packages exist only once those are built
+        let mut aur_services: Vec<String> = Vec::new();
+        // Blacklists and initramfs modules apply only once the packages are in
+        let mut module_rules: Vec<drivers::Rule> = Vec::new();
+        let mut matched = 0;
+        for rule in drivers::load_rules() {
+            if !rule.matches(&lspci_output, &lsusb_output) {
+                continue;
+            }
+            matched += 1;
+            if !rule.note.is_empty() {
+                tui::print_info(&format!("Detected {} - {}", rule.name, rule.note));
+            } else if !rule.packages.is_empty() {
+                tui::print_info(&format!("Detected {} - installing drivers...", rule.name));
+            } else {
+                tui::print_info(&format!("Detected {}", rule.name));
+            }
+            if !rule.blacklist.is_empty() || !rule.modules.is_empty() {
+                module_rules.push(rule.clone());
+            }
+            let (pkg_list, service_list) = if rule.aur {
+                (&mut self.driver_aur, &mut aur_services)
+            } else {
+                (&mut driver_packages, &mut services)
+            };
+            for pkg in rule.packages {
+                if !pkg_list.contains(&pkg) {
+                    pkg_list.push(pkg);
+                }
+            }
+            for service in rule.services {
+                if !service_list.contains(&service) {
+                    service_list.push(service);
+                }
+            }
+            for param in rule.kernel_params {
+                if !self.driver_params.contains(&param) {
+                    self.driver_params.push(param);
+                }
+            }
+        }
+        if matched == 0 {
+            tui::print_info("No hardware needing extra drivers detected - using mesa");
         }
 
-        let has_realtek_wifi = lspci_lower.contains("realtek")
-            && (lspci_lower.contains("wireless") || lspci_lower.contains("rtl8"));
+        // ── [hardware] overrides ───────────────────────────────
+        let hw = &self.config.hardware;
+        for pkg in &hw.extra_drivers {
+            if !driver_packages.contains(pkg) {
+                driver_packages.push(pkg.clone());
+            }
+        }
+        if !hw.skip_drivers.is_empty() {
+            driver_packages.retain(|p| !hw.skip_drivers.contains(p));
+            tui::print_info(&format!("Skipping drivers: {}", hw.skip_drivers.join(", ")));
+        }
+        self.driver_aur.retain(|p| !hw.skip_drivers.contains(p));
 
-        if has_realtek_wifi {
-            tui::print_info("Detected Realtek wireless - linux-firmware should cover it");
-            // Most Realtek chips are covered by linux-firmware
-            // rtw88/rtw89 drivers are in-kernel since linux 6.x
+        // ── Multilib repository for 32-bit libs ────────────────
+        // Enabled before the install: one missing lib32 package fails the
+        // whole pacman transaction
+        let has_32bit = driver_packages.iter().any(|p| p.starts_with("lib32-"));
+        let pkgs = &self.config.packages;
+        match pkgs.multilib {
+            Some(false) => {
+                if has_32bit {
+                    tui::print_info("[packages] multilib = false - skipping 32-bit driver libraries");
+                    driver_packages.retain(|p| !p.starts_with("lib32-"));
+                }
+            }
+            Some(true) => {
+                self.enable_multilib();
+            }
+            None => {
+                if has_32bit || pkgs.steam || pkgs.lutris {
+                    self.enable_multilib();
+                }
+            }
         }
 
+        self.hardware.drivers = driver_packages.clone();
+        self.hardware.drivers.extend(self.driver_aur.iter().cloned());
+
         // ── Install detected driver packages ───────────────────
         if !driver_packages.is_empty() {
             let pkg_list = driver_packages.join(" ");
@@ -684,84 +2917,214 @@ nameserver 1.1.1.1\n";
 
             // Install via pacman in chroot
             let cmd = format!("pacman -S --noconfirm --needed {pkg_list}");
-            if self.run_chroot(&cmd) {
+            let installed = self.run_chroot(&cmd);
+            if installed {
                 tui::print_success("Hardware drivers installed successfully");
             } else {
                 tui::print_warning("Some driver packages may have failed - system should still work");
             }
+            self.defer_unbuilt_dkms(&driver_packages, installed);
         } else {
             tui::print_success("Base GPU drivers (mesa) already included");
         }
 
-        // ── Enable multilib repository for 32-bit libs ─────────
-        let has_32bit = driver_packages.iter().any(|p| p.starts_with("lib32-"));
-        if has_32bit {
-            let pacman_conf = format!("{}/etc/pacman.conf", self.mount_point);
-            let conf_content = self.exec_output(&format!("cat {pacman_conf}"));
-            if !conf_content.contains("[multilib]") || conf_content.contains("#[multilib]") {
-                tui::print_info("Enabling multilib repository for 32-bit driver support...");
-                self.run_chroot(
-                    "sed -i '/^#\\[multilib\\]/,/^#Include/ s/^#//' /etc/pacman.conf",
-                );
-                self.run_chroot("pacman -Sy --noconfirm");
-                // Retry 32-bit packages after enabling multilib
-                let lib32_pkgs: Vec<&str> = driver_packages
-                    .iter()
-                    .filter(|p| p.starts_with("lib32-"))
-                    .map(|s| s.as_str())
-                    .collect();
-                if !lib32_pkgs.is_empty() {
-                    let cmd = format!(
-                        "pacman -S --noconfirm --needed {}",
-                        lib32_pkgs.join(" ")
-                    );
-                    self.run_chroot(&cmd);
+        for service in &services {
+            self.run_chroot(&format!("systemctl enable {service}"));
+        }
+
+        // ── Blacklists and early KMS modules ───────────────────
+        let installed: Vec<String> =
+            driver_packages.iter().chain(&self.driver_aur).cloned().collect();
+        let (mut blacklist, mut early): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+        for rule in module_rules.iter().filter(|r| r.is_installed(&installed)) {
+            for module in &rule.blacklist {
+                if !blacklist.contains(module) {
+                    blacklist.push(module.clone());
                 }
             }
+            for module in &rule.modules {
+                if !early.contains(module) {
+                    early.push(module.clone());
+                }
+            }
+        }
+        if !blacklist.is_empty() {
+            self.blacklist_modules(&blacklist);
+        }
+        // A failed or deferred (DKMS) install leaves no module for mkinitcpio to find
+        early.retain(|m| {
+            self.run_command(&format!(
+                "find {}/usr/lib/modules -name '{m}.ko*' | grep -q .",
+                self.mount_point
+            ))
+        });
+        if !early.is_empty() {
+            tui::print_info(&format!("Early KMS: loading {} from the initramfs", early.join(", ")));
+            for module in &early {
+                self.add_mkinitcpio_module(module);
+            }
+        }
+        if !blacklist.is_empty() || !early.is_empty() {
+            self.run_chroot("mkinitcpio -P");
+        }
+
+        if !self.driver_aur.is_empty() {
+            tui::print_info(&format!(
+                "AUR driver packages added to the AUR list: {}",
+                self.driver_aur.join(", ")
+            ));
+        }
+        if !aur_services.is_empty() {
+            let script = format!(
+                "#!/bin/bash\n\
+                 # Services of AUR driver packages (auto-generated by Blunux installer)\n\
+                 systemctl enable --now {}\n",
+                aur_services.join(" ")
+            );
+            self.queue_firstboot("65-driver-services", &script);
+        }
+    }
+
+    /// Write /etc/modprobe.d/blunux-drivers.conf; the caller rebuilds the
+    /// initramfs, which would otherwise still load the blacklisted modules
+    fn blacklist_modules(&self, modules: &[String]) {
+        tui::print_info(&format!("Blacklisting conflicting drivers: {}", modules.join(", ")));
+        let mut conf = "# Replaced by packaged drivers (generated by Blunux installer)\n".to_string();
+        for module in modules {
+            conf.push_str(&format!("blacklist {module}\n"));
+        }
+        self.run_command(&format!("mkdir -p {}/etc/modprobe.d", self.mount_point));
+        self.write_file(
+            &format!("{}/etc/modprobe.d/blunux-drivers.conf", self.mount_point),
+            &conf,
+        );
+    }
+
+    /// DKMS modules only build in the chroot when the headers of an
+    /// installed kernel match; anything not reported as installed by
+    /// `dkms status` is rebuilt on first boot against the running kernel
+    fn defer_unbuilt_dkms(&self, driver_packages: &[String], installed: bool) {
+        let dkms: Vec<&str> = driver_packages
+            .iter()
+            .filter(|p| p.contains("-dkms"))
+            .map(|p| p.as_str())
+            .collect();
+        if dkms.is_empty() {
+            return;
+        }
+        let status =
+            self.exec_output(&format!("arch-chroot {} dkms status 2>/dev/null", self.mount_point));
+        let all_built = status.lines().any(|l| !l.trim().is_empty())
+            && status.lines().all(|l| l.trim().is_empty() || l.ends_with(": installed"));
+        if installed && all_built {
+            return;
+        }
+
+        let script = format!(
+            "#!/bin/bash\n\
+             # DKMS drivers that could not be built in the installer chroot\n\
+             # (auto-generated by Blunux installer)\n\
+             set -e\n\
+             pacman -S --noconfirm --needed {}\n\
+             dkms autoinstall\n",
+            dkms.join(" ")
+        );
+        self.queue_firstboot("55-driver-dkms", &script);
+        tui::print_warning(&format!(
+            "DKMS drivers will be built on first boot: {} (reboot once more afterwards)",
+            dkms.join(", ")
+        ));
+    }
+
+    /// Uncomment [multilib] in the target pacman.conf and refresh the
+    /// databases. Returns false when it was already enabled.
+    fn enable_multilib(&self) -> bool {
+        let pacman_conf = format!("{}/etc/pacman.conf", self.mount_point);
+        let conf_content = self.exec_output(&format!("cat {pacman_conf}"));
+        if conf_content.contains("[multilib]") && !conf_content.contains("#[multilib]") {
+            return false;
+        }
+        tui::print_info("Enabling multilib repository for 32-bit support...");
+        self.run_chroot("sed -i '/^#\\[multilib\\]/,/^#Include/ s/^#//' /etc/pacman.conf");
+        self.run_chroot("pacman -Sy --noconfirm");
+        true
+    }
+
+    /// [locale] user_language: a per-user LANG through the skeleton, read
+    /// by /etc/profile.d/locale.sh for shells and by Plasma for the session
+    fn configure_user_locale(&self, system_lang: &str) {
+        let Some(lang) = self.config.locale.user_language.as_deref() else {
+            return;
+        };
+        if lang == system_lang {
+            return;
         }
+        let language = lang.split(['_', '@']).next().unwrap_or(lang);
+        self.apply_files(&[
+            FileEntry::file("/etc/skel/.config/locale.conf", "", &format!("LANG={lang}.UTF-8\n")),
+            FileEntry::file(
+                "/etc/skel/.config/plasma-localerc",
+                "",
+                &format!("[Formats]\nLANG={lang}.UTF-8\n\n[Translations]\nLANGUAGE={language}\n"),
+            ),
+        ]);
+        tui::print_success(&format!(
+            "User language: {lang}.UTF-8 (system: {system_lang}.UTF-8)"
+        ));
     }
 
     fn configure_locale(&self) -> bool {
         let locale_gen_path = format!("{}/etc/locale.gen", self.mount_point);
         let mut locale = String::new();
-        for lang in &self.config.locale.languages {
+        for lang in self.config.locale.generated_languages() {
             locale.push_str(&format!("{lang}.UTF-8 UTF-8\n"));
         }
-        if !self.config.locale.languages.contains(&"en_US".to_string()) {
-            locale.push_str("en_US.UTF-8 UTF-8\n");
-        }
         self.append_file(&locale_gen_path, &locale);
 
         self.run_chroot("locale-gen");
 
-        let default_lang = self
-            .config
-            .locale
-            .languages
-            .first()
-            .cloned()
-            .unwrap_or_else(|| "en_US".to_string());
+        let default_lang = self.config.locale.system_language();
         let locale_conf = format!("LANG={default_lang}.UTF-8\n");
         self.write_file(
             &format!("{}/etc/locale.conf", self.mount_point),
             &locale_conf,
         );
+        self.configure_user_locale(default_lang);
 
         // Always write vconsole.conf with KEYMAP and FONT
         // Missing FONT causes systemd-vconsole-setup.service to fail at boot
-        let keymap = self
-            .config
-            .locale
-            .keyboards
-            .first()
-            .cloned()
-            .unwrap_or_else(|| "us".to_string());
+        let keyboards = &self.config.locale.keyboards;
+        let keymap = keyboards.first().map_or("us".to_string(), |kb| kb.console_keymap());
         let vconsole = format!("KEYMAP={keymap}\nFONT=ter-v16n\n");
         self.write_file(
             &format!("{}/etc/vconsole.conf", self.mount_point),
             &vconsole,
         );
 
+        // X11 layouts, in the format localectl set-x11-keymap writes; SDDM
+        // and KWin on Wayland take their defaults from here as well
+        if !keyboards.is_empty() {
+            let join = |f: fn(&Keyboard) -> &str| {
+                keyboards.iter().map(f).collect::<Vec<_>>().join(",")
+            };
+            let mut section = format!(
+                "Section \"InputClass\"\n        Identifier \"system-keyboard\"\n        \
+                 MatchIsKeyboard \"on\"\n        Option \"XkbLayout\" \"{}\"\n",
+                join(|kb| &kb.layout)
+            );
+            if keyboards.iter().any(|kb| !kb.variant.is_empty()) {
+                let variants = join(|kb| &kb.variant);
+                section += &format!("        Option \"XkbVariant\" \"{variants}\"\n");
+            }
+            if keyboards.len() > 1 {
+                section += "        Option \"XkbOptions\" \"grp:alt_shift_toggle\"\n";
+            }
+            section += "EndSection\n";
+            let dir = format!("{}/etc/X11/xorg.conf.d", self.mount_point);
+            self.run_command(&format!("mkdir -p {dir}"));
+            self.write_file(&format!("{dir}/00-keyboard.conf"), &section);
+        }
+
         true
     }
 
@@ -770,66 +3133,492 @@ nameserver 1.1.1.1\n";
             return true;
         }
 
-        let env_content = match self.config.input_method.engine.as_str() {
-            "kime" => "\n# Kime Korean Input Method\nGTK_IM_MODULE=kime\nQT_IM_MODULE=kime\nXMODIFIERS=@im=kime\n",
-            "fcitx5" => "\n# Fcitx5 Input Method\nGTK_IM_MODULE=fcitx\nQT_IM_MODULE=fcitx\nXMODIFIERS=@im=fcitx\n",
-            "ibus" => "\n# IBus Input Method\nGTK_IM_MODULE=ibus\nQT_IM_MODULE=ibus\nXMODIFIERS=@im=ibus\n",
+        let (name, im) = match self.config.input_method.engine.as_str() {
+            "kime" => ("Kime Korean Input Method", "kime"),
+            "fcitx5" => ("Fcitx5 Input Method", "fcitx"),
+            "ibus" => ("IBus Input Method", "ibus"),
             _ => return true,
         };
+        // On Wayland GTK and Qt talk to KWin's text-input protocol, which
+        // hands the keys to the input method KWin started; the legacy
+        // modules would bypass it. XWayland clients still need XMODIFIERS.
+        let env_content = if self.wayland_session() {
+            format!("\n# {name} (Wayland: started by KWin)\nXMODIFIERS=@im={im}\n")
+        } else {
+            format!(
+                "\n# {name}\nGTK_IM_MODULE={im}\nQT_IM_MODULE={im}\nXMODIFIERS=@im={im}\n"
+            )
+        };
 
         let env_dir = format!("{}/etc/environment.d", self.mount_point);
         self.run_command(&format!("mkdir -p {env_dir}"));
-        self.write_file(&format!("{env_dir}/input-method.conf"), env_content);
+        self.write_file(&format!("{env_dir}/input-method.conf"), &env_content);
+
+        true
+    }
+
+    /// UID and GID of `user` from the target's /etc/passwd; the host's
+    /// passwd knows nothing about users created in the chroot
+    fn user_ids(&self, user: &str) -> Option<(u32, u32)> {
+        let passwd = fs::read_to_string(format!("{}/etc/passwd", self.mount_point)).ok()?;
+        passwd.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 || fields[0] != user {
+                return None;
+            }
+            Some((fields[2].parse().ok()?, fields[3].parse().ok()?))
+        })
+    }
+
+    fn configure_users(&self) -> bool {
+        // Set root password
+        let root_cmd = format!(
+            "echo 'root:{}' | chpasswd",
+            self.config.install.root_password
+        );
+        self.run_chroot(&format!("sh -c \"{root_cmd}\""));
+
+        // Create user (network group for WiFi/NM management). A home kept
+        // from a previous install is reused as-is, but its files may belong
+        // to an old UID
+        let user = &self.config.install.username;
+        let home_exists = Path::new(&format!("{}/home/{user}", self.mount_point)).is_dir();
+        let mut opts = if home_exists { format!("-M -d /home/{user}") } else { "-m".to_string() };
+        if let Some(uid) = self.config.install.uid {
+            // useradd gives the user's own group the same number when free
+            opts.push_str(&format!(" -u {uid}"));
+        }
+        self.run_chroot(&format!(
+            "useradd {opts} -G wheel,audio,video,storage,optical,network,power,input -s /bin/bash {user}"
+        ));
+        if home_exists {
+            tui::print_info(&format!("Reusing existing /home/{user}"));
+            self.run_chroot(&format!(
+                "sh -c '[ \"$(stat -c %u /home/{user})\" = \"$(id -u {user})\" ] || chown -R {user}: /home/{user}'"
+            ));
+        }
+
+        // Set user password
+        let user_cmd = format!(
+            "echo '{}:{}' | chpasswd",
+            self.config.install.username, self.config.install.user_password
+        );
+        self.run_chroot(&format!("sh -c \"{user_cmd}\""));
+
+        // Configure sudo
+        let sudoers = format!("{}/etc/sudoers.d/wheel", self.mount_point);
+        self.write_file(&sudoers, "%wheel ALL=(ALL:ALL) ALL\n");
+        self.run_command(&format!("chmod 440 {sudoers}"));
+
+        // Configure SDDM autologin (kiosk mode bypasses SDDM entirely)
+        if self.config.install.autologin && !self.config.kiosk.enabled {
+            let sddm_conf_dir = format!("{}/etc/sddm.conf.d", self.mount_point);
+            self.run_command(&format!("mkdir -p {sddm_conf_dir}"));
+
+            let autologin_content = format!(
+                "[Autologin]\nUser={}\nSession={}\nRelogin=true\n",
+                self.config.install.username,
+                if self.wayland_session() { "plasma" } else { "plasmax11" }
+            );
+            self.write_file(
+                &format!("{sddm_conf_dir}/autologin.conf"),
+                &autologin_content,
+            );
+            tui::print_success(&format!(
+                "SDDM autologin configured for user: {}",
+                self.config.install.username
+            ));
+        }
+
+        self.configure_console();
+        self.configure_kiosk();
+        self.configure_games_drive();
+        self.configure_vm_images();
 
         true
     }
 
-    fn configure_users(&self) -> bool {
-        // Set root password
-        let root_cmd = format!(
-            "echo 'root:{}' | chpasswd",
-            self.config.install.root_password
-        );
-        self.run_chroot(&format!("sh -c \"{root_cmd}\""));
+    /// Point Docker and the user's VirtualBox folder at the nodatacow
+    /// @vmimages subvolume. Runs after the user exists.
+    fn configure_vm_images(&self) {
+        if !self.config.btrfs_vm_subvolume() {
+            return;
+        }
+        let (_, dir) = disk::BTRFS_VM_SUBVOLUME;
+        let user = &self.config.install.username;
+        if self.config.packages.docker {
+            self.apply_files(&[FileEntry::file(
+                "/etc/docker/daemon.json",
+                "",
+                &format!("{{\n  \"data-root\": \"{dir}/docker\"\n}}\n"),
+            )]);
+        }
+        if self.config.packages.virtualbox {
+            let vms = format!("/home/{user}/VirtualBox VMs");
+            // A kept home may already have its own VM folder
+            self.run_chroot(&format!(
+                "sh -c 'mkdir -p {dir}/{user} && chown {user}: {dir}/{user}; \
+                 [ -e \"{vms}\" ] || {{ ln -s {dir}/{user} \"{vms}\" && chown -h {user}: \"{vms}\"; }}'"
+            ));
+        }
+        tui::print_success(&format!("VM images and container data on nodatacow {dir}"));
+    }
+
+    /// [[disk.extra]] games = true: make the drive writable for the install
+    /// user so Steam can use it as a library. Runs after the user exists.
+    fn configure_games_drive(&self) {
+        let Some(drive) = self.config.disk.extra.iter().find(|x| x.games) else {
+            return;
+        };
+        let mp = &self.mount_point;
+        let user = &self.config.install.username;
+        let target = &drive.mountpoint;
+
+        // NTFS has no Unix ownership: map every file to the user in fstab
+        if drive.filesystem == "ntfs" {
+            let uid = self.exec_output(&format!("arch-chroot {mp} id -u {user}"));
+            let gid = self.exec_output(&format!("arch-chroot {mp} id -g {user}"));
+            let fstab_path = format!("{mp}/etc/fstab");
+            let fstab = fs::read_to_string(&fstab_path).unwrap_or_default();
+            let updated: String = fstab
+                .lines()
+                .map(|line| {
+                    let f: Vec<&str> = line.split_whitespace().collect();
+                    if f.len() >= 4 && f[1] == target.as_str() {
+                        format!(
+                            "{}  {}  {}  {},uid={uid},gid={gid},umask=022  {}\n",
+                            f[0],
+                            f[1],
+                            f[2],
+                            f[3],
+                            f[4..].join(" ")
+                        )
+                    } else {
+                        format!("{line}\n")
+                    }
+                })
+                .collect();
+            self.write_file(&fstab_path, &updated);
+        }
+
+        if !self.run_chroot(&format!("mount {target}")) {
+            tui::print_warning(&format!("Could not mount games drive at {target}"));
+            return;
+        }
+        if drive.filesystem == "ntfs" {
+            // Proton prefixes contain names NTFS rejects ("c:"), so
+            // compatdata lives on the Linux filesystem
+            let compat = format!("/home/{user}/.local/share/Steam/steamapps/compatdata");
+            let link = format!("{target}/SteamLibrary/steamapps/compatdata");
+            self.run_chroot(&format!(
+                "sh -c 'mkdir -p {target}/SteamLibrary/steamapps {compat} && \
+                 chown -R {user}: /home/{user}/.local && \
+                 {{ [ -e {link} ] || ln -s {compat} {link}; }}'"
+            ));
+        } else {
+            // Default ACL keeps files created by other users (or root)
+            // writable for the user
+            self.run_chroot(&format!(
+                "sh -c 'chown {user}: {target} && setfacl -m u:{user}:rwx,d:u:{user}:rwx {target}'"
+            ));
+        }
+        self.run_chroot(&format!("umount {target}"));
+        tui::print_success(&format!("Games drive ready at {target} (owned by {user})"));
+    }
+
+    /// Kiosk mode: a restricted user running one fullscreen app under cage on
+    /// tty1, restarted whenever it exits
+    fn configure_kiosk(&self) {
+        let kiosk = &self.config.kiosk;
+        if !kiosk.enabled {
+            return;
+        }
+
+        tui::print_info(&format!("Configuring kiosk mode for user: {}", kiosk.user));
 
-        // Create user (network group for WiFi/NM management)
+        // Restricted account: no wheel, no password login, no shell
         self.run_chroot(&format!(
-            "useradd -m -G wheel,audio,video,storage,optical,network,power,input -s /bin/bash {}",
-            self.config.install.username
+            "useradd -m -G audio,video,input -s /usr/bin/nologin {}",
+            kiosk.user
         ));
+        self.run_chroot(&format!("passwd -l {}", kiosk.user));
+
+        let service = format!(
+            "[Unit]\n\
+             Description=Blunux kiosk application\n\
+             After=systemd-user-sessions.service plymouth-quit-wait.service\n\
+             Conflicts=getty@tty1.service\n\
+             \n\
+             [Service]\n\
+             User={user}\n\
+             PAMName=login\n\
+             TTYPath=/dev/tty1\n\
+             TTYReset=yes\n\
+             TTYVHangup=yes\n\
+             TTYVTDisallocate=yes\n\
+             StandardInput=tty-fail\n\
+             UtmpIdentifier=tty1\n\
+             UtmpMode=user\n\
+             ExecStart=/usr/bin/cage -s -- {app}\n\
+             Restart=always\n\
+             RestartSec=2\n\
+             \n\
+             [Install]\n\
+             WantedBy=graphical.target\n",
+            user = kiosk.user,
+            app = kiosk.app
+        );
+        self.write_file(
+            &format!("{}/etc/systemd/system/blunux-kiosk.service", self.mount_point),
+            &service,
+        );
+        self.run_chroot("systemctl disable sddm");
+        self.run_chroot("systemctl disable getty@tty1.service 2>/dev/null || true");
+        self.run_chroot("systemctl enable blunux-kiosk.service");
 
-        // Set user password
-        let user_cmd = format!(
-            "echo '{}:{}' | chpasswd",
-            self.config.install.username, self.config.install.user_password
+        if kiosk.disable_usb_storage {
+            self.write_file(
+                &format!("{}/etc/modprobe.d/blunux-kiosk.conf", self.mount_point),
+                "# Kiosk mode: no USB mass storage\n\
+                 install usb_storage /bin/false\n\
+                 install uas /bin/false\n",
+            );
+        }
+
+        // Reboot automatically after a kernel panic instead of hanging
+        self.write_file(
+            &format!("{}/etc/sysctl.d/90-blunux-kiosk.conf", self.mount_point),
+            "kernel.panic = 10\n",
         );
-        self.run_chroot(&format!("sh -c \"{user_cmd}\""));
 
-        // Configure sudo
-        let sudoers = format!("{}/etc/sudoers.d/wheel", self.mount_point);
-        self.write_file(&sudoers, "%wheel ALL=(ALL:ALL) ALL\n");
-        self.run_command(&format!("chmod 440 {sudoers}"));
+        tui::print_success(&format!("Kiosk mode configured: {}", kiosk.app));
+    }
 
-        // Configure SDDM autologin
-        if self.config.install.autologin {
+    /// NumLock, tty autologin and the default boot target from [system]
+    fn configure_console(&self) {
+        let sys = &self.config.system;
+
+        if sys.numlock {
             let sddm_conf_dir = format!("{}/etc/sddm.conf.d", self.mount_point);
             self.run_command(&format!("mkdir -p {sddm_conf_dir}"));
+            self.write_file(
+                &format!("{sddm_conf_dir}/numlock.conf"),
+                "[General]\nNumlock=on\n",
+            );
 
-            let autologin_content = format!(
-                "[Autologin]\nUser={}\nSession=plasma\nRelogin=true\n",
-                self.config.install.username
+            // Plasma session (NumLock=0 means "turn on")
+            let xdg_dir = format!("{}/etc/xdg", self.mount_point);
+            self.run_command(&format!("mkdir -p {xdg_dir}"));
+            self.append_file(
+                &format!("{xdg_dir}/kcminputrc"),
+                "[Keyboard]\nNumLock=0\n",
+            );
+
+            // Virtual consoles
+            let getty_dir = format!(
+                "{}/etc/systemd/system/getty@.service.d",
+                self.mount_point
             );
+            self.run_command(&format!("mkdir -p {getty_dir}"));
             self.write_file(
-                &format!("{sddm_conf_dir}/autologin.conf"),
-                &autologin_content,
+                &format!("{getty_dir}/activate-numlock.conf"),
+                "[Service]\nExecStartPre=-/bin/sh -c 'setleds -D +num < /dev/%I'\n",
+            );
+            tui::print_success("NumLock enabled at boot");
+        }
+
+        if !sys.tty_autologin.is_empty() {
+            let tty = &sys.tty_autologin;
+            let dropin_dir = format!(
+                "{}/etc/systemd/system/getty@{tty}.service.d",
+                self.mount_point
+            );
+            self.run_command(&format!("mkdir -p {dropin_dir}"));
+            let autologin = format!(
+                "[Service]\n\
+                 ExecStart=\n\
+                 ExecStart=-/sbin/agetty -o '-p -f -- \\\\u' --noclear --autologin {} %I $TERM\n",
+                self.config.install.username
             );
+            self.write_file(&format!("{dropin_dir}/autologin.conf"), &autologin);
+            self.run_chroot(&format!("systemctl enable getty@{tty}.service"));
             tui::print_success(&format!(
-                "SDDM autologin configured for user: {}",
+                "Console autologin on {tty} for user: {}",
                 self.config.install.username
             ));
         }
 
-        true
+        self.run_chroot(&format!(
+            "systemctl set-default {}.target",
+            sys.default_target
+        ));
+    }
+
+    /// `option` when TRIM has to be requested at every unlock: LUKS1
+    /// containers on an SSD, whose header cannot store --persistent flags
+    fn luks1_discard(&self, option: &'static str) -> &'static str {
+        if self.config.disk.encryption.luks_version == 1 && self.partition_layout.ssd {
+            option
+        } else {
+            ""
+        }
+    }
+
+    /// root=LABEL= with [disk] fstab = "label", so a cloned image boots
+    /// without a new UUID; LUKS, LVM and ZFS roots have stable names anyway
+    fn root_label_param(&self) -> Option<String> {
+        let plain_root = !self.config.uses_luks()
+            && !self.config.disk.lvm.enabled
+            && self.config.disk.filesystem != Filesystem::Zfs;
+        (self.config.disk.fstab == "label" && plain_root)
+            .then(|| format!("root=LABEL={}", disk::root_fs_label(&self.config)))
+    }
+
+    /// Kernel command line for EFISTUB boot entries
+    fn nmbl_kernel_params(&self) -> String {
+        let root_uuid = disk::probe::uuid(&self.partition_layout.root_partition);
+
+        // With LVM the root filesystem is a logical volume inside cryptroot
+        let mapped_root = if self.config.disk.lvm.enabled {
+            disk::LVM_ROOT
+        } else {
+            "/dev/mapper/cryptroot"
+        };
+        let root_param = if self.config.unlocks_root_from_crypttab() {
+            // Unlocked by sd-encrypt from /etc/crypttab.initramfs
+            format!("root={mapped_root}")
+        } else if self.config.uses_luks() {
+            let discard = self.luks1_discard(":allow-discards");
+            format!("cryptdevice=UUID={root_uuid}:cryptroot{discard} root={mapped_root}")
+        } else if self.config.disk.lvm.enabled {
+            format!("root={}", disk::LVM_ROOT)
+        } else if self.config.disk.filesystem == Filesystem::Zfs {
+            format!("root=ZFS={}", disk::ZFS_ROOT_DATASET)
+        } else if self.config.disk.filesystem == Filesystem::Bcachefs {
+            format!("root=UUID={root_uuid} rootfstype=bcachefs")
+        } else if let Some(root) = self.root_label_param() {
+            root
+        } else {
+            format!("root=UUID={root_uuid}")
+        };
+        let mut kernel_params = format!("{root_param} rw quiet loglevel=3");
+        if self.config.disk.filesystem == Filesystem::Btrfs {
+            kernel_params.push_str(" rootflags=subvol=@");
+        }
+        if let Some(resume) = self.resume_params() {
+            kernel_params.push(' ');
+            kernel_params.push_str(&resume);
+        }
+        if self.config.uses_luks() && !self.config.disk.encryption.ssh_unlock_key.is_empty() {
+            // netconf brings up networking in the initramfs for the SSH server
+            kernel_params.push_str(" ip=dhcp");
+        }
+        if self.config.security.apparmor {
+            kernel_params.push(' ');
+            kernel_params.push_str(APPARMOR_LSM);
+        }
+        for param in &self.driver_params {
+            kernel_params.push(' ');
+            kernel_params.push_str(param);
+        }
+        kernel_params
+    }
+
+    /// [install] removable_fallback, unless the ESP is shared with another OS
+    /// whose own EFI/BOOT loader must not be replaced. Disk images always
+    /// get it: they have no boot entry on the machines they are written to.
+    fn wants_removable_fallback(&self) -> bool {
+        (self.config.install.removable_fallback || !self.config.image.is_empty())
+            && !self.partition_layout.keep_efi
+    }
+
+    /// Why efibootmgr cannot work on this machine, if it cannot: efivarfs
+    /// missing or read-only. NVRAM filled by kernel crash dumps only warns,
+    /// as efibootmgr itself reports when no space is left.
+    fn efivars_problem(&self) -> Option<String> {
+        let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+        let options = mounts.lines().find_map(|l| {
+            let f: Vec<&str> = l.split_whitespace().collect();
+            (f.len() >= 4 && f[1] == EFIVARS).then(|| f[3].to_string())
+        });
+        match options {
+            None => return Some(format!("efivarfs is not mounted at {EFIVARS}")),
+            Some(o) if o.split(',').any(|opt| opt == "ro") => {
+                if !self.run_command(&format!("mount -o remount,rw {EFIVARS}")) {
+                    return Some("efivarfs is read-only".to_string());
+                }
+            }
+            Some(_) => {}
+        }
+
+        let dumps = fs::read_dir(EFIVARS)
+            .map(|d| {
+                d.flatten()
+                    .filter(|e| e.file_name().to_string_lossy().starts_with("dump-"))
+                    .count()
+            })
+            .unwrap_or(0);
+        if dumps > 0 {
+            tui::print_warning(&format!(
+                "{dumps} kernel crash dump(s) are stored in UEFI NVRAM and may leave no room for a boot entry"
+            ));
+        }
+        None
+    }
+
+    /// Whether the first BootOrder entry is the one labelled `label`
+    fn boot_entry_first(&self, label: &str) -> bool {
+        let out = self.exec_output(&format!("arch-chroot {} efibootmgr", self.mount_point));
+        let Some(first) = out
+            .lines()
+            .find_map(|l| l.strip_prefix("BootOrder:"))
+            .and_then(|order| order.trim().split(',').next())
+        else {
+            return false;
+        };
+        out.lines().any(|l| {
+            l.strip_prefix(&format!("Boot{first}"))
+                .is_some_and(|name| name.trim_start_matches('*').trim_start().starts_with(label))
+        })
+    }
+
+    /// Build a unified kernel image at the removable-media path, so NMBL
+    /// boots even when the firmware has no (or ignores its) boot entry
+    fn install_removable_uki(&self, kernel: &str, kernel_params: &str) -> bool {
+        let cmdline = format!("{}/etc/kernel/cmdline", self.mount_point);
+        self.run_command(&format!("mkdir -p {}/etc/kernel", self.mount_point));
+        if !self.write_file(&cmdline, &format!("{kernel_params}\n")) {
+            return false;
+        }
+        self.run_chroot("mkdir -p /boot/efi/EFI/BOOT")
+            && self.run_chroot(&nmbl_uki_command(kernel))
+    }
+
+    /// Split the EFI partition into (disk, partition number) for efibootmgr
+    fn efi_disk_and_part(&self) -> (String, String) {
+        let efi_part = &self.partition_layout.efi_partition;
+        if efi_part.contains("nvme") || efi_part.contains("mmcblk") || efi_part.contains("loop") {
+            let p_pos = efi_part.rfind('p').unwrap_or(efi_part.len());
+            (
+                efi_part[..p_pos].to_string(),
+                efi_part[p_pos + 1..].to_string(),
+            )
+        } else {
+            // Find where trailing digits start: /dev/sda1 -> split at 'a'/'1' boundary
+            let bytes = efi_part.as_bytes();
+            let mut num_start = bytes.len();
+            for i in (0..bytes.len()).rev() {
+                if bytes[i].is_ascii_digit() {
+                    num_start = i;
+                } else {
+                    break;
+                }
+            }
+            (
+                efi_part[..num_start].to_string(),
+                efi_part[num_start..].to_string(),
+            )
+        }
     }
 
     fn install_bootloader(&self) -> bool {
@@ -846,19 +3635,7 @@ nameserver 1.1.1.1\n";
                     kernel = "linux".to_string();
                 }
 
-                let root_uuid = self.exec_output(&format!(
-                    "blkid -s UUID -o value {}",
-                    self.partition_layout.root_partition
-                ));
-
-                let root_param = if self.config.install.use_encryption {
-                    format!(
-                        "cryptdevice=UUID={root_uuid}:cryptroot root=/dev/mapper/cryptroot"
-                    )
-                } else {
-                    format!("root=UUID={root_uuid}")
-                };
-                let kernel_params = format!("{root_param} rw quiet loglevel=3");
+                let kernel_params = self.nmbl_kernel_params();
 
                 // Copy kernel and initramfs to ESP
                 self.run_chroot("mkdir -p /boot/efi/EFI/Blunux");
@@ -869,31 +3646,7 @@ nameserver 1.1.1.1\n";
                     "cp /boot/initramfs-{kernel}.img /boot/efi/EFI/Blunux/initramfs-{kernel}.img"
                 ));
 
-                // Parse EFI partition for efibootmgr
-                let efi_part = &self.partition_layout.efi_partition;
-                let (efi_disk, efi_part_num) =
-                    if efi_part.contains("nvme") || efi_part.contains("mmcblk") {
-                        let p_pos = efi_part.rfind('p').unwrap_or(efi_part.len());
-                        (
-                            efi_part[..p_pos].to_string(),
-                            efi_part[p_pos + 1..].to_string(),
-                        )
-                    } else {
-                        // Find where trailing digits start: /dev/sda1 -> split at 'a'/'1' boundary
-                        let bytes = efi_part.as_bytes();
-                        let mut num_start = bytes.len();
-                        for i in (0..bytes.len()).rev() {
-                            if bytes[i].is_ascii_digit() {
-                                num_start = i;
-                            } else {
-                                break;
-                            }
-                        }
-                        (
-                            efi_part[..num_start].to_string(),
-                            efi_part[num_start..].to_string(),
-                        )
-                    };
+                let (efi_disk, efi_part_num) = self.efi_disk_and_part();
 
                 let efi_cmd = format!(
                     "efibootmgr --create \
@@ -904,9 +3657,32 @@ nameserver 1.1.1.1\n";
                      --unicode \"{kernel_params} initrd=\\EFI\\Blunux\\initramfs-{kernel}.img\""
                 );
 
-                if !self.run_chroot(&efi_cmd) {
-                    tui::print_error("Failed to create UEFI boot entry");
-                    return false;
+                // Without a usable boot entry, boot from the removable-media
+                // path that every UEFI firmware tries on its own
+                let entry_ok = match self.efivars_problem() {
+                    // The image boots on other machines; this one's NVRAM stays untouched
+                    _ if !self.config.image.is_empty() => false,
+                    Some(problem) => {
+                        tui::print_warning(&format!("{problem}; efibootmgr cannot be used"));
+                        false
+                    }
+                    None if !self.run_chroot(&efi_cmd) => {
+                        tui::print_warning("Failed to create UEFI boot entry");
+                        false
+                    }
+                    None if !self.boot_entry_first("Blunux") => {
+                        tui::print_warning("The firmware did not accept the new BootOrder");
+                        false
+                    }
+                    None => true,
+                };
+                let removable = !entry_ok || self.wants_removable_fallback();
+                if removable {
+                    tui::print_info(&format!("NMBL: Installing fallback {NMBL_REMOVABLE_PATH}..."));
+                    if !self.install_removable_uki(&kernel, &kernel_params) {
+                        tui::print_error("Failed to install the removable-media boot image");
+                        return false;
+                    }
                 }
 
                 // Create pacman hook for kernel updates
@@ -930,12 +3706,15 @@ nameserver 1.1.1.1\n";
                     &hook_content,
                 );
 
-                let update_script = format!(
+                let mut update_script = format!(
                     "#!/bin/bash\n\
                      # NMBL: Copy updated kernel/initramfs to ESP\n\
                      cp /boot/vmlinuz-{kernel} /boot/efi/EFI/Blunux/vmlinuz-{kernel}\n\
                      cp /boot/initramfs-{kernel}.img /boot/efi/EFI/Blunux/initramfs-{kernel}.img\n"
                 );
+                if removable {
+                    update_script.push_str(&format!("{}\n", nmbl_uki_command(&kernel)));
+                }
                 self.write_file(
                     &format!("{}/usr/local/bin/nmbl-update", self.mount_point),
                     &update_script,
@@ -953,29 +3732,269 @@ nameserver 1.1.1.1\n";
         }
 
         // GRUB (default)
-        if disk::is_uefi() {
+        if self.config.disk.encryption.encrypt_boot && self.config.uses_luks() {
+            // Must be set before grub-install so the cryptodisk modules are embedded
             self.run_chroot(
-                "grub-install --target=x86_64-efi --efi-directory=/boot/efi --bootloader-id=Blunux",
+                "sh -c \"grep -q '^GRUB_ENABLE_CRYPTODISK=' /etc/default/grub \
+                 && sed -i 's/^GRUB_ENABLE_CRYPTODISK=.*/GRUB_ENABLE_CRYPTODISK=y/' /etc/default/grub \
+                 || echo 'GRUB_ENABLE_CRYPTODISK=y' >> /etc/default/grub\"",
+            );
+        }
+        if self.config.unlocks_root_from_crypttab() {
+            // cryptroot itself is opened by sd-encrypt from /etc/crypttab.initramfs
+            let root = if self.config.disk.lvm.enabled {
+                disk::LVM_ROOT
+            } else {
+                "/dev/mapper/cryptroot"
+            };
+            self.run_chroot(&format!(
+                "sed -i 's|^GRUB_CMDLINE_LINUX=.*|GRUB_CMDLINE_LINUX=\"root={root}\"|' /etc/default/grub"
+            ));
+        } else if let Some(root) = self.root_label_param() {
+            // grub-mkconfig then puts the device path first; the later root= wins
+            self.append_file(
+                &format!("{}/etc/default/grub", self.mount_point),
+                "GRUB_DISABLE_LINUX_UUID=true\n",
             );
-        } else {
             self.run_chroot(&format!(
-                "grub-install --target=i386-pc {}",
-                self.config.install.target_disk
+                "sed -i 's|^GRUB_CMDLINE_LINUX=.*|GRUB_CMDLINE_LINUX=\"{root}\"|' /etc/default/grub"
             ));
         }
 
-        tui::print_info("Configuring GRUB for direct boot...");
-        self.run_chroot("sed -i 's/^GRUB_TIMEOUT=.*/GRUB_TIMEOUT=0/' /etc/default/grub");
-        self.run_chroot(
-            "sed -i 's/^GRUB_TIMEOUT_STYLE=.*/GRUB_TIMEOUT_STYLE=hidden/' /etc/default/grub",
-        );
-        self.run_chroot("grep -q '^GRUB_TIMEOUT_STYLE=' /etc/default/grub || echo 'GRUB_TIMEOUT_STYLE=hidden' >> /etc/default/grub");
+        let target = &self.config.install.target_disk;
+        match self.partition_layout.scheme {
+            PartitionScheme::GptUefi => {
+                // A disk image boots elsewhere: no entry in this machine's NVRAM
+                let nvram = if self.config.image.is_empty() { "" } else { " --no-nvram" };
+                self.run_chroot(&format!(
+                    "grub-install --target=x86_64-efi --efi-directory=/boot/efi \
+                     --bootloader-id=Blunux{nvram}"
+                ));
+                // Second copy at EFI/BOOT/BOOTX64.EFI; --removable leaves NVRAM alone
+                if self.wants_removable_fallback()
+                    && !self.run_chroot(
+                        "grub-install --target=x86_64-efi --efi-directory=/boot/efi --removable",
+                    )
+                {
+                    tui::print_warning("Could not install the fallback EFI/BOOT/BOOTX64.EFI loader");
+                }
+            }
+            // On GPT, grub-install finds the bios_grub partition by its flag
+            PartitionScheme::GptBios | PartitionScheme::MbrBios => {
+                if !self.run_chroot(&format!("grub-install --target=i386-pc {target}")) {
+                    let hint = if self.partition_layout.scheme == PartitionScheme::GptBios {
+                        " (is the bios_grub partition present?)"
+                    } else {
+                        ""
+                    };
+                    tui::print_error(&format!("Failed to install GRUB to {target}{hint}"));
+                    return false;
+                }
+            }
+        }
+
+        if self.config.security.apparmor {
+            self.run_chroot(&format!(
+                "sed -i 's/^GRUB_CMDLINE_LINUX_DEFAULT=\"\\(.*\\)\"/GRUB_CMDLINE_LINUX_DEFAULT=\"\\1 {APPARMOR_LSM}\"/' /etc/default/grub"
+            ));
+        }
+        if let Some(resume) = self.resume_params() {
+            self.run_chroot(&format!(
+                "sed -i 's|^GRUB_CMDLINE_LINUX_DEFAULT=\"\\(.*\\)\"|GRUB_CMDLINE_LINUX_DEFAULT=\"\\1 {resume}\"|' /etc/default/grub"
+            ));
+        }
+        if !self.driver_params.is_empty() {
+            self.run_chroot(&format!(
+                "sed -i 's|^GRUB_CMDLINE_LINUX_DEFAULT=\"\\(.*\\)\"|GRUB_CMDLINE_LINUX_DEFAULT=\"\\1 {}\"|' /etc/default/grub",
+                self.driver_params.join(" ")
+            ));
+        }
+
+        if self.config.disk.alongside.enabled || !self.config.disk.reuse_esp.is_empty() {
+            // Dual boot: show the menu and let os-prober add the other OS
+            tui::print_info("Configuring GRUB menu for dual boot...");
+            self.run_chroot("sed -i 's/^GRUB_TIMEOUT=.*/GRUB_TIMEOUT=5/' /etc/default/grub");
+            self.run_chroot(
+                "sed -i 's/^#\\?GRUB_DISABLE_OS_PROBER=.*/GRUB_DISABLE_OS_PROBER=false/' /etc/default/grub",
+            );
+            self.run_chroot("grep -q '^GRUB_DISABLE_OS_PROBER=' /etc/default/grub || echo 'GRUB_DISABLE_OS_PROBER=false' >> /etc/default/grub");
+        } else {
+            tui::print_info("Configuring GRUB for direct boot...");
+            self.run_chroot("sed -i 's/^GRUB_TIMEOUT=.*/GRUB_TIMEOUT=0/' /etc/default/grub");
+            self.run_chroot(
+                "sed -i 's/^GRUB_TIMEOUT_STYLE=.*/GRUB_TIMEOUT_STYLE=hidden/' /etc/default/grub",
+            );
+            self.run_chroot("grep -q '^GRUB_TIMEOUT_STYLE=' /etc/default/grub || echo 'GRUB_TIMEOUT_STYLE=hidden' >> /etc/default/grub");
+        }
         self.run_chroot("grub-mkconfig -o /boot/grub/grub.cfg");
 
         true
     }
 
-    fn finalize(&self) -> bool {
+    /// Clear machine-id, SSH host keys and other per-machine state, and
+    /// install a first-boot unit that recreates them on every clone
+    fn generalize(&self) {
+        tui::print_info("Generalizing image (machine-id, SSH host keys)...");
+        let mp = &self.mount_point;
+
+        // An empty (not missing) machine-id makes systemd generate a new one
+        // and treat the boot as a first boot (ConditionFirstBoot=yes)
+        self.write_file(&format!("{mp}/etc/machine-id"), "");
+        self.run_command(&format!(
+            "rm -f {mp}/var/lib/dbus/machine-id \
+             {mp}/etc/ssh/ssh_host_*_key {mp}/etc/ssh/ssh_host_*_key.pub \
+             {mp}/var/lib/systemd/random-seed {mp}/var/lib/systemd/credential.secret"
+        ));
+
+        let script = "#!/bin/bash\n\
+                      # Per-clone identity (auto-generated by Blunux installer)\n\
+                      if command -v ssh-keygen >/dev/null; then\n\
+                      \x20   ssh-keygen -A\n\
+                      fi\n\
+                      # Initramfs SSH unlock keys are baked into the image\n\
+                      if [ -d /etc/dropbear ] || [ -d /etc/tinyssh ]; then\n\
+                      \x20   rm -f /etc/dropbear/dropbear_*_host_key\n\
+                      \x20   rm -rf /etc/tinyssh/sshkeydir\n\
+                      \x20   mkinitcpio -P\n\
+                      \x20   [ -x /usr/local/bin/nmbl-update ] && /usr/local/bin/nmbl-update\n\
+                      fi\n\
+                      exit 0\n";
+        let script_path = format!("{mp}/usr/local/bin/blunux-regenerate-identity");
+        self.run_command(&format!("mkdir -p {mp}/usr/local/bin"));
+        self.write_file(&script_path, script);
+        self.run_command(&format!("chmod 755 {script_path}"));
+
+        let service = "[Unit]\n\
+                       Description=Regenerate per-machine identity for this clone\n\
+                       ConditionFirstBoot=yes\n\
+                       Before=sshd.service\n\
+                       \n\
+                       [Service]\n\
+                       Type=oneshot\n\
+                       ExecStart=/usr/local/bin/blunux-regenerate-identity\n\
+                       \n\
+                       [Install]\n\
+                       WantedBy=multi-user.target\n";
+        self.write_file(
+            &format!("{mp}/etc/systemd/system/blunux-regenerate-identity.service"),
+            service,
+        );
+        self.run_chroot("systemctl enable blunux-regenerate-identity.service");
+
+        tui::print_success("Image generalized - each clone gets a new identity on first boot");
+    }
+
+    /// Write INSTALL_REPORT: what was installed and on which hardware
+    fn write_install_report(&self) {
+        let cfg = &self.config;
+        let report = serde_json::json!({
+            "installer_version": env!("CARGO_PKG_VERSION"),
+            "blunux_version": cfg.blunux.version,
+            "installed_at": self.exec_output("date -Iseconds").trim(),
+            "hostname": cfg.install.hostname,
+            "target_disk": cfg.install.target_disk,
+            "layout": cfg.disk.layout,
+            "filesystem": cfg.disk.filesystem.label(),
+            "encryption": cfg.uses_luks(),
+            "luks": if cfg.uses_luks() {
+                disk::luks_parameters(&self.partition_layout.root_partition)
+            } else {
+                serde_json::Value::Null
+            },
+            "kernel": cfg.kernel.type_,
+            "bootloader": cfg.install.bootloader,
+            "hardware": self.hardware,
+        });
+        let Ok(json) = serde_json::to_string_pretty(&report) else {
+            return;
+        };
+        let path = format!("{}{INSTALL_REPORT}", self.mount_point);
+        self.run_command(&format!("mkdir -p $(dirname {path})"));
+        if self.write_file(&path, &format!("{json}\n")) {
+            tui::print_success(&format!("Install report written to {INSTALL_REPORT}"));
+        } else {
+            tui::print_warning("Could not write the install report");
+        }
+    }
+
+    /// Copy the partition table backups out of the live system's RAM into
+    /// PARTITION_BACKUPS, so they survive the reboot
+    fn save_partition_backups(&self) {
+        if !Path::new(disk::BACKUP_DIR).is_dir() {
+            return;
+        }
+        let dest = format!("{}{PARTITION_BACKUPS}", self.mount_point);
+        if self.run_command(&format!(
+            "mkdir -p {dest} && cp -rn {}/. {dest}/ && chmod -R go= {dest}",
+            disk::BACKUP_DIR
+        )) {
+            tui::print_success(&format!("Partition table backups saved to {PARTITION_BACKUPS}"));
+        } else {
+            tui::print_warning(&format!(
+                "Could not save the partition table backups - copy {} off before rebooting",
+                disk::BACKUP_DIR
+            ));
+        }
+    }
+
+    /// Create `files` in the target: missing parent directories, then the
+    /// file (content or a copy of source) or directory, its mode and owner.
+    /// Owners are looked up in the target's passwd, so users must exist.
+    fn apply_files(&self, files: &[FileEntry]) -> bool {
+        let username = &self.config.install.username;
+        let chown = |owner: &str, path: &str| {
+            // "user:" also sets the user's login group
+            let owner = if owner.contains(':') {
+                owner.to_string()
+            } else {
+                format!("{owner}:")
+            };
+            self.run_chroot(&format!("chown {owner} '{path}'"))
+        };
+
+        for f in files {
+            let path = f.target_path(username);
+            let host_path = format!("{}{path}", self.mount_point);
+
+            // Parents created under ~/ belong to the user, others to root
+            let mut missing = Vec::new();
+            let mut parent = Path::new(&path).parent();
+            while let Some(dir) = parent {
+                if Path::new(&format!("{}{}", self.mount_point, dir.display())).exists() {
+                    break;
+                }
+                missing.push(dir);
+                parent = dir.parent();
+            }
+            for dir in missing.iter().rev() {
+                let dir = dir.display().to_string();
+                let created = fs::create_dir(format!("{}{dir}", self.mount_point)).is_ok()
+                    && (!f.in_home() || chown(username, &dir));
+                if !created {
+                    tui::print_error(&format!("Could not create directory {dir}"));
+                    return false;
+                }
+            }
+
+            let written = if let Some(content) = &f.content {
+                self.write_file(&host_path, content)
+            } else if !f.source.is_empty() {
+                self.run_command(&format!("cp '{}' '{host_path}'", f.source))
+            } else {
+                fs::create_dir_all(&host_path).is_ok()
+            };
+            let done = written
+                && self.run_command(&format!("chmod {} '{host_path}'", f.mode()))
+                && chown(f.owner(username), &path);
+            if !done {
+                tui::print_error(&format!("Could not create {path}"));
+                return false;
+            }
+        }
+        true
+    }
+
+    fn finalize(&mut self) -> bool {
         let user_home = format!(
             "{}/home/{}",
             self.mount_point, self.config.install.username
@@ -1031,11 +4050,13 @@ nameserver 1.1.1.1\n";
         }
         tui::print_success("Blunux branding configured");
 
+        self.write_install_report();
+        self.save_partition_backups();
+
         // 2. Create package installation script
         let script_packages = self.config.get_script_package_list();
         if !script_packages.is_empty() {
             tui::print_info("Creating package installation script...");
-            let script_path = format!("{user_home}/install-packages.sh");
 
             let mut pkg_script = r#"#!/bin/bash
 # Blunux Package Installation Script (auto-generated by installer)
@@ -1113,105 +4134,17 @@ echo "Please log out and log back in for changes to take effect."
 "#,
             );
 
-            self.write_file(&script_path, &pkg_script);
-            self.run_command(&format!("chmod +x {script_path}"));
+            self.apply_files(&[FileEntry::file("~/install-packages.sh", "0755", &pkg_script)]);
             tui::print_success(
                 "Created ~/install-packages.sh - run after first boot to install selected packages",
             );
         }
 
-        // 3. Create kime installation script (backup)
-        if self.config.input_method.enabled && self.config.input_method.engine == "kime" {
-            let script_path = format!("{user_home}/kime-install.sh");
-            let kime_script = r#"#!/bin/bash
-# KIME Installation Script (auto-generated by Blunux installer)
-# Run this if kime was not installed during system installation
-
-set -e
-
-echo "Installing kime-git..."
-
-# Check if yay is installed
-if ! command -v yay &> /dev/null; then
-    echo "Installing yay first..."
-    BUILDDIR=$(mktemp -d)
-    git clone https://aur.archlinux.org/yay-bin.git "$BUILDDIR/yay-bin"
-    cd "$BUILDDIR/yay-bin"
-    makepkg -si --noconfirm
-    cd /
-    rm -rf "$BUILDDIR"
-fi
-
-# Install kime-git
-yay -S --noconfirm --needed kime-git
-
-echo "kime-git installed successfully!"
-echo "Please log out and log back in for changes to take effect."
-"#;
-            self.write_file(&script_path, kime_script);
-            self.run_command(&format!("chmod +x {script_path}"));
-            tui::print_info("Created ~/kime-install.sh backup script");
-        }
-
-        // 4. Create linux-bore setup script (if selected)
-        if self.config.kernel.type_ == "linux-bore" {
-            tui::print_info("linux-bore kernel selected - will be installed after first boot");
-            let bore_script_path = format!("{user_home}/setup-linux-bore.sh");
-            let bore_script = r#"#!/bin/bash
-# Linux-BORE Kernel Setup Script (auto-generated by Blunux installer)
-# Run this after first boot to complete linux-bore installation
-
-set -e
-
-echo "=========================================="
-echo "  Linux-BORE Kernel Setup"
-echo "=========================================="
-
-# Check if yay is installed
-if ! command -v yay &> /dev/null; then
-    echo "Installing yay first..."
-    BUILDDIR=$(mktemp -d)
-    git clone https://aur.archlinux.org/yay-bin.git "$BUILDDIR/yay-bin"
-    cd "$BUILDDIR/yay-bin"
-    makepkg -si --noconfirm
-    cd /
-    rm -rf "$BUILDDIR"
-fi
-
-# Install linux-cachyos kernel (BORE scheduler)
-echo "Installing linux-cachyos kernel with BORE scheduler (this may take a while)..."
-yay -S --noconfirm --needed linux-cachyos linux-cachyos-headers
-
-# Update boot configuration
-if [ -f /usr/local/bin/nmbl-update ]; then
-    echo "Updating EFISTUB boot entry (NMBL)..."
-    sudo /usr/local/bin/nmbl-update
-    ROOT_UUID=$(blkid -s UUID -o value $(findmnt -n -o SOURCE /))
-    sudo efibootmgr --create --disk $(findmnt -n -o SOURCE /boot/efi | sed 's/[0-9]*$//') \
-        --part $(findmnt -n -o SOURCE /boot/efi | grep -o '[0-9]*$') \
-        --label "Blunux" \
-        --loader "\\EFI\\Blunux\\vmlinuz-linux-cachyos" \
-        --unicode "root=UUID=$ROOT_UUID rw quiet loglevel=3 initrd=\\EFI\\Blunux\\initramfs-linux-cachyos.img"
-else
-    echo "Updating GRUB configuration..."
-    sudo grub-mkconfig -o /boot/grub/grub.cfg
-fi
-
-echo ""
-echo "=========================================="
-echo "  Linux-CachyOS (BORE) installation complete!"
-echo "=========================================="
-echo ""
-echo "Please reboot to use the linux-cachyos kernel."
-"#;
-            self.write_file(&bore_script_path, bore_script);
-            self.run_command(&format!("chmod +x {bore_script_path}"));
-            tui::print_info("Created ~/setup-linux-bore.sh - run after first boot!");
-        }
+        // 3. AUR packages ([packages] aur, kime-git, linux-bore kernel)
+        self.install_aur_packages();
 
-        // 5. Create system check script
+        // 4. Create system check script
         {
-            let syschk_script_path = format!("{user_home}/syschk.sh");
             let syschk_script = r#"#!/bin/bash
 # System Check Script (auto-generated by Blunux installer)
 # Downloads and runs syschk.jl with Julia
@@ -1227,18 +4160,14 @@ curl -fsSL "$SYSCHK_URL" -o "$SYSCHK_FILE"
 echo "Running system check..."
 julia "$SYSCHK_FILE"
 "#;
-            self.write_file(&syschk_script_path, syschk_script);
-            self.run_command(&format!("chmod +x {syschk_script_path}"));
+            self.apply_files(&[FileEntry::file("~/syschk.sh", "0755", syschk_script)]);
             tui::print_info("Created ~/syschk.sh - system check script");
         }
 
-        // 6. Configure kime input method
+        // 5. Configure kime input method
         if self.config.input_method.enabled && self.config.input_method.engine == "kime" {
             tui::print_info("Configuring kime input method...");
 
-            let kime_config_dir = format!("{user_home}/.config/kime");
-            self.run_command(&format!("mkdir -p {kime_config_dir}"));
-
             let kime_config = r#"indicator:
   icon_color: Black
 
@@ -1270,12 +4199,8 @@ engine:
     word_commit: false
     auto_reorder: true
 "#;
-            self.write_file(&format!("{kime_config_dir}/config.yaml"), kime_config);
-
-            // Create autostart entry
-            let autostart_dir = format!("{user_home}/.config/autostart");
-            self.run_command(&format!("mkdir -p {autostart_dir}"));
 
+            // Autostart entry
             let kime_desktop = "[Desktop Entry]\n\
                                 Type=Application\n\
                                 Name=Kime Input Method\n\
@@ -1283,12 +4208,8 @@ engine:
                                 Terminal=false\n\
                                 Categories=Utility;\n\
                                 X-GNOME-Autostart-enabled=true\n";
-            self.write_file(&format!("{autostart_dir}/kime.desktop"), kime_desktop);
-
-            // Create systemd user service
-            let systemd_dir = format!("{user_home}/.config/systemd/user");
-            self.run_command(&format!("mkdir -p {systemd_dir}"));
 
+            // systemd user service
             let kime_service = "[Unit]\n\
                                 Description=Korean Input Method Editor\n\
                                 After=graphical-session.target\n\
@@ -1305,60 +4226,82 @@ engine:
                                 \n\
                                 [Install]\n\
                                 WantedBy=graphical-session.target\n";
-            self.write_file(&format!("{systemd_dir}/kime.service"), kime_service);
-
-            self.run_chroot(&format!(
-                "su - {username} -c 'systemctl --user enable kime.service' 2>/dev/null || true"
-            ));
-
-            // Configure KDE Plasma virtual keyboard
-            let kwinrc_path = format!("{user_home}/.config/kwinrc");
-            let kwinrc_content = "[Wayland]\nInputMethod[$e]=/usr/share/applications/kime.desktop\n";
-            if Path::new(&kwinrc_path).exists() {
-                self.append_file(&kwinrc_path, &format!("\n{kwinrc_content}"));
-            } else {
-                self.write_file(&kwinrc_path, kwinrc_content);
-            }
-
-            // Create environment files
-            let bash_profile = "# Kime Input Method\n\
-                                export GTK_IM_MODULE=kime\n\
-                                export QT_IM_MODULE=kime\n\
-                                export XMODIFIERS=@im=kime\n\
-                                export LANG=ko_KR.UTF-8\n";
-            self.append_file(&format!("{user_home}/.bash_profile"), bash_profile);
 
             let xprofile = "export GTK_IM_MODULE=kime\n\
                             export QT_IM_MODULE=kime\n\
                             export XMODIFIERS=@im=kime\n";
-            self.write_file(&format!("{user_home}/.xprofile"), xprofile);
 
-            // System-wide environment
-            let env_d_content = "GTK_IM_MODULE=kime\n\
-                                 QT_IM_MODULE=kime\n\
-                                 XMODIFIERS=@im=kime\n";
-            self.run_command(&format!(
-                "mkdir -p {}/etc/environment.d",
-                self.mount_point
-            ));
-            self.write_file(
-                &format!("{}/etc/environment.d/kime.conf", self.mount_point),
-                env_d_content,
-            );
+            // Per-user files go to /etc/skel so every user gets them;
+            // blunux-firstlogin copies them into homes that predate them.
+            // The system-wide environment comes from configure_input_method.
+            self.apply_files(&[FileEntry::file(
+                "/etc/skel/.config/kime/config.yaml",
+                "",
+                kime_config,
+            )]);
+            if self.wayland_session() {
+                // KWin starts kime itself over the input-method protocol
+                self.set_kwin_input_method("kime.desktop");
+            } else {
+                self.apply_files(&[
+                    FileEntry::file("/etc/skel/.config/autostart/kime.desktop", "", kime_desktop),
+                    FileEntry::file("/etc/systemd/user/kime.service", "", kime_service),
+                    FileEntry::file("/etc/skel/.xprofile", "", xprofile),
+                ]);
+                self.run_chroot("systemctl --global enable kime.service");
+            }
 
+            self.install_firstlogin();
             tui::print_success("kime input method configured");
+        } else if self.config.input_method.enabled {
+            match self.config.input_method.engine.as_str() {
+                "fcitx5" => self.configure_fcitx5(),
+                "ibus" => self.configure_ibus(),
+                _ => {}
+            }
         }
 
-        // 7. Fix home directory ownership
+        // 6. Fix home directory ownership
         tui::print_info("Fixing home directory ownership...");
-        self.run_command(&format!("chown -R 1000:1000 {user_home}"));
+        let Some((uid, gid)) = self.user_ids(username) else {
+            tui::print_error(&format!("User {username} is missing from the target's passwd"));
+            return false;
+        };
+        self.run_command(&format!("chown -R {uid}:{gid} {user_home}"));
         self.run_command(&format!("chmod 700 {user_home}"));
         self.run_command(&format!("chmod 700 {user_home}/.config"));
         tui::print_success("Home directory ownership fixed");
 
-        // 8. Unmount and finish
+        // 7. [[files]] from config.toml, after the ownership fix so their
+        // owners stick
+        if !self.config.files.is_empty() {
+            tui::print_info("Creating files from config.toml...");
+            if !self.apply_files(&self.config.files) {
+                return false;
+            }
+            tui::print_success(&format!("{} file(s) created", self.config.files.len()));
+        }
+
+        // 8. Strip per-machine identity for golden images
+        if self.config.install.generalize {
+            self.generalize();
+        }
+
+        // 9. Unmount and finish; a swap file on the target would keep it busy
+        self.disable_live_swap();
         disk::unmount_partitions(&self.mount_point);
 
         true
     }
+
+    /// After an abort: unmount the target and close what was opened on
+    /// it, so the disk can be reused without a reboot
+    pub fn unwind(&mut self) {
+        if self.error_message.is_empty() {
+            self.error_message = format!("aborted during: {}", self.step);
+        }
+        tui::print_info(&format!("Unmounting {}...", self.mount_point));
+        self.disable_live_swap();
+        disk::unmount_partitions(&self.mount_point);
+    }
 }
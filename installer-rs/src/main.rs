@@ -1,28 +1,66 @@
+mod abort;
 mod config;
 mod disk;
+mod drivers;
+mod hardware;
 mod installer;
+mod repos;
+mod telemetry;
 mod tui;
 
-use config::Config;
+use config::{Config, Filesystem, Keyboard, PackagesConfig, SwapMode};
 use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 use std::process;
+use std::time::Instant;
 
 fn print_usage(program: &str) {
     println!();
     println!("{}Usage:{}", tui::BOLD, tui::RESET);
     println!("  {program} [config.toml]");
+    println!("  {program} [config.toml] --image <out.img> --size <20G>");
+    println!("  {program} generate-config <out.toml>");
     println!();
     println!("{}Options:{}", tui::BOLD, tui::RESET);
     println!("  --help, -h     Show this help message");
     println!("  --version, -v  Show version information");
+    println!("  --force        Allow reusing the existing / partition (format = false),");
+    println!("                 or overwriting an existing --image file given a --size");
+    println!("  --skip-disk-checks");
+    println!("                 Install even if the target disk looks too small or is part");
+    println!("                 of an active RAID array / LVM volume group");
+    println!("  --list-disks   Print the disks the installer sees as JSON (partitions,");
+    println!("                 installed systems, SMART warnings) and exit");
+    println!("  --list-hardware");
+    println!("                 Print the hardware survey and matching driver packages as JSON");
+    println!("  --image <file>  Install into a disk image (golden image) instead of a disk;");
+    println!("                 the image is attached as a loop device and generalized");
+    println!("  --size <size>  Size of a new --image, e.g. 20G (sparse file)");
+    println!("  --restore-partition-table <disk>");
+    println!("                 Undo partitioning: restore the newest partition table and LUKS");
+    println!("                 headers saved in {} before the disk was changed", disk::BACKUP_DIR);
+    println!("                 (also kept in /var/backups/blunux-partition-tables on the new system)");
     println!();
     println!("{}Examples:{}", tui::BOLD, tui::RESET);
     println!("  {program}                    # Interactive mode");
     println!("  {program} config.toml        # Use config file");
+    println!("  {program} generate-config pc.toml  # Answer the wizard, install later");
+    println!("  {program} fleet.toml --image blunux.img --size 20G");
+    println!("  {program} --list-disks > disks.json");
+    println!("  {program} --restore-partition-table /dev/sda");
     println!();
 }
 
+/// Release the loop device of --image when the installer stops early
+fn detach_image(cfg: &Config) {
+    if !cfg.image.is_empty() {
+        disk::detach_image(&cfg.install.target_disk);
+    }
+}
+
 fn check_root() -> bool {
     unsafe {
         if libc::getuid() != 0 {
@@ -71,226 +109,1111 @@ fn select_config_file() -> Option<String> {
     None
 }
 
-fn interactive_setup(cfg: &mut Config) {
-    tui::clear_screen();
-    tui::print_banner();
+/// Checkbox screens for the optional package groups.
+/// Returns false when the user backs out of the first group.
+fn select_package_groups(p: &mut PackagesConfig) -> bool {
+    let mut groups: Vec<(&str, Vec<(&str, &mut bool)>)> = vec![
+        (
+            "Browsers / 브라우저",
+            vec![
+                ("Firefox", &mut p.firefox),
+                ("Naver Whale", &mut p.whale),
+                ("Google Chrome", &mut p.chrome),
+                ("Mullvad Browser", &mut p.mullvad),
+            ],
+        ),
+        (
+            "Office / 오피스",
+            vec![
+                ("LibreOffice", &mut p.libreoffice),
+                ("Hancom Office (hoffice)", &mut p.hoffice),
+                ("TeX Live", &mut p.texlive),
+            ],
+        ),
+        (
+            "Development / 개발 도구",
+            vec![
+                ("VS Code", &mut p.vscode),
+                ("Sublime Text", &mut p.sublime),
+                ("Rust", &mut p.rust),
+                ("Julia", &mut p.julia),
+                ("Node.js", &mut p.nodejs),
+                ("GitHub CLI", &mut p.github_cli),
+            ],
+        ),
+        (
+            "Multimedia / 멀티미디어",
+            vec![
+                ("VLC", &mut p.vlc),
+                ("OBS Studio", &mut p.obs),
+                ("FreeTuxTV", &mut p.freetv),
+                ("yt-dlp", &mut p.ytdlp),
+                ("FreeTube", &mut p.freetube),
+            ],
+        ),
+    ];
 
-    println!();
-    tui::print_info("Starting interactive setup / 대화형 설정 시작\n");
-
-    // Step 1: Select disk
-    let disks = disk::get_disks();
-    let selected_disk = tui::select_disk(&disks);
-    match selected_disk {
-        Some(d) => cfg.install.target_disk = d.device,
-        None => {
-            tui::print_error("No disk selected. Exiting.");
-            process::exit(1);
+    let mut g = 0;
+    while g < groups.len() {
+        let (title, items) = &mut groups[g];
+        let labels: Vec<&str> = items.iter().map(|(label, _)| *label).collect();
+        let current: Vec<bool> = items.iter().map(|(_, flag)| **flag).collect();
+        match tui::multi_select(title, &labels, &current) {
+            Some(chosen) => {
+                for ((_, flag), value) in items.iter_mut().zip(chosen) {
+                    **flag = value;
+                }
+                g += 1;
+            }
+            None if g == 0 => return false,
+            None => g -= 1,
         }
     }
+    true
+}
 
-    // Warn about data loss
-    println!();
-    tui::print_warning(&format!(
-        "All data on {} will be DESTROYED!",
-        cfg.install.target_disk
-    ));
-    if !tui::confirm("Are you sure you want to continue?", false) {
-        tui::print_info("Installation cancelled.");
-        process::exit(0);
+/// Interactive setup steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Disk,
+    Profile,
+    Layout,
+    Hostname,
+    Username,
+    Passwords,
+    Timezone,
+    Keyboard,
+    Kernel,
+    Encryption,
+    Filesystem,
+    Swap,
+    InputMethod,
+    Packages,
+}
+
+/// Wizard flow chosen at the start of an interactive setup
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// A profile and the few answers only the user can give; everything
+    /// else keeps its default
+    Guided,
+    /// Every step
+    Advanced,
+}
+
+/// Steps of the guided flow; the encryption password is only asked for
+/// profiles that encrypt the disk
+const GUIDED_STEPS: [Step; 6] = [
+    Step::Disk,
+    Step::Profile,
+    Step::Username,
+    Step::Passwords,
+    Step::Encryption,
+    Step::Timezone,
+];
+
+const ADVANCED_STEPS: [Step; 13] = [
+    Step::Disk,
+    Step::Layout,
+    Step::Hostname,
+    Step::Username,
+    Step::Passwords,
+    Step::Timezone,
+    Step::Keyboard,
+    Step::Kernel,
+    Step::Encryption,
+    Step::Filesystem,
+    Step::Swap,
+    Step::InputMethod,
+    Step::Packages,
+];
+
+/// A guided setup profile
+struct Profile {
+    label: &'static str,
+    /// [disk] layout
+    layout: &'static str,
+    /// LibreOffice and VLC next to Firefox
+    apps: bool,
+    /// VS Code, Rust, Node.js, GitHub CLI
+    development: bool,
+    /// Steam and Lutris
+    gaming: bool,
+}
+
+const PROFILES: [Profile; 5] = [
+    Profile {
+        label: "Desktop - browser, office and media apps (Recommended)",
+        layout: "simple",
+        apps: true,
+        development: false,
+        gaming: false,
+    },
+    Profile {
+        label: "Developer - adds VS Code, Rust, Node.js and GitHub CLI",
+        layout: "simple",
+        apps: true,
+        development: true,
+        gaming: false,
+    },
+    Profile {
+        label: "Gaming - adds Steam and Lutris",
+        layout: "simple",
+        apps: true,
+        development: false,
+        gaming: true,
+    },
+    Profile {
+        label: "Secure laptop - encrypted disk (LUKS + LVM)",
+        layout: "encrypted-lvm",
+        apps: true,
+        development: false,
+        gaming: false,
+    },
+    Profile {
+        label: "Minimal - KDE Plasma and Firefox only",
+        layout: "simple",
+        apps: false,
+        development: false,
+        gaming: false,
+    },
+];
+
+/// Apply `profile` over the defaults; the layout only for whole-disk installs
+fn apply_profile(cfg: &mut Config, profile: &Profile) {
+    if !cfg.disk.alongside.enabled
+        && cfg.disk.keep_home.partition.is_empty()
+        && cfg.disk.partitions.is_empty()
+    {
+        cfg.disk.layout = profile.layout.to_string();
+        cfg.apply_layout();
     }
 
-    // Step 2: Set hostname (skip if loaded from config.toml)
-    if cfg.loaded_from_file && !cfg.install.hostname.is_empty() {
-        tui::print_info(&format!(
-            "Hostname: {} (from config.toml)",
-            cfg.install.hostname
-        ));
-    } else {
-        println!();
-        let default = if cfg.install.hostname.is_empty() {
-            "blunux"
-        } else {
-            &cfg.install.hostname
+    let p = &mut cfg.packages;
+    p.firefox = true;
+    p.libreoffice = profile.apps;
+    p.vlc = profile.apps;
+    p.vscode = profile.development;
+    p.rust = profile.development;
+    p.nodejs = profile.development;
+    p.github_cli = profile.development;
+    p.steam = profile.gaming;
+    p.lutris = profile.gaming;
+}
+
+/// Outcome of one wizard step
+enum Nav {
+    Next,
+    Back,
+}
+
+fn is_cjk_locale(cfg: &Config) -> bool {
+    cfg.locale
+        .languages
+        .iter()
+        .any(|l| l.contains("ko") || l.contains("ja") || l.contains("zh"))
+}
+
+/// Whether a step needs no prompt because its value came from config.toml
+/// (or it only displays information)
+fn step_preconfigured(step: Step, cfg: &Config) -> bool {
+    match step {
+        Step::Disk => {
+            !cfg.image.is_empty() || (cfg.loaded_from_file && !cfg.install.target_disk.is_empty())
+        }
+        Step::Profile => cfg.loaded_from_file,
+        // Dual boot / keep-home / manual partitions already fix the plan
+        Step::Layout => {
+            cfg.loaded_from_file
+                || cfg.disk.alongside.enabled
+                || !cfg.disk.keep_home.partition.is_empty()
+                || !cfg.disk.partitions.is_empty()
+        }
+        Step::Hostname => cfg.loaded_from_file && !cfg.install.hostname.is_empty(),
+        Step::Username => cfg.loaded_from_file && !cfg.install.username.is_empty(),
+        Step::Passwords => {
+            !cfg.install.root_password.is_empty() && !cfg.install.user_password.is_empty()
+        }
+        Step::Timezone => {
+            cfg.loaded_from_file
+                || !(cfg.locale.timezone.is_empty() || cfg.locale.timezone == "UTC")
+        }
+        Step::Keyboard => cfg.loaded_from_file || !cfg.locale.keyboards.is_empty(),
+        Step::Kernel => cfg.loaded_from_file && !cfg.kernel.type_.is_empty(),
+        Step::Encryption => {
+            !(cfg.needs_encryption_password() && cfg.install.encryption_password.is_empty())
+        }
+        // encrypted-lvm and btrfs-snapshots fix the filesystem themselves
+        Step::Filesystem => {
+            !cfg.disk.filesystem.is_experimental()
+                && (cfg.loaded_from_file
+                    || matches!(cfg.disk.layout.as_str(), "encrypted-lvm" | "btrfs-snapshots"))
+        }
+        Step::Swap => cfg.loaded_from_file,
+        Step::InputMethod => {
+            !is_cjk_locale(cfg) || (cfg.loaded_from_file && !cfg.input_method.engine.is_empty())
+        }
+        Step::Packages => cfg.loaded_from_file,
+    }
+}
+
+/// Smallest partition offered for installing alongside another OS
+const MIN_ALONGSIDE_MIB: u64 = 20 * 1024;
+
+/// Forget a dual-boot, keep-home or manual partition plan, which only
+/// makes sense for the disk it was made for
+fn reset_disk_plan(cfg: &mut Config) {
+    cfg.disk.partitions.clear();
+    cfg.disk.alongside = Default::default();
+    cfg.disk.keep_home = Default::default();
+}
+
+/// Offer to install next to a Windows/Linux install found on the target
+/// disk, to reinstall over a Linux install keeping its /home, or to use
+/// existing partitions, instead of erasing the disk. False when the user
+/// backs out, so the disk can be picked again.
+fn select_install_type(cfg: &mut Config) -> bool {
+    let found = disk::detect_existing_os(&cfg.install.target_disk);
+    // (label, os, keep home)
+    let mut choices: Vec<(String, &disk::ExistingOs, bool)> = Vec::new();
+    for os in &found {
+        if let Some(home) = &os.home_partition {
+            choices.push((
+                format!("Reinstall over {} ({}), keep /home on {home}", os.name, os.partition),
+                os,
+                true,
+            ));
+        }
+        if os.free_mib() >= MIN_ALONGSIDE_MIB {
+            choices.push((
+                format!(
+                    "Install alongside {} ({}, up to {} GiB free)",
+                    os.name,
+                    os.partition,
+                    os.free_mib() / 1024
+                ),
+                os,
+                false,
+            ));
+        }
+    }
+    let existing = disk::probe::partitions(&cfg.install.target_disk);
+    if choices.is_empty() && existing.is_empty() {
+        reset_disk_plan(cfg);
+        return true;
+    }
+
+    let mut options = vec!["Erase entire disk / 디스크 전체 삭제"];
+    options.extend(choices.iter().map(|(label, _, _)| label.as_str()));
+    if !existing.is_empty() {
+        options.push("Use existing partitions (manual) / 기존 파티션 사용");
+    }
+    let Some(choice) = tui::menu_select("Installation type / 설치 방식", &options, 0) else {
+        return false;
+    };
+    reset_disk_plan(cfg);
+    if choice == 0 {
+        return true;
+    }
+    if choice > choices.len() {
+        return select_manual_partitions(cfg, &existing);
+    }
+
+    let (_, os, keep_home) = choices[choice - 1];
+    if keep_home {
+        cfg.disk.keep_home.partition = os.home_partition.clone().unwrap_or_default();
+        cfg.disk.keep_home.root = os.partition.clone();
+        return true;
+    }
+
+    let max_gib = os.free_mib() / 1024;
+    let default = format!("{}G", max_gib.min(60));
+    loop {
+        let Some(size) = tui::input_prompt(
+            &format!("Space for Blunux (max {max_gib}G) / Blunux 용량"),
+            &default,
+        ) else {
+            return false;
         };
-        cfg.install.hostname = tui::input_prompt("Hostname / 호스트명", default);
+        match config::parse_size_mib(&size) {
+            Some(mib) if (MIN_ALONGSIDE_MIB..=os.free_mib()).contains(&mib) => {
+                cfg.disk.alongside.enabled = true;
+                cfg.disk.alongside.partition = os.partition.clone();
+                cfg.disk.alongside.size = size;
+                return true;
+            }
+            _ => tui::print_error(&format!("Enter a size between 20G and {max_gib}G")),
+        }
     }
+}
 
-    // Step 3: Set username (skip if loaded from config.toml)
-    if cfg.loaded_from_file && !cfg.install.username.is_empty() {
-        tui::print_info(&format!(
-            "Username: {} (from config.toml)",
-            cfg.install.username
-        ));
-    } else {
-        let default = if cfg.install.username.is_empty() {
-            "user"
-        } else {
-            &cfg.install.username
+/// Manual partitioning: pick the root, ESP, /home and swap partitions among
+/// `parts`. Only root (and swap) are always formatted; the partition table is
+/// left alone. False when the root or ESP choice is cancelled.
+fn select_manual_partitions(cfg: &mut Config, parts: &[disk::probe::BlockDevice]) -> bool {
+    let labels: Vec<String> = parts
+        .iter()
+        .map(|p| {
+            let fs = p.fstype.as_deref().unwrap_or("no filesystem");
+            format!("{} - {} ({fs})", p.path, p.size_human())
+        })
+        .collect();
+    let with_none = |none: &'static str| -> Vec<&str> {
+        std::iter::once(none).chain(labels.iter().map(|s| s.as_str())).collect()
+    };
+    let spec = |mountpoint: &str, filesystem: &str, format: bool, device: &str| {
+        config::PartitionSpec {
+            mountpoint: mountpoint.to_string(),
+            size: "rest".to_string(),
+            filesystem: filesystem.to_string(),
+            encrypt: false,
+            format,
+            device: device.to_string(),
+        }
+    };
+
+    let options: Vec<&str> = labels.iter().map(|s| s.as_str()).collect();
+    let Some(root) = tui::menu_select("Root partition (formatted) / 루트 파티션", &options, 0)
+    else {
+        return false;
+    };
+    let mut specs = vec![spec("/", "", true, &parts[root].path)];
+
+    if disk::is_uefi() {
+        let default = parts.iter().position(|p| p.fstype.as_deref() == Some("vfat")).unwrap_or(0);
+        let title = "EFI system partition / EFI 파티션";
+        let Some(esp) = tui::menu_select(title, &options, default) else {
+            return false;
         };
-        cfg.install.username = tui::input_prompt("Username / 사용자명", default);
+        // Another OS may boot from it
+        let format = tui::confirm("Format the EFI partition? / EFI 파티션 포맷", false);
+        specs.push(spec("/boot/efi", "vfat", format, &parts[esp].path));
     }
 
-    // Step 4: Set passwords
-    let passwords_configured =
-        !cfg.install.root_password.is_empty() && !cfg.install.user_password.is_empty();
-    if !passwords_configured {
-        println!();
-        tui::print_info("Setting passwords / 비밀번호 설정");
+    let home_options = with_none("No separate /home / /home 없음");
+    match tui::menu_select("/home partition / 홈 파티션", &home_options, 0) {
+        Some(0) | None => {}
+        Some(i) => {
+            let part = &parts[i - 1];
+            let format = tui::confirm("Format /home (erases it)? / /home 포맷", false);
+            let fs = if format { "ext4" } else { part.fstype.as_deref().unwrap_or("ext4") };
+            specs.push(spec("/home", fs, format, &part.path));
+        }
+    }
 
-        loop {
-            cfg.install.root_password = tui::password_input("Root password / 루트 비밀번호");
-            let confirm = tui::password_input("Confirm root password / 확인");
-            if cfg.install.root_password == confirm {
-                break;
-            }
-            tui::print_error("Passwords do not match. Try again.");
+    let swap_options = with_none("No swap partition / 스왑 파티션 없음");
+    let default = parts
+        .iter()
+        .position(|p| p.fstype.as_deref() == Some("swap"))
+        .map_or(0, |i| i + 1);
+    match tui::menu_select("Swap partition / 스왑 파티션", &swap_options, default) {
+        Some(0) | None => {}
+        Some(i) => {
+            specs.push(spec("", "swap", true, &parts[i - 1].path));
         }
+    }
+    cfg.disk.partitions = specs;
+    true
+}
 
-        loop {
-            cfg.install.user_password = tui::password_input("User password / 사용자 비밀번호");
-            let confirm = tui::password_input("Confirm user password / 확인");
-            if cfg.install.user_password == confirm {
-                break;
-            }
-            tui::print_error("Passwords do not match. Try again.");
+/// Offer to put the ESP on another disk (e.g. root on a new NVMe drive,
+/// boot files next to an existing ESP drive) for whole-disk UEFI installs:
+/// an existing ESP there is shared, or the disk is erased for a new one
+fn select_esp_disk(cfg: &mut Config, disks: &[tui::DiskInfo]) {
+    if !disk::is_uefi()
+        || cfg.disk.alongside.enabled
+        || !cfg.disk.keep_home.partition.is_empty()
+        || !cfg.disk.partitions.is_empty()
+    {
+        return;
+    }
+    let others: Vec<&tui::DiskInfo> =
+        disks.iter().filter(|d| d.device != cfg.install.target_disk).collect();
+    if others.is_empty() {
+        return;
+    }
+
+    // (esp_disk, reuse_esp) of each choice after "same disk"
+    let mut choices: Vec<(String, String)> = Vec::new();
+    let mut labels: Vec<String> = Vec::new();
+    for d in &others {
+        if let Some(esp) = disk::find_esp(&d.device) {
+            labels.push(format!("{esp} - existing EFI partition on {}, kept / 유지", d.model));
+            choices.push((String::new(), esp));
         }
-    } else {
-        tui::print_info("Passwords: configured (from config.toml)");
+    }
+    for d in &others {
+        labels.push(format!("{} - {} ({}), erased / 삭제됨", d.device, d.model, d.size));
+        choices.push((d.device.clone(), String::new()));
     }
 
-    // Step 5: Timezone selection (skip if loaded from config.toml)
-    if !cfg.loaded_from_file && (cfg.locale.timezone.is_empty() || cfg.locale.timezone == "UTC") {
-        println!();
-        let tz_options = [
-            "Asia/Seoul",
-            "Asia/Tokyo",
-            "Asia/Shanghai",
-            "Europe/Stockholm",
-            "Europe/London",
-            "America/New_York",
-            "America/Los_Angeles",
-            "UTC",
-        ];
-        let tz_idx = tui::menu_select("Select timezone / 시간대 선택", &tz_options, 0);
-        cfg.locale.timezone = tz_options[tz_idx].to_string();
+    let same = format!("Same disk ({}) / 같은 디스크", cfg.install.target_disk);
+    let mut options = vec![same.as_str()];
+    options.extend(labels.iter().map(|s| s.as_str()));
+    let default = choices
+        .iter()
+        .position(|(esp_disk, reuse)| {
+            (!esp_disk.is_empty() && *esp_disk == cfg.disk.esp_disk)
+                || (!reuse.is_empty() && *reuse == cfg.disk.reuse_esp)
+        })
+        .map_or(0, |i| i + 1);
+    match tui::menu_select("EFI partition location / EFI 파티션 위치", &options, default) {
+        Some(0) => {
+            cfg.disk.esp_disk.clear();
+            cfg.disk.reuse_esp.clear();
+        }
+        Some(i) => (cfg.disk.esp_disk, cfg.disk.reuse_esp) = choices[i - 1].clone(),
+        None => {}
+    }
+}
+
+/// What the chosen install type destroys, for the confirmation prompts
+fn data_loss_warning(cfg: &Config) -> String {
+    let disk = &cfg.install.target_disk;
+    if cfg.disk.alongside.enabled {
+        format!("An existing partition on {disk} will be SHRUNK - back up important data first!")
+    } else if !cfg.disk.keep_home.partition.is_empty() {
+        format!(
+            "The old root partition and ESP on {disk} will be ERASED (/home on {} is kept)",
+            cfg.disk.keep_home.partition
+        )
+    } else if cfg.disk.keeps_partition_table() {
+        format!("Partitions on {disk} with format = true will be ERASED (the others are kept)")
+    } else if !cfg.disk.esp_disk.is_empty() {
+        format!("All data on {disk} and {} will be DESTROYED!", cfg.disk.esp_disk)
     } else {
-        tui::print_info(&format!(
+        format!("All data on {disk} will be DESTROYED!")
+    }
+}
+
+/// Report a step whose value was not prompted for
+fn show_preconfigured(step: Step, cfg: &Config) {
+    match step {
+        Step::Disk if !cfg.image.is_empty() => tui::print_info(&format!(
+            "Target: disk image {} ({})",
+            cfg.image, cfg.install.target_disk
+        )),
+        Step::Disk => tui::print_info(&format!(
+            "Target disk: {} (from config.toml)",
+            cfg.install.target_disk
+        )),
+        Step::Profile => {}
+        Step::Layout => {
+            if cfg.loaded_from_file && !cfg.disk.layout.is_empty() {
+                tui::print_info(&format!("Disk layout: {} (from config.toml)", cfg.disk.layout));
+            }
+        }
+        Step::Hostname => tui::print_info(&format!(
+            "Hostname: {} (from config.toml)",
+            cfg.install.hostname
+        )),
+        Step::Username => tui::print_info(&format!(
+            "Username: {} (from config.toml)",
+            cfg.install.username
+        )),
+        Step::Passwords => tui::print_info("Passwords: configured (from config.toml)"),
+        Step::Timezone => tui::print_info(&format!(
             "Timezone: {} (from config.toml)",
             cfg.locale.timezone
-        ));
+        )),
+        Step::Keyboard => tui::print_info(&format!(
+            "Keyboard: {} (from config.toml)",
+            cfg.locale.keyboards.first().map_or("us".to_string(), |k| k.to_string())
+        )),
+        Step::Kernel => tui::print_info(&format!(
+            "Kernel: {} (from config.toml)",
+            cfg.kernel.type_
+        )),
+        Step::Encryption => tui::print_info(&format!(
+            "Encryption: {} (from config.toml)",
+            if cfg.needs_encryption_password() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        )),
+        Step::Filesystem => tui::print_info(&format!(
+            "Filesystem: {} (from config.toml [disk] section)",
+            cfg.disk.filesystem.label()
+        )),
+        Step::Swap => tui::print_info(&format!(
+            "Swap: {} (from config.toml [disk] section)",
+            cfg.disk.swap.label()
+        )),
+        Step::InputMethod => {
+            if cfg.loaded_from_file && !cfg.input_method.engine.is_empty() {
+                tui::print_info(&format!(
+                    "Input method: {} (from config.toml)",
+                    cfg.input_method.engine
+                ));
+            }
+        }
+        Step::Packages => tui::print_info("Packages: from config.toml"),
     }
+}
 
-    // Step 6: Keyboard layout (skip if loaded from config.toml)
-    if !cfg.loaded_from_file && cfg.locale.keyboards.is_empty() {
-        println!();
-        let kb_options = [
-            "us - US English",
-            "kr - Korean",
-            "jp - Japanese",
-            "gb - UK English",
-            "de - German",
-            "fr - French",
-            "se - Swedish",
-        ];
-        let kb_idx = tui::menu_select("Select keyboard layout / 키보드 레이아웃", &kb_options, 0);
-        let kb_code = &kb_options[kb_idx][..2];
-        cfg.locale.keyboards = vec![kb_code.to_string()];
-    } else {
+/// Ask for a password twice until both entries match; None = back
+fn password_twice(prompt: &str, confirm_prompt: &str) -> Option<String> {
+    loop {
+        let password = tui::password_input(prompt)?;
+        let confirm = tui::password_input(confirm_prompt)?;
+        if password == confirm {
+            return Some(password);
+        }
+        tui::print_error("Passwords do not match. Try again.");
+    }
+}
+
+/// Tell which keyboard the disk passphrase will be typed on at boot
+fn passphrase_keymap_notice(cfg: &Config) {
+    let keyboard = cfg.locale.keyboards.first();
+    let keymap = keyboard.map_or("us".to_string(), |kb| kb.console_keymap());
+    if keymap != "us" {
         tui::print_info(&format!(
-            "Keyboard: {} (from config.toml)",
-            cfg.locale.keyboards[0]
+            "At boot the passphrase is typed with the {keymap} console keymap \
+             (added to the initramfs)."
+        ));
+    } else if keyboard.is_some_and(|kb| kb.layout != "us") {
+        tui::print_warning(&format!(
+            "There is no console keymap for {}: at boot the passphrase is typed \
+             on a US keyboard layout.",
+            keyboard.map_or(String::new(), |kb| kb.to_string())
         ));
     }
+    if cfg.disk.encryption.encrypt_boot {
+        tui::print_warning(
+            "GRUB asks for the passphrase first and always uses the US keyboard layout.",
+        );
+    }
+}
 
-    // Step 7: Kernel selection (skip if loaded from config.toml)
-    let kernel_is_configured = cfg.loaded_from_file && !cfg.kernel.type_.is_empty();
-    if !kernel_is_configured {
-        println!();
-        let kernel_options = [
-            "linux - Standard kernel",
-            "linux-lts - Long-term support kernel",
-            "linux-zen - Performance-optimized kernel",
-        ];
-        let kernel_idx = tui::menu_select("Select kernel / 커널 선택", &kernel_options, 0);
-        cfg.kernel.type_ = match kernel_idx {
-            0 => "linux".to_string(),
-            1 => "linux-lts".to_string(),
-            _ => "linux-zen".to_string(),
-        };
+/// Why `passphrase` is a poor disk passphrase, if it is one
+fn passphrase_weakness(passphrase: &str) -> Option<&'static str> {
+    let classes = [
+        passphrase.chars().any(|c| c.is_ascii_lowercase()),
+        passphrase.chars().any(|c| c.is_ascii_uppercase()),
+        passphrase.chars().any(|c| c.is_ascii_digit()),
+        passphrase.chars().any(|c| c.is_ascii_punctuation() || c == ' '),
+    ];
+    if !passphrase.is_ascii() {
+        // Console keymaps cannot type Hangul and most other non-ASCII text
+        Some("contains characters that may not be typeable at the boot prompt")
+    } else if passphrase.len() < 8 {
+        Some("is shorter than 8 characters")
+    } else if passphrase.len() < 16 && classes.iter().filter(|c| **c).count() < 3 {
+        Some("is short and uses few kinds of characters")
     } else {
-        tui::print_info(&format!(
-            "Kernel: {} (from config.toml)",
-            cfg.kernel.type_
-        ));
+        None
     }
+}
 
-    // Step 8: Encryption option
-    tui::print_info(&format!(
-        "Encryption: {} (from config.toml)",
-        if cfg.install.use_encryption {
-            "enabled"
+/// eMMC and SD cards get f2fs and little or no swap unless config.toml
+/// chose otherwise; the Filesystem and Swap steps start from these
+fn suggest_flash_defaults(cfg: &mut Config) {
+    if cfg.loaded_from_file || !disk::is_flash_card(&cfg.install.target_disk) {
+        return;
+    }
+    if cfg.disk.filesystem == Filesystem::Ext4 {
+        cfg.disk.filesystem = Filesystem::F2fs;
+    }
+    if matches!(cfg.disk.swap, SwapMode::Suspend | SwapMode::Partition) {
+        // f2fs has no swap files
+        cfg.disk.swap = if cfg.disk.filesystem.supports_swapfile() {
+            SwapMode::Small
         } else {
-            "disabled"
-        }
+            SwapMode::None
+        };
+    }
+    tui::print_info(&format!(
+        "eMMC/SD card detected: {} root, swap {} suggested / eMMC/SD 카드 감지",
+        cfg.disk.filesystem.fs_type(),
+        cfg.disk.swap.label()
     ));
-    if cfg.install.use_encryption && cfg.install.encryption_password.is_empty() {
-        loop {
-            cfg.install.encryption_password =
-                tui::password_input("Encryption password / 암호화 비밀번호");
-            let confirm = tui::password_input("Confirm encryption password / 확인");
-            if cfg.install.encryption_password == confirm {
+}
+
+fn run_step(step: Step, mode: Mode, cfg: &mut Config) -> Nav {
+    match step {
+        Step::Disk => {
+            let disks = disk::get_disks();
+            // Backing out of the installation type picks the disk again
+            loop {
+                match tui::select_disk(&disks) {
+                    Some(d) if d.device != cfg.install.target_disk => {
+                        cfg.install.target_disk = d.device;
+                        reset_disk_plan(cfg);
+                        if cfg.disk.esp_disk == cfg.install.target_disk {
+                            cfg.disk.esp_disk.clear();
+                            cfg.disk.reuse_esp.clear();
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        tui::print_error("No disk selected. Exiting.");
+                        process::exit(1);
+                    }
+                }
+                if select_install_type(cfg) {
+                    break;
+                }
+            }
+            if mode == Mode::Advanced {
+                select_esp_disk(cfg, &disks);
+            }
+            suggest_flash_defaults(cfg);
+
+            // Warn about data loss
+            println!();
+            tui::print_warning(&data_loss_warning(cfg));
+            if !tui::confirm("Are you sure you want to continue?", false) {
+                tui::print_info("Installation cancelled.");
+                process::exit(0);
+            }
+
+            let whole_disk = !cfg.disk.alongside.enabled
+                && cfg.disk.keep_home.partition.is_empty()
+                && !cfg.disk.keeps_partition_table();
+            if whole_disk
+                && mode == Mode::Advanced
+                && cfg.disk.secure_erase == "none"
+                && tui::confirm(
+                    "Securely erase the old data first (slow on hard disks)? / 기존 데이터 보안 삭제",
+                    false,
+                )
+            {
+                cfg.disk.secure_erase = "auto".to_string();
+            }
+        }
+        Step::Profile => {
+            println!();
+            let options: Vec<&str> = PROFILES.iter().map(|p| p.label).collect();
+            let default = PROFILES
+                .iter()
+                .position(|p| p.layout == cfg.disk.layout && p.gaming == cfg.packages.steam)
+                .unwrap_or(0);
+            match tui::menu_select("What is this computer for? / 용도 선택", &options, default) {
+                Some(idx) => apply_profile(cfg, &PROFILES[idx]),
+                None => return Nav::Back,
+            }
+        }
+        Step::Layout => {
+            // dual-boot-alongside is offered by the Disk step when another
+            // OS is found
+            let layouts = [
+                ("simple", "simple - ESP + ext4 root (default)"),
+                ("encrypted-lvm", "encrypted-lvm - LUKS + LVM root/home/swap"),
+                ("btrfs-snapshots", "btrfs-snapshots - btrfs subvolumes + snapper"),
+            ];
+            let options: Vec<&str> = layouts.iter().map(|(_, label)| *label).collect();
+            let default = layouts
+                .iter()
+                .position(|(name, _)| *name == cfg.disk.layout)
+                .unwrap_or(0);
+            match tui::menu_select("Disk layout / 디스크 구성", &options, default) {
+                Some(i) => {
+                    cfg.disk.layout = layouts[i].0.to_string();
+                    cfg.apply_layout();
+                }
+                None => return Nav::Back,
+            }
+        }
+        Step::Hostname => {
+            println!();
+            let default = if cfg.install.hostname.is_empty() {
+                "blunux"
+            } else {
+                &cfg.install.hostname
+            };
+            match tui::input_prompt("Hostname / 호스트명", default) {
+                Some(v) => cfg.install.hostname = v,
+                None => return Nav::Back,
+            }
+        }
+        Step::Username => {
+            let default = if cfg.install.username.is_empty() {
+                "user"
+            } else {
+                &cfg.install.username
+            };
+            match tui::input_prompt("Username / 사용자명", default) {
+                Some(v) => cfg.install.username = v,
+                None => return Nav::Back,
+            }
+        }
+        Step::Passwords => {
+            println!();
+            tui::print_info("Setting passwords / 비밀번호 설정");
+            let Some(root) =
+                password_twice("Root password / 루트 비밀번호", "Confirm root password / 확인")
+            else {
+                return Nav::Back;
+            };
+            let Some(user) =
+                password_twice("User password / 사용자 비밀번호", "Confirm user password / 확인")
+            else {
+                return Nav::Back;
+            };
+            cfg.install.root_password = root;
+            cfg.install.user_password = user;
+        }
+        Step::Timezone => {
+            println!();
+            // Common zones first, then every zone known to the live system
+            let mut tz_options: Vec<String> = [
+                "Asia/Seoul",
+                "Asia/Tokyo",
+                "Asia/Shanghai",
+                "Europe/Stockholm",
+                "Europe/London",
+                "America/New_York",
+                "America/Los_Angeles",
+                "UTC",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+            if let Ok(out) = process::Command::new("timedatectl")
+                .arg("list-timezones")
+                .output()
+            {
+                for tz in String::from_utf8_lossy(&out.stdout).lines() {
+                    if !tz.is_empty() && !tz_options.iter().any(|t| t == tz) {
+                        tz_options.push(tz.to_string());
+                    }
+                }
+            }
+            let tz_refs: Vec<&str> = tz_options.iter().map(|s| s.as_str()).collect();
+            // Preselect the earlier choice when revisiting; UTC is the unset default
+            let default = tz_options
+                .iter()
+                .position(|t| *t == cfg.locale.timezone && t != "UTC")
+                .unwrap_or(0);
+            match tui::menu_select("Select timezone / 시간대 선택", &tz_refs, default) {
+                Some(idx) => cfg.locale.timezone = tz_options[idx].clone(),
+                None => return Nav::Back,
+            }
+        }
+        Step::Keyboard => {
+            println!();
+            let kb_options = [
+                "us - US English",
+                "us-intl - US International (dead keys)",
+                "kr - Korean",
+                "kr-kr104 - Korean (101/104 key compatible)",
+                "jp - Japanese",
+                "gb - UK English",
+                "de - German",
+                "de-neo - German (Neo 2)",
+                "fr - French",
+                "se - Swedish",
+            ];
+            let code = |option: &str| option.split(" - ").next().unwrap_or("us").to_string();
+            let default = cfg
+                .locale
+                .keyboards
+                .first()
+                .and_then(|k| kb_options.iter().position(|o| code(o) == k.to_string()))
+                .unwrap_or(0);
+            let kb_title = "Select keyboard layout / 키보드 레이아웃";
+            match tui::menu_select(kb_title, &kb_options, default) {
+                Some(idx) => cfg.locale.keyboards = vec![Keyboard::parse(&code(kb_options[idx]))],
+                None => return Nav::Back,
+            }
+        }
+        Step::Kernel => {
+            println!();
+            let kernel_options = [
+                "linux - Standard kernel",
+                "linux-lts - Long-term support kernel",
+                "linux-zen - Performance-optimized kernel",
+            ];
+            let default = match cfg.kernel.type_.as_str() {
+                "linux-lts" => 1,
+                "linux-zen" => 2,
+                _ => 0,
+            };
+            cfg.kernel.type_ =
+                match tui::menu_select("Select kernel / 커널 선택", &kernel_options, default) {
+                    Some(0) => "linux".to_string(),
+                    Some(1) => "linux-lts".to_string(),
+                    Some(_) => "linux-zen".to_string(),
+                    None => return Nav::Back,
+                };
+        }
+        Step::Encryption => {
+            show_preconfigured(Step::Encryption, cfg);
+            passphrase_keymap_notice(cfg);
+            loop {
+                let Some(v) = password_twice(
+                    "Encryption password / 암호화 비밀번호",
+                    "Confirm encryption password / 확인",
+                ) else {
+                    return Nav::Back;
+                };
+                if let Some(problem) = passphrase_weakness(&v) {
+                    tui::print_warning(&format!("This passphrase {problem}."));
+                    if !tui::confirm("Use it anyway?", false) {
+                        continue;
+                    }
+                }
+                cfg.install.encryption_password = v;
                 break;
             }
-            tui::print_error("Passwords do not match. Try again.");
+        }
+        Step::Filesystem => {
+            if cfg.disk.filesystem.is_experimental() {
+                // Experimental choices need explicit confirmation
+                show_preconfigured(Step::Filesystem, cfg);
+                tui::print_warning(&format!(
+                    "{} is EXPERIMENTAL and may lose data or fail to boot.",
+                    cfg.disk.filesystem.fs_type()
+                ));
+                if !tui::confirm("Use this experimental filesystem anyway?", false) {
+                    tui::print_info("Falling back to ext4");
+                    cfg.disk.filesystem = Filesystem::Ext4;
+                }
+            } else {
+                println!();
+                let choices = [
+                    (Filesystem::Ext4, "ext4 - Standard Linux filesystem (Recommended)"),
+                    (Filesystem::Xfs, "xfs - High performance for large files"),
+                    (Filesystem::F2fs, "f2fs - Flash storage (eMMC / SD card)"),
+                    (Filesystem::Btrfs, "btrfs - Snapshots and compression"),
+                ];
+                let options: Vec<&str> = choices.iter().map(|(_, label)| *label).collect();
+                let default = choices
+                    .iter()
+                    .position(|(fs, _)| *fs == cfg.disk.filesystem)
+                    .unwrap_or(0);
+                match tui::menu_select("Root filesystem / 루트 파일 시스템", &options, default) {
+                    Some(idx) => cfg.disk.filesystem = choices[idx].0,
+                    None => return Nav::Back,
+                }
+                if cfg.disk.filesystem == Filesystem::Btrfs {
+                    let tools = ["snapper", "timeshift"];
+                    let options = [
+                        "snapper - Hourly + pacman snapshots, bootable from GRUB (Recommended)",
+                        "timeshift - Hourly + pacman snapshots, restore from a GUI",
+                        "none - No automatic snapshots",
+                    ];
+                    let default = if !cfg.disk.snapshots {
+                        2
+                    } else {
+                        tools.iter().position(|t| *t == cfg.disk.snapshot_tool).unwrap_or(0)
+                    };
+                    match tui::menu_select("Automatic snapshots / 자동 스냅샷", &options, default) {
+                        Some(2) => cfg.disk.snapshots = false,
+                        Some(idx) => {
+                            cfg.disk.snapshots = true;
+                            cfg.disk.snapshot_tool = tools[idx].to_string();
+                        }
+                        None => return Nav::Back,
+                    }
+                } else {
+                    cfg.disk.snapshots = false;
+                }
+            }
+        }
+        Step::Swap => {
+            println!();
+            let modes = [
+                SwapMode::Suspend,
+                SwapMode::Small,
+                SwapMode::File,
+                SwapMode::Partition,
+                SwapMode::None,
+            ];
+            let options = [
+                "suspend - Swap file of RAM size, allows hibernation (Recommended)",
+                "small - Swap file of half the RAM",
+                "file - Swap file of RAM size, at most 8 GB",
+                "partition - Swap partition of RAM size at the end of the disk",
+                "none - No swap",
+            ];
+            let default = modes.iter().position(|m| *m == cfg.disk.swap).unwrap_or(0);
+            match tui::menu_select("Swap / 스왑", &options, default) {
+                Some(idx) => cfg.disk.swap = modes[idx].clone(),
+                None => return Nav::Back,
+            }
+        }
+        Step::InputMethod => {
+            println!();
+            let im_options = [
+                "kime - Korean Input Method (Recommended for Korean)",
+                "fcitx5 - Flexible Input Method (CJK)",
+                "ibus - Intelligent Input Bus",
+                "none - No input method",
+            ];
+            let default = if !cfg.input_method.enabled {
+                3
+            } else {
+                match cfg.input_method.engine.as_str() {
+                    "fcitx5" => 1,
+                    "ibus" => 2,
+                    _ => 0,
+                }
+            };
+            match tui::menu_select("Select input method / 입력기 선택", &im_options, default) {
+                Some(3) => cfg.input_method.enabled = false,
+                Some(idx) => {
+                    cfg.input_method.enabled = true;
+                    cfg.input_method.engine = match idx {
+                        0 => "kime".to_string(),
+                        1 => "fcitx5".to_string(),
+                        _ => "ibus".to_string(),
+                    };
+                }
+                None => return Nav::Back,
+            }
+        }
+        Step::Packages => {
+            println!();
+            tui::print_info("Select optional packages / 추가 패키지 선택");
+            if !select_package_groups(&mut cfg.packages) {
+                return Nav::Back;
+            }
         }
     }
+    Nav::Next
+}
 
-    // Step 9: Swap configuration display
+/// Run the wizard; without `with_disk` the disk step is left out and
+/// nothing on this machine is probed (generate-config)
+fn interactive_setup(cfg: &mut Config, with_disk: bool) {
+    tui::clear_screen();
+    tui::print_banner();
+
+    println!();
+    tui::print_info("Starting interactive setup / 대화형 설정 시작");
     tui::print_info(&format!(
-        "Swap: {} (from config.toml [disk] section)",
-        cfg.disk.swap.label()
+        "Enter '{}' at a prompt (or press ← in menus) to go back / 이전 단계로",
+        tui::BACK
     ));
+    println!();
 
-    // Step 10: Input method (skip if loaded from config.toml)
-    let has_lang = |prefix: &str| -> bool {
-        cfg.locale.languages.iter().any(|l| l.contains(prefix))
+    // A config file already answered the questions; show them all
+    let mode = if cfg.loaded_from_file {
+        Mode::Advanced
+    } else {
+        let options = [
+            "Guided - a few questions, recommended settings / 간단 설치",
+            "Advanced - every option / 고급 설치",
+        ];
+        match tui::menu_select("Setup mode / 설치 모드", &options, 0) {
+            Some(1) => Mode::Advanced,
+            _ => Mode::Guided,
+        }
+    };
+    let steps: &[Step] = match mode {
+        Mode::Guided => &GUIDED_STEPS,
+        Mode::Advanced => &ADVANCED_STEPS,
     };
-    let is_cjk_locale = has_lang("ko") || has_lang("ja") || has_lang("zh");
-    let im_already_configured = cfg.loaded_from_file && !cfg.input_method.engine.is_empty();
+    let steps: Vec<Step> =
+        steps.iter().copied().filter(|s| with_disk || *s != Step::Disk).collect();
 
-    if is_cjk_locale && !im_already_configured {
-        println!();
-        let im_options = [
-            "kime - Korean Input Method (Recommended for Korean)",
-            "fcitx5 - Flexible Input Method (CJK)",
-            "ibus - Intelligent Input Bus",
-            "none - No input method",
-        ];
-        let im_idx = tui::menu_select("Select input method / 입력기 선택", &im_options, 0);
-        if im_idx == 3 {
-            cfg.input_method.enabled = false;
+    // Decided once, so going back revisits the same prompts with their current values
+    let preconfigured: Vec<bool> = steps.iter().map(|s| step_preconfigured(*s, cfg)).collect();
+
+    let mut i = 0;
+    let mut going_back = false;
+    while i < steps.len() {
+        // The disk step may just have fixed the layout, and the profile or
+        // layout step turned encryption on
+        let skip = if matches!(steps[i], Step::Layout | Step::Encryption) {
+            step_preconfigured(steps[i], cfg)
         } else {
-            cfg.input_method.enabled = true;
-            cfg.input_method.engine = match im_idx {
-                0 => "kime".to_string(),
-                1 => "fcitx5".to_string(),
-                _ => "ibus".to_string(),
-            };
+            preconfigured[i]
+        };
+        if skip {
+            if going_back && i > 0 {
+                i -= 1;
+            } else {
+                going_back = false;
+                if mode == Mode::Advanced {
+                    show_preconfigured(steps[i], cfg);
+                }
+                i += 1;
+            }
+            continue;
+        }
+
+        let nav = run_step(steps[i], mode, cfg);
+        if tui::input_closed() {
+            tui::print_error("Input closed. Exiting.");
+            process::exit(1);
+        }
+        match nav {
+            Nav::Next => {
+                going_back = false;
+                i += 1;
+            }
+            Nav::Back => {
+                // The disk step has no "back": it is always first
+                if i > 0 && steps[i - 1] != Step::Disk {
+                    going_back = true;
+                    i -= 1;
+                } else {
+                    tui::print_warning("Already at the first step");
+                }
+            }
         }
-    } else if im_already_configured {
-        tui::print_info(&format!(
-            "Input method: {} (from config.toml)",
-            cfg.input_method.engine
-        ));
     }
 }
 
+/// `generate-config out.toml`: the wizard without root, network or disks,
+/// saved for unattended installs on other machines
+fn generate_config(path: &str) {
+    if path.is_empty() {
+        tui::print_error("generate-config needs an output file, e.g. config.toml");
+        process::exit(1);
+    }
+    if Path::new(path).exists()
+        && !tui::confirm(&format!("{path} exists. Overwrite it? / 덮어쓰기"), false)
+    {
+        return;
+    }
+
+    let mut config = Config::default();
+    interactive_setup(&mut config, false);
+    if let Err(e) = config.validate() {
+        tui::print_error(&format!("Invalid configuration: {e}"));
+        process::exit(1);
+    }
+
+    // The file holds the root and user passwords
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(config.to_toml().as_bytes()));
+    if let Err(e) = written {
+        tui::print_error(&format!("Could not write {path}: {e}"));
+        process::exit(1);
+    }
+    println!();
+    tui::print_success(&format!("Configuration written to {path}"));
+    tui::print_info(&format!(
+        "Install with: blunux-installer {path} (the target disk is chosen on that machine)"
+    ));
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|a| a == "generate-config") {
+        generate_config(args.get(2).map_or("", String::as_str));
+        return;
+    }
     let mut config_path = String::new();
+    let mut force = false;
+    let mut skip_disk_checks = false;
+    let mut restore_disk: Option<String> = None;
+    let mut list: Option<&str> = None;
+    let mut image = String::new();
+    let mut image_size = String::new();
 
-    for arg in args.iter().skip(1) {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
         match arg.as_str() {
             "--help" | "-h" => {
                 print_usage(&args[0]);
@@ -300,6 +1223,14 @@ fn main() {
                 println!("Blunux Installer v1.0.0 (Rust)");
                 return;
             }
+            "--force" => force = true,
+            "--skip-disk-checks" => skip_disk_checks = true,
+            "--restore-partition-table" => {
+                restore_disk = Some(iter.next().cloned().unwrap_or_default());
+            }
+            "--list-disks" | "--list-hardware" => list = Some(arg.as_str()),
+            "--image" => image = iter.next().cloned().unwrap_or_default(),
+            "--size" => image_size = iter.next().cloned().unwrap_or_default(),
             _ => {
                 if !arg.starts_with('-') {
                     config_path = arg.clone();
@@ -313,6 +1244,47 @@ fn main() {
         process::exit(1);
     }
 
+    // Inspection for provisioning scripts: JSON on stdout, then exit
+    if let Some(what) = list {
+        let json = if what == "--list-disks" {
+            serde_json::to_string_pretty(&disk::disk_reports())
+        } else {
+            let mut survey = hardware::survey();
+            survey.drivers = hardware::driver_packages();
+            serde_json::to_string_pretty(&survey)
+        };
+        match json {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let image_mib = if image_size.is_empty() {
+        0
+    } else {
+        match config::parse_size_mib(&image_size) {
+            Some(mib) if mib > 0 => mib,
+            _ => {
+                tui::print_error(&format!("--size {image_size}: expected e.g. 20G or 20480M"));
+                process::exit(1);
+            }
+        }
+    };
+
+    // Rescue mode: undo a previous partitioning, then exit
+    if let Some(disk) = restore_disk {
+        if disk.is_empty() {
+            tui::print_error("--restore-partition-table needs a disk, e.g. /dev/sda");
+            process::exit(1);
+        }
+        let restored = disk::restore_partition_table(&disk);
+        process::exit(if restored { 0 } else { 1 });
+    }
+
     tui::clear_screen();
     tui::print_banner();
 
@@ -351,30 +1323,93 @@ fn main() {
         tui::print_info("No configuration file found. Using interactive mode.");
     }
 
+    config.force = force;
+    config.skip_disk_checks = skip_disk_checks;
+
+    // A disk image replaces whatever target config.toml names
+    if !image.is_empty() {
+        match disk::attach_image(&image, image_mib, force) {
+            Ok(device) => {
+                tui::print_info(&format!("Disk image {image} attached as {device}"));
+                config.install.target_disk = device;
+                config.image = image.clone();
+                // Each machine the image is written to needs its own identity
+                config.install.generalize = true;
+            }
+            Err(e) => {
+                tui::print_error(&e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // A serial, WWN, model or "largest" picks the disk node on this machine
+    let target_spec = config.install.target_disk.clone();
+    if !target_spec.is_empty() && config.image.is_empty() {
+        match disk::resolve_target_disk(&target_spec) {
+            Ok(node) => {
+                if node != target_spec {
+                    tui::print_info(&format!("Target disk {target_spec}: {node}"));
+                }
+                config.install.target_disk = node;
+            }
+            Err(e) => {
+                tui::print_error(&e);
+                tui::print_info("Falling back to disk selection...");
+                config.install.target_disk.clear();
+            }
+        }
+    }
+
     // Interactive setup
-    interactive_setup(&mut config);
+    interactive_setup(&mut config, true);
+
+    if let Err(e) = config.validate() {
+        tui::print_error(&format!("Invalid configuration: {e}"));
+        detach_image(&config);
+        process::exit(1);
+    }
+
+    // The prompts below take their default after [install] prompt_timeout.
+    // A config.toml install may never have shown the disk step, so the
+    // destructive ones only default to "yes" with [install] unattended.
+    let walk_away = config.install.unattended;
+    tui::set_prompt_timeout(config.install.prompt_timeout);
 
     // Show installation summary
     println!();
-    tui::show_summary(
-        &config.install.target_disk,
-        &config.install.hostname,
-        &config.install.username,
-        &config.locale.timezone,
-        config.locale.keyboards.first().map(|s| s.as_str()).unwrap_or("us"),
-        &config.kernel.type_,
-        config.install.use_encryption,
-        config.disk.swap.label(),
-    );
+    tui::show_summary(&config);
+
+    // Validate the whole plan before anything touches the disk
+    println!();
+    let target_disk = config.install.target_disk.clone();
+    let warning = data_loss_warning(&config);
+    let plan = disk::plan_preview(&config);
+    let current = disk::current_partitions(&config);
+    let mut inst = installer::Installer::new(config);
+    if !inst.preflight() {
+        tui::print_error(&format!("Installation aborted: {}", inst.get_error()));
+        tui::print_info(&format!("{target_disk} has not been modified."));
+        detach_image(inst.config());
+        process::exit(1);
+    }
+
+    // The partition plan is confirmed on its own, before the data-loss prompt
+    if !plan.is_empty() {
+        tui::draw_partition_plan(&target_disk, &plan, &current);
+        if !tui::confirm("Is this partition plan correct? / 파티션 구성이 맞습니까?", walk_away) {
+            tui::print_info("Installation cancelled.");
+            detach_image(inst.config());
+            return;
+        }
+    }
 
     // Final confirmation
     println!();
-    tui::print_warning(&format!(
-        "This will ERASE ALL DATA on {}",
-        config.install.target_disk
-    ));
-    if !tui::confirm("Start installation? / 설치를 시작하시겠습니까?", false) {
+    tui::print_warning(&warning);
+    if !tui::confirm("Start installation? / 설치를 시작하시겠습니까?", walk_away) {
         tui::print_info("Installation cancelled.");
+        detach_image(inst.config());
         return;
     }
 
@@ -382,11 +1417,49 @@ fn main() {
     println!();
     tui::print_info("Starting installation... / 설치 시작...\n");
 
-    let mut inst = installer::Installer::new(config);
-    let success = inst.install();
+    let started = Instant::now();
+    abort::install_handlers();
+    // Steps that only warn on failure may run to the end after an abort
+    let success = inst.install() && !abort::requested();
+    abort::restore_handlers();
+    if abort::requested() {
+        println!();
+        tui::print_warning("Installation aborted / 설치 중단됨");
+        inst.unwind();
+    }
+    let outcome = telemetry::Outcome {
+        success,
+        seconds: started.elapsed().as_secs(),
+        failed_step: (!success).then(|| inst.current_step().to_string()),
+    };
+    let report = telemetry::payload(inst.config(), &inst.hardware(), &outcome);
+
+    let image = inst.config().image.clone();
+    if !image.is_empty() {
+        // A failed install leaves the target mounted
+        if !success && !abort::requested() {
+            inst.unwind();
+        }
+        detach_image(inst.config());
+    }
 
     println!();
-    if success {
+    if success && !image.is_empty() {
+        tui::draw_box(
+            "Image Complete! / 이미지 생성 완료!",
+            &[
+                "",
+                &format!("  Disk image written to {image}"),
+                &format!("  디스크 이미지 생성됨: {image}"),
+                "",
+                "  Write it to a disk, e.g. dd if=<image> of=/dev/sdX bs=4M",
+                "  Each copy gets its own machine-id and SSH keys on first boot.",
+                "",
+            ],
+        );
+        telemetry::offer(inst.config(), &report);
+        return;
+    } else if success {
         tui::draw_box(
             "Installation Complete! / 설치 완료!",
             &[
@@ -404,9 +1477,12 @@ fn main() {
     } else {
         tui::print_error(&format!("Installation failed: {}", inst.get_error()));
         tui::print_info("Please check the error message and try again.");
+        telemetry::offer(inst.config(), &report);
         process::exit(1);
     }
 
+    telemetry::offer(inst.config(), &report);
+
     // Ask to reboot
     if tui::confirm("Reboot now? / 지금 재부팅하시겠습니까?", true) {
         let _ = process::Command::new("reboot").status();
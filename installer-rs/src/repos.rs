@@ -0,0 +1,81 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::Command;
+
+/// A third-party pacman repository whose signing key is pinned here, so
+/// trust never depends on whatever key a keyserver hands out first
+pub struct Repo {
+    pub name: &'static str,
+    pub server: &'static str,
+    /// Full fingerprint of the signing key (40 hex digits, no spaces)
+    pub fingerprint: &'static str,
+    pub sig_level: &'static str,
+}
+
+/// Root-on-ZFS kernel modules and tools
+pub const ARCHZFS: Repo = Repo {
+    name: "archzfs",
+    server: "https://archzfs.com/$repo/$arch",
+    fingerprint: "DDF7DB817396A49B2A2723F7403BD972F75D9D76",
+    sig_level: "Required",
+};
+
+/// Repositories [packages] repos may name. A Blunux repository goes here
+/// once it exists, with the fingerprint of its signing key.
+pub const KNOWN: &[&Repo] = &[&ARCHZFS];
+
+pub fn find(name: &str) -> Option<&'static Repo> {
+    KNOWN.iter().copied().find(|r| r.name == name)
+}
+
+fn run(cmd: &str) -> bool {
+    Command::new("sh")
+        .args(["-c", cmd])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Import and locally sign `repo`'s key in the keyring under `root` ("" for
+/// the live system), then add the repository to `root`/etc/pacman.conf.
+/// Fails if the key served under that fingerprint is not the pinned one.
+pub fn add(root: &str, repo: &Repo) -> Result<(), String> {
+    let gpgdir = format!("{root}/etc/pacman.d/gnupg");
+    let fpr = repo.fingerprint;
+    if !run(&format!("pacman-key --gpgdir {gpgdir} --recv-keys {fpr}")) {
+        return Err(format!("Could not fetch the {} signing key {fpr}", repo.name));
+    }
+    let listed = Command::new("gpg")
+        .args(["--homedir", &gpgdir, "--with-colons", "--fingerprint", fpr])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        .unwrap_or_default();
+    let pinned = listed
+        .lines()
+        .filter(|l| l.starts_with("fpr:"))
+        .any(|l| l.split(':').nth(9) == Some(fpr));
+    if !pinned {
+        return Err(format!(
+            "The key received for {} does not match the pinned fingerprint {fpr}",
+            repo.name
+        ));
+    }
+    if !run(&format!("pacman-key --gpgdir {gpgdir} --lsign-key {fpr}")) {
+        return Err(format!("Could not sign the {} key {fpr}", repo.name));
+    }
+
+    let conf_path = format!("{root}/etc/pacman.conf");
+    let conf = fs::read_to_string(&conf_path).unwrap_or_default();
+    if conf.lines().any(|l| l.trim() == format!("[{}]", repo.name)) {
+        return Ok(());
+    }
+    let section = format!(
+        "\n[{}]\nSigLevel = {}\nServer = {}\n",
+        repo.name, repo.sig_level, repo.server
+    );
+    OpenOptions::new()
+        .append(true)
+        .open(&conf_path)
+        .and_then(|mut f| f.write_all(section.as_bytes()))
+        .map_err(|e| format!("Could not add [{}] to {conf_path}: {e}", repo.name))
+}
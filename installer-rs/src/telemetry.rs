@@ -0,0 +1,87 @@
+use crate::config::Config;
+use crate::hardware::Survey;
+use crate::tui;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Where the anonymous install reports go
+pub const ENDPOINT: &str = "https://blunux.com/api/install-report";
+
+/// How the install went
+pub struct Outcome {
+    pub success: bool,
+    pub seconds: u64,
+    /// Step the install failed in, e.g. "Installing packages"
+    pub failed_step: Option<String>,
+}
+
+/// The report as it would be sent. Hardware is reduced to classes (vendors,
+/// rounded RAM, disk transports): no serials, MACs, names or paths.
+pub fn payload(config: &Config, hw: &Survey, outcome: &Outcome) -> Value {
+    let mut gpus: Vec<&str> = hw.gpus.iter().map(|g| g.vendor.as_str()).collect();
+    gpus.dedup();
+    let storage: Vec<String> = hw
+        .storage
+        .iter()
+        .map(|d| format!("{}/{}", d.transport, if d.ssd { "ssd" } else { "hdd" }))
+        .collect();
+    json!({
+        "installer": env!("CARGO_PKG_VERSION"),
+        "result": if outcome.success { "success" } else { "failed" },
+        "failed_step": outcome.failed_step,
+        "duration_secs": outcome.seconds,
+        "hardware": {
+            "firmware": hw.firmware,
+            "cpu_vendor": hw.cpu.vendor,
+            "cpu_threads": hw.cpu.threads,
+            // Nearest GiB hides the exact amount
+            "ram_gib": hw.ram_mb.map(|mb| (mb + 512) / 1024),
+            "gpu_vendors": gpus,
+            "storage": storage,
+        },
+        "install": {
+            "bootloader": config.install.bootloader,
+            "kernel": config.kernel.type_,
+            "filesystem": config.disk.filesystem.label(),
+            "encrypted": config.uses_luks(),
+        },
+    })
+}
+
+/// Show the report and send it if [install] telemetry allows it, asking
+/// first when unset. Never fails the install.
+pub fn offer(config: &Config, report: &Value) {
+    if config.install.telemetry == Some(false) {
+        return;
+    }
+    println!();
+    tui::print_info("Anonymous install report / 익명 설치 보고서:");
+    println!("{}", serde_json::to_string_pretty(report).unwrap_or_default());
+    if config.install.telemetry.is_none()
+        && !tui::confirm(&format!("Send this report to {ENDPOINT}? / 보고서를 보낼까요?"), false)
+    {
+        return;
+    }
+    if send(report) {
+        tui::print_success("Report sent, thank you / 보고서를 보냈습니다");
+    } else {
+        tui::print_warning("Could not send the install report");
+    }
+}
+
+fn send(report: &Value) -> bool {
+    let child = Command::new("curl")
+        .args(["-fsS", "-m", "15", "-H", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", ENDPOINT])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(report.to_string().as_bytes());
+    }
+    child.wait().map(|s| s.success()).unwrap_or(false)
+}
@@ -1,4 +1,7 @@
+use crate::config::Config;
+use crate::disk::PlanRow;
 use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 // ANSI color codes
 pub const RESET: &str = "\x1b[0m";
@@ -15,6 +18,26 @@ pub struct DiskInfo {
     pub device: String,
     pub model: String,
     pub size: String,
+    pub serial: String,
+    /// "nvme", "sata", "usb", ... (empty if lsblk does not know)
+    pub transport: String,
+    pub rotational: bool,
+    pub removable: bool,
+}
+
+impl DiskInfo {
+    /// Transport and media kind, e.g. "nvme, SSD" or "usb, removable"
+    pub fn kind(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.transport.is_empty() {
+            parts.push(self.transport.clone());
+        }
+        parts.push(if self.rotational { "HDD" } else { "SSD" }.to_string());
+        if self.removable {
+            parts.push("removable".to_string());
+        }
+        parts.join(", ")
+    }
 }
 
 pub fn print_banner() {
@@ -48,6 +71,25 @@ pub fn print_step(step: i32, total: i32, msg: &str) {
     println!("{MAGENTA}[{step}/{total}] {RESET}{msg}");
 }
 
+/// Redraw a progress bar in place; the line is finished once `done`
+/// reaches `total`
+pub fn print_progress(msg: &str, done: u64, total: u64) {
+    const WIDTH: usize = 30;
+    let total = total.max(1);
+    let done = done.min(total);
+    let filled = (done * WIDTH as u64 / total) as usize;
+    print!(
+        "\r{BLUE}[*] {RESET}{msg} [{GREEN}{}{RESET}{}] {:>3}%",
+        "█".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        done * 100 / total
+    );
+    if done == total {
+        println!();
+    }
+    let _ = io::stdout().flush();
+}
+
 pub fn clear_screen() {
     print!("\x1b[2J\x1b[H");
     let _ = io::stdout().flush();
@@ -92,7 +134,274 @@ pub fn draw_box(title: &str, lines: &[&str]) {
     println!("╝{RESET}");
 }
 
-pub fn menu_select(title: &str, options: &[&str], default_selection: usize) -> usize {
+/// "512M" / "93.1G" for a size in MiB
+fn format_mib(mib: u64) -> String {
+    if mib >= 1024 {
+        format!("{:.1}G", mib as f64 / 1024.0)
+    } else {
+        format!("{mib}M")
+    }
+}
+
+/// Proportional bar of the planned partitions, a table of them, and the
+/// partitions on the disk now with what happens to each
+pub fn draw_partition_plan(disk: &str, parts: &[PlanRow], current: &[(PlanRow, &str)]) {
+    const WIDTH: usize = 56;
+    const COLORS: [&str; 6] = [CYAN, GREEN, YELLOW, MAGENTA, BLUE, RED];
+
+    let total: u64 = parts.iter().map(|p| p.size_mib).sum::<u64>().max(1);
+    // Every partition gets at least one cell; the last absorbs rounding
+    let mut widths: Vec<usize> = parts
+        .iter()
+        .map(|p| ((p.size_mib as f64 / total as f64 * WIDTH as f64).round() as usize).max(1))
+        .collect();
+    let used: usize = widths.iter().sum();
+    if let Some(last) = widths.last_mut() {
+        *last = (*last + WIDTH).saturating_sub(used).max(1);
+    }
+
+    println!("\n  {BOLD}Partition plan for {disk}{RESET}\n");
+    print!("  ");
+    for (i, w) in widths.iter().enumerate() {
+        print!("{}{}{RESET}", COLORS[i % COLORS.len()], "█".repeat(*w));
+    }
+    println!("\n");
+    print_plan_header();
+    for (i, part) in parts.iter().enumerate() {
+        let device = if part.device.is_empty() { "(new)" } else { &part.device };
+        print!("  {}█{RESET} ", COLORS[i % COLORS.len()]);
+        print_plan_row(device, part, &part.label);
+    }
+
+    println!("\n  {BOLD}Currently on {disk} / 현재 디스크{RESET}\n");
+    if current.is_empty() {
+        println!("  (no partitions)");
+    } else {
+        print_plan_header();
+    }
+    for (part, fate) in current {
+        let color = match *fate {
+            "kept" => GREEN,
+            "shrunk" => YELLOW,
+            _ => RED,
+        };
+        print!("    ");
+        print_plan_row(&part.device, part, &format!("{color}{}{RESET}", fate.to_uppercase()));
+    }
+    println!();
+}
+
+fn print_plan_header() {
+    println!(
+        "    {BOLD}{:<22} {:>8} {:>8} {:>8}  {:<11} {:<10}{RESET}",
+        "Device", "Start", "End", "Size", "Filesystem", "Mount"
+    );
+}
+
+fn print_plan_row(device: &str, part: &PlanRow, note: &str) {
+    let (start, end) = match part.start_mib {
+        Some(start) => (format_mib(start), format_mib(start + part.size_mib)),
+        None => ("-".to_string(), "-".to_string()),
+    };
+    println!(
+        "{device:<22} {start:>8} {end:>8} {:>8}  {:<11} {:<10} {note}",
+        format_mib(part.size_mib),
+        part.filesystem,
+        part.mountpoint
+    );
+}
+
+/// Typed at a line prompt to return to the previous setup step
+pub const BACK: &str = "<";
+
+/// Number of options shown per page in `menu_select`
+const MENU_PAGE_SIZE: usize = 10;
+
+/// Terminal in non-canonical, no-echo mode; restored on drop
+struct RawMode {
+    saved: nix::sys::termios::Termios,
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        use nix::sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices};
+        let stdin = io::stdin();
+        let saved = termios::tcgetattr(&stdin).ok()?;
+        let mut raw = saved.clone();
+        raw.local_flags &= !(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG);
+        // Block until at least one byte is available
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(&stdin, SetArg::TCSANOW, &raw).ok()?;
+        Some(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = nix::sys::termios::tcsetattr(
+            io::stdin(),
+            nix::sys::termios::SetArg::TCSANOW,
+            &self.saved,
+        );
+    }
+}
+
+/// Set once a menu finds stdin closed; every later menu would go back too
+static INPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// stdin reached end of file in a menu
+pub fn input_closed() -> bool {
+    INPUT_CLOSED.load(Ordering::Relaxed)
+}
+
+enum Key {
+    Up,
+    Down,
+    Left,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    Interrupt,
+    /// stdin closed or unreadable
+    Eof,
+    Char(char),
+    Other,
+}
+
+fn read_key() -> Key {
+    use std::io::Read;
+    let mut stdin = io::stdin().lock();
+    let mut byte = [0u8; 1];
+    if stdin.read_exact(&mut byte).is_err() {
+        INPUT_CLOSED.store(true, Ordering::Relaxed);
+        return Key::Eof;
+    }
+    match byte[0] {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x03 => Key::Interrupt,
+        0x1b => {
+            let mut seq = [0u8; 2];
+            if stdin.read_exact(&mut seq).is_err() || seq[0] != b'[' {
+                return Key::Other;
+            }
+            match seq[1] {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'D' => Key::Left,
+                b'5' | b'6' => {
+                    let mut tilde = [0u8; 1];
+                    let _ = stdin.read_exact(&mut tilde);
+                    if seq[1] == b'5' {
+                        Key::PageUp
+                    } else {
+                        Key::PageDown
+                    }
+                }
+                _ => Key::Other,
+            }
+        }
+        b if b.is_ascii_graphic() || b == b' ' => Key::Char(b as char),
+        _ => Key::Other,
+    }
+}
+
+/// Menu with type-to-filter, arrow-key navigation and paging.
+/// Falls back to a numbered prompt when stdin is not a terminal.
+/// Returns None when the user goes back (← or `BACK`) or stdin is closed.
+/// Ctrl+C is delivered as SIGINT once the terminal is restored, so an
+/// install in progress aborts and unwinds instead of exiting here.
+pub fn menu_select(title: &str, options: &[&str], default_selection: usize) -> Option<usize> {
+    let Some(raw) = RawMode::enable() else {
+        return menu_select_numbered(title, options, default_selection);
+    };
+
+    let mut filter = String::new();
+    let mut cursor = default_selection.min(options.len().saturating_sub(1));
+    let mut drawn_lines = 0usize;
+
+    println!();
+    loop {
+        let needle = filter.to_lowercase();
+        let matches: Vec<usize> = (0..options.len())
+            .filter(|&i| options[i].to_lowercase().contains(&needle))
+            .collect();
+        // cursor is a position within `matches`
+        if cursor >= matches.len() {
+            cursor = matches.len().saturating_sub(1);
+        }
+
+        let page = cursor / MENU_PAGE_SIZE;
+        let pages = matches.len().div_ceil(MENU_PAGE_SIZE).max(1);
+        let start = page * MENU_PAGE_SIZE;
+        let end = (start + MENU_PAGE_SIZE).min(matches.len());
+
+        // Redraw in place
+        let mut out = String::new();
+        if drawn_lines > 0 {
+            out.push_str(&format!("\x1b[{drawn_lines}A\r\x1b[J"));
+        }
+        out.push_str(&format!("{BOLD}{title}{RESET}\n"));
+        out.push_str(&format!("Filter: {CYAN}{filter}{RESET}_\n"));
+        out.push_str(&format!("{}\n", "-".repeat(40)));
+        for (pos, &idx) in matches.iter().enumerate().take(end).skip(start) {
+            if pos == cursor {
+                out.push_str(&format!("  {GREEN}> {BOLD}{}{RESET}\n", options[idx]));
+            } else {
+                out.push_str(&format!("    {}\n", options[idx]));
+            }
+        }
+        if matches.is_empty() {
+            out.push_str(&format!("    {YELLOW}(no matches){RESET}\n"));
+        }
+        out.push_str(&format!(
+            "{BLUE}Page {}/{pages} ({} matches) - ↑↓ move, PgUp/PgDn page, type to filter, Enter select, ← back{RESET}\n",
+            page + 1,
+            matches.len()
+        ));
+        drawn_lines = out.matches('\n').count();
+        print!("{out}");
+        let _ = io::stdout().flush();
+
+        match read_key() {
+            Key::Up => cursor = cursor.saturating_sub(1),
+            Key::Down => {
+                if cursor + 1 < matches.len() {
+                    cursor += 1;
+                }
+            }
+            Key::PageUp => cursor = cursor.saturating_sub(MENU_PAGE_SIZE),
+            Key::PageDown => {
+                cursor = (cursor + MENU_PAGE_SIZE).min(matches.len().saturating_sub(1));
+            }
+            Key::Backspace => {
+                filter.pop();
+                cursor = 0;
+            }
+            Key::Char(c) => {
+                filter.push(c);
+                cursor = 0;
+            }
+            Key::Enter => {
+                if let Some(&idx) = matches.get(cursor) {
+                    return Some(idx);
+                }
+            }
+            Key::Left | Key::Eof => return None,
+            Key::Interrupt => {
+                drop(raw);
+                println!();
+                let _ = nix::sys::signal::raise(nix::sys::signal::Signal::SIGINT);
+                return None;
+            }
+            Key::Other => {}
+        }
+    }
+}
+
+fn menu_select_numbered(title: &str, options: &[&str], default_selection: usize) -> Option<usize> {
     println!();
     println!("{BOLD}{title}{RESET}");
     println!("{}", "-".repeat(40));
@@ -106,23 +415,90 @@ pub fn menu_select(title: &str, options: &[&str], default_selection: usize) -> u
     }
 
     println!();
-    print!("Enter selection [1-{}]: ", options.len());
+    print!("Enter selection [1-{}, {BACK} = back]: ", options.len());
     let _ = io::stdout().flush();
 
     let mut input = String::new();
-    io::stdin().lock().read_line(&mut input).unwrap_or(0);
+    if io::stdin().lock().read_line(&mut input).unwrap_or(0) == 0 {
+        INPUT_CLOSED.store(true, Ordering::Relaxed);
+        return None;
+    }
     let input = input.trim();
 
     if input.is_empty() {
-        return default_selection;
+        return Some(default_selection);
+    }
+    if input == BACK {
+        return None;
     }
 
     match input.parse::<usize>() {
-        Ok(n) if n >= 1 && n <= options.len() => n - 1,
-        _ => default_selection,
+        Ok(n) if n >= 1 && n <= options.len() => Some(n - 1),
+        _ => Some(default_selection),
     }
 }
 
+/// Checkbox-style multi-select. Entering item numbers toggles them;
+/// an empty line accepts the current selection; `BACK` returns None.
+pub fn multi_select(title: &str, options: &[&str], selected: &[bool]) -> Option<Vec<bool>> {
+    let mut state: Vec<bool> = (0..options.len())
+        .map(|i| selected.get(i).copied().unwrap_or(false))
+        .collect();
+
+    loop {
+        println!();
+        println!("{BOLD}{title}{RESET}");
+        println!("{}", "-".repeat(40));
+
+        for (i, option) in options.iter().enumerate() {
+            let mark = if state[i] {
+                format!("{GREEN}[x]{RESET}")
+            } else {
+                "[ ]".to_string()
+            };
+            println!("  {CYAN}{:>2}{RESET} {mark} {option}", i + 1);
+        }
+
+        println!();
+        print!("Toggle items (e.g. 1 3), 'a' = all, 'n' = none, '{BACK}' = back, Enter = done: ");
+        let _ = io::stdout().flush();
+
+        let mut input = String::new();
+        io::stdin().lock().read_line(&mut input).unwrap_or(0);
+        let input = input.trim();
+
+        match input {
+            "" => return Some(state),
+            BACK => return None,
+            "a" | "A" => state.iter_mut().for_each(|s| *s = true),
+            "n" | "N" => state.iter_mut().for_each(|s| *s = false),
+            _ => {
+                for token in input.split([' ', ',']).filter(|t| !t.is_empty()) {
+                    match token.parse::<usize>() {
+                        Ok(n) if n >= 1 && n <= options.len() => state[n - 1] = !state[n - 1],
+                        _ => print_warning(&format!("Ignoring invalid selection: {token}")),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Seconds before `confirm` takes its default answer; 0 = wait forever
+static PROMPT_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// [install] prompt_timeout
+pub fn set_prompt_timeout(secs: u64) {
+    PROMPT_TIMEOUT.store(secs, Ordering::Relaxed);
+}
+
+/// Wait up to `secs` for input on stdin
+fn stdin_ready(secs: u64) -> bool {
+    let mut fds = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    let ms = i32::try_from(secs.saturating_mul(1000)).unwrap_or(i32::MAX);
+    unsafe { libc::poll(&mut fds, 1, ms) > 0 }
+}
+
 pub fn confirm(question: &str, default_yes: bool) -> bool {
     println!();
     if default_yes {
@@ -130,8 +506,17 @@ pub fn confirm(question: &str, default_yes: bool) -> bool {
     } else {
         print!("{YELLOW}{question}{RESET} [y/N]: ");
     }
+    let timeout = PROMPT_TIMEOUT.load(Ordering::Relaxed);
+    if timeout > 0 {
+        print!("{CYAN}({timeout}s){RESET} ");
+    }
     let _ = io::stdout().flush();
 
+    if timeout > 0 && !stdin_ready(timeout) {
+        println!("{}", if default_yes { "y (timeout)" } else { "n (timeout)" });
+        return default_yes;
+    }
+
     let mut input = String::new();
     io::stdin().lock().read_line(&mut input).unwrap_or(0);
     let input = input.trim();
@@ -143,7 +528,8 @@ pub fn confirm(question: &str, default_yes: bool) -> bool {
     input.to_lowercase().starts_with('y')
 }
 
-pub fn input_prompt(prompt: &str, default_value: &str) -> String {
+/// Line prompt; returns None when the user enters `BACK`
+pub fn input_prompt(prompt: &str, default_value: &str) -> Option<String> {
     if default_value.is_empty() {
         print!("{prompt}: ");
     } else {
@@ -156,20 +542,27 @@ pub fn input_prompt(prompt: &str, default_value: &str) -> String {
     let input = input.trim().to_string();
 
     if input.is_empty() {
-        default_value.to_string()
+        Some(default_value.to_string())
+    } else if input == BACK {
+        None
     } else {
-        input
+        Some(input)
     }
 }
 
-pub fn password_input(prompt: &str) -> String {
+/// Hidden line prompt; returns None when the user enters `BACK`
+pub fn password_input(prompt: &str) -> Option<String> {
     print!("{prompt}: ");
     let _ = io::stdout().flush();
 
     // Disable echo using termios
     let password = disable_echo_and_read();
     println!(); // newline after hidden input
-    password
+    if password == BACK {
+        None
+    } else {
+        Some(password)
+    }
 }
 
 fn disable_echo_and_read() -> String {
@@ -220,12 +613,16 @@ pub fn select_disk(disks: &[DiskInfo]) -> Option<DiskInfo> {
 
     for (i, disk) in disks.iter().enumerate() {
         println!(
-            "  {CYAN}[{}]{RESET} {} - {} ({})",
+            "  {CYAN}[{}]{RESET} {} - {} ({}) [{}]",
             i + 1,
             disk.device,
             disk.size,
-            disk.model
+            disk.model,
+            disk.kind()
         );
+        if !disk.serial.is_empty() {
+            println!("      S/N {}", disk.serial);
+        }
     }
 
     println!("  {RED}[0]{RESET} Cancel");
@@ -247,27 +644,96 @@ pub fn select_disk(disks: &[DiskInfo]) -> Option<DiskInfo> {
     }
 }
 
-pub fn show_summary(
-    disk: &str,
-    hostname: &str,
-    username: &str,
-    timezone: &str,
-    keyboard: &str,
-    kernel: &str,
-    encryption: bool,
-    swap_mode: &str,
-) {
-    let enc_str = if encryption { "Yes" } else { "No" };
-    let l_disk = format!("  Target disk:    {disk}");
-    let l_host = format!("  Hostname:       {hostname}");
-    let l_user = format!("  Username:       {username}");
-    let l_tz = format!("  Timezone:       {timezone}");
+pub fn show_summary(cfg: &Config) {
+    let enc_str = if cfg.install.use_encryption { "Yes" } else { "No" };
+    let keyboard = cfg.locale.keyboards.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+    let keyboard = if keyboard.is_empty() { "us".to_string() } else { keyboard.join(", ") };
+    let alongside = &cfg.disk.alongside;
+    let l_disk = if alongside.enabled {
+        let part = if alongside.partition.is_empty() { "auto" } else { &alongside.partition };
+        format!(
+            "  Target disk:    {} (alongside {part}, {})",
+            cfg.install.target_disk, alongside.size
+        )
+    } else if !cfg.disk.keep_home.partition.is_empty() {
+        format!(
+            "  Target disk:    {} (reinstall, keep /home on {})",
+            cfg.install.target_disk, cfg.disk.keep_home.partition
+        )
+    } else if !cfg.disk.layout.is_empty() {
+        format!("  Target disk:    {} ({} layout)", cfg.install.target_disk, cfg.disk.layout)
+    } else {
+        format!("  Target disk:    {}", cfg.install.target_disk)
+    };
+    let l_host = format!("  Hostname:       {}", cfg.install.hostname);
+    let l_user = format!("  Username:       {}", cfg.install.username);
+    let l_tz = format!("  Timezone:       {}", cfg.locale.timezone);
     let l_kb = format!("  Keyboard:       {keyboard}");
-    let l_kern = format!("  Kernel:         {kernel}");
+    let l_kern = format!("  Kernel:         {}", cfg.kernel.type_);
+    let lvm = &cfg.disk.lvm;
+    let l_fs = if lvm.enabled {
+        let root = if lvm.root_size.is_empty() { "rest" } else { &lvm.root_size };
+        let home = if lvm.home { " + home (rest)" } else { "" };
+        format!(
+            "  Filesystem:     {} on LVM (root {root}{home})",
+            cfg.disk.filesystem.label()
+        )
+    } else if cfg.disk.snapshots {
+        format!(
+            "  Filesystem:     {} + {} snapshots",
+            cfg.disk.filesystem.label(),
+            cfg.disk.snapshot_tool
+        )
+    } else {
+        format!("  Filesystem:     {}", cfg.disk.filesystem.label())
+    };
     let l_enc = format!("  Encryption:     {enc_str}");
-    let l_swap = format!("  Swap:           {swap_mode}");
-
-    let lines: Vec<&str> = vec![
+    let l_swap = format!("  Swap:           {}", cfg.disk.swap.label());
+    let keep_table = cfg.disk.keeps_partition_table();
+    let mut extra_lines: Vec<String> = cfg
+        .disk
+        .partitions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let target = if p.is_swap() { "swap" } else { &p.mountpoint };
+            let luks = if p.encrypt { ", encrypted" } else { "" };
+            if keep_table {
+                let action = if p.format { "format" } else { "keep" };
+                let device = if p.device.is_empty() {
+                    format!("partition {}", i + 1)
+                } else {
+                    p.device.clone()
+                };
+                format!("  Partition:      {target} ({device}, {action}{luks})")
+            } else {
+                format!("  Partition:      {target} ({}{luks})", p.size)
+            }
+        })
+        .collect();
+    if !cfg.disk.home_split.is_empty() {
+        extra_lines.push(format!(
+            "  Home:           separate partition (root {}, /home the rest)",
+            cfg.disk.home_split
+        ));
+    }
+    if cfg.disk.secure_erase != "none" {
+        extra_lines.push(format!("  Secure erase:   {}", cfg.disk.secure_erase));
+    }
+    if !cfg.disk.esp_disk.is_empty() {
+        extra_lines.push(format!("  EFI disk:       {} (erased)", cfg.disk.esp_disk));
+    }
+    extra_lines.extend(cfg.disk.extra.iter().map(|d| {
+        let action = match (d.format, d.games) {
+            (true, true) => "format, games",
+            (true, false) => "format",
+            (false, true) => "keep, games",
+            (false, false) => "keep",
+        };
+        format!("  Extra disk:     {} -> {} ({action})", d.device, d.mountpoint)
+    }));
+
+    let mut lines: Vec<&str> = vec![
         "",
         &l_disk,
         &l_host,
@@ -275,11 +741,55 @@ pub fn show_summary(
         &l_tz,
         &l_kb,
         &l_kern,
+        &l_fs,
         &l_enc,
         &l_swap,
-        "  Desktop:        KDE Plasma",
-        "",
     ];
+    lines.extend(extra_lines.iter().map(|s| s.as_str()));
+    let l_kiosk = format!("  Kiosk:          {} ({})", cfg.kiosk.app, cfg.kiosk.user);
+    if cfg.kiosk.enabled {
+        lines.push(&l_kiosk);
+    }
+    let sec = &cfg.security;
+    let sec_features: Vec<&str> = [
+        (sec.firewall, "firewall"),
+        (sec.apparmor, "apparmor"),
+        (sec.sysctl_hardening, "sysctl"),
+        (sec.auto_updates, "auto-updates"),
+        (sec.disable_root_ssh, "no-root-ssh"),
+        (sec.antivirus, "clamav"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, name)| *name)
+    .collect();
+    let l_sec = if sec.preset.is_empty() {
+        format!("  Security:       {}", sec_features.join(", "))
+    } else {
+        format!("  Security:       {} ({})", sec.preset, sec_features.join(", "))
+    };
+    if !sec_features.is_empty() {
+        lines.push(&l_sec);
+    }
+    let l_ansible = format!("  Ansible pull:   {}", cfg.provision.ansible_repo);
+    if !cfg.provision.ansible_repo.is_empty() {
+        lines.push(&l_ansible);
+    }
+    let l_salt = format!("  Salt master:    {}", cfg.provision.salt_master);
+    if !cfg.provision.salt_master.is_empty() {
+        lines.push(&l_salt);
+    }
+    let container_names: Vec<String> = cfg
+        .containers
+        .iter()
+        .map(|c| format!("{} ({})", c.name, c.distro))
+        .collect();
+    let l_containers = format!("  Containers:     {}", container_names.join(", "));
+    if !container_names.is_empty() {
+        lines.push(&l_containers);
+    }
+    lines.push("  Desktop:        KDE Plasma");
+    lines.push("");
 
     draw_box("Installation Summary / 설치 요약", &lines);
 }
@@ -216,6 +216,8 @@ const BASE_PACKAGES = [
     "dosfstools",
     "mtools",
     "btrfs-progs",               # Btrfs filesystem tools
+    "bcachefs-tools",            # bcachefs tools (experimental root filesystem option)
+    "smartmontools",             # smartctl health check of the target disk before install
     # Package management
     "archlinux-keyring",
     "reflector",